@@ -6,7 +6,6 @@ from random import randint
 from random import uniform
 import argparse
 import json
-import csv
 import sys
 import logging
 import os
@@ -326,7 +325,7 @@ class Client:
     '''
 
     # Each response will be a JSON object through TCP
-    # actual result from sql will be in csv format
+    # actual result from sql will be in rows of typed cells, along with the columns
 
     # Note: each function will take whatever retrieved by req.getParameter(varname) as argument 
     # sql response types:
@@ -1089,7 +1088,7 @@ class Client:
             return ["0"] * 20 
 
         if ALLOW_ABORT and OK not in response["reply"]["Query"]:
-            if "aborted" in response["reply"]["Query"]["Err"]:
+            if "aborted" in response["reply"]["Query"]["Err"]["message"]:
                 return "Abort"
 
         if OK not in response["reply"]["Query"]:
@@ -1097,20 +1096,26 @@ class Client:
             return "Err"
 
         response = response["reply"]["Query"][OK]
-        self.logger.critical("csv string: {}".format(response))
-        if not response:
+        self.logger.critical("query result: {}".format(response))
+        if not response["columns"] and response["affected_rows"] is not None:
+            result = [["Affected rows"], [str(response["affected_rows"])]]
+        elif not response["rows"]:
             self.logger.warning("Response to {} is empty".format(name))
             return "Empty"
-
-        result = list(csv.reader(response.splitlines()))
-        for i in range(len(result)):
-            for j in range(len(result[0])):
-                if result[i][j].startswith('"') and result[i][j].endswith('"'):
-                    result[i][j] = result[i][j][1:-1]
-        self.logger.info("csv list: {}".format(result))
+        else:
+            result = [[self.cellToText(cell) for cell in row] for row in response["rows"]]
+        self.logger.info("result list: {}".format(result))
         
         return result
     
+    def cellToText(self, cell):
+        # Render a typed cell the same way as the text format of postgres
+        if cell is None:
+            return ""
+        if isinstance(cell, bool):
+            return "t" if cell else "f"
+        return str(cell)
+
     def isErr(self, response):
         return response == "Err"
     
@@ -0,0 +1,441 @@
+use super::transaction::Transaction;
+use crate::comm::scheduler_api::Message;
+use crate::comm::{MsqlResponse, MsqlResult};
+use crate::core::*;
+use futures::pin_mut;
+use futures::prelude::*;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{debug, trace, warn};
+
+type JsonStream = SymmetricallyFramed<Framed<TcpStream, LengthDelimitedCodec>, Message, SymmetricalJson<Message>>;
+
+/// A connection to the Scheduler
+///
+/// Requests are sent one at a time, and each of them waits for its reply.
+/// Once the tcp connection is broken, the next request reconnects to the Scheduler.
+/// A request dropped before its reply breaks the tcp connection, so that the reply is never mistaken for another.
+/// As the Scheduler aborts the unclosed transaction of a dropped connection,
+/// the ongoing transaction is lost when the tcp connection is broken, so that
+/// the requests within that `Transaction` fail instead of reconnecting, until it is ended.
+///
+/// # Examples
+/// ```no_run
+/// use o2versioner::client::Connection;
+/// use o2versioner::core::{EarlyReleaseTables, MsqlBeginTx, MsqlQuery, TableOps};
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let mut conn = Connection::connect("127.0.0.1:2077").await?;
+///
+///     // Single read query
+///     let result = conn
+///         .query(MsqlQuery::auto_annotated("SELECT * FROM t0;", EarlyReleaseTables::default())?)
+///         .await?;
+///     println!("{}", result.to_csv());
+///
+///     // Transaction is rolled back if it is dropped before commit
+///     let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("read t0 write t1"))).await?;
+///     tx.query(MsqlQuery::auto_annotated("UPDATE t1 SET a = 1;", EarlyReleaseTables::default())?)
+///         .await?;
+///     tx.commit().await?;
+///     Ok(())
+/// }
+/// ```
+pub struct Connection {
+    addrs: Vec<SocketAddr>,
+    /// `None` if the tcp connection is broken
+    stream: Arc<Mutex<Option<JsonStream>>>,
+    reconnect_attempts: u32,
+    reconnect_interval: Duration,
+    /// Whether a `Transaction` is ongoing on this connection
+    in_transaction: bool,
+}
+
+impl Connection {
+    /// Connect to the Scheduler at `addrs`
+    pub async fn connect<A: ToSocketAddrs>(addrs: A) -> io::Result<Self> {
+        let addrs: Vec<_> = lookup_host(addrs).await?.collect();
+        let stream = open(&addrs).await?;
+        Ok(Self {
+            addrs,
+            stream: Arc::new(Mutex::new(Some(stream))),
+            reconnect_attempts: 3,
+            reconnect_interval: Duration::from_millis(100),
+            in_transaction: false,
+        })
+    }
+
+    /// Set the number of attempts to reconnect to the Scheduler and the interval between them,
+    /// will overwrite previous value
+    pub fn set_reconnect(mut self, attempts: u32, interval: Duration) -> Self {
+        self.reconnect_attempts = attempts;
+        self.reconnect_interval = interval;
+        self
+    }
+
+    /// Whether the tcp connection is not broken, the result is `true` if the connection is in use
+    pub fn is_connected(&self) -> bool {
+        self.stream.try_lock().map_or(true, |stream| stream.is_some())
+    }
+
+    /// Send a raw `Message` to the Scheduler and wait for its reply
+    ///
+    /// If the tcp connection is broken, an `MsqlErrorKind::Unavailable` error is returned,
+    /// and the Scheduler is reconnected for the next request outside of a `Transaction`.
+    pub async fn request(&self, msg: Message) -> Result<Message, MsqlError> {
+        self.request_cancellable(msg, future::pending()).await
+    }
+
+    /// Send a raw `Message` to the Scheduler and wait for its reply,
+    /// `Message::RequestCancel` is sent if `cancel` completes before the reply
+    pub async fn request_cancellable<F>(&self, msg: Message, cancel: F) -> Result<Message, MsqlError>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut stream = self.stream.lock().await;
+        // Taken out until the reply is received, so that it is left broken if this future is dropped
+        let mut json_stream = match stream.take() {
+            Some(json_stream) => json_stream,
+            None if self.in_transaction => {
+                // A new tcp connection would silently run the rest of the transaction outside of it
+                return Err(MsqlError::unavailable(
+                    "Connection to Scheduler was broken, transaction lost",
+                ));
+            }
+            None => self.reconnect().await?,
+        };
+
+        let reply = send_and_receive_cancellable(&mut json_stream, msg, cancel)
+            .await
+            .map_err(|e| MsqlError::unavailable(format!("Connection to Scheduler is broken: {}", e)))?;
+        *stream = Some(json_stream);
+        Ok(reply)
+    }
+
+    /// Send a `Msql` request to the Scheduler
+    pub async fn execute(&self, msql: Msql) -> Result<MsqlResponse, MsqlError> {
+        into_msql_response(self.request(Message::RequestMsql(msql)).await?)
+    }
+
+    /// Send a `MsqlText` request to the Scheduler
+    pub async fn execute_text(&self, msqltext: MsqlText) -> Result<MsqlResponse, MsqlError> {
+        into_msql_response(self.request(Message::RequestMsqlText(msqltext)).await?)
+    }
+
+    /// Send a `Msql` request to the Scheduler, which cancels it if it is not done within `timeout`
+    ///
+    /// Only queries can be cancelled, and they are replied with a `MsqlErrorKind::Cancelled` error once cancelled.
+    /// The transaction can go on after the cancelled query, unless it is aborted by the Scheduler.
+    pub async fn execute_with_timeout(&self, msql: Msql, timeout: Duration) -> Result<MsqlResponse, MsqlError> {
+        let msg = Message::request_with_timeout(Message::RequestMsql(msql), timeout.as_millis() as u64);
+        into_msql_response(self.request(msg).await?)
+    }
+
+    /// Send a `Msql` request to the Scheduler, which is cancelled if `cancel` completes before the reply
+    ///
+    /// Same as `Connection::execute_with_timeout`, only queries can be cancelled.
+    pub async fn execute_cancellable<F>(&self, msql: Msql, cancel: F) -> Result<MsqlResponse, MsqlError>
+    where
+        F: Future<Output = ()>,
+    {
+        into_msql_response(self.request_cancellable(Message::RequestMsql(msql), cancel).await?)
+    }
+
+    /// Begin a transaction, which is rolled back if the returned `Transaction` is dropped before ending it
+    pub async fn begin(&mut self, msqlbegintx: MsqlBeginTx) -> Result<Transaction<'_>, MsqlError> {
+        match self.execute(Msql::BeginTx(msqlbegintx)).await? {
+            MsqlResponse::BeginTx(res) => res.map(move |_| {
+                self.in_transaction = true;
+                Transaction::new(self)
+            }),
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    /// Execute a single query outside of a transaction
+    pub async fn query(&mut self, msqlquery: MsqlQuery) -> Result<MsqlResult, MsqlError> {
+        self.execute_query(msqlquery).await
+    }
+
+    pub(super) async fn execute_query(&self, msqlquery: MsqlQuery) -> Result<MsqlResult, MsqlError> {
+        match self.execute(Msql::Query(msqlquery)).await? {
+            MsqlResponse::Query(res) => res,
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    pub(super) fn end_transaction(&mut self) {
+        self.in_transaction = false;
+    }
+
+    /// Roll back the current transaction without waiting for the reply,
+    /// the next request is sent only after the rollback is done
+    pub(super) fn rollback_in_background(&self) {
+        // No request can be ongoing as the transaction borrows the connection mutably
+        let mut stream = match self.stream.clone().try_lock_owned() {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Some(mut json_stream) = stream.take() {
+                        let msg = Message::RequestMsql(Msql::EndTx(MsqlEndTx::rollback()));
+                        match send_and_receive(&mut json_stream, msg).await {
+                            Ok(reply) => {
+                                debug!("Rolled back dropped transaction: {:?}", reply);
+                                *stream = Some(json_stream);
+                            }
+                            Err(e) => warn!("Cannot roll back dropped transaction: {}", e),
+                        }
+                    }
+                });
+            }
+            Err(_) => {
+                // Without a runtime, the Scheduler aborts the transaction once the tcp connection is dropped
+                warn!("Dropping the connection to roll back the transaction");
+                *stream = None;
+            }
+        }
+    }
+
+    async fn reconnect(&self) -> Result<JsonStream, MsqlError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match open(&self.addrs).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if attempt >= self.reconnect_attempts => {
+                    return Err(MsqlError::unavailable(format!("Cannot reconnect to Scheduler: {}", e)));
+                }
+                Err(e) => {
+                    warn!("Reconnecting to Scheduler, attempt {} failed: {}", attempt, e);
+                    sleep(self.reconnect_interval).await;
+                }
+            }
+        }
+    }
+}
+
+async fn open(addrs: &[SocketAddr]) -> io::Result<JsonStream> {
+    let tcp_stream = TcpStream::connect(addrs).await?;
+    Ok(SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    ))
+}
+
+async fn send_and_receive(stream: &mut JsonStream, msg: Message) -> io::Result<Message> {
+    send_and_receive_cancellable(stream, msg, future::pending()).await
+}
+
+/// The reply is still waited for after sending `Message::RequestCancel`
+async fn send_and_receive_cancellable<F>(stream: &mut JsonStream, msg: Message, cancel: F) -> io::Result<Message>
+where
+    F: Future<Output = ()>,
+{
+    trace!("-> {:?}", msg);
+    stream.send(msg).await?;
+
+    pin_mut!(cancel);
+    let mut cancelled = false;
+    let reply = loop {
+        tokio::select! {
+            reply = stream.try_next() => break reply?,
+            _ = &mut cancel, if !cancelled => {
+                cancelled = true;
+                trace!("-> {:?}", Message::RequestCancel);
+                stream.send(Message::RequestCancel).await?;
+            }
+        }
+    };
+    let reply = reply.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed by Scheduler"))?;
+    trace!("<- {:?}", reply);
+    Ok(reply)
+}
+
+fn into_msql_response(reply: Message) -> Result<MsqlResponse, MsqlError> {
+    match reply {
+        Message::Reply(msqlresponse) => Ok(msqlresponse),
+        Message::InvalidMsqlText(e) => Err(MsqlError::annotation(e)),
+        other => Err(MsqlError::internal(format!(
+            "Invalid reply from Scheduler: {:?}",
+            other
+        ))),
+    }
+}
+
+pub(super) fn unexpected_response(msqlresponse: MsqlResponse) -> MsqlError {
+    MsqlError::internal(format!("Unexpected response from Scheduler: {:?}", msqlresponse))
+}
+
+/// Unit test for `Connection`
+#[cfg(test)]
+mod tests_connection {
+    use super::*;
+    use crate::util::tests_helper;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let _guard = tests_helper::init_logger();
+        let addr = "127.0.0.1:24601";
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_handle = tokio::spawn(async move {
+            // The first connection is closed right away
+            drop(listener.accept().await.unwrap());
+
+            // The second connection echoes all messages back
+            let (mut tcp_stream, _) = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tcp_stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let conn = Connection::connect(addr).await.unwrap();
+        let err = conn.request(Message::test("0")).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        assert!(err.is_retryable());
+        assert!(!conn.is_connected());
+
+        assert_eq!(conn.request(Message::test("1")).await.unwrap(), Message::test("1"));
+        assert!(conn.is_connected());
+        assert_eq!(
+            conn.execute_text(MsqlText::endtx(Option::<String>::None, MsqlEndTxMode::Commit))
+                .await
+                .unwrap_err()
+                .kind(),
+            MsqlErrorKind::Internal
+        );
+
+        drop(conn);
+        server_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_failed() {
+        let _guard = tests_helper::init_logger();
+        let addr = "127.0.0.1:24602";
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_handle = tokio::spawn(async move {
+            drop(listener.accept().await.unwrap());
+        });
+
+        let conn = Connection::connect(addr)
+            .await
+            .unwrap()
+            .set_reconnect(2, Duration::from_millis(10));
+        server_handle.await.unwrap();
+
+        // The listener is closed, so that both the request and the reconnection fail
+        assert_eq!(
+            conn.request(Message::test("0")).await.unwrap_err().kind(),
+            MsqlErrorKind::Unavailable
+        );
+        let err = conn.request(Message::test("1")).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        assert!(err.message().starts_with("Cannot reconnect to Scheduler"));
+    }
+
+    #[tokio::test]
+    async fn test_broken_transaction() {
+        let _guard = tests_helper::init_logger();
+        let addr = "127.0.0.1:24603";
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_handle = tokio::spawn(async move {
+            // The first connection is closed right after beginning the transaction
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut stream = SymmetricallyFramed::new(
+                Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+                SymmetricalJson::<Message>::default(),
+            );
+            stream.try_next().await.unwrap().unwrap();
+            stream.send(Message::Reply(MsqlResponse::begintx_ok())).await.unwrap();
+            drop(stream);
+
+            // The second connection is only made after the transaction is ended, and echoes all messages back
+            let (mut tcp_stream, _) = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tcp_stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let mut conn = Connection::connect(addr).await.unwrap();
+        let mut tx = conn.begin(MsqlBeginTx::default()).await.unwrap();
+        let msqlquery = MsqlQuery::new(
+            "select * from t0;",
+            TableOps::from("read t0"),
+            EarlyReleaseTables::default(),
+        )
+        .unwrap();
+
+        let err = tx.query(msqlquery.clone()).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        assert!(err.message().starts_with("Connection to Scheduler is broken"));
+
+        // Not reconnected within the transaction
+        let err = tx.query(msqlquery).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        assert_eq!(err.message(), "Connection to Scheduler was broken, transaction lost");
+        assert_eq!(
+            tx.commit().await.unwrap_err().message(),
+            "Connection to Scheduler was broken, transaction lost"
+        );
+        assert!(!conn.is_connected());
+
+        // Reconnected once the transaction is ended
+        assert_eq!(conn.request(Message::test("0")).await.unwrap(), Message::test("0"));
+        assert!(conn.is_connected());
+
+        drop(conn);
+        server_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_request() {
+        let _guard = tests_helper::init_logger();
+        let addr = "127.0.0.1:24604";
+
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let server_handle = tokio::spawn(async move {
+            // The first connection begins the transaction, and then never replies the commit
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let mut stream = SymmetricallyFramed::new(
+                Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+                SymmetricalJson::<Message>::default(),
+            );
+            stream.try_next().await.unwrap().unwrap();
+            stream.send(Message::Reply(MsqlResponse::begintx_ok())).await.unwrap();
+            while let Ok(Some(_)) = stream.try_next().await {}
+
+            // The second connection echoes all messages back
+            let (mut tcp_stream, _) = listener.accept().await.unwrap();
+            let (mut reader, mut writer) = tcp_stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let mut conn = Connection::connect(addr).await.unwrap();
+        let tx = conn.begin(MsqlBeginTx::default()).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(100), tx.commit())
+            .await
+            .unwrap_err();
+
+        // The commit is dropped before its reply, breaking the connection and ending the transaction
+        assert!(!conn.is_connected());
+        assert_eq!(conn.request(Message::test("0")).await.unwrap(), Message::test("0"));
+        assert!(conn.is_connected());
+
+        drop(conn);
+        server_handle.await.unwrap();
+    }
+}
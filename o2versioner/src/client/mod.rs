@@ -0,0 +1,12 @@
+//! The `client` module - Async client of the Scheduler
+//! - `Connection` to send `Msql` requests to the Scheduler, with automatic reconnection
+//! - `Transaction` guard, which rolls back the transaction if it is dropped without being ended
+//! - `ConnectionManager` to pool `Connection`s with `bb8`
+
+mod connection;
+mod pool;
+mod transaction;
+
+pub use connection::Connection;
+pub use pool::ConnectionManager;
+pub use transaction::Transaction;
@@ -0,0 +1,71 @@
+use super::connection::Connection;
+use crate::core::MsqlError;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use tokio::net::{lookup_host, ToSocketAddrs};
+use tracing::info;
+
+/// Manages `Connection`s to the Scheduler for `bb8::Pool`
+///
+/// # Examples
+/// ```no_run
+/// use bb8::Pool;
+/// use o2versioner::client::ConnectionManager;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let pool = Pool::builder()
+///         .max_size(4)
+///         .build(ConnectionManager::new("127.0.0.1:2077").await)
+///         .await?;
+///
+///     // To get a connection exclusively
+///     // let conn = pool.get().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ConnectionManager {
+    addrs: Vec<SocketAddr>,
+}
+
+impl ConnectionManager {
+    pub async fn new<A>(addrs: A) -> Self
+    where
+        A: ToSocketAddrs,
+    {
+        Self {
+            addrs: lookup_host(addrs).await.expect("Unexpected socket addresses").collect(),
+        }
+    }
+}
+
+impl Drop for ConnectionManager {
+    fn drop(&mut self) {
+        info!("ConnectionManager with connections to {:?} terminated", self.addrs);
+    }
+}
+
+#[async_trait]
+impl bb8::ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = MsqlError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        Connection::connect(&self.addrs[..])
+            .await
+            .map_err(|e| MsqlError::unavailable(format!("Cannot connect to Scheduler: {}", e)))
+    }
+
+    async fn is_valid(&self, conn: &mut bb8::PooledConnection<'_, Self>) -> Result<(), Self::Error> {
+        if conn.is_connected() {
+            Ok(())
+        } else {
+            Err(MsqlError::unavailable("Connection to Scheduler is broken"))
+        }
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        !conn.is_connected()
+    }
+}
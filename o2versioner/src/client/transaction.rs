@@ -0,0 +1,77 @@
+use super::connection::{unexpected_response, Connection};
+use crate::comm::{MsqlResponse, MsqlResult};
+use crate::core::*;
+use tracing::warn;
+
+/// A transaction on a `Connection`, returned by `Connection::begin`
+///
+/// The transaction must be ended with `commit` or `rollback`.
+/// If it is dropped before that, a `MsqlEndTx::rollback()` is sent to the Scheduler,
+/// and the next request on the `Connection` waits for the rollback to finish.
+pub struct Transaction<'a> {
+    conn: &'a mut Connection,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(conn: &'a mut Connection) -> Self {
+        Self { conn, finished: false }
+    }
+
+    /// Execute a query within the transaction
+    pub async fn query(&mut self, msqlquery: MsqlQuery) -> Result<MsqlResult, MsqlError> {
+        self.conn.execute_query(msqlquery).await
+    }
+
+    /// Set a new savepoint
+    pub async fn savepoint<S: Into<String>>(&mut self, name: S) -> Result<(), MsqlError> {
+        self.execute_savepoint(MsqlSavepoint::savepoint(name)).await
+    }
+
+    /// Roll back to an existing savepoint
+    pub async fn rollback_to<S: Into<String>>(&mut self, name: S) -> Result<(), MsqlError> {
+        self.execute_savepoint(MsqlSavepoint::rollback_to(name)).await
+    }
+
+    /// Release an existing savepoint
+    pub async fn release<S: Into<String>>(&mut self, name: S) -> Result<(), MsqlError> {
+        self.execute_savepoint(MsqlSavepoint::release(name)).await
+    }
+
+    /// Commit the transaction
+    pub async fn commit(self) -> Result<(), MsqlError> {
+        self.end(MsqlEndTx::commit()).await
+    }
+
+    /// Roll back the transaction
+    pub async fn rollback(self) -> Result<(), MsqlError> {
+        self.end(MsqlEndTx::rollback()).await
+    }
+
+    async fn execute_savepoint(&mut self, msqlsavepoint: MsqlSavepoint) -> Result<(), MsqlError> {
+        match self.conn.execute(Msql::Savepoint(msqlsavepoint)).await? {
+            MsqlResponse::Savepoint(res) => res.map(|_| ()),
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    async fn end(mut self, msqlendtx: MsqlEndTx) -> Result<(), MsqlError> {
+        // Once the request is sent, the transaction is ended by the Scheduler even if it fails
+        self.finished = true;
+        match self.conn.execute(Msql::EndTx(msqlendtx)).await? {
+            MsqlResponse::EndTx(res) => res.map(|_| ()),
+            other => Err(unexpected_response(other)),
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            warn!("Transaction is dropped without being ended, rolling back");
+            self.conn.rollback_in_background();
+        }
+        // Also if dropped while ending it, since the connection is then broken without its reply
+        self.conn.end_transaction();
+    }
+}
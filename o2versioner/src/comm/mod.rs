@@ -1,11 +1,13 @@
 //! The `comm` module - Communication Protocols and Messages
 //! - Between client and scheduler
+//! - Between client and scheduler in the PostgreSQL frontend/backend protocol
 //! - Between scheduler and sequencer
 //! - Between scheduler and dbproxy
 
 mod msql_response;
+pub mod pg_protocol;
 pub mod scheduler_api;
 pub mod scheduler_dbproxy;
 pub mod scheduler_sequencer;
 
-pub use msql_response::MsqlResponse;
+pub use msql_response::{MsqlColumn, MsqlResponse, MsqlResult};
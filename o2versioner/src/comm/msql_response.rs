@@ -1,47 +1,171 @@
-use crate::core::Msql;
+use crate::core::{Msql, MsqlError};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A column of a `MsqlResult`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MsqlColumn {
+    name: String,
+    type_name: String,
+}
+
+impl MsqlColumn {
+    pub fn new<S: Into<String>, T: Into<String>>(name: S, type_name: T) -> Self {
+        Self {
+            name: name.into(),
+            type_name: type_name.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the Sql type of the column, for example `int4` or `varchar`
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+/// The structured result of a `Msql::Query`
+///
+/// Each row contains one cell per column, where every cell is a typed Json value,
+/// and Sql `NULL` is represented as Json `null`.
+/// A query returning no columns, such as an `INSERT`, reports its number of affected rows instead.
+///
+/// # Examples
+/// ```
+/// use o2versioner::comm::{MsqlColumn, MsqlResult};
+/// use serde_json::json;
+///
+/// let result = MsqlResult::new(
+///     vec![MsqlColumn::new("id", "int4"), MsqlColumn::new("name", "text")],
+///     vec![vec![json!(1), json!("ray")], vec![json!(2), json!(null)]],
+/// );
+/// assert_eq!(result.to_csv(), "id,name\n1,ray\n2,\n");
+///
+/// let result = MsqlResult::affected(3);
+/// assert_eq!(result.affected_rows(), Some(3));
+/// assert_eq!(result.to_csv(), "Affected rows\n3\n");
+/// ```
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MsqlResult {
+    columns: Vec<MsqlColumn>,
+    rows: Vec<Vec<Value>>,
+    affected_rows: Option<u64>,
+}
+
+impl MsqlResult {
+    /// Result of a query returning rows
+    pub fn new(columns: Vec<MsqlColumn>, rows: Vec<Vec<Value>>) -> Self {
+        Self {
+            columns,
+            rows,
+            affected_rows: None,
+        }
+    }
+
+    /// Result of a query returning no rows
+    pub fn affected(affected_rows: u64) -> Self {
+        Self {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            affected_rows: Some(affected_rows),
+        }
+    }
+
+    pub fn columns(&self) -> &[MsqlColumn] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> &[Vec<Value>] {
+        &self.rows
+    }
+
+    pub fn affected_rows(&self) -> Option<u64> {
+        self.affected_rows
+    }
+
+    /// Render the result as a csv string
+    ///
+    /// The header is the column names, followed by one record per row where `NULL` is an empty field.
+    /// The number of affected rows is rendered under an `Affected rows` header if there is no column.
+    pub fn to_csv(&self) -> String {
+        let mut wrt = csv::Writer::from_writer(vec![]);
+
+        if self.columns.is_empty() {
+            if let Some(affected_rows) = self.affected_rows {
+                wrt.write_record(["Affected rows"]).unwrap();
+                wrt.write_record(&[affected_rows.to_string()]).unwrap();
+            }
+        } else {
+            wrt.write_record(self.columns.iter().map(|column| column.name()))
+                .unwrap();
+            self.rows.iter().for_each(|row| {
+                wrt.write_record(row.iter().map(|cell| match cell {
+                    Value::Null => String::new(),
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                }))
+                .unwrap();
+            });
+        }
+
+        String::from_utf8(wrt.into_inner().unwrap()).unwrap()
+    }
+}
 
 /// A response for a `Msql` request
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum MsqlResponse {
-    BeginTx(Result<(), String>),
-    Query(Result<String, String>),
-    EndTx(Result<String, String>),
+    BeginTx(Result<(), MsqlError>),
+    Query(Result<MsqlResult, MsqlError>),
+    EndTx(Result<String, MsqlError>),
+    Savepoint(Result<String, MsqlError>),
 }
 
 impl MsqlResponse {
-    pub fn err<S: Into<String>>(err: S, msql: &Msql) -> Self {
+    pub fn err(err: MsqlError, msql: &Msql) -> Self {
         match msql {
             Msql::BeginTx(_) => Self::begintx_err(err),
             Msql::Query(_) => Self::query_err(err),
             Msql::EndTx(_) => Self::endtx_err(err),
+            Msql::Savepoint(_) => Self::savepoint_err(err),
         }
     }
 
-    pub fn begintx_err<S: Into<String>>(err: S) -> Self {
-        Self::BeginTx(Err(err.into()))
+    pub fn begintx_err(err: MsqlError) -> Self {
+        Self::BeginTx(Err(err))
     }
 
     pub fn begintx_ok() -> Self {
         Self::BeginTx(Ok(()))
     }
 
-    pub fn query_err<S: Into<String>>(err: S) -> Self {
-        Self::Query(Err(err.into()))
+    pub fn query_err(err: MsqlError) -> Self {
+        Self::Query(Err(err))
     }
 
-    pub fn query_ok<S: Into<String>>(ok: S) -> Self {
-        Self::Query(Ok(ok.into()))
+    pub fn query_ok(ok: MsqlResult) -> Self {
+        Self::Query(Ok(ok))
     }
 
-    pub fn endtx_err<S: Into<String>>(err: S) -> Self {
-        Self::EndTx(Err(err.into()))
+    pub fn endtx_err(err: MsqlError) -> Self {
+        Self::EndTx(Err(err))
     }
 
     pub fn endtx_ok<S: Into<String>>(ok: S) -> Self {
         Self::EndTx(Ok(ok.into()))
     }
 
+    pub fn savepoint_err(err: MsqlError) -> Self {
+        Self::Savepoint(Err(err))
+    }
+
+    pub fn savepoint_ok<S: Into<String>>(ok: S) -> Self {
+        Self::Savepoint(Ok(ok.into()))
+    }
+
     pub fn is_begintx(&self) -> bool {
         match self {
             Self::BeginTx(_) => true,
@@ -63,11 +187,29 @@ impl MsqlResponse {
         }
     }
 
+    pub fn is_savepoint(&self) -> bool {
+        match self {
+            Self::Savepoint(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn is_ok(&self) -> bool {
         match self {
             Self::BeginTx(r) => r.is_ok(),
             Self::Query(r) => r.is_ok(),
             Self::EndTx(r) => r.is_ok(),
+            Self::Savepoint(r) => r.is_ok(),
+        }
+    }
+
+    /// Returns the `MsqlError` if the response is an error
+    pub fn try_get_err(&self) -> Option<&MsqlError> {
+        match self {
+            Self::BeginTx(r) => r.as_ref().err(),
+            Self::Query(r) => r.as_ref().err(),
+            Self::EndTx(r) => r.as_ref().err(),
+            Self::Savepoint(r) => r.as_ref().err(),
         }
     }
 
@@ -76,6 +218,7 @@ impl MsqlResponse {
             Self::BeginTx(r) => r.is_err(),
             Self::Query(r) => r.is_err(),
             Self::EndTx(r) => r.is_err(),
+            Self::Savepoint(r) => r.is_err(),
         }
     }
 }
@@ -91,16 +234,96 @@ mod tests_msql_response {
         let msqlbegintx = Msql::BeginTx(MsqlBeginTx::default());
         let msqlquery = Msql::Query(MsqlQuery::new("", TableOps::default(), EarlyReleaseTables::default()).unwrap());
         let msqlendtx = Msql::EndTx(MsqlEndTx::commit());
+        let msqlsavepoint = Msql::Savepoint(MsqlSavepoint::savepoint("sp0"));
+
+        let err = MsqlError::internal("a");
 
-        assert_eq!(MsqlResponse::err("a", &msqlbegintx), MsqlResponse::begintx_err("a"));
-        assert_eq!(MsqlResponse::err("a", &msqlquery), MsqlResponse::query_err("a"));
-        assert_eq!(MsqlResponse::err("a", &msqlendtx), MsqlResponse::endtx_err("a"));
+        assert_eq!(
+            MsqlResponse::err(err.clone(), &msqlbegintx),
+            MsqlResponse::begintx_err(err.clone())
+        );
+        assert_eq!(
+            MsqlResponse::err(err.clone(), &msqlquery),
+            MsqlResponse::query_err(err.clone())
+        );
+        assert_eq!(
+            MsqlResponse::err(err.clone(), &msqlendtx),
+            MsqlResponse::endtx_err(err.clone())
+        );
+        assert_eq!(
+            MsqlResponse::err(err.clone(), &msqlsavepoint),
+            MsqlResponse::savepoint_err(err.clone())
+        );
+        assert_eq!(MsqlResponse::err(err.clone(), &msqlquery).try_get_err(), Some(&err));
+        assert_eq!(MsqlResponse::begintx_ok().try_get_err(), None);
     }
 
     #[test]
     fn test_ok() {
         assert!(MsqlResponse::begintx_ok().is_begintx());
-        assert!(MsqlResponse::query_ok("a").is_query());
+        assert!(MsqlResponse::query_ok(MsqlResult::default()).is_query());
         assert!(MsqlResponse::endtx_ok("a").is_endtx());
+        assert!(MsqlResponse::savepoint_ok("a").is_savepoint());
+    }
+}
+
+/// Unit test for `MsqlResult`
+#[cfg(test)]
+mod tests_msql_result {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_csv() {
+        let result = MsqlResult::new(
+            vec![MsqlColumn::new("id", "int4"), MsqlColumn::new("addr", "varchar")],
+            vec![vec![json!(1), json!("1 King St, Toronto")], vec![json!(2), json!(null)]],
+        );
+        assert_eq!(result.to_csv(), "id,addr\n1,\"1 King St, Toronto\"\n2,\n");
+
+        let result = MsqlResult::new(vec![MsqlColumn::new("id", "int4")], vec![]);
+        assert_eq!(result.to_csv(), "id\n");
+
+        assert_eq!(MsqlResult::affected(0).to_csv(), "Affected rows\n0\n");
+        assert_eq!(MsqlResult::default().to_csv(), "");
+    }
+
+    #[test]
+    fn test_json() {
+        let result = MsqlResult::new(
+            vec![MsqlColumn::new("id", "int4"), MsqlColumn::new("price", "float8")],
+            vec![vec![json!(1), json!(null)]],
+        );
+        let result_json = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            result_json,
+            json!({
+                "columns": [{"name": "id", "type_name": "int4"}, {"name": "price", "type_name": "float8"}],
+                "rows": [[1, null]],
+                "affected_rows": null
+            })
+        );
+        assert_eq!(serde_json::from_value::<MsqlResult>(result_json).unwrap(), result);
+
+        let response = MsqlResponse::query_ok(MsqlResult::affected(2));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({"Query": {"Ok": {"columns": [], "rows": [], "affected_rows": 2}}})
+        );
+
+        let response = MsqlResponse::query_err(MsqlError::unavailable("Dbproxy servers are all offline"));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "Query": {
+                    "Err": {
+                        "kind": "unavailable",
+                        "message": "Dbproxy servers are all offline",
+                        "retryable": true,
+                        "sqlstate": null
+                    }
+                }
+            })
+        );
     }
 }
@@ -0,0 +1,657 @@
+//! Communication between client and scheduler in the PostgreSQL frontend/backend protocol
+//!
+//! Only the subset of protocol 3.0 needed by the scheduler is implemented:
+//! startup without authentication, simple query, extended query and transaction status.
+//! See <https://www.postgresql.org/docs/current/protocol-message-formats.html>
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Protocol version 3.0
+pub const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST_CODE: i32 = 80877103;
+const GSSENC_REQUEST_CODE: i32 = 80877104;
+const CANCEL_REQUEST_CODE: i32 = 80877102;
+
+/// Upper bound of the length of a single message
+const MAX_MESSAGE_LEN: usize = 1 << 30;
+
+/// Format code of a parameter or a column
+pub const TEXT_FORMAT: i16 = 0;
+pub const BINARY_FORMAT: i16 = 1;
+
+/// Target of a `Describe` or a `Close`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PgTarget {
+    Statement,
+    Portal,
+}
+
+/// Message sent from the client to the scheduler
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FrontendMessage {
+    /// Startup with the protocol version and the connection parameters, such as `user` and `database`
+    Startup {
+        version: i32,
+        params: Vec<(String, String)>,
+    },
+    /// Request for an encrypted connection
+    SslRequest,
+    /// Request for cancelling a running query, sent on a new connection
+    CancelRequest {
+        process_id: i32,
+        secret_key: i32,
+    },
+    /// Simple query, which may contain multiple statements
+    Query(String),
+    Parse {
+        name: String,
+        query: String,
+        param_types: Vec<u32>,
+    },
+    Bind {
+        portal: String,
+        statement: String,
+        param_formats: Vec<i16>,
+        params: Vec<Option<Bytes>>,
+        result_formats: Vec<i16>,
+    },
+    Describe {
+        target: PgTarget,
+        name: String,
+    },
+    Execute {
+        portal: String,
+        max_rows: i32,
+    },
+    Close {
+        target: PgTarget,
+        name: String,
+    },
+    Sync,
+    Flush,
+    Terminate,
+    /// Any other message identified by its type byte, which is not supported
+    Unsupported(u8),
+}
+
+/// Transaction status reported in `BackendMessage::ReadyForQuery`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransactionStatus {
+    Idle,
+    InTransaction,
+    /// In a failed transaction block, where queries are rejected until the end of the transaction
+    Failed,
+}
+
+/// Description of a column in `BackendMessage::RowDescription`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FieldDescription {
+    pub name: String,
+    pub type_oid: u32,
+    pub format: i16,
+}
+
+/// Message sent from the scheduler to the client
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BackendMessage {
+    /// Single byte reply to `FrontendMessage::SslRequest`, encryption is not supported
+    SslRefused,
+    AuthenticationOk,
+    ParameterStatus {
+        name: String,
+        value: String,
+    },
+    BackendKeyData {
+        process_id: i32,
+        secret_key: i32,
+    },
+    ReadyForQuery(TransactionStatus),
+    RowDescription(Vec<FieldDescription>),
+    /// A row of values, where `None` is Sql `NULL`
+    DataRow(Vec<Option<Bytes>>),
+    CommandComplete(String),
+    EmptyQueryResponse,
+    /// An error with a SQLSTATE code
+    ErrorResponse {
+        code: String,
+        message: String,
+    },
+    ParseComplete,
+    BindComplete,
+    CloseComplete,
+    NoData,
+    PortalSuspended,
+    ParameterDescription(Vec<u32>),
+}
+
+/// Codec for the scheduler side of a connection,
+/// which decodes `FrontendMessage` and encodes `BackendMessage`
+#[derive(Debug)]
+pub struct PgServerCodec {
+    is_startup: bool,
+}
+
+impl Default for PgServerCodec {
+    fn default() -> Self {
+        Self { is_startup: true }
+    }
+}
+
+impl PgServerCodec {
+    /// Decode a `FrontendMessage` from `src`, returns `None` if `src` does not contain a full message yet
+    pub fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<FrontendMessage>> {
+        // The startup phase does not have the type byte
+        let header_len = if self.is_startup { 0 } else { 1 };
+        if src.len() < header_len + 4 {
+            return Ok(None);
+        }
+
+        let mut len_bytes = [0; 4];
+        len_bytes.copy_from_slice(&src[header_len..header_len + 4]);
+        let len = i32::from_be_bytes(len_bytes);
+        if len < 4 || len as usize > MAX_MESSAGE_LEN {
+            return Err(invalid_data(format!("Invalid message length {}", len)));
+        }
+        let total_len = header_len + len as usize;
+        if src.len() < total_len {
+            src.reserve(total_len - src.len());
+            return Ok(None);
+        }
+
+        let mut msg = src.split_to(total_len).freeze();
+        let tag = if self.is_startup { None } else { Some(msg.get_u8()) };
+        msg.advance(4);
+
+        match tag {
+            None => self.decode_startup(msg).map(Some),
+            Some(tag) => decode_message(tag, msg).map(Some),
+        }
+    }
+
+    fn decode_startup(&mut self, mut msg: Bytes) -> io::Result<FrontendMessage> {
+        match get_i32(&mut msg)? {
+            SSL_REQUEST_CODE | GSSENC_REQUEST_CODE => Ok(FrontendMessage::SslRequest),
+            CANCEL_REQUEST_CODE => Ok(FrontendMessage::CancelRequest {
+                process_id: get_i32(&mut msg)?,
+                secret_key: get_i32(&mut msg)?,
+            }),
+            version => {
+                let mut params = Vec::new();
+                loop {
+                    let name = get_cstr(&mut msg)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    params.push((name, get_cstr(&mut msg)?));
+                }
+                self.is_startup = false;
+                Ok(FrontendMessage::Startup { version, params })
+            }
+        }
+    }
+}
+
+fn decode_message(tag: u8, mut msg: Bytes) -> io::Result<FrontendMessage> {
+    let message = match tag {
+        b'Q' => FrontendMessage::Query(get_cstr(&mut msg)?),
+        b'P' => {
+            let name = get_cstr(&mut msg)?;
+            let query = get_cstr(&mut msg)?;
+            let num_param_types = get_i16(&mut msg)?;
+            let param_types = (0..num_param_types)
+                .map(|_| get_i32(&mut msg).map(|oid| oid as u32))
+                .collect::<io::Result<_>>()?;
+            FrontendMessage::Parse {
+                name,
+                query,
+                param_types,
+            }
+        }
+        b'B' => {
+            let portal = get_cstr(&mut msg)?;
+            let statement = get_cstr(&mut msg)?;
+            let num_param_formats = get_i16(&mut msg)?;
+            let param_formats = (0..num_param_formats)
+                .map(|_| get_i16(&mut msg))
+                .collect::<io::Result<_>>()?;
+            let num_params = get_i16(&mut msg)?;
+            let params = (0..num_params)
+                .map(|_| match get_i32(&mut msg)? {
+                    -1 => Ok(None),
+                    len if len < 0 || len as usize > msg.remaining() => {
+                        Err(invalid_data(format!("Invalid parameter length {}", len)))
+                    }
+                    len => Ok(Some(msg.split_to(len as usize))),
+                })
+                .collect::<io::Result<_>>()?;
+            let num_result_formats = get_i16(&mut msg)?;
+            let result_formats = (0..num_result_formats)
+                .map(|_| get_i16(&mut msg))
+                .collect::<io::Result<_>>()?;
+            FrontendMessage::Bind {
+                portal,
+                statement,
+                param_formats,
+                params,
+                result_formats,
+            }
+        }
+        b'D' => FrontendMessage::Describe {
+            target: get_target(&mut msg)?,
+            name: get_cstr(&mut msg)?,
+        },
+        b'E' => FrontendMessage::Execute {
+            portal: get_cstr(&mut msg)?,
+            max_rows: get_i32(&mut msg)?,
+        },
+        b'C' => FrontendMessage::Close {
+            target: get_target(&mut msg)?,
+            name: get_cstr(&mut msg)?,
+        },
+        b'S' => FrontendMessage::Sync,
+        b'H' => FrontendMessage::Flush,
+        b'X' => FrontendMessage::Terminate,
+        other => FrontendMessage::Unsupported(other),
+    };
+
+    Ok(message)
+}
+
+impl PgServerCodec {
+    /// Encode a `BackendMessage` into `dst`
+    pub fn encode(&mut self, item: BackendMessage, dst: &mut BytesMut) -> io::Result<()> {
+        let tag = match &item {
+            BackendMessage::SslRefused => {
+                dst.put_u8(b'N');
+                return Ok(());
+            }
+            BackendMessage::AuthenticationOk => b'R',
+            BackendMessage::ParameterStatus { .. } => b'S',
+            BackendMessage::BackendKeyData { .. } => b'K',
+            BackendMessage::ReadyForQuery(_) => b'Z',
+            BackendMessage::RowDescription(_) => b'T',
+            BackendMessage::DataRow(_) => b'D',
+            BackendMessage::CommandComplete(_) => b'C',
+            BackendMessage::EmptyQueryResponse => b'I',
+            BackendMessage::ErrorResponse { .. } => b'E',
+            BackendMessage::ParseComplete => b'1',
+            BackendMessage::BindComplete => b'2',
+            BackendMessage::CloseComplete => b'3',
+            BackendMessage::NoData => b'n',
+            BackendMessage::PortalSuspended => b's',
+            BackendMessage::ParameterDescription(_) => b't',
+        };
+
+        dst.put_u8(tag);
+        // The length is filled in after the body is written
+        let len_pos = dst.len();
+        dst.put_i32(0);
+
+        match item {
+            BackendMessage::AuthenticationOk => dst.put_i32(0),
+            BackendMessage::ParameterStatus { name, value } => {
+                put_cstr(dst, &name);
+                put_cstr(dst, &value);
+            }
+            BackendMessage::BackendKeyData { process_id, secret_key } => {
+                dst.put_i32(process_id);
+                dst.put_i32(secret_key);
+            }
+            BackendMessage::ReadyForQuery(status) => dst.put_u8(match status {
+                TransactionStatus::Idle => b'I',
+                TransactionStatus::InTransaction => b'T',
+                TransactionStatus::Failed => b'E',
+            }),
+            BackendMessage::RowDescription(fields) => {
+                dst.put_i16(fields.len() as i16);
+                fields.into_iter().for_each(|field| {
+                    put_cstr(dst, &field.name);
+                    // Table oid and column attribute number are unknown
+                    dst.put_i32(0);
+                    dst.put_i16(0);
+                    dst.put_u32(field.type_oid);
+                    // Variable type size and no type modifier
+                    dst.put_i16(-1);
+                    dst.put_i32(-1);
+                    dst.put_i16(field.format);
+                });
+            }
+            BackendMessage::DataRow(values) => {
+                dst.put_i16(values.len() as i16);
+                values.into_iter().for_each(|value| match value {
+                    Some(value) => {
+                        dst.put_i32(value.len() as i32);
+                        dst.put_slice(&value);
+                    }
+                    None => dst.put_i32(-1),
+                });
+            }
+            BackendMessage::CommandComplete(tag) => put_cstr(dst, &tag),
+            BackendMessage::ErrorResponse { code, message } => {
+                dst.put_u8(b'S');
+                put_cstr(dst, "ERROR");
+                dst.put_u8(b'V');
+                put_cstr(dst, "ERROR");
+                dst.put_u8(b'C');
+                put_cstr(dst, &code);
+                dst.put_u8(b'M');
+                put_cstr(dst, &message);
+                dst.put_u8(0);
+            }
+            BackendMessage::ParameterDescription(type_oids) => {
+                dst.put_i16(type_oids.len() as i16);
+                type_oids.into_iter().for_each(|type_oid| dst.put_u32(type_oid));
+            }
+            _ => {}
+        }
+
+        let len = (dst.len() - len_pos) as i32;
+        dst[len_pos..len_pos + 4].copy_from_slice(&len.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// The scheduler side of a connection, which reads `FrontendMessage` and writes `BackendMessage`
+///
+/// Messages written are buffered until `PgServerStream::flush`.
+pub struct PgServerStream<S> {
+    stream: S,
+    codec: PgServerCodec,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+}
+
+impl<S> PgServerStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            codec: PgServerCodec::default(),
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+        }
+    }
+
+    /// Read the next message, returns `None` if the connection is closed
+    pub async fn read(&mut self) -> io::Result<Option<FrontendMessage>> {
+        let mut chunk = [0; 8192];
+        loop {
+            if let Some(message) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(Some(message));
+            }
+            let n = self.stream.read(&mut chunk).await?;
+            if n == 0 {
+                return if self.read_buf.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed within a message",
+                    ))
+                };
+            }
+            self.read_buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Buffer a message to be written
+    pub fn send(&mut self, message: BackendMessage) -> io::Result<()> {
+        self.codec.encode(message, &mut self.write_buf)
+    }
+
+    /// Write all buffered messages
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.stream.write_all(&self.write_buf).await?;
+        self.write_buf.clear();
+        self.stream.flush().await
+    }
+}
+
+fn invalid_data<S: Into<String>>(s: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, s.into())
+}
+
+fn get_i16(buf: &mut Bytes) -> io::Result<i16> {
+    if buf.remaining() < 2 {
+        return Err(invalid_data("Unexpected end of message"));
+    }
+    Ok(buf.get_i16())
+}
+
+fn get_i32(buf: &mut Bytes) -> io::Result<i32> {
+    if buf.remaining() < 4 {
+        return Err(invalid_data("Unexpected end of message"));
+    }
+    Ok(buf.get_i32())
+}
+
+fn get_cstr(buf: &mut Bytes) -> io::Result<String> {
+    let end = buf
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| invalid_data("Unterminated string in message"))?;
+    let s = buf.split_to(end);
+    buf.advance(1);
+    String::from_utf8(s.to_vec()).map_err(|_| invalid_data("Invalid UTF-8 string in message"))
+}
+
+fn get_target(buf: &mut Bytes) -> io::Result<PgTarget> {
+    if !buf.has_remaining() {
+        return Err(invalid_data("Unexpected end of message"));
+    }
+    match buf.get_u8() {
+        b'S' => Ok(PgTarget::Statement),
+        b'P' => Ok(PgTarget::Portal),
+        other => Err(invalid_data(format!("Invalid target {}", other as char))),
+    }
+}
+
+fn put_cstr(dst: &mut BytesMut, s: &str) {
+    dst.put_slice(s.as_bytes());
+    dst.put_u8(0);
+}
+
+/// Unit test for `PgServerCodec`
+#[cfg(test)]
+mod tests_pg_server_codec {
+    use super::*;
+
+    fn frame(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        buf.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn test_decode_startup() {
+        let mut codec = PgServerCodec::default();
+
+        let mut src = BytesMut::new();
+        src.put_i32(8);
+        src.put_i32(SSL_REQUEST_CODE);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(FrontendMessage::SslRequest));
+        assert!(codec.is_startup);
+
+        let mut body = BytesMut::new();
+        body.put_i32(PROTOCOL_VERSION);
+        put_cstr(&mut body, "user");
+        put_cstr(&mut body, "ray");
+        put_cstr(&mut body, "database");
+        put_cstr(&mut body, "tpcw");
+        body.put_u8(0);
+        src.put_i32(body.len() as i32 + 4);
+        // Partial message
+        src.put_slice(&body[..5]);
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        src.put_slice(&body[5..]);
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Startup {
+                version: PROTOCOL_VERSION,
+                params: vec![
+                    (String::from("user"), String::from("ray")),
+                    (String::from("database"), String::from("tpcw"))
+                ]
+            })
+        );
+        assert!(!codec.is_startup);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn test_decode_messages() {
+        let mut codec = PgServerCodec { is_startup: false };
+        let mut src = BytesMut::new();
+
+        src.put_slice(&frame(b'Q', b"select 1;\0"));
+
+        let mut body = BytesMut::new();
+        put_cstr(&mut body, "s0");
+        put_cstr(&mut body, "select * from t0 where id = $1");
+        body.put_i16(1);
+        body.put_i32(23);
+        src.put_slice(&frame(b'P', &body));
+
+        let mut body = BytesMut::new();
+        put_cstr(&mut body, "");
+        put_cstr(&mut body, "s0");
+        body.put_i16(1);
+        body.put_i16(TEXT_FORMAT);
+        body.put_i16(2);
+        body.put_i32(2);
+        body.put_slice(b"42");
+        body.put_i32(-1);
+        body.put_i16(0);
+        src.put_slice(&frame(b'B', &body));
+
+        src.put_slice(&frame(b'D', b"P\0"));
+        src.put_slice(&frame(b'E', b"\0\0\0\0\0"));
+        src.put_slice(&frame(b'S', b""));
+        src.put_slice(&frame(b'd', b"copy"));
+
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Query(String::from("select 1;")))
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Parse {
+                name: String::from("s0"),
+                query: String::from("select * from t0 where id = $1"),
+                param_types: vec![23]
+            })
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Bind {
+                portal: String::new(),
+                statement: String::from("s0"),
+                param_formats: vec![TEXT_FORMAT],
+                params: vec![Some(Bytes::from_static(b"42")), None],
+                result_formats: vec![]
+            })
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Describe {
+                target: PgTarget::Portal,
+                name: String::new()
+            })
+        );
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Execute {
+                portal: String::new(),
+                max_rows: 0
+            })
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(FrontendMessage::Sync));
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(FrontendMessage::Unsupported(b'd'))
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut codec = PgServerCodec { is_startup: false };
+        let mut src = BytesMut::from(&frame(b'P', b"s0\0select 1")[..]);
+        assert!(codec.decode(&mut src).is_err());
+
+        let mut src = BytesMut::from(&b"Q\x00\x00\x00\x02"[..]);
+        assert!(codec.decode(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut codec = PgServerCodec::default();
+        let mut dst = BytesMut::new();
+
+        codec.encode(BackendMessage::SslRefused, &mut dst).unwrap();
+        assert_eq!(&dst[..], b"N");
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                BackendMessage::ReadyForQuery(TransactionStatus::InTransaction),
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(&dst[..], &frame(b'Z', b"T")[..]);
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(BackendMessage::CommandComplete(String::from("SELECT 2")), &mut dst)
+            .unwrap();
+        assert_eq!(&dst[..], &frame(b'C', b"SELECT 2\0")[..]);
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                BackendMessage::DataRow(vec![Some(Bytes::from_static(b"ray")), None]),
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(
+            &dst[..],
+            &frame(b'D', b"\x00\x02\x00\x00\x00\x03ray\xff\xff\xff\xff")[..]
+        );
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                BackendMessage::RowDescription(vec![FieldDescription {
+                    name: String::from("id"),
+                    type_oid: 23,
+                    format: TEXT_FORMAT,
+                }]),
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(
+            &dst[..],
+            &frame(
+                b'T',
+                b"\x00\x01id\0\x00\x00\x00\x00\x00\x00\x00\x00\x00\x17\xff\xff\xff\xff\xff\xff\x00\x00"
+            )[..]
+        );
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                BackendMessage::ErrorResponse {
+                    code: String::from("42601"),
+                    message: String::from("bad"),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(&dst[..], &frame(b'E', b"SERROR\0VERROR\0C42601\0Mbad\0\0")[..]);
+    }
+}
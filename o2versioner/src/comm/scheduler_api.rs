@@ -56,11 +56,49 @@ use serde::{Deserialize, Serialize};
 ///     rmt_end_tx,
 ///     Message::RequestMsqlText(MsqlText::EndTx {
 ///         tx: Some(String::from("tx0")),
-///         mode: MsqlEndTxMode::Commit
+///         mode: MsqlEndTxMode::Commit,
+///         ack: None
 ///     })
 /// );
 /// ```
 ///
+/// To do a `Message::RequestWithTimeout {..}`, which is cancelled if not done within 500 ms:
+/// ```
+/// use o2versioner::comm::scheduler_api::Message;
+/// use o2versioner::core::MsqlText;
+///
+/// let rwt_query_str = r#"
+/// {
+///     "request_with_timeout":{
+///         "timeout":500,
+///         "request":{
+///             "request_msql_text":{
+///                 "op":"query",
+///                 "query":"select * from t;",
+///                 "tableops":"read t"
+///             }
+///         }
+///     }
+/// }"#;
+/// let rwt_query: Message = serde_json::from_str(rwt_query_str).unwrap();
+/// assert_eq!(
+///     rwt_query,
+///     Message::request_with_timeout(
+///         Message::RequestMsqlText(MsqlText::query("select * from t;", "read t", Option::<String>::None)),
+///         500
+///     )
+/// );
+/// ```
+///
+/// To do a `Message::RequestCancel`:
+/// ```
+/// use o2versioner::comm::scheduler_api::Message;
+///
+/// let cancel_str = r#""request_cancel""#;
+/// let cancel: Message = serde_json::from_str(cancel_str).unwrap();
+/// assert_eq!(cancel, Message::RequestCancel);
+/// ```
+///
 /// To do a `Message::RequestCrash(..)`:
 /// ```
 /// use o2versioner::comm::scheduler_api::Message;
@@ -83,6 +121,12 @@ pub enum Message {
     RequestMsql(Msql),
     /// Request in `MsqlText` format
     RequestMsqlText(MsqlText),
+    /// `RequestMsql` or `RequestMsqlText` that is cancelled if it is not done within `timeout`, in units of ms
+    RequestWithTimeout { timeout: u64, request: Box<Message> },
+    /// Cancel the ongoing request of the connection, which is then replied with a `MsqlErrorKind::Cancelled` error.
+    /// Only queries can be cancelled, and they are still replied as usual if already done.
+    /// There is no reply to it, and it is ignored if there is no ongoing request
+    RequestCancel,
     /// Request the Scheduler to crash with session info dumped out
     RequestCrash(String),
     /// Unimplemented yet, reserved for testing
@@ -91,7 +135,7 @@ pub enum Message {
     InvalidRequest,
     /// Invalid `MsqlText`, happens when `MsqlText` in the `RequestMsqlText` request cannot be converted into `Msql`
     InvalidMsqlText(String),
-    /// Response to the incoming `RequestMsql` or `RequestMsqlText`
+    /// Response to the incoming `RequestMsql`, `RequestMsqlText` or `RequestWithTimeout`
     Reply(MsqlResponse),
 }
 
@@ -103,6 +147,14 @@ impl Message {
     pub fn request_crash<S: Into<String>>(s: S) -> Self {
         Message::RequestCrash(s.into())
     }
+
+    /// `timeout` is in units of ms
+    pub fn request_with_timeout(request: Message, timeout: u64) -> Self {
+        Message::RequestWithTimeout {
+            timeout,
+            request: Box::new(request),
+        }
+    }
 }
 
 /// Unit test for `Message`
@@ -124,6 +176,7 @@ mod tests_message {
         let a = Message::RequestMsqlText(MsqlText::EndTx {
             tx: Some(String::from("tx0")),
             mode: MsqlEndTxMode::Commit,
+            ack: None,
         });
         println!("{}", serde_json::to_string(&a).unwrap());
 
@@ -162,6 +215,26 @@ mod tests_message {
         println!("{:?}", b);
     }
 
+    #[test]
+    fn test_request_with_timeout_json() {
+        let msg = Message::request_with_timeout(
+            Message::RequestMsqlText(MsqlText::auto_query("select * from t;", Option::<String>::None)),
+            500,
+        );
+        let msg_json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(msg_json["request_with_timeout"]["timeout"], 500);
+        assert_eq!(
+            msg_json["request_with_timeout"]["request"]["request_msql_text"]["query"],
+            "select * from t;"
+        );
+        assert_eq!(serde_json::from_value::<Message>(msg_json).unwrap(), msg);
+
+        assert_eq!(
+            serde_json::to_string(&Message::RequestCancel).unwrap(),
+            r#""request_cancel""#
+        );
+    }
+
     #[test]
     fn test_request_crash() {
         let crash_req_msg = Message::RequestCrash(String::from("just for fun"));
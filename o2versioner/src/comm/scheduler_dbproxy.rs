@@ -1,8 +1,9 @@
 //! Communication between scheduler and dbproxy
 
-use super::msql_response::MsqlResponse;
+use super::msql_response::{MsqlColumn, MsqlResponse, MsqlResult};
 use crate::core::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Expecting every request will have a response replied back via the same tcp stream
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,15 +12,60 @@ pub enum Message {
     MsqlRequest(RequestMeta, Msql, Option<TxVN>),
     /// The repsone to the `MsqlRequest`
     MsqlResponse(RequestMeta, MsqlResponse),
+    /// Cancel the `MsqlRequest` if it is still waiting for its versions, or interrupt it if it is being executed.
+    /// There is no response to it, the `MsqlRequest` is replied with an error once cancelled, or as usual otherwise
+    Cancel(RequestMeta),
+    /// Set the versions of all tables of a newly added dbproxy, sent before any `MsqlRequest`.
+    /// There is no response to it
+    InitVersion(HashMap<String, VN>),
+    /// Sent periodically by the scheduler to check whether the dbproxy is alive
+    Heartbeat,
+    /// The response to the `Heartbeat`, with the `MsqlRequest`s still waiting for their versions.
+    /// They are not timed out by the scheduler, as they are held by other transactions instead of the dbproxy
+    HeartbeatAck(Vec<RequestMeta>),
+    /// Take a `DbSnapshot` of the tables, sent to a donor dbproxy while quiesced
+    SnapshotRequest(RequestMeta, Vec<String>),
+    /// The response to the `SnapshotRequest`
+    Snapshot(RequestMeta, Result<DbSnapshot, MsqlError>),
+    /// Describe the columns of the Sql query by preparing it on the database, without executing it
+    DescribeRequest(RequestMeta, String),
+    /// The response to the `DescribeRequest`, a query returning no rows has no columns
+    Description(RequestMeta, Result<Vec<MsqlColumn>, MsqlError>),
+    /// Replace the data of a new or recovered dbproxy with the `DbSnapshot`, and then set the versions of all tables.
+    /// All requests received after it are held until restored
+    Restore(RequestMeta, HashMap<String, VN>, DbSnapshot),
+    /// The response to the `Restore`, sent once restored
+    Restored(RequestMeta, Result<(), MsqlError>),
+    /// Sent first after the scheduler reconnects, with all requests not replied yet, in the order they were sent
+    Resync(Vec<RequestMeta>),
+    /// The response to the `Resync`, with the requests never received, and the versions of all tables.
+    /// The requests already executed are replied again before it
+    Resynced(Vec<RequestMeta>, HashMap<String, VN>),
     /// Response to an invalid request, for exmample, sending `MsqlResponse(MsqlResponse)` to the dbproxy
     Invalid,
 }
 
+/// The data of the tables of a dbproxy, restored by another dbproxy to catch up
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DbSnapshot {
+    /// All rows of each table, `Table` -> `MsqlResult`
+    pub tables: HashMap<String, MsqlResult>,
+    /// The last value of each sequence owned by a column of the tables, such as a `serial` column.
+    /// `None` if the sequence has not been used yet
+    pub sequences: HashMap<String, Option<i64>>,
+}
+
 impl Message {
     pub fn try_get_request_meta(&self) -> Result<&RequestMeta, ()> {
         match self {
             Self::MsqlRequest(meta, _, _) => Ok(meta),
             Self::MsqlResponse(meta, _) => Ok(meta),
+            Self::SnapshotRequest(meta, _) => Ok(meta),
+            Self::Snapshot(meta, _) => Ok(meta),
+            Self::DescribeRequest(meta, _) => Ok(meta),
+            Self::Description(meta, _) => Ok(meta),
+            Self::Restore(meta, _, _) => Ok(meta),
+            Self::Restored(meta, _) => Ok(meta),
             _ => Err(()),
         }
     }
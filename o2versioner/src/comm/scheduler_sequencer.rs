@@ -1,16 +1,44 @@
-//! Communication between scheduler and sequencer
+//! Communication between scheduler and sequencer,
+//! and between the primary sequencer and its backup
 
-use crate::core::{ClientMeta, MsqlBeginTx, TxVN};
+use crate::core::{ClientMeta, MsqlBeginTx, TxVN, VN};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Debug, strum::AsRefStr, Serialize, Deserialize)]
+/// Version numbers to assign next for a single table
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TableVNRecord {
+    pub next_for_read: VN,
+    pub next_for_write: VN,
+}
+
+/// Version number records of tables, keyed by the table name
+pub type VNRecords = HashMap<String, TableVNRecord>;
+
+#[derive(Debug, Clone, strum::AsRefStr, Serialize, Deserialize)]
 pub enum Message {
     Invalid,
     RequestTxVN(ClientMeta, MsqlBeginTx),
     ReplyTxVN(Option<TxVN>),
+    /// The Sequencer is not the primary one, the request should be sent to the other Sequencer
+    ReplyNotPrimary,
     RequestBlock,
     RequestUnblock,
     ReplyBlockUnblock(String),
     RequestStop,
     ReplyStop,
+    /// Version number records of all tables, which are no longer changed once blocked
+    RequestVNRecords,
+    ReplyVNRecords(VNRecords),
+    /// Primary -> backup, exchange all version number records and recently assigned `TxVN`s
+    RequestSync(VNRecords, Vec<(ClientMeta, TxVN)>),
+    /// `None` if the backup has already taken over from the primary
+    ReplySync(Option<(VNRecords, Vec<(ClientMeta, TxVN)>)>),
+    /// Primary -> backup, a single assigned `TxVN` together with the new records of its tables
+    RequestReplicate(ClientMeta, TxVN, VNRecords),
+    /// `false` if the backup has already taken over from the primary
+    ReplyReplicate(bool),
+    /// A request or its reply with the request id, so that requests can be pipelined over a single connection
+    /// and their replies can be matched
+    Tagged(u64, Box<Message>),
 }
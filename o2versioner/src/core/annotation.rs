@@ -0,0 +1,743 @@
+use super::operation::*;
+use std::collections::HashSet;
+use std::iter::FromIterator;
+use std::ops::Range;
+
+/// Automatically annotate a Sql query by analyzing its text,
+/// returning the `TableOps` of all tables accessed by the query
+///
+/// # Examples
+/// ```
+/// use o2versioner::core::{annotate_query, TableOps};
+///
+/// assert_eq!(
+///     annotate_query("INSERT INTO t0 SELECT * FROM t1 JOIN t2 ON t1.id = t2.id;"),
+///     Ok(TableOps::from("write t0 read t1 t2"))
+/// );
+/// ```
+///
+/// # Notes
+/// 1. Supports `SELECT`, `INSERT`, `UPDATE` and `DELETE`, including joins, subqueries and common table expressions
+/// 2. Tables in `FROM`, `JOIN` and `USING` clauses are read, while targets of `INSERT`, `UPDATE` and `DELETE` are written
+/// 3. Unquoted identifiers are folded to lower case, quoted identifiers are kept as is
+/// 4. Names of common table expressions are not tables, and are not included
+/// 5. Multiple statements separated by `;` are annotated together
+pub fn annotate_query(query: &str) -> Result<TableOps, &'static str> {
+    let tokens = tokenize(query)?;
+    let statements: Vec<_> = tokens
+        .split(|token| *token == Token::Semicolon)
+        .filter(|statement| !statement.is_empty())
+        .collect();
+
+    if statements.is_empty() {
+        return Err("Cannot auto annotate an empty query");
+    }
+
+    let mut tableops = Vec::new();
+    for statement in statements {
+        tableops.extend(StatementAnalyzer::new(statement).analyze()?);
+    }
+    Ok(TableOps::from_iter(tableops))
+}
+
+/// Check whether the Sql `query` has several statements separated by `;`
+///
+/// Separators within comments and literals are skipped,
+/// and a `query` that cannot be tokenized is taken as a single statement.
+///
+/// # Examples
+/// ```
+/// use o2versioner::core::is_multi_statement;
+///
+/// assert!(is_multi_statement("UPDATE t0 SET a = 1; DELETE FROM t1;"));
+/// assert!(!is_multi_statement("SELECT ';' FROM t0;"));
+/// ```
+pub fn is_multi_statement(query: &str) -> bool {
+    tokenize(query)
+        .map(|tokens| {
+            tokens
+                .split(|token| *token == Token::Semicolon)
+                .filter(|statement| !statement.is_empty())
+                .count()
+                > 1
+        })
+        .unwrap_or(false)
+}
+
+/// Start of the Sql comment carrying the annotation
+const ANNOTATION_START: &str = "/*o2v";
+
+/// Extract the annotation embedded as a Sql comment in the `query`, such as
+/// `/*o2v read t0 write t1 release t1*/ UPDATE t1 ...`
+///
+/// Returns the `query` with the annotation stripped, and the `TableOps` and `EarlyReleaseTables`
+/// annotated if there is such a comment.
+///
+/// # Examples
+/// ```
+/// use o2versioner::core::{extract_annotation, EarlyReleaseTables, TableOps};
+///
+/// assert_eq!(
+///     extract_annotation("/*o2v read t0 write t1 release t1*/ UPDATE t1 SET a = (SELECT b FROM t0);"),
+///     (
+///         String::from("UPDATE t1 SET a = (SELECT b FROM t0);"),
+///         Some((TableOps::from("read t0 write t1"), EarlyReleaseTables::from("t1")))
+///     )
+/// );
+/// ```
+///
+/// # Notes
+/// 1. Words before the keyword `release` are parsed as `TableOps`, and words after it as `EarlyReleaseTables`
+/// 2. Keywords are case insensitive
+/// 3. Only the first annotation is extracted, the `query` is returned as is if there is none
+/// 4. `/*o2v` inside a literal, a quoted identifier or another comment does not start an annotation
+pub fn extract_annotation(query: &str) -> (String, Option<(TableOps, EarlyReleaseTables)>) {
+    let comment = scan(query).ok().and_then(|(_, comments)| {
+        comments
+            .into_iter()
+            .find(|comment| query[comment.clone()].starts_with(ANNOTATION_START))
+    });
+    let (start, end) = match comment {
+        Some(comment) => (comment.start, comment.end - "*/".len()),
+        None => return (query.to_owned(), None),
+    };
+
+    let tokens: Vec<_> = query[start + ANNOTATION_START.len()..end].split_whitespace().collect();
+    let (ops, ertables) = match tokens.iter().position(|token| token.eq_ignore_ascii_case("release")) {
+        Some(idx) => (&tokens[..idx], &tokens[idx + 1..]),
+        None => (&tokens[..], &tokens[tokens.len()..]),
+    };
+
+    (
+        format!("{} {}", &query[..start], &query[end + 2..]).trim().to_owned(),
+        Some((
+            TableOps::from(ops.join(" ")),
+            EarlyReleaseTables::from_iter(ertables.iter().copied()),
+        )),
+    )
+}
+
+/// Keywords that can never be a table name or a table alias
+const RESERVED: &[&str] = &[
+    "all",
+    "and",
+    "as",
+    "conflict",
+    "cross",
+    "default",
+    "do",
+    "else",
+    "end",
+    "except",
+    "fetch",
+    "for",
+    "from",
+    "full",
+    "group",
+    "having",
+    "inner",
+    "intersect",
+    "into",
+    "join",
+    "lateral",
+    "left",
+    "limit",
+    "natural",
+    "not",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "outer",
+    "returning",
+    "right",
+    "select",
+    "set",
+    "tablesample",
+    "then",
+    "union",
+    "using",
+    "values",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+/// Functions whose arguments use `FROM` as a separator instead of a clause
+const FROM_AS_SEPARATOR: &[&str] = &["extract", "overlay", "position", "substring", "trim"];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    /// Unquoted identifier or keyword, folded to lower case
+    Word(String),
+    /// Double-quoted identifier
+    QuotedWord(String),
+    LParen,
+    RParen,
+    Comma,
+    Period,
+    Semicolon,
+    /// Literals, operators and parameters
+    Other,
+}
+
+/// Split the Sql text into `Token`s, skipping all comments and literals
+fn tokenize(sql: &str) -> Result<Vec<Token>, &'static str> {
+    scan(sql).map(|(tokens, _)| tokens)
+}
+
+/// Split the Sql text into `Token`s like `tokenize`, and also return the byte ranges of
+/// its outermost block comments, including their `/*` and `*/`
+fn scan(sql: &str) -> Result<(Vec<Token>, Vec<Range<usize>>), &'static str> {
+    let chars: Vec<char> = sql.chars().collect();
+    let offsets: Vec<usize> = sql
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(sql.len()))
+        .collect();
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '-' if next == Some('-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if next == Some('*') => {
+                // Comments can be nested
+                let start = i;
+                let mut depth = 0;
+                loop {
+                    if i + 1 >= chars.len() {
+                        return Err("Unterminated comment in query");
+                    } else if chars[i] == '/' && chars[i + 1] == '*' {
+                        depth += 1;
+                        i += 2;
+                    } else if chars[i] == '*' && chars[i + 1] == '/' {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                comments.push(offsets[start]..offsets[i]);
+            }
+            '\'' => {
+                i = skip_quoted(&chars, i, '\'', false).ok_or("Unterminated string literal in query")?;
+                tokens.push(Token::Other);
+            }
+            '"' => {
+                let end = skip_quoted(&chars, i, '"', false).ok_or("Unterminated quoted identifier in query")?;
+                let ident: String = chars[i + 1..end - 1].iter().collect();
+                tokens.push(Token::QuotedWord(ident.replace("\"\"", "\"")));
+                i = end;
+            }
+            '$' if matches!(next, Some(n) if n.is_ascii_digit()) => {
+                // Positional parameter
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            }
+            '$' => {
+                // Dollar-quoted string, $tag$...$tag$
+                let tag_end = (i + 1..chars.len())
+                    .find(|j| !(chars[*j].is_alphanumeric() || chars[*j] == '_'))
+                    .filter(|j| chars[*j] == '$')
+                    .ok_or("Malformed dollar-quoted string in query")?;
+                let tag = &chars[i..=tag_end];
+                i = (tag_end + 1..chars.len())
+                    .find(|j| chars[*j..].starts_with(tag))
+                    .map(|j| j + tag.len())
+                    .ok_or("Unterminated dollar-quoted string in query")?;
+                tokens.push(Token::Other);
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+                if word == "e" && chars.get(i) == Some(&'\'') {
+                    // String literal with C-style escapes
+                    i = skip_quoted(&chars, i, '\'', true).ok_or("Unterminated string literal in query")?;
+                    tokens.push(Token::Other);
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+            _ if c.is_ascii_digit() => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '.' || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            }
+            '(' | ')' | ',' | '.' | ';' => {
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    '.' => Token::Period,
+                    _ => Token::Semicolon,
+                });
+                i += 1;
+            }
+            _ => {
+                tokens.push(Token::Other);
+                i += 1;
+            }
+        }
+    }
+
+    Ok((tokens, comments))
+}
+
+/// Returns the index right after the closing `quote` of the quoted section starting at `start`
+fn skip_quoted(chars: &[char], start: usize, quote: char, backslash_escape: bool) -> Option<usize> {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if backslash_escape && chars[i] == '\\' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                // Escaped by doubling the quote
+                i += 2;
+            } else {
+                return Some(i + 1);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Collects the `TableOp`s from the `Token`s of a single Sql statement
+struct StatementAnalyzer<'a> {
+    tokens: &'a [Token],
+    ctes: HashSet<String>,
+    tableops: Vec<TableOp>,
+}
+
+impl<'a> StatementAnalyzer<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            tokens,
+            ctes: HashSet::new(),
+            tableops: Vec::new(),
+        }
+    }
+
+    fn analyze(mut self) -> Result<Vec<TableOp>, &'static str> {
+        match self.tokens.first() {
+            Some(Token::LParen) => (),
+            Some(Token::Word(w)) if ["select", "insert", "update", "delete", "with", "values"].contains(&&w[..]) => (),
+            _ => return Err("Auto annotation only supports SELECT, INSERT, UPDATE and DELETE statements"),
+        }
+
+        self.collect_ctes();
+
+        // Function names (if any) for each currently opened parenthesis
+        let mut paren_stack: Vec<Option<&str>> = Vec::new();
+        for i in 0..self.tokens.len() {
+            match &self.tokens[i] {
+                Token::LParen => paren_stack.push(self.keyword(i.wrapping_sub(1))),
+                Token::RParen => {
+                    paren_stack.pop();
+                }
+                Token::Word(w) => match &w[..] {
+                    "from" => {
+                        let in_separator_func =
+                            matches!(paren_stack.last(), Some(Some(func)) if FROM_AS_SEPARATOR.contains(func));
+                        let prev = self.keyword(i.wrapping_sub(1));
+                        if in_separator_func || prev == Some("distinct") {
+                            // IS DISTINCT FROM, EXTRACT(.. FROM ..), etc.
+                        } else if prev == Some("delete") {
+                            self.table_ref(i + 1, RWOperation::W, true);
+                        } else {
+                            self.table_list(i + 1, RWOperation::R);
+                        }
+                    }
+                    "join" => {
+                        self.table_ref(i + 1, RWOperation::R, true);
+                    }
+                    "using" if self.tokens.get(i + 1) != Some(&Token::LParen) => {
+                        // DELETE .. USING, but not JOIN .. USING (columns)
+                        self.table_list(i + 1, RWOperation::R);
+                    }
+                    "update" => match self.keyword(i.wrapping_sub(1)) {
+                        // Row locking clauses, or INSERT .. ON CONFLICT DO UPDATE
+                        Some("for") | Some("key") | Some("do") => (),
+                        _ => {
+                            self.table_ref(i + 1, RWOperation::W, true);
+                        }
+                    },
+                    "into" => {
+                        if self.keyword(i.wrapping_sub(1)) == Some("insert") {
+                            self.table_ref(i + 1, RWOperation::W, false);
+                        } else {
+                            return Err("Auto annotation does not support SELECT INTO");
+                        }
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        Ok(self.tableops)
+    }
+
+    /// Collect the names of all common table expressions, `name [(columns)] AS [[NOT] MATERIALIZED] (`
+    fn collect_ctes(&mut self) {
+        for i in 0..self.tokens.len() {
+            let is_cte_start = match self.tokens.get(i.wrapping_sub(1)) {
+                Some(Token::Comma) => true,
+                Some(Token::Word(w)) => w == "with" || w == "recursive",
+                _ => false,
+            };
+            if !is_cte_start {
+                continue;
+            }
+            if let Some(name) = self.identifier(i) {
+                let mut pos = self.skip_parens(i + 1);
+                if self.keyword(pos) != Some("as") {
+                    continue;
+                }
+                pos += 1;
+                while matches!(self.keyword(pos), Some("not") | Some("materialized")) {
+                    pos += 1;
+                }
+                if self.tokens.get(pos) == Some(&Token::LParen) {
+                    self.ctes.insert(name);
+                }
+            }
+        }
+    }
+
+    /// Parse a comma-separated list of table references starting at `pos`
+    fn table_list(&mut self, mut pos: usize, op: RWOperation) {
+        loop {
+            pos = self.table_ref(pos, op, true);
+            if self.tokens.get(pos) == Some(&Token::Comma) {
+                pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Parse a single table reference starting at `pos`, returns the position right after it.
+    ///
+    /// Subqueries are skipped over, since the tables within them are visited separately.
+    fn table_ref(&mut self, mut pos: usize, op: RWOperation, allow_alias: bool) -> usize {
+        while matches!(self.keyword(pos), Some("only") | Some("lateral")) {
+            pos += 1;
+        }
+
+        if self.tokens.get(pos) == Some(&Token::LParen) {
+            pos = self.skip_parens(pos);
+        } else if let Some(mut name) = self.identifier(pos) {
+            pos += 1;
+            while self.tokens.get(pos) == Some(&Token::Period) {
+                match self.identifier(pos + 1) {
+                    Some(part) => {
+                        name.push('.');
+                        name.push_str(&part);
+                        pos += 2;
+                    }
+                    None => break,
+                }
+            }
+
+            if !allow_alias {
+                // INSERT INTO table (columns)
+                self.add_table(name, op);
+                return pos;
+            } else if self.tokens.get(pos) == Some(&Token::LParen) {
+                // Set-returning function, not a table
+                pos = self.skip_parens(pos);
+            } else {
+                self.add_table(name, op);
+            }
+        } else {
+            return pos;
+        }
+
+        // Optional alias with optional column aliases
+        if self.keyword(pos) == Some("as") {
+            pos += 1;
+        }
+        if self.identifier(pos).is_some() {
+            pos = self.skip_parens(pos + 1);
+        }
+        pos
+    }
+
+    fn add_table(&mut self, name: String, op: RWOperation) {
+        if !self.ctes.contains(&name) {
+            self.tableops.push(TableOp::new(name, op));
+        }
+    }
+
+    /// If there is a parenthesized group at `pos`, returns the position right after it;
+    /// else returns `pos`
+    fn skip_parens(&self, pos: usize) -> usize {
+        if self.tokens.get(pos) != Some(&Token::LParen) {
+            return pos;
+        }
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(pos) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.tokens.len()
+    }
+
+    /// The unquoted word at `pos`
+    fn keyword(&self, pos: usize) -> Option<&'a str> {
+        match self.tokens.get(pos) {
+            Some(Token::Word(w)) => Some(&w[..]),
+            _ => None,
+        }
+    }
+
+    /// The identifier at `pos`, which must be either quoted or not reserved
+    fn identifier(&self, pos: usize) -> Option<String> {
+        match self.tokens.get(pos) {
+            Some(Token::Word(w)) if !RESERVED.contains(&&w[..]) => Some(w.clone()),
+            Some(Token::QuotedWord(w)) => Some(w.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Unit test for `annotate_query`
+#[cfg(test)]
+mod tests_annotate_query {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        assert_eq!(annotate_query("select * from t0;"), Ok(TableOps::from("read t0")));
+        assert_eq!(
+            annotate_query("SELECT a.x, b.y FROM T0 a, t1 AS b WHERE a.id = b.id"),
+            Ok(TableOps::from("read t0 t1"))
+        );
+        assert_eq!(
+            annotate_query("select * from t0 inner join t1 on t0.id = t1.id left outer join t2 using (id)"),
+            Ok(TableOps::from("read t0 t1 t2"))
+        );
+        assert_eq!(
+            annotate_query("select * from t0 x(a, b) natural join \"T1\" for update"),
+            Ok(TableOps::from("read t0 T1"))
+        );
+        assert_eq!(annotate_query("select 1;"), Ok(TableOps::default()));
+        assert_eq!(
+            annotate_query("select * from generate_series(1, 10) g, t0"),
+            Ok(TableOps::from("read t0"))
+        );
+    }
+
+    #[test]
+    fn test_subquery() {
+        assert_eq!(
+            annotate_query(
+                "select * from (select id from t0 where x in (select x from t1)) s, t2 \
+                 where exists (select 1 from t3 where t3.id = s.id)"
+            ),
+            Ok(TableOps::from("read t0 t1 t2 t3"))
+        );
+        assert_eq!(
+            annotate_query("select * from t0 cross join lateral (select * from t1 where t1.id = t0.id) s"),
+            Ok(TableOps::from("read t0 t1"))
+        );
+        assert_eq!(
+            annotate_query("select * from t0 union all select * from t1 except (select * from t2)"),
+            Ok(TableOps::from("read t0 t1 t2"))
+        );
+    }
+
+    #[test]
+    fn test_cte() {
+        assert_eq!(
+            annotate_query("with s as (select * from t0), r (x) as (select x from t1) select * from s join r on true"),
+            Ok(TableOps::from("read t0 t1"))
+        );
+        assert_eq!(
+            annotate_query("WITH d AS (DELETE FROM t0 RETURNING *) INSERT INTO t1 SELECT * FROM d"),
+            Ok(TableOps::from("write t0 t1"))
+        );
+    }
+
+    #[test]
+    fn test_write() {
+        assert_eq!(
+            annotate_query("insert into t0 (a, b) values (1, 'from t1');"),
+            Ok(TableOps::from("write t0"))
+        );
+        assert_eq!(
+            annotate_query("update only t0 x set a = 1 where x.id = 2"),
+            Ok(TableOps::from("write t0"))
+        );
+        assert_eq!(
+            annotate_query("delete from t0 where id = $1"),
+            Ok(TableOps::from("write t0"))
+        );
+        assert_eq!(
+            annotate_query("insert into t0 values (1) on conflict (id) do update set a = excluded.a"),
+            Ok(TableOps::from("write t0"))
+        );
+        assert_eq!(
+            annotate_query("update t0 set a = 1; delete from t1;"),
+            Ok(TableOps::from("write t0 t1"))
+        );
+    }
+
+    #[test]
+    fn test_mixed() {
+        let tableops = annotate_query("INSERT INTO t0 SELECT * FROM t1").unwrap();
+        assert_eq!(tableops, TableOps::from("write t0 read t1"));
+        assert!(tableops.access_pattern().is_mixed());
+
+        assert_eq!(
+            annotate_query("update t0 set a = t1.a from t1 where t0.id in (select id from t2)"),
+            Ok(TableOps::from("write t0 read t1 t2"))
+        );
+        assert_eq!(
+            annotate_query("delete from t0 using t1, t2 where t0.id = t1.id"),
+            Ok(TableOps::from("write t0 read t1 t2"))
+        );
+        assert_eq!(
+            annotate_query("update t0 set a = (select max(a) from t0)"),
+            Ok(TableOps::from("write t0"))
+        );
+    }
+
+    #[test]
+    fn test_not_tables() {
+        assert_eq!(
+            annotate_query(
+                "select extract(year from d), substring(s from 2), trim(both from s) \
+                 from t0 where a is distinct from b"
+            ),
+            Ok(TableOps::from("read t0"))
+        );
+        assert_eq!(
+            annotate_query(
+                "select 'select * from t1', E'it\\'s from t2', $$from t3$$, $x$from t4$x$ \
+                 from t0 -- from t5\n /* from /* t6 */ */"
+            ),
+            Ok(TableOps::from("read t0"))
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        assert!(annotate_query("").is_err());
+        assert!(annotate_query(" ; ").is_err());
+        assert!(annotate_query("create table t0 (id int)").is_err());
+        assert!(annotate_query("select * from t0; drop table t0").is_err());
+        assert!(annotate_query("select * into t1 from t0").is_err());
+        assert!(annotate_query("select 'abc from t0").is_err());
+        assert!(annotate_query("select * from t0 /* abc").is_err());
+    }
+
+    #[test]
+    fn test_multi_statement() {
+        assert!(is_multi_statement("update t0 set a = 1; delete from t1;"));
+        assert!(is_multi_statement("select 1;select 2"));
+        assert!(!is_multi_statement("select * from t0;"));
+        assert!(!is_multi_statement(";; select * from t0 ;"));
+        assert!(!is_multi_statement("select ';', $$;$$ from t0 -- ; select 1\n"));
+        assert!(!is_multi_statement("select 'abc; select 1"));
+    }
+}
+
+/// Unit test for `extract_annotation`
+#[cfg(test)]
+mod tests_extract_annotation {
+    use super::*;
+
+    #[test]
+    fn test_no_annotation() {
+        assert_eq!(
+            extract_annotation("select * from t0 /* comment */;"),
+            (String::from("select * from t0 /* comment */;"), None)
+        );
+        assert_eq!(
+            extract_annotation("select * from t0 /*o2v read t0"),
+            (String::from("select * from t0 /*o2v read t0"), None)
+        );
+        assert_eq!(
+            extract_annotation("INSERT INTO t VALUES ('/*o2v read x*/')"),
+            (String::from("INSERT INTO t VALUES ('/*o2v read x*/')"), None)
+        );
+        assert_eq!(
+            extract_annotation("select \"/*o2v read x*/\" from t0 /* /*o2v read y*/ */;"),
+            (
+                String::from("select \"/*o2v read x*/\" from t0 /* /*o2v read y*/ */;"),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_annotation() {
+        assert_eq!(
+            extract_annotation("BEGIN /*o2v read t0 WRITE t1*/"),
+            (
+                String::from("BEGIN"),
+                Some((TableOps::from("read t0 write t1"), EarlyReleaseTables::default()))
+            )
+        );
+        assert_eq!(
+            extract_annotation("/*o2v write t1 RELEASE t1 */ update t1 set a = 1;"),
+            (
+                String::from("update t1 set a = 1;"),
+                Some((TableOps::from("write t1"), EarlyReleaseTables::from("t1")))
+            )
+        );
+        assert_eq!(
+            extract_annotation("select/*o2v read t0*/a from t0;"),
+            (
+                String::from("select a from t0;"),
+                Some((TableOps::from("read t0"), EarlyReleaseTables::default()))
+            )
+        );
+        assert_eq!(
+            extract_annotation("/*o2v*/ select 1;"),
+            (
+                String::from("select 1;"),
+                Some((TableOps::default(), EarlyReleaseTables::default()))
+            )
+        );
+        assert_eq!(
+            extract_annotation("select '/*o2v read x*/', 'é' from t0 /*o2v read t0*/"),
+            (
+                String::from("select '/*o2v read x*/', 'é' from t0"),
+                Some((TableOps::from("read t0"), EarlyReleaseTables::default()))
+            )
+        );
+    }
+}
@@ -28,6 +28,12 @@ impl Default for DbVN {
     }
 }
 
+impl From<HashMap<String, VN>> for DbVN {
+    fn from(version_vector: HashMap<String, VN>) -> Self {
+        Self(version_vector)
+    }
+}
+
 impl DbVN {
     /// Get the `DbTableVN` from current `DbVN` that matches with the argument `TableOp`
     pub fn get_from_tableop(&self, tableop: &TableOp) -> DbTableVN {
@@ -58,8 +64,8 @@ impl DbVN {
     /// is allowed to execute
     ///
     /// # Important:
-    /// 1. You must check the original `TableOps` that is used to yield `txtablevns`
-    /// for any potential mixing of R and W.
+    /// 1. Each `TxTableVN` is checked by its own `RWOperation`, so a query with mixed R and W
+    /// checks its R tables with the read rule and its W tables with the write rule.
     /// 2. Use `TxVN::get_from_tableops(&self, tableops: &TableOps)` to get a list of `TxTableVN`
     /// for checking
     ///
@@ -133,6 +139,25 @@ impl DbVN {
         });
     }
 
+    /// Check whether every table in `version_vector` has exactly its version number there
+    pub fn has_reached(&self, version_vector: &HashMap<String, VN>) -> bool {
+        version_vector
+            .iter()
+            .all(|(table, vn)| self.0.get(table).cloned().unwrap_or_default() == *vn)
+    }
+
+    /// Check whether every table in `version_vector` has at least its version number there
+    pub fn has_passed(&self, version_vector: &HashMap<String, VN>) -> bool {
+        version_vector
+            .iter()
+            .all(|(table, vn)| self.0.get(table).cloned().unwrap_or_default() >= *vn)
+    }
+
+    /// Get the version numbers of all tables
+    pub fn to_version_vector(&self) -> HashMap<String, VN> {
+        self.0.clone()
+    }
+
     /// Get the sum of VNs of all tables
     pub fn get_version_sum(&self) -> VN {
         self.0.iter().fold(0, |s, (_, vn)| s + vn)
@@ -291,6 +316,47 @@ mod tests_dbvn {
         assert!(dbvn.can_execute_query(&[]));
     }
 
+    #[test]
+    fn test_can_execute_mixed_query() {
+        let dbvn = DbVN(
+            [("t0", 5), ("t1", 6)]
+                .iter()
+                .cloned()
+                .map(|(s, vn)| (s.to_owned(), vn as VN))
+                .collect(),
+        );
+        let tableops = TableOps::from("write t0 read t1");
+        assert!(tableops.access_pattern().is_mixed());
+
+        // W with ==, R with >=
+        let txvn0 = TxVN::new().set_txtablevns(vec![
+            TxTableVN::new("t0", 5, RWOperation::W),
+            TxTableVN::new("t1", 4, RWOperation::R),
+        ]);
+        assert!(dbvn.can_execute_query(&txvn0.get_from_tableops(&tableops).unwrap()));
+
+        // W is behind
+        let txvn1 = TxVN::new().set_txtablevns(vec![
+            TxTableVN::new("t0", 4, RWOperation::W),
+            TxTableVN::new("t1", 4, RWOperation::R),
+        ]);
+        assert!(!dbvn.can_execute_query(&txvn1.get_from_tableops(&tableops).unwrap()));
+
+        // R is not ready yet
+        let txvn2 = TxVN::new().set_txtablevns(vec![
+            TxTableVN::new("t0", 5, RWOperation::W),
+            TxTableVN::new("t1", 7, RWOperation::R),
+        ]);
+        assert!(!dbvn.can_execute_query(&txvn2.get_from_tableops(&tableops).unwrap()));
+
+        // R table assigned a W VN within the transaction
+        let txvn3 = TxVN::new().set_txtablevns(vec![
+            TxTableVN::new("t0", 5, RWOperation::W),
+            TxTableVN::new("t1", 5, RWOperation::W),
+        ]);
+        assert!(!dbvn.can_execute_query(&txvn3.get_from_tableops(&tableops).unwrap()));
+    }
+
     #[test]
     fn test_release_version() {
         let mut dbvn = DbVN(
@@ -322,4 +388,22 @@ mod tests_dbvn {
         dbvn.release_version(txvn0.into_dbvn_release_request());
         assert!(dbvn.can_execute_query(&txvn1.get_from_tableops(&tableops).unwrap()));
     }
+
+    #[test]
+    fn test_has_passed() {
+        let dbvn = DbVN(
+            [("t0", 5), ("t1", 6)]
+                .iter()
+                .cloned()
+                .map(|(s, vn)| (s.to_owned(), vn as VN))
+                .collect(),
+        );
+        let version_vector = |vns: &[(&str, VN)]| vns.iter().map(|(s, vn)| (s.to_string(), *vn)).collect();
+
+        assert!(dbvn.has_passed(&dbvn.to_version_vector()));
+        assert!(dbvn.has_passed(&version_vector(&[("t0", 4), ("t1", 6)])));
+        assert!(dbvn.has_passed(&version_vector(&[("t2", 0)])));
+        assert!(!dbvn.has_passed(&version_vector(&[("t0", 6), ("t1", 6)])));
+        assert!(!dbvn.has_passed(&version_vector(&[("t2", 1)])));
+    }
 }
@@ -6,7 +6,7 @@ pub enum Legality {
     /// Legal
     Legal,
     /// The operation must be rejected, but following operations are still accepted
-    Critical(String),
+    Critical(MsqlError),
     /// The operation must be rejected, and the server should panic because the error cannot be handled as for now
     Panic(String),
 }
@@ -17,9 +17,14 @@ impl Legality {
         Self::Legal
     }
 
-    /// Returns a `Legality::Critical` variant with the argument
+    /// Returns a `Legality::Critical` variant with the argument as a `MsqlErrorKind::Illegal` error
     pub fn critical<S: Into<String>>(s: S) -> Self {
-        Self::Critical(s.into())
+        Self::Critical(MsqlError::illegal(s))
+    }
+
+    /// Returns a `Legality::Critical` variant with the argument as a `MsqlErrorKind::Annotation` error
+    pub fn critical_annotation<S: Into<String>>(s: S) -> Self {
+        Self::Critical(MsqlError::annotation(s))
     }
 
     /// Returns a `Legality::Panic` variant with the argument
@@ -39,7 +44,7 @@ impl Legality {
                 .filter(|tableop| txvn.get_from_tableop(tableop).is_none())
                 .map(|tableop| tableop.table())
                 .collect();
-            Err(Self::critical(format!(
+            Err(Self::critical_annotation(format!(
                 "Query is using tables not declared in the BeginTx: {:?}",
                 missing_tableops
             )))
@@ -48,10 +53,77 @@ impl Legality {
         }
     }
 
+    /// Check whether the `TableOps` within the argument `MsqlQuery` correctly annotates
+    /// the Sql of the query, and if error, returns `Err(Legality::Critical)`
+    ///
+    /// The annotation is correct if all tables accessed by the Sql are declared,
+    /// and all tables written by the Sql are declared as W. Over-declaration is allowed.
+    fn check_tableops_match_sql(query: &MsqlQuery) -> Result<(), Self> {
+        let sql_tableops = annotate_query(query.query())
+            .map_err(|e| Self::critical_annotation(format!("Cannot verify the annotation of the query: {}", e)))?;
+
+        let mut undeclared = Vec::new();
+        let mut undeclared_writes = Vec::new();
+        for sql_tableop in sql_tableops.get() {
+            match query
+                .tableops()
+                .get()
+                .iter()
+                .find(|tableop| tableop.table() == sql_tableop.table())
+            {
+                None => undeclared.push(sql_tableop.table()),
+                Some(tableop) if tableop.op() == RWOperation::R && sql_tableop.op() == RWOperation::W => {
+                    undeclared_writes.push(sql_tableop.table())
+                }
+                _ => (),
+            }
+        }
+
+        if undeclared.is_empty() && undeclared_writes.is_empty() {
+            Ok(())
+        } else {
+            Err(Self::critical_annotation(format!(
+                "Query annotation does not match the Sql, tables not declared: {:?}, tables written but declared as R: {:?}",
+                undeclared, undeclared_writes
+            )))
+        }
+    }
+
+    /// Check whether the name of the argument `MsqlSavepoint` is a valid Sql identifier,
+    /// and if error, returns `Err(Legality::Critical)`
+    fn check_savepoint_name(savepoint: &MsqlSavepoint) -> Result<(), Self> {
+        let mut chars = savepoint.name().chars();
+        let valid = match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+            }
+            _ => false,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(Self::critical(format!(
+                "Savepoint name is not a valid identifier: {:?}",
+                savepoint.name()
+            )))
+        }
+    }
+
     /// A single point for query diagnostics,
     /// all legalization must be done before this function call.
     /// Code after this function call in later stages can simply panic.
-    pub fn final_check(msql: &Msql, txvn_opt: &Option<TxVN>) -> Self {
+    ///
+    /// If `strict_annotation`, the `TableOps` of `Msql::Query` is also verified against its Sql.
+    pub fn final_check(msql: &Msql, txvn_opt: &Option<TxVN>, strict_annotation: bool) -> Self {
+        if let Msql::Query(query) = msql {
+            if strict_annotation {
+                if let Err(err) = Self::check_tableops_match_sql(query) {
+                    return err;
+                }
+            }
+        }
+
         match msql {
             Msql::BeginTx(_begintx) => {
                 if txvn_opt.is_some() {
@@ -63,21 +135,20 @@ impl Legality {
             Msql::Query(query) => {
                 if let Some(txvn) = txvn_opt.as_ref() {
                     match &query.tableops().access_pattern() {
-                        AccessPattern::Mixed => Self::critical("Does not support query with mixed R and W"),
                         AccessPattern::ReadOnly => {
                             if let Err(err) = Self::check_tableops_match_txvn(query, txvn) {
                                 err
                             } else if query.has_early_release() {
-                                Self::critical("Does not support early release on R queries")
+                                Self::critical_annotation("Does not support early release on R queries")
                             } else {
                                 Self::legal()
                             }
                         }
-                        AccessPattern::WriteOnly => {
+                        AccessPattern::WriteOnly | AccessPattern::Mixed => {
                             if let Err(err) = Self::check_tableops_match_txvn(query, txvn) {
                                 err
                             } else if txvn.get_from_ertables(&query.early_release_tables()).is_err() {
-                                Self::critical(
+                                Self::critical_annotation(
                                         "Tables marked for early release was not declared in the BeginTx or has already been released",
                                     )
                             } else {
@@ -87,9 +158,10 @@ impl Legality {
                     }
                 } else {
                     match &query.tableops().access_pattern() {
-                        AccessPattern::Mixed => Self::critical("Does not support query with mixed R and W"),
                         AccessPattern::ReadOnly => Self::legal(),
-                        AccessPattern::WriteOnly => Self::panic("Query does not have a valid BeginTx"),
+                        AccessPattern::WriteOnly | AccessPattern::Mixed => {
+                            Self::panic("Query does not have a valid BeginTx")
+                        }
                     }
                 }
             }
@@ -100,6 +172,145 @@ impl Legality {
                     Self::legal()
                 }
             }
+            Msql::Savepoint(savepoint) => {
+                if txvn_opt.is_none() {
+                    Self::critical("There is no transaction for the savepoint")
+                } else if let Err(err) = Self::check_savepoint_name(savepoint) {
+                    err
+                } else {
+                    Self::legal()
+                }
+            }
         }
     }
 }
+
+/// Unit test for `Legality`
+#[cfg(test)]
+mod tests_legality {
+    use super::*;
+
+    fn final_check_query(query: &str, tableops: &str) -> Legality {
+        Legality::final_check(
+            &Msql::Query(MsqlQuery::new(query, TableOps::from(tableops), EarlyReleaseTables::default()).unwrap()),
+            &None,
+            true,
+        )
+    }
+
+    #[test]
+    fn test_mixed_query() {
+        let txvn = TxVN::new().set_txtablevns(vec![
+            TxTableVN::new("t0", 0, RWOperation::W),
+            TxTableVN::new("t1", 0, RWOperation::R),
+        ]);
+        let query = Msql::Query(
+            MsqlQuery::new(
+                "insert into t0 select * from t1",
+                TableOps::from("write t0 read t1"),
+                EarlyReleaseTables::from("t0"),
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            Legality::final_check(&query, &Some(txvn.clone()), false),
+            Legality::Legal
+        ));
+        assert!(matches!(
+            Legality::final_check(&query, &None, false),
+            Legality::Panic(_)
+        ));
+
+        let query = Msql::Query(
+            MsqlQuery::new(
+                "insert into t1 select * from t0",
+                TableOps::from("write t1 read t0"),
+                EarlyReleaseTables::default(),
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            Legality::final_check(&query, &Some(txvn), false),
+            Legality::Critical(_)
+        ));
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let txvn = Some(TxVN::new().set_txtablevns(vec![TxTableVN::new("t0", 0, RWOperation::W)]));
+        assert!(matches!(
+            Legality::final_check(&Msql::Savepoint(MsqlSavepoint::savepoint("sp_0")), &txvn, false),
+            Legality::Legal
+        ));
+        assert!(matches!(
+            Legality::final_check(&Msql::Savepoint(MsqlSavepoint::rollback_to("sp_0")), &txvn, true),
+            Legality::Legal
+        ));
+        assert!(matches!(
+            Legality::final_check(&Msql::Savepoint(MsqlSavepoint::release("sp_0")), &None, false),
+            Legality::Critical(e) if e.kind() == MsqlErrorKind::Illegal
+        ));
+        assert!(matches!(
+            Legality::final_check(&Msql::Savepoint(MsqlSavepoint::savepoint("sp; drop")), &txvn, false),
+            Legality::Critical(_)
+        ));
+        assert!(matches!(
+            Legality::final_check(&Msql::Savepoint(MsqlSavepoint::savepoint("")), &txvn, false),
+            Legality::Critical(_)
+        ));
+    }
+
+    #[test]
+    fn test_strict_annotation() {
+        assert!(matches!(
+            final_check_query("select * from t0 join t1 on t0.id = t1.id", "read t0 t1"),
+            Legality::Legal
+        ));
+        assert!(matches!(
+            final_check_query("select * from t0", "read t0 t1 t2"),
+            Legality::Legal
+        ));
+
+        match final_check_query("select * from t0, t1, t2", "read t1") {
+            Legality::Critical(e) => {
+                assert_eq!(e.kind(), MsqlErrorKind::Annotation);
+                assert!(e.message().contains(r#"tables not declared: ["t0", "t2"]"#), "{}", e);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        match final_check_query("update t1 set a = 1", "read t1") {
+            Legality::Critical(e) => {
+                assert_eq!(e.kind(), MsqlErrorKind::Annotation);
+                assert!(
+                    e.message().contains(r#"tables written but declared as R: ["t1"]"#),
+                    "{}",
+                    e
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(matches!(
+            final_check_query("drop table t0", "read t0"),
+            Legality::Critical(_)
+        ));
+
+        // Not verified when not strict
+        assert!(matches!(
+            Legality::final_check(
+                &Msql::Query(
+                    MsqlQuery::new(
+                        "select * from t0",
+                        TableOps::from("read t1"),
+                        EarlyReleaseTables::default()
+                    )
+                    .unwrap()
+                ),
+                &None,
+                false
+            ),
+            Legality::Legal
+        ));
+    }
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::net::SocketAddr;
 
 /// Meta data regarding the current client session
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct ClientMeta {
     client_addr: SocketAddr,
     cur_txid: usize,
@@ -41,7 +41,7 @@ impl ClientMeta {
 }
 
 /// Meta data regarding the current request within the current client session
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct RequestMeta {
     pub client_addr: SocketAddr,
     pub cur_txid: usize,
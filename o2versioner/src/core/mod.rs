@@ -3,16 +3,20 @@
 //! - Everything here is written in synchronous style.
 //! - Does not use any synchronization primitives.
 
+mod annotation;
 mod database_version;
 mod legality;
 mod metadata;
 mod msql;
+mod msql_error;
 mod operation;
 mod transaction_version;
 
+pub use annotation::{annotate_query, extract_annotation, is_multi_statement};
 pub use database_version::{DbTableVN, DbVN};
 pub use legality::Legality;
 pub use metadata::{ClientMeta, RequestMeta};
 pub use msql::*;
+pub use msql_error::{MsqlError, MsqlErrorKind};
 pub use operation::{AccessPattern, EarlyReleaseTables, RWOperation, TableOp, TableOps};
 pub use transaction_version::{DbVNReleaseRequest, TxTableVN, TxVN, VN};
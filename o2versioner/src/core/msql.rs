@@ -1,5 +1,7 @@
+use super::annotation::{annotate_query, extract_annotation};
 use super::operation::*;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::convert::TryFrom;
 
 /// Representing the final form of all `M: IntoMsqlFinalString`
@@ -137,11 +139,25 @@ impl MsqlBeginTx {
 ///     EarlyReleaseTables::from("table0"))
 /// .unwrap();
 /// ```
+///
+/// With parameters bound to the placeholders `$1`, `$2`, ...
+/// ```
+/// use o2versioner::core::{EarlyReleaseTables, MsqlQuery, TableOps};
+/// use serde_json::json;
+/// MsqlQuery::new(
+///     "UPDATE table0 SET name = $1 WHERE id = $2;",
+///     TableOps::from("WRITE table0"),
+///     EarlyReleaseTables::default())
+/// .unwrap()
+/// .set_params(vec![json!("ray"), json!(20)]);
+/// ```
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MsqlQuery {
     query: String,
     tableops: TableOps,
     ertables: EarlyReleaseTables,
+    #[serde(default)]
+    params: Vec<Value>,
 }
 
 impl IntoMsqlFinalString for MsqlQuery {
@@ -152,20 +168,45 @@ impl IntoMsqlFinalString for MsqlQuery {
 
 impl MsqlQuery {
     /// Create a new query, `tableops` must correctly annotate the `query`
+    ///
+    /// A query with `AccessPattern::Mixed` is treated as a write query.
     pub fn new<S: Into<String>>(
         query: S,
         tableops: TableOps,
         ertables: EarlyReleaseTables,
     ) -> Result<Self, &'static str> {
-        if let AccessPattern::Mixed = tableops.access_pattern() {
-            Err("Only read-only or write-only Msql query is supported, but not the mixed!")
-        } else {
-            Ok(Self {
-                query: query.into(),
-                tableops,
-                ertables,
-            })
-        }
+        Ok(Self {
+            query: query.into(),
+            tableops,
+            ertables,
+            params: Vec::new(),
+        })
+    }
+
+    /// Create a new query, where the `TableOps` is automatically annotated from the `query`
+    ///
+    /// # Examples
+    /// ```
+    /// use o2versioner::core::{EarlyReleaseTables, MsqlQuery, TableOps};
+    /// let query = MsqlQuery::auto_annotated(
+    ///     "SELECT * FROM table0 JOIN table1 ON table0.id = table1.id;",
+    ///     EarlyReleaseTables::default())
+    /// .unwrap();
+    /// assert_eq!(query.tableops(), &TableOps::from("READ table0 table1"));
+    /// ```
+    pub fn auto_annotated<S: Into<String>>(query: S, ertables: EarlyReleaseTables) -> Result<Self, &'static str> {
+        let query = query.into();
+        let tableops = annotate_query(&query)?;
+        Self::new(query, tableops, ertables)
+    }
+
+    /// Set the parameters bound to the placeholders of the query, will overwrite previous value
+    ///
+    /// The n-th parameter is bound to the placeholder `$n`, and is converted according to
+    /// the type of the placeholder by the dbproxy.
+    pub fn set_params(mut self, params: Vec<Value>) -> Self {
+        self.params = params;
+        self
     }
 
     /// Get the `AccessPattern` of the current query
@@ -188,6 +229,16 @@ impl MsqlQuery {
         &self.ertables
     }
 
+    /// Get a ref to the parameters of the query
+    pub fn params(&self) -> &[Value] {
+        &self.params[..]
+    }
+
+    /// Check whether the query has parameters to bind
+    pub fn has_params(&self) -> bool {
+        !self.params.is_empty()
+    }
+
     /// Check whether the query has early release request
     pub fn has_early_release(&self) -> bool {
         !self.ertables.is_empty()
@@ -212,19 +263,61 @@ pub enum MsqlEndTxMode {
     Rollback,
 }
 
+/// Enum representing how many replicas must apply a commit before it is acknowledged to the client
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, strum::AsRefStr)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitAck {
+    /// The first replica
+    First,
+    /// More than half of the live replicas
+    Majority,
+    /// All live replicas
+    All,
+}
+
+impl Default for CommitAck {
+    fn default() -> Self {
+        Self::First
+    }
+}
+
+impl CommitAck {
+    /// Number of replicas required out of `num_replicas` live replicas
+    ///
+    /// # Examples
+    /// ```
+    /// use o2versioner::core::CommitAck;
+    ///
+    /// assert_eq!(CommitAck::First.required(3), 1);
+    /// assert_eq!(CommitAck::Majority.required(3), 2);
+    /// assert_eq!(CommitAck::Majority.required(4), 3);
+    /// assert_eq!(CommitAck::All.required(3), 3);
+    /// ```
+    pub fn required(&self, num_replicas: usize) -> usize {
+        match self {
+            Self::First => 1.min(num_replicas),
+            Self::Majority => num_replicas / 2 + 1,
+            Self::All => num_replicas,
+        }
+    }
+}
+
 /// End a Msql transaction
 ///
 /// # Examples
 /// ```
-/// use o2versioner::core::MsqlEndTx;
+/// use o2versioner::core::{CommitAck, MsqlEndTx};
 ///
 /// MsqlEndTx::commit();
+/// MsqlEndTx::commit().set_ack(Some(CommitAck::All));
 /// MsqlEndTx::rollback().set_name(Some("tx1"));
 /// ```
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct MsqlEndTx {
     tx: Option<String>,
     mode: MsqlEndTxMode,
+    /// Overrides `SchedulerConf::commit_ack` for this transaction
+    ack: Option<CommitAck>,
 }
 
 impl IntoMsqlFinalString for MsqlEndTx {
@@ -245,7 +338,11 @@ impl IntoMsqlFinalString for MsqlEndTx {
 
 impl From<MsqlEndTxMode> for MsqlEndTx {
     fn from(mode: MsqlEndTxMode) -> Self {
-        Self { tx: None, mode }
+        Self {
+            tx: None,
+            mode,
+            ack: None,
+        }
     }
 }
 
@@ -255,6 +352,7 @@ impl MsqlEndTx {
         Self {
             tx: None,
             mode: MsqlEndTxMode::Commit,
+            ack: None,
         }
     }
 
@@ -263,6 +361,7 @@ impl MsqlEndTx {
         Self {
             tx: None,
             mode: MsqlEndTxMode::Rollback,
+            ack: None,
         }
     }
 
@@ -278,6 +377,13 @@ impl MsqlEndTx {
         self
     }
 
+    /// Set the commit acknowledgement policy for this transaction, will overwrite previous value.
+    /// The policy of the Scheduler is used if `None`
+    pub fn set_ack(mut self, ack: Option<CommitAck>) -> Self {
+        self.ack = ack;
+        self
+    }
+
     /// Get a ref to the optional transaction name
     pub fn name(&self) -> Option<&str> {
         self.tx.as_ref().map(|s| &s[..])
@@ -288,12 +394,99 @@ impl MsqlEndTx {
         self.mode
     }
 
+    /// Get the commit acknowledgement policy of this transaction, if overridden
+    pub fn ack(&self) -> Option<CommitAck> {
+        self.ack
+    }
+
     /// Unwrap into (name: Option<String>, mode: MsqlEndTxMode)
     pub fn unwrap(self) -> (Option<String>, MsqlEndTxMode) {
         (self.tx, self.mode)
     }
 }
 
+/// Enum representing the savepoint mode, can be `Savepoint`, `RollbackTo` or `Release`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, strum::AsRefStr)]
+#[serde(rename_all = "snake_case")]
+pub enum MsqlSavepointMode {
+    Savepoint,
+    RollbackTo,
+    Release,
+}
+
+/// Set, rollback to, or release a savepoint within a Msql transaction
+///
+/// Savepoints only exist inside of a transaction, and they do not end the transaction.
+/// All versions of the transaction are still held until the `MsqlEndTx`.
+///
+/// # Examples
+/// ```
+/// use o2versioner::core::MsqlSavepoint;
+///
+/// MsqlSavepoint::savepoint("sp0");
+/// MsqlSavepoint::rollback_to("sp0");
+/// MsqlSavepoint::release("sp0");
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MsqlSavepoint {
+    name: String,
+    mode: MsqlSavepointMode,
+}
+
+impl IntoMsqlFinalString for MsqlSavepoint {
+    fn into_msqlfinalstring(self) -> MsqlFinalString {
+        let mut sql = match self.mode() {
+            MsqlSavepointMode::Savepoint => String::from("SAVEPOINT "),
+            MsqlSavepointMode::RollbackTo => String::from("ROLLBACK TO SAVEPOINT "),
+            MsqlSavepointMode::Release => String::from("RELEASE SAVEPOINT "),
+        };
+        sql.push_str(self.name());
+        sql.push(';');
+        MsqlFinalString(sql)
+    }
+}
+
+impl MsqlSavepoint {
+    /// Returns a `MsqlSavepoint` that sets a new savepoint
+    pub fn savepoint<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            mode: MsqlSavepointMode::Savepoint,
+        }
+    }
+
+    /// Returns a `MsqlSavepoint` that rolls back to an existing savepoint
+    pub fn rollback_to<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            mode: MsqlSavepointMode::RollbackTo,
+        }
+    }
+
+    /// Returns a `MsqlSavepoint` that releases an existing savepoint
+    pub fn release<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            mode: MsqlSavepointMode::Release,
+        }
+    }
+
+    /// Get a ref to the savepoint name
+    pub fn name(&self) -> &str {
+        &self.name[..]
+    }
+
+    /// Get the savepoint mode
+    pub fn mode(&self) -> MsqlSavepointMode {
+        self.mode
+    }
+
+    /// Unwrap into (name: String, mode: MsqlSavepointMode)
+    pub fn unwrap(self) -> (String, MsqlSavepointMode) {
+        (self.name, self.mode)
+    }
+}
+
 /// Represents a Msql command variant.
 /// The main user interface for Msql.
 ///
@@ -303,6 +496,7 @@ pub enum Msql {
     BeginTx(MsqlBeginTx),
     Query(MsqlQuery),
     EndTx(MsqlEndTx),
+    Savepoint(MsqlSavepoint),
 }
 
 impl Msql {
@@ -377,6 +571,27 @@ impl Msql {
             _ => Err(()),
         }
     }
+
+    /// Check whether the current `Msql` is a `Msql::Savepoint` variant
+    pub fn is_savepoint(&self) -> bool {
+        matches!(self, Self::Savepoint(_))
+    }
+
+    /// Try to get a `&MsqlSavepoint` if the current `Msql` is a `Msql::Savepoint` variant
+    pub fn try_get_savepoint(&self) -> Result<&MsqlSavepoint, &'static str> {
+        match self {
+            Self::Savepoint(x) => Ok(x),
+            _ => Err("Msql is not a Msql::Savepoint"),
+        }
+    }
+
+    /// Try to get a `&mut MsqlSavepoint` if the current `Msql` is a `Msql::Savepoint` variant
+    pub fn try_get_mut_savepoint(&mut self) -> Result<&mut MsqlSavepoint, &'static str> {
+        match self {
+            Self::Savepoint(x) => Ok(x),
+            _ => Err("Msql is not a Msql::Savepoint"),
+        }
+    }
 }
 
 impl IntoMsqlFinalString for Msql {
@@ -385,6 +600,7 @@ impl IntoMsqlFinalString for Msql {
             Self::BeginTx(msqlbegintx) => msqlbegintx.into_msqlfinalstring(),
             Self::Query(msqlquery) => msqlquery.into_msqlfinalstring(),
             Self::EndTx(msqlendtx) => msqlendtx.into_msqlfinalstring(),
+            Self::Savepoint(msqlsavepoint) => msqlsavepoint.into_msqlfinalstring(),
         }
     }
 }
@@ -402,13 +618,26 @@ impl TryFrom<MsqlText> for Msql {
                 query,
                 tableops,
                 ertables,
-            } => MsqlQuery::new(
-                query,
-                TableOps::from(tableops),
-                EarlyReleaseTables::from(ertables.unwrap_or(String::from(""))),
-            )
-            .map(|mq| Self::Query(mq)),
-            MsqlText::EndTx { tx, mode } => Ok(Self::EndTx(MsqlEndTx::from(mode).set_name(tx))),
+                params,
+            } => {
+                // The comment annotation is always stripped, and is overwritten by the explicit annotation
+                let (query, annotation) = extract_annotation(&query);
+                let (comment_tableops, comment_ertables) = match annotation {
+                    Some((tableops, ertables)) => (Some(tableops), Some(ertables)),
+                    None => (None, None),
+                };
+                let ertables = ertables
+                    .map(EarlyReleaseTables::from)
+                    .or(comment_ertables)
+                    .unwrap_or_default();
+                match tableops.map(TableOps::from).or(comment_tableops) {
+                    Some(tableops) => MsqlQuery::new(query, tableops, ertables),
+                    None => MsqlQuery::auto_annotated(query, ertables),
+                }
+                .map(|mq| Self::Query(mq.set_params(params)))
+            }
+            MsqlText::EndTx { tx, mode, ack } => Ok(Self::EndTx(MsqlEndTx::from(mode).set_name(tx).set_ack(ack))),
+            MsqlText::Savepoint { name, mode } => Ok(Self::Savepoint(MsqlSavepoint { name, mode })),
         }
     }
 }
@@ -437,8 +666,9 @@ impl TryFrom<MsqlText> for Msql {
 ///     query,
 ///     MsqlText::Query {
 ///         query: String::from("select * from t;"),
-///         tableops: String::from("read t"),
-///         ertables: Some(String::from("t0 t1 t2"))
+///         tableops: Some(String::from("read t")),
+///         ertables: Some(String::from("t0 t1 t2")),
+///         params: vec![]
 ///     }
 /// );
 /// ```
@@ -458,12 +688,74 @@ impl TryFrom<MsqlText> for Msql {
 ///     query,
 ///     MsqlText::Query {
 ///         query: String::from("select * from t;"),
-///         tableops: String::from("read t"),
-///         ertables: None
+///         tableops: Some(String::from("read t")),
+///         ertables: None,
+///         params: vec![]
 ///     }
 /// );
 /// ```
 ///
+/// With parameters, `MsqlText::Query`
+/// ```
+/// use o2versioner::core::MsqlText;
+/// use serde_json::json;
+/// // "params" are bound to the placeholders $1, $2, ... in order
+/// let query_str = r#"
+/// {
+///     "op":"query",
+///     "query":"select * from t where id = $1 and name = $2;",
+///     "tableops":"read t",
+///     "params":[20, "ray"]
+/// }"#;
+/// let query: MsqlText = serde_json::from_str(query_str).unwrap();
+/// assert_eq!(
+///     query,
+///     MsqlText::Query {
+///         query: String::from("select * from t where id = $1 and name = $2;"),
+///         tableops: Some(String::from("read t")),
+///         ertables: None,
+///         params: vec![json!(20), json!("ray")]
+///     }
+/// );
+/// ```
+///
+/// Skipping `tableops` for automatic annotation, `MsqlText::Query`
+/// ```
+/// use o2versioner::core::{Msql, MsqlText, TableOps};
+/// use std::convert::TryFrom;
+/// // "tableops" will be annotated from "query" when converting into Msql
+/// let query_str = r#"
+/// {
+///     "op":"query",
+///     "query":"select * from t0 join t1 on t0.id = t1.id;"
+/// }"#;
+/// let query: MsqlText = serde_json::from_str(query_str).unwrap();
+/// let msql = Msql::try_from(query).unwrap();
+/// assert_eq!(
+///     msql.try_get_query().unwrap().tableops(),
+///     &TableOps::from("read t0 t1")
+/// );
+/// ```
+///
+/// Embedding the annotation as a Sql comment in "query", `MsqlText::Query`
+/// ```
+/// use o2versioner::core::{EarlyReleaseTables, Msql, MsqlText, TableOps};
+/// use std::convert::TryFrom;
+/// // The comment "/*o2v ...*/" is stripped from "query" when converting into Msql,
+/// // words after "release" are the tables for early release
+/// let query_str = r#"
+/// {
+///     "op":"query",
+///     "query":"/*o2v read t0 write t1 release t1*/ update t1 set a = (select b from t0);"
+/// }"#;
+/// let query: MsqlText = serde_json::from_str(query_str).unwrap();
+/// let msql = Msql::try_from(query).unwrap();
+/// let msqlquery = msql.try_get_query().unwrap();
+/// assert_eq!(msqlquery.query(), "update t1 set a = (select b from t0);");
+/// assert_eq!(msqlquery.tableops(), &TableOps::from("read t0 write t1"));
+/// assert_eq!(msqlquery.early_release_tables(), &EarlyReleaseTables::from("t1"));
+/// ```
+///
 /// `MsqlText::BeginTx`
 /// ```
 /// use o2versioner::core::{MsqlEndTxMode, MsqlText};
@@ -522,7 +814,48 @@ impl TryFrom<MsqlText> for Msql {
 ///     endtx,
 ///     MsqlText::EndTx {
 ///         tx: Some(String::from("tx2")),
-///         mode: MsqlEndTxMode::Commit
+///         mode: MsqlEndTxMode::Commit,
+///         ack: None
+///     }
+/// );
+/// ```
+///
+/// Waiting for all replicas to apply the commit, `MsqlText::EndTx`
+/// ```
+/// use o2versioner::core::{CommitAck, MsqlEndTxMode, MsqlText};
+/// // Use "first", "majority" or "all" for CommitAck
+/// let endtx_str = r#"
+/// {
+///     "op":"end_tx",
+///     "mode":"commit",
+///     "ack":"all"
+/// }"#;
+/// let endtx: MsqlText = serde_json::from_str(endtx_str).unwrap();
+/// assert_eq!(
+///     endtx,
+///     MsqlText::endtx(Option::<String>::None, MsqlEndTxMode::Commit).set_ack(Some(CommitAck::All))
+/// );
+/// ```
+///
+/// `MsqlText::Savepoint`
+/// ```
+/// use o2versioner::core::{MsqlSavepointMode, MsqlText};
+/// // "op":"savepoint" suggests MsqlText::Savepoint
+/// // Use "savepoint" for MsqlSavepointMode::Savepoint
+/// // Use "rollback_to" for MsqlSavepointMode::RollbackTo
+/// // Use "release" for MsqlSavepointMode::Release
+/// let savepoint_str = r#"
+/// {
+///     "op":"savepoint",
+///     "name":"sp0",
+///     "mode":"rollback_to"
+/// }"#;
+/// let savepoint: MsqlText = serde_json::from_str(savepoint_str).unwrap();
+/// assert_eq!(
+///     savepoint,
+///     MsqlText::Savepoint {
+///         name: String::from("sp0"),
+///         mode: MsqlSavepointMode::RollbackTo
 ///     }
 /// );
 /// ```
@@ -536,14 +869,23 @@ pub enum MsqlText {
     },
     Query {
         query: String,
-        tableops: String,
+        #[serde(default)]
+        tableops: Option<String>,
         #[serde(default)]
         ertables: Option<String>,
+        #[serde(default)]
+        params: Vec<Value>,
     },
     EndTx {
         #[serde(default)]
         tx: Option<String>,
         mode: MsqlEndTxMode,
+        #[serde(default)]
+        ack: Option<CommitAck>,
+    },
+    Savepoint {
+        name: String,
+        mode: MsqlSavepointMode,
     },
 }
 
@@ -567,8 +909,40 @@ impl MsqlText {
     {
         Self::Query {
             query: query.into(),
-            tableops: tableops.into(),
+            tableops: Some(tableops.into()),
+            ertables: ertables.map(|s| s.into()),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn auto_query<S1, S2>(query: S1, ertables: Option<S2>) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Self::Query {
+            query: query.into(),
+            tableops: None,
             ertables: ertables.map(|s| s.into()),
+            params: Vec::new(),
+        }
+    }
+
+    /// Set the parameters of a `MsqlText::Query`, other variants are returned unchanged
+    pub fn set_params(self, params: Vec<Value>) -> Self {
+        match self {
+            Self::Query {
+                query,
+                tableops,
+                ertables,
+                ..
+            } => Self::Query {
+                query,
+                tableops,
+                ertables,
+                params,
+            },
+            other => other,
         }
     }
 
@@ -579,6 +953,25 @@ impl MsqlText {
         Self::EndTx {
             tx: tx.map(|s| s.into()),
             mode,
+            ack: None,
+        }
+    }
+
+    /// Set the commit acknowledgement policy of a `MsqlText::EndTx`, other variants are returned unchanged
+    pub fn set_ack(self, ack: Option<CommitAck>) -> Self {
+        match self {
+            Self::EndTx { tx, mode, .. } => Self::EndTx { tx, mode, ack },
+            other => other,
+        }
+    }
+
+    pub fn savepoint<S>(name: S, mode: MsqlSavepointMode) -> Self
+    where
+        S: Into<String>,
+    {
+        Self::Savepoint {
+            name: name.into(),
+            mode,
         }
     }
 }
@@ -646,6 +1039,21 @@ mod tests_into_msqlfinalstring {
         assert_eq!(mfs, MsqlFinalString::new("ROLLBACK TRAN tx1;"));
     }
 
+    #[test]
+    fn test_from_msqlsavepoint() {
+        assert_eq!(
+            MsqlFinalString::from(MsqlSavepoint::savepoint("sp0")),
+            MsqlFinalString::new("SAVEPOINT sp0;")
+        );
+        assert_eq!(
+            MsqlFinalString::from(MsqlSavepoint::rollback_to("sp0")),
+            MsqlFinalString::new("ROLLBACK TO SAVEPOINT sp0;")
+        );
+
+        let mfs: MsqlFinalString = MsqlSavepoint::release("sp1").into();
+        assert_eq!(mfs, MsqlFinalString::new("RELEASE SAVEPOINT sp1;"));
+    }
+
     #[test]
     fn test_from_msql() {
         assert_eq!(
@@ -670,6 +1078,9 @@ mod tests_into_msqlfinalstring {
 
         let mfs: MsqlFinalString = Msql::EndTx(MsqlEndTx::rollback().set_name(Some("tx1"))).into();
         assert_eq!(mfs, MsqlFinalString::new("ROLLBACK TRAN tx1;"));
+
+        let mfs: MsqlFinalString = Msql::Savepoint(MsqlSavepoint::rollback_to("sp2")).into();
+        assert_eq!(mfs, MsqlFinalString::new("ROLLBACK TO SAVEPOINT sp2;"));
     }
 }
 
@@ -696,14 +1107,14 @@ mod tests_msqlquery {
         .is_ok());
 
         assert!(MsqlQuery::new(
-            "some_black_magic;",
+            "Insert into table0 select * from table1;",
             TableOps::from_iter(vec![
                 TableOp::new("table0", RWOperation::W),
                 TableOp::new("table1", RWOperation::R)
             ]),
             EarlyReleaseTables::default()
         )
-        .is_err());
+        .is_ok());
     }
 
     #[test]
@@ -723,6 +1134,64 @@ mod tests_msqlquery {
         )
         .unwrap();
         assert!(q2.access_pattern().is_read_only());
+
+        let q3 = MsqlQuery::new(
+            "Insert into table0 select * from table1;",
+            TableOps::from_iter(vec![
+                TableOp::new("table0", RWOperation::W),
+                TableOp::new("table1", RWOperation::R),
+            ]),
+            EarlyReleaseTables::default(),
+        )
+        .unwrap();
+        assert!(q3.access_pattern().is_mixed());
+    }
+
+    #[test]
+    fn test_params() {
+        let q = MsqlQuery::new(
+            "Select * from table0 where id = $1 and name = $2;",
+            TableOps::from("read table0"),
+            EarlyReleaseTables::default(),
+        )
+        .unwrap();
+        assert!(!q.has_params());
+        assert!(q.params().is_empty());
+
+        let q = q.set_params(vec![serde_json::json!(20), serde_json::json!("ray")]);
+        assert!(q.has_params());
+        assert_eq!(q.params(), &[serde_json::json!(20), serde_json::json!("ray")]);
+
+        // Parameters are not part of the final string
+        assert_eq!(
+            MsqlFinalString::from(q.clone()),
+            MsqlFinalString::new("Select * from table0 where id = $1 and name = $2;")
+        );
+
+        // Parameters survive the serialization
+        let q_str = serde_json::to_string(&q).unwrap();
+        assert_eq!(serde_json::from_str::<MsqlQuery>(&q_str).unwrap(), q);
+    }
+
+    #[test]
+    fn test_auto_annotated() {
+        let q = MsqlQuery::auto_annotated(
+            "Select * from table0 where id in (select id from table1);",
+            EarlyReleaseTables::from("table0"),
+        )
+        .unwrap();
+        assert_eq!(q.tableops(), &TableOps::from("read table0 table1"));
+        assert!(q.has_early_release());
+
+        let q2 = MsqlQuery::auto_annotated(
+            "Update table1 set name='ray' where id = 20;",
+            EarlyReleaseTables::default(),
+        )
+        .unwrap();
+        assert_eq!(q2.tableops(), &TableOps::from("write table1"));
+        assert!(q2.access_pattern().is_write_only());
+
+        assert!(MsqlQuery::auto_annotated("truncate table0;", EarlyReleaseTables::default()).is_err());
     }
 }
 
@@ -747,8 +1216,9 @@ mod tests_msql {
         assert_eq!(
             Msql::try_from(MsqlText::Query {
                 query: String::from("select * from table0;"),
-                tableops: String::from("read table0"),
-                ertables: Some(String::from(""))
+                tableops: Some(String::from("read table0")),
+                ertables: Some(String::from("")),
+                params: vec![]
             }),
             MsqlQuery::new(
                 "select * from table0;",
@@ -758,13 +1228,81 @@ mod tests_msql {
             .map(|q| Msql::Query(q))
         );
 
+        assert_eq!(
+            Msql::try_from(MsqlText::auto_query("update table1 set a = 1;", Some("table1"))),
+            MsqlQuery::new(
+                "update table1 set a = 1;",
+                TableOps::from_iter(vec![TableOp::new("table1", RWOperation::W)]),
+                EarlyReleaseTables::from("table1")
+            )
+            .map(Msql::Query)
+        );
+
+        assert!(Msql::try_from(MsqlText::auto_query("drop table table1;", Option::<String>::None)).is_err());
+
+        assert_eq!(
+            Msql::try_from(MsqlText::auto_query(
+                "/*o2v read table0 write table1 release table1*/ update table1 set a = 1;",
+                Option::<String>::None
+            )),
+            MsqlQuery::new(
+                "update table1 set a = 1;",
+                TableOps::from("read table0 write table1"),
+                EarlyReleaseTables::from("table1")
+            )
+            .map(Msql::Query)
+        );
+
+        // Explicit annotation overwrites the comment annotation
+        assert_eq!(
+            Msql::try_from(MsqlText::query(
+                "update table1 set a = 1; /*o2v write table1 release table1*/",
+                "write table1 table2",
+                Some("")
+            )),
+            MsqlQuery::new(
+                "update table1 set a = 1;",
+                TableOps::from("write table1 table2"),
+                EarlyReleaseTables::default()
+            )
+            .map(Msql::Query)
+        );
+
+        assert_eq!(
+            Msql::try_from(
+                MsqlText::auto_query("update table1 set a = $1 where b = $2;", Option::<String>::None)
+                    .set_params(vec![serde_json::json!(null), serde_json::json!(true)])
+            ),
+            MsqlQuery::new(
+                "update table1 set a = $1 where b = $2;",
+                TableOps::from("write table1"),
+                EarlyReleaseTables::default()
+            )
+            .map(|q| Msql::Query(q.set_params(vec![serde_json::json!(null), serde_json::json!(true)])))
+        );
+
         assert_eq!(
             Msql::try_from(MsqlText::EndTx {
                 tx: Some(String::from("t3")),
                 mode: MsqlEndTxMode::Rollback,
+                ack: None,
             }),
             Ok(Msql::EndTx(MsqlEndTx::rollback().set_name(Some("t3"))))
-        )
+        );
+
+        assert_eq!(
+            Msql::try_from(MsqlText::endtx(Some("t4"), MsqlEndTxMode::Commit).set_ack(Some(CommitAck::Majority))),
+            Ok(Msql::EndTx(
+                MsqlEndTx::commit()
+                    .set_name(Some("t4"))
+                    .set_ack(Some(CommitAck::Majority))
+            ))
+        );
+
+        assert_eq!(
+            Msql::try_from(MsqlText::savepoint("sp0", MsqlSavepointMode::Release)),
+            Ok(Msql::Savepoint(MsqlSavepoint::release("sp0")))
+        );
     }
 
     #[test]
@@ -795,6 +1333,18 @@ mod tests_msql {
         )
         .is_endtx());
         assert!(Msql::EndTx(MsqlEndTx::rollback()).is_endtx());
+        assert!(!Msql::Savepoint(MsqlSavepoint::savepoint("sp0")).is_endtx());
+    }
+
+    #[test]
+    fn test_is_savepoint() {
+        assert!(!Msql::BeginTx(MsqlBeginTx::default()).is_savepoint());
+        assert!(!Msql::EndTx(MsqlEndTx::commit()).is_savepoint());
+        assert!(Msql::Savepoint(MsqlSavepoint::savepoint("sp0")).is_savepoint());
+        let msql = Msql::Savepoint(MsqlSavepoint::rollback_to("sp0"));
+        let savepoint = msql.try_get_savepoint().unwrap();
+        assert_eq!(savepoint.name(), "sp0");
+        assert_eq!(savepoint.mode(), MsqlSavepointMode::RollbackTo);
     }
 }
 
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Enum representing the category of a `MsqlError`
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, strum::AsRefStr, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MsqlErrorKind {
+    /// The request is not allowed in the current state of the connection,
+    /// for example beginning a transaction while another one is not finished yet
+    Illegal,
+    /// The `TableOps` annotation is invalid, or does not match with the Sql or the `MsqlBeginTx`
+    Annotation,
+    /// The Sequencer cannot assign versions to the transaction, for example when it is blocked
+    Sequencer,
+    /// The Scheduler or the Dbproxy is not available to execute the request
+    Unavailable,
+    /// The DBMS failed to execute the Sql, the SQLSTATE is attached if available
+    Database,
+    /// The transaction was rolled back by the Scheduler, as it was idle or alive for too long
+    Timeout,
+    /// The request was cancelled by the client, or it was not done before its timeout
+    Cancelled,
+    /// Unexpected failure within the system
+    Internal,
+}
+
+/// The error of a `Msql` request
+///
+/// # Examples
+/// ```
+/// use o2versioner::core::{MsqlError, MsqlErrorKind};
+///
+/// let err = MsqlError::sequencer("Can't get a TxVN from Sequencer");
+/// assert_eq!(err.kind(), MsqlErrorKind::Sequencer);
+/// assert!(err.is_retryable());
+///
+/// let err = MsqlError::database("could not serialize access due to concurrent update", Some("40001"));
+/// assert_eq!(err.sqlstate(), Some("40001"));
+/// assert!(err.is_retryable());
+///
+/// let err = MsqlError::database("relation \"t0\" does not exist", Some("42P01"));
+/// assert!(!err.is_retryable());
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MsqlError {
+    kind: MsqlErrorKind,
+    message: String,
+    retryable: bool,
+    sqlstate: Option<String>,
+}
+
+impl fmt::Display for MsqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.sqlstate {
+            Some(sqlstate) => write!(f, "{} [{}]: {}", self.kind.as_ref(), sqlstate, self.message),
+            None => write!(f, "{}: {}", self.kind.as_ref(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for MsqlError {}
+
+impl MsqlError {
+    /// Create a new error, which is retryable only for `MsqlErrorKind::Sequencer` and `MsqlErrorKind::Unavailable`
+    pub fn new<S: Into<String>>(kind: MsqlErrorKind, message: S) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            retryable: matches!(kind, MsqlErrorKind::Sequencer | MsqlErrorKind::Unavailable),
+            sqlstate: None,
+        }
+    }
+
+    pub fn illegal<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Illegal, message)
+    }
+
+    pub fn annotation<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Annotation, message)
+    }
+
+    pub fn sequencer<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Sequencer, message)
+    }
+
+    pub fn unavailable<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Unavailable, message)
+    }
+
+    pub fn internal<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Internal, message)
+    }
+
+    pub fn timeout<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Timeout, message)
+    }
+
+    pub fn cancelled<S: Into<String>>(message: S) -> Self {
+        Self::new(MsqlErrorKind::Cancelled, message)
+    }
+
+    /// Create a new `MsqlErrorKind::Database` error,
+    /// which is retryable if the SQLSTATE is a serialization failure or a deadlock
+    pub fn database<S: Into<String>, C: Into<String>>(message: S, sqlstate: Option<C>) -> Self {
+        let sqlstate = sqlstate.map(|sqlstate| sqlstate.into());
+        Self {
+            kind: MsqlErrorKind::Database,
+            message: message.into(),
+            retryable: matches!(sqlstate.as_deref(), Some("40001") | Some("40P01")),
+            sqlstate,
+        }
+    }
+
+    /// Set whether the request can be retried, will overwrite previous value
+    pub fn set_retryable(mut self, retryable: bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn kind(&self) -> MsqlErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+
+    /// The five-character SQLSTATE code, if the error was produced by the DBMS
+    pub fn sqlstate(&self) -> Option<&str> {
+        self.sqlstate.as_deref()
+    }
+}
+
+/// Unit test for `MsqlError`
+#[cfg(test)]
+mod tests_msql_error {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_retryable() {
+        assert!(!MsqlError::illegal("a").is_retryable());
+        assert!(!MsqlError::annotation("a").is_retryable());
+        assert!(MsqlError::sequencer("a").is_retryable());
+        assert!(MsqlError::unavailable("a").is_retryable());
+        assert!(!MsqlError::internal("a").is_retryable());
+        assert!(!MsqlError::timeout("a").is_retryable());
+        assert!(!MsqlError::cancelled("a").is_retryable());
+        assert!(MsqlError::database("a", Some("40P01")).is_retryable());
+        assert!(!MsqlError::database("a", Option::<String>::None).is_retryable());
+        assert!(MsqlError::internal("a").set_retryable(true).is_retryable());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(MsqlError::annotation("bad").to_string(), "Annotation: bad");
+        assert_eq!(
+            MsqlError::database("bad", Some("42601")).to_string(),
+            "Database [42601]: bad"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let err = MsqlError::database("deadlock detected", Some("40P01"));
+        let err_json = serde_json::to_value(&err).unwrap();
+        assert_eq!(
+            err_json,
+            json!({
+                "kind": "database",
+                "message": "deadlock detected",
+                "retryable": true,
+                "sqlstate": "40P01"
+            })
+        );
+        assert_eq!(serde_json::from_value::<MsqlError>(err_json).unwrap(), err);
+    }
+}
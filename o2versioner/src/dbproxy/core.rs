@@ -1,7 +1,6 @@
-use crate::comm::MsqlResponse;
+use crate::comm::{MsqlResponse, MsqlResult};
 use crate::core::*;
 use async_trait::async_trait;
-use csv::Writer;
 use futures::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
@@ -9,7 +8,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::sync::Notify;
-use tokio_postgres::SimpleQueryMessage;
+use tokio_postgres::CancelToken;
 use tracing::debug;
 
 #[derive(Debug, Clone)]
@@ -30,31 +29,17 @@ impl QueueMessage {
         }
     }
 
-    pub fn into_sqlresponse(self, raw: Result<Vec<SimpleQueryMessage>, tokio_postgres::error::Error>) -> QueryResult {
-        let result;
-        let succeed;
-        let writer = PostgreToCsvWriter::new(self.operation_type.clone());
-        match raw {
-            Ok(message) => {
-                result = writer.to_csv(message);
-                succeed = true;
-            }
-            Err(err) => {
-                result = err.to_string();
-                succeed = false;
-            }
-        }
-
+    pub fn into_sqlresponse(self, result: Result<MsqlResult, MsqlError>) -> QueryResult {
         let result_type = match self.operation_type {
             Task::READ | Task::SINGLEREAD | Task::WRITE => QueryResultType::QUERY,
             Task::COMMIT | Task::ABORT => QueryResultType::END,
+            Task::SAVEPOINT => QueryResultType::SAVEPOINT,
         };
 
         QueryResult {
             identifier: self.identifier,
             result,
             result_type,
-            succeed,
             contained_newer_versions: self.versions,
             contained_early_release_version: self.msql.try_get_query().ok().and_then(|q| {
                 if q.has_early_release() {
@@ -91,13 +76,14 @@ impl PendingQueue {
                     assert!(!op.has_early_release());
                     Task::READ
                 }
-                AccessPattern::WriteOnly => Task::WRITE,
-                _ => panic!("Illegal access pattern"),
+                // Mixed queries are executed as writes, DbVN checks each table by its own operation
+                AccessPattern::WriteOnly | AccessPattern::Mixed => Task::WRITE,
             },
             Msql::EndTx(op) => match op.mode() {
                 MsqlEndTxMode::Commit => Task::COMMIT,
                 MsqlEndTxMode::Rollback => Task::ABORT,
             },
+            Msql::Savepoint(_) => Task::SAVEPOINT,
             _ => {panic!("Not defined operation")}
         };
 
@@ -172,7 +158,28 @@ impl PendingQueue {
         self.notify.clone()
     }
 
+    /// Remove the request if it is still waiting for its versions
+    ///
+    /// Only queries can be cancelled, as a commit, an abort or a savepoint must be applied
+    /// on all replicas alike once it is sent.
+    pub fn cancel(&mut self, identifier: &RequestMeta) -> Option<QueueMessage> {
+        let idx = self.queue.iter().position(|op| {
+            op.identifier == *identifier && matches!(op.operation_type, Task::READ | Task::SINGLEREAD | Task::WRITE)
+        })?;
+        debug!("PendingQueue cancelled {:?}", identifier);
+        Some(self.queue.remove(idx))
+    }
+
+    /// The requests still waiting for their versions, which are not being executed yet
+    pub fn waiting_requests(&self) -> Vec<RequestMeta> {
+        self.queue.iter().map(|op| op.identifier.clone()).collect()
+    }
+
     pub async fn get_all_version_ready_task(&mut self, version: Arc<Mutex<DbVersion>>) -> Vec<QueueMessage> {
+        if version.lock().await.is_restoring() {
+            return Vec::new();
+        }
+
         let mut ready_queue = Vec::new();
         let mut unready_queue = Vec::new();
         let mut tx_set = HashSet::new();
@@ -214,6 +221,7 @@ impl PendingQueue {
 
 pub struct DbVersion {
     pub db_version: DbVN,
+    restoring: bool,
     notify: Arc<Notify>,
 }
 
@@ -221,10 +229,27 @@ impl DbVersion {
     pub fn new(db_versions: DbVN) -> Self {
         Self {
             db_version: db_versions,
+            restoring: false,
             notify: Arc::new(Notify::new()),
         }
     }
 
+    /// Hold all tasks until `restored`, while the data is being restored
+    pub fn restoring(&mut self) {
+        self.restoring = true;
+    }
+
+    /// The data is restored to `db_version`, resume the held tasks
+    pub fn restored(&mut self, db_version: DbVN) {
+        self.db_version = db_version;
+        self.restoring = false;
+        self.notify.notify_one();
+    }
+
+    pub fn is_restoring(&self) -> bool {
+        self.restoring
+    }
+
     pub fn release_on_transaction(&mut self, transaction_version: TxVN) {
         self.release_on_request(transaction_version.into_dbvn_release_request());
     }
@@ -245,6 +270,31 @@ impl DbVersion {
     }
 }
 
+/// The requests being executed by postgres, which can be interrupted with a backend cancel
+#[derive(Default)]
+pub struct RunningRequests {
+    cancel_tokens: HashMap<RequestMeta, CancelToken>,
+}
+
+impl RunningRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self, identifier: RequestMeta, cancel_token: CancelToken) {
+        self.cancel_tokens.insert(identifier, cancel_token);
+    }
+
+    pub fn finish(&mut self, identifier: &RequestMeta) {
+        self.cancel_tokens.remove(identifier);
+    }
+
+    /// Returns `None` if the request is not being executed
+    pub fn get_cancel_token(&self, identifier: &RequestMeta) -> Option<CancelToken> {
+        self.cancel_tokens.get(identifier).cloned()
+    }
+}
+
 #[async_trait]
 trait Repository {
     async fn start_transaction(&mut self);
@@ -287,81 +337,19 @@ trait Repository {
 //     }
 // }
 
-pub struct PostgreToCsvWriter {
-    mode: Task,
-    wrt: Writer<Vec<u8>>,
-}
-
-#[derive(Serialize)]
-pub struct Row {
-    label: String,
-    value: String,
-}
-
-impl PostgreToCsvWriter {
-    pub fn new(mode: Task) -> Self {
-        let wrt = Writer::from_writer(vec![]);
-        Self { mode: mode, wrt: wrt }
-    }
-
-    pub fn to_csv(self, message: Vec<tokio_postgres::SimpleQueryMessage>) -> String {
-        match self.mode {
-            Task::READ | Task::SINGLEREAD => return self.convert_result_to_csv_string(message),
-            Task::WRITE => return self.generate_csv_string_with_header(message, "Affected rows".to_string()),
-            Task::COMMIT => return self.generate_csv_string_with_header(message, "Status".to_string()),
-            Task::ABORT => return self.generate_csv_string_with_header(message, "Status".to_string()),
-        }
-    }
-
-    pub fn convert_result_to_csv_string(mut self, message: Vec<tokio_postgres::SimpleQueryMessage>) -> String {
-        message.iter().for_each(|q_message| match q_message {
-            tokio_postgres::SimpleQueryMessage::Row(query_row) => {
-                let len = query_row.len();
-                let mut row: Vec<String> = Vec::new();
-                row.reserve(len);
-
-                for index in 0..len {
-                    row.push(format!("\"{}\"", query_row.get(index).unwrap()));
-                }
-
-                self.wrt.write_record(&row).unwrap();
-            }
-            _ => {}
-        });
-
-        String::from_utf8(self.wrt.into_inner().unwrap()).unwrap()
-    }
-
-    pub fn generate_csv_string_with_header(
-        mut self,
-        message: Vec<tokio_postgres::SimpleQueryMessage>,
-        header: String,
-    ) -> String {
-        self.wrt.write_record(vec![header]).unwrap();
-
-        message.iter().for_each(|q_message| match q_message {
-            tokio_postgres::SimpleQueryMessage::CommandComplete(status) => {
-                self.wrt.write_record(vec![status.clone().to_string()]).unwrap();
-            }
-            _ => {}
-        });
-
-        String::from_utf8(self.wrt.into_inner().unwrap()).unwrap()
-    }
-}
-
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Clone)]
 pub enum QueryResultType {
     BEGIN,
     QUERY,
     END,
+    SAVEPOINT,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct QueryResult {
     pub identifier: RequestMeta,
-    pub result: String,
-    pub succeed: bool,
+    pub result: Result<MsqlResult, MsqlError>,
     pub result_type: QueryResultType,
     pub contained_newer_versions: TxVN,
     pub contained_early_release_version: Option<EarlyReleaseTables>,
@@ -369,31 +357,13 @@ pub struct QueryResult {
 
 impl QueryResult {
     pub fn into_msql_response(self) -> MsqlResponse {
-        let message = match self.result_type {
-            QueryResultType::BEGIN => {
-                if self.succeed {
-                    MsqlResponse::begintx_ok()
-                } else {
-                    MsqlResponse::begintx_err(self.result)
-                }
-            }
-            QueryResultType::QUERY => {
-                if self.succeed {
-                    MsqlResponse::query_ok(self.result)
-                } else {
-                    MsqlResponse::query_err(self.result)
-                }
-            }
-            QueryResultType::END => {
-                if self.succeed {
-                    MsqlResponse::endtx_ok(self.result)
-                } else {
-                    MsqlResponse::endtx_err(self.result)
-                }
-            }
-        };
-
-        message
+        match self.result_type {
+            QueryResultType::BEGIN => MsqlResponse::BeginTx(self.result.map(|_| ())),
+            QueryResultType::QUERY => MsqlResponse::Query(self.result),
+            // Transaction ending and savepoints return no rows, so the csv rendering is enough
+            QueryResultType::END => MsqlResponse::EndTx(self.result.map(|result| result.to_csv())),
+            QueryResultType::SAVEPOINT => MsqlResponse::Savepoint(self.result.map(|result| result.to_csv())),
+        }
     }
 
     pub fn acquire_early_release(&mut self) -> Result<DbVNReleaseRequest, &'static str> {
@@ -404,6 +374,7 @@ impl QueryResult {
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Task {
     READ,
@@ -411,6 +382,7 @@ pub enum Task {
     WRITE,
     ABORT,
     COMMIT,
+    SAVEPOINT,
 }
 
 //================================Test================================//
@@ -486,9 +458,12 @@ pub enum Task {
 
 #[cfg(test)]
 mod tests {
+    use super::super::postgresdb::{self, execute_statement, ColumnCache};
     use super::*;
+    use crate::comm::MsqlColumn;
     use bb8::Pool;
     use bb8_postgres::PostgresConnectionManager;
+    use serde_json::json;
     use tokio_postgres::NoTls;
 
     #[test]
@@ -510,29 +485,19 @@ mod tests {
 
             let conn = pool.get().await.unwrap();
             conn.simple_query("START TRANSACTION;").await.unwrap();
-            let result = conn
-                .simple_query(
-                    "INSERT INTO tbltest (name, age, designation, salary) VALUES ('haha', 100, 'Manager', 99999)",
-                )
-                .await
-                .unwrap();
+            let result = execute_statement(
+                &*conn,
+                &ColumnCache::default(),
+                "INSERT INTO tbltest (name, age, designation, salary) VALUES ('haha', 100, 'Manager', 99999)",
+                &[],
+            )
+            .await
+            .unwrap();
             conn.simple_query("COMMIT;").await.unwrap();
 
-            result.iter().for_each(|q_message| match q_message {
-                tokio_postgres::SimpleQueryMessage::Row(query_row) => {
-                    let len = query_row.len();
-                    for index in 0..len {
-                        println!("value is : {}", query_row.get(index).unwrap().to_string());
-                    }
-                }
-                tokio_postgres::SimpleQueryMessage::CommandComplete(complete_status) => {
-                    println!("Command result is : {}", complete_status);
-                }
-                _ => {}
-            });
+            println!("Affected rows is : {:?}", result.affected_rows());
 
-            let writer = PostgreToCsvWriter::new(Task::WRITE);
-            let csv = writer.to_csv(result);
+            let csv = result.to_csv();
 
             println!("Converted string is: {}", csv);
         });
@@ -555,28 +520,90 @@ mod tests {
             let pool = Pool::builder().max_size(size).build(manager).await.unwrap();
 
             let conn = pool.get().await.unwrap();
-            let result = conn.simple_query("SELECT * FROM address LIMIT 10;").await.unwrap();
-
-            result.iter().for_each(|q_message| match q_message {
-                tokio_postgres::SimpleQueryMessage::Row(query_row) => {
-                    let len = query_row.len();
-                    for index in 0..len {
-                        println!("value is : {}", query_row.get(index).unwrap().to_string());
-                    }
-                }
-                tokio_postgres::SimpleQueryMessage::CommandComplete(complete_status) => {
-                    println!("Command result is : {}", complete_status);
-                }
-                _ => {}
+            let result = execute_statement(&*conn, &ColumnCache::default(), "SELECT * FROM address LIMIT 10;", &[])
+                .await
+                .unwrap();
+
+            result.rows().iter().for_each(|row| {
+                row.iter().for_each(|value| println!("value is : {}", value));
             });
 
-            let writer = PostgreToCsvWriter::new(Task::READ);
-            let csv = writer.to_csv(result);
+            let csv = result.to_csv();
 
             println!("Converted string is: {}", csv);
         });
     }
 
+    #[tokio::test]
+    #[ignore]
+    async fn postgres_column_cache_test() {
+        let pool = postgresdb::connect("host=localhost port=5432 dbname=Test user=postgres password=Abc@123", 1).await;
+        let conn = pool.get().await.unwrap();
+        let columns = ColumnCache::default();
+
+        // Prepared along the first time, and then taken from the cache
+        let query = "SELECT 1::int4 AS a, 'x'::text AS b, '1 day'::interval AS c;";
+        let result = execute_statement(&*conn, &columns, query, &[]).await.unwrap();
+        assert_eq!(
+            result.columns(),
+            &[
+                MsqlColumn::new("a", "int4"),
+                MsqlColumn::new("b", "text"),
+                MsqlColumn::new("c", "interval")
+            ]
+        );
+        assert_eq!(result.rows(), &[vec![json!(1), json!("x"), json!("1 day")]]);
+        assert_eq!(execute_statement(&*conn, &columns, query, &[]).await.unwrap(), result);
+
+        // Columns are known without any row
+        let query = "SELECT 1::int8 AS a WHERE false;";
+        for _ in 0..2 {
+            let result = execute_statement(&*conn, &columns, query, &[]).await.unwrap();
+            assert_eq!(result.columns(), &[MsqlColumn::new("a", "int8")]);
+            assert!(result.rows().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn postgres_dump_restore_test() {
+        let pool = postgresdb::connect("host=localhost port=5432 dbname=Test user=postgres password=Abc@123", 2).await;
+        pool.get()
+            .await
+            .unwrap()
+            .batch_execute(
+                r#"DROP TABLE IF EXISTS "Dump ""t0""";
+                CREATE TABLE "Dump ""t0""" (id serial PRIMARY KEY, "Span" interval, note text, doc jsonb);
+                INSERT INTO "Dump ""t0""" ("Span", note, doc)
+                VALUES ('1 day', E'a\tb\\c\nd', '"s"'), (NULL, NULL, '[1, true]');"#,
+            )
+            .await
+            .unwrap();
+
+        let table = String::from(r#"Dump "t0""#);
+        let snapshot = postgresdb::dump(pool.clone(), vec![table.clone()]).await.unwrap();
+        assert_eq!(snapshot.tables[&table].rows().len(), 2);
+        assert_eq!(snapshot.sequences.values().collect::<Vec<_>>(), vec![&Some(2)]);
+
+        // Diverged, and then restored
+        pool.get()
+            .await
+            .unwrap()
+            .batch_execute(r#"DELETE FROM "Dump ""t0"""; INSERT INTO "Dump ""t0""" (note) VALUES ('x');"#)
+            .await
+            .unwrap();
+        postgresdb::restore(pool.clone(), snapshot.clone()).await.unwrap();
+        assert_eq!(postgresdb::dump(pool.clone(), vec![table]).await.unwrap(), snapshot);
+
+        // The serial column goes on from the restored sequence
+        let conn = pool.get().await.unwrap();
+        let result = execute_statement(&*conn, &ColumnCache::default(), r#"INSERT INTO "Dump ""t0""" (note) VALUES ('y') RETURNING id;"#, &[])
+            .await
+            .unwrap();
+        assert_eq!(result.rows(), &[vec![json!(3)]]);
+        conn.batch_execute(r#"DROP TABLE "Dump ""t0""";"#).await.unwrap();
+    }
+
     #[tokio::test]
     #[ignore]
     async fn pending_queue_task_order_test() {
@@ -767,4 +794,91 @@ mod tests {
         println!("Number of tasks is: {}", ready_tasks.len());
         assert!(ready_tasks.len() == 4);
     }
+
+    #[tokio::test]
+    async fn pending_queue_restoring_test() {
+        let dbversion = Arc::new(Mutex::new(DbVersion::new(Default::default())));
+        let mut queue = PendingQueue::new();
+        let table0_dbvn = |vn| DbVN::from(vec![(String::from("table0"), vn)].into_iter().collect::<HashMap<_, _>>());
+
+        queue.push(QueueMessage::new(
+            RequestMeta {
+                client_addr: "127.0.0.1:8080".parse().unwrap(),
+                cur_txid: 0,
+                request_id: 0,
+            },
+            Task::WRITE,
+            Msql::Query(
+                MsqlQuery::new(
+                    "update table0 set a = 1",
+                    TableOps::from("WRITE table0"),
+                    EarlyReleaseTables::default(),
+                )
+                .unwrap(),
+            ),
+            TxVN::new().set_txtablevns(vec![TxTableVN::new("table0", 3, RWOperation::W)]),
+        ));
+
+        // Held while restoring, even though the version is ready
+        dbversion.lock().await.db_version = table0_dbvn(3);
+        dbversion.lock().await.restoring();
+        assert!(queue.get_all_version_ready_task(dbversion.clone()).await.is_empty());
+
+        // Still waits on the restored version
+        dbversion.lock().await.restored(table0_dbvn(2));
+        assert!(queue.get_all_version_ready_task(dbversion.clone()).await.is_empty());
+
+        // Ready once restored to the version
+        dbversion.lock().await.restoring();
+        dbversion.lock().await.restored(table0_dbvn(3));
+        assert!(!dbversion.lock().await.is_restoring());
+        assert_eq!(queue.get_all_version_ready_task(dbversion).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pending_queue_cancel_test() {
+        let dbversion = Arc::new(Mutex::new(DbVersion::new(Default::default())));
+        let mut queue = PendingQueue::new();
+        let meta = |request_id| RequestMeta {
+            client_addr: "127.0.0.1:8080".parse().unwrap(),
+            cur_txid: 0,
+            request_id,
+        };
+        let write = |request_id| {
+            QueueMessage::new(
+                meta(request_id),
+                Task::WRITE,
+                Msql::Query(
+                    MsqlQuery::new(
+                        "update table0 set a = 1",
+                        TableOps::from("WRITE table0"),
+                        EarlyReleaseTables::default(),
+                    )
+                    .unwrap(),
+                ),
+                TxVN::new().set_txtablevns(vec![TxTableVN::new("table0", request_id as u64, RWOperation::W)]),
+            )
+        };
+
+        // Both are waiting for their versions
+        queue.push(write(1));
+        queue.push(write(2));
+        assert!(queue.get_all_version_ready_task(dbversion.clone()).await.is_empty());
+
+        let cancelled = queue.cancel(&meta(1)).unwrap();
+        assert_eq!(cancelled.identifier, meta(1));
+        assert!(queue.cancel(&meta(1)).is_none());
+        assert_eq!(queue.queue.len(), 1);
+        assert_eq!(queue.queue[0].identifier, meta(2));
+
+        // A commit waiting for its versions is never cancelled
+        queue.push(QueueMessage::new(
+            meta(3),
+            Task::COMMIT,
+            Msql::EndTx(MsqlEndTx::commit()),
+            TxVN::new().set_txtablevns(vec![TxTableVN::new("table0", 3, RWOperation::W)]),
+        ));
+        assert!(queue.cancel(&meta(3)).is_none());
+        assert_eq!(queue.queue.len(), 2);
+    }
 }
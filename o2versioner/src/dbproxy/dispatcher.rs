@@ -1,4 +1,4 @@
-use super::core::{DbVersion, PendingQueue, QueryResult, QueueMessage, Task};
+use super::core::{DbVersion, PendingQueue, QueryResult, QueueMessage, RunningRequests, Task};
 use super::mockdb;
 use super::postgresdb;
 use crate::util::conf::DbProxyConf;
@@ -8,7 +8,6 @@ use bb8_postgres::bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
 use futures::prelude::*;
 use std::collections::HashMap;
-use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
@@ -29,8 +28,12 @@ pub struct Dispatcher {
     pending_queue: Arc<Mutex<PendingQueue>>,
     responder_sender: mpsc::Sender<QueryResult>,
     conf: DbProxyConf,
+    pool_opt: Option<Pool<PostgresConnectionManager<NoTls>>>,
+    /// Shared by all connections of the pool
+    columns: postgresdb::ColumnCache,
     version: Arc<Mutex<DbVersion>>,
     transactions: Arc<Mutex<HashMap<Uuid, mpsc::Sender<QueueMessage>>>>,
+    running_requests: Arc<Mutex<RunningRequests>>,
     stop_receiver: Option<oneshot::Receiver<()>>,
 }
 
@@ -39,8 +42,10 @@ impl Dispatcher {
         pending_queue: Arc<Mutex<PendingQueue>>,
         responder_sender: mpsc::Sender<QueryResult>,
         conf: DbProxyConf,
+        pool_opt: Option<Pool<PostgresConnectionManager<NoTls>>>,
         version: Arc<Mutex<DbVersion>>,
         transactions: Arc<Mutex<HashMap<Uuid, mpsc::Sender<QueueMessage>>>>,
+        running_requests: Arc<Mutex<RunningRequests>>,
     ) -> (oneshot::Sender<()>, Self) {
         let (sender, receiver) = oneshot::channel();
 
@@ -50,8 +55,11 @@ impl Dispatcher {
                 pending_queue,
                 responder_sender,
                 conf,
+                pool_opt,
+                columns: Default::default(),
                 version,
                 transactions,
+                running_requests,
                 stop_receiver: Some(receiver),
             },
         )
@@ -71,29 +79,13 @@ impl Dispatcher {
 impl Executor for Dispatcher {
     #[instrument(name = "dispatcher", skip(self))]
     async fn run(mut self: Box<Self>) {
-        let postgres_pool_size = 100;
         let transaction_channel_queue_size = 100;
         info!("started");
 
         let mut task_notify = self.pending_queue.lock().await.get_notify();
         let mut version_notify = self.version.lock().await.get_notify();
 
-        let pool_opt = if let Some(sql_conf) = &self.conf.sql_conf {
-            info!("Connecting to db with: {}", sql_conf);
-            Some(
-                Pool::builder()
-                    .max_size(postgres_pool_size)
-                    .build(PostgresConnectionManager::new(
-                        tokio_postgres::Config::from_str(sql_conf).unwrap(),
-                        NoTls,
-                    ))
-                    .await
-                    .unwrap(),
-            )
-        } else {
-            info!("Using mocked db");
-            None
-        };
+        let pool_opt = self.pool_opt.take();
 
         if let Some(db_mock_latency) = self.conf.db_mock_latency.as_ref() {
             info!("Using mocked db latency: {:?}", db_mock_latency);
@@ -132,8 +124,10 @@ impl Executor for Dispatcher {
                 .for_each(|op| {
                     let mock_db_latency_provider_opt_cloned = mock_db_latency_provider_opt.clone();
                     let pool_opt_cloned = pool_opt.clone();
+                    let columns_cloned = self.columns.clone();
                     let responder_sender_cloned = self.responder_sender.clone();
                     let transactions_cloned = self.transactions.clone();
+                    let running_requests_cloned = self.running_requests.clone();
                     async move {
                         match op.operation_type {
                             Task::SINGLEREAD => {
@@ -143,8 +137,10 @@ impl Executor for Dispatcher {
                                         transaction_uuid,
                                         op.identifier.to_client_meta(),
                                         pool,
+                                        columns_cloned,
                                         op.clone(),
                                         responder_sender_cloned,
+                                        running_requests_cloned,
                                     ))
                                 } else {
                                     let transaction_uuid = op.versions.uuid().clone();
@@ -175,7 +171,9 @@ impl Executor for Dispatcher {
                                                         op.identifier.to_client_meta(),
                                                         transaction_channel_queue_size,
                                                         pool,
+                                                        columns_cloned,
                                                         responder_sender_cloned,
+                                                        running_requests_cloned,
                                                     );
                                                 (transaction_tx, Box::new(transaction_executor))
                                             } else {
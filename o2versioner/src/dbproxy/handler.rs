@@ -1,5 +1,6 @@
-use super::core::{DbVersion, PendingQueue};
+use super::core::{DbVersion, PendingQueue, RunningRequests};
 use super::dispatcher::Dispatcher;
+use super::postgresdb;
 use super::transceiver;
 use crate::util::conf::DbProxyConf;
 use crate::util::executor::Executor;
@@ -9,6 +10,8 @@ use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 use tracing::{field, info, instrument, Instrument, Span};
 
+const POSTGRES_POOL_SIZE: u32 = 100;
+
 /// Main entrance for the DbProxy
 #[instrument(name = "dbproxy", skip(conf), fields(message=field::Empty))]
 pub async fn main(conf: DbProxyConf) {
@@ -23,22 +26,44 @@ pub async fn main(conf: DbProxyConf) {
     // PendingQueue
     let pending_queue = Arc::new(Mutex::new(PendingQueue::new()));
 
+    // Requests being executed by postgres, shared by dispatcher and receiver for cancelling them
+    let running_requests = Arc::new(Mutex::new(RunningRequests::new()));
+
     // Responder sender and receiver
     let (responder_sender, responder_receiver) = mpsc::channel(100);
 
+    // Db connection pool, shared by dispatcher and receiver
+    let pool_opt = if let Some(sql_conf) = &conf.sql_conf {
+        info!("Connecting to db with: {}", sql_conf);
+        Some(postgresdb::connect(sql_conf, POSTGRES_POOL_SIZE).await)
+    } else {
+        info!("Using mocked db");
+        None
+    };
+
     // Dispatcher
     let (dispatcher_stopper, dispatcher) = Dispatcher::new(
         pending_queue.clone(),
-        responder_sender,
+        responder_sender.clone(),
         conf.clone(),
+        pool_opt.clone(),
         version.clone(),
         transactions.clone(),
+        running_requests.clone(),
     );
     let dispatcher_handle = tokio::spawn(Box::new(dispatcher).run().in_current_span());
 
     // Receiver and responder
-    let (receiver, responder) =
-        transceiver::connection(conf.addr, pending_queue.clone(), responder_receiver, version).await;
+    let (receiver, responder) = transceiver::connection(
+        conf.addr,
+        pending_queue.clone(),
+        responder_sender,
+        responder_receiver,
+        version,
+        pool_opt,
+        running_requests,
+    )
+    .await;
     let responder_handle = tokio::spawn(Box::new(responder).run().in_current_span());
     let receiver_handle = tokio::spawn(Box::new(receiver).run().in_current_span());
 
@@ -1,4 +1,6 @@
 use super::core::{QueryResult, QueueMessage, Task};
+use crate::comm::scheduler_dbproxy::DbSnapshot;
+use crate::comm::{MsqlColumn, MsqlResult};
 use crate::core::*;
 use crate::util::conf::{DbMockLatency, LatencyDistr};
 use crate::util::executor::Executor;
@@ -85,6 +87,26 @@ impl DbMockLatencyProvider {
     }
 }
 
+/// There is no data without a real DBMS, so every table is dumped as empty
+pub async fn dump(tables: Vec<String>) -> Result<DbSnapshot, MsqlError> {
+    info!("Dumped {} tables", tables.len());
+    Ok(DbSnapshot {
+        tables: tables.into_iter().map(|table| (table, MsqlResult::default())).collect(),
+        ..Default::default()
+    })
+}
+
+/// There is no schema without a real DBMS, so every query is described without columns
+pub async fn describe(_sql: String) -> Result<Vec<MsqlColumn>, MsqlError> {
+    Ok(Vec::new())
+}
+
+/// There is no data to restore without a real DBMS
+pub async fn restore(snapshot: DbSnapshot) -> Result<(), MsqlError> {
+    info!("Restored {} tables", snapshot.tables.len());
+    Ok(())
+}
+
 /// Executes a single Sql read query without transaction without a real DBMS
 pub struct SingleReadExecutor {
     transaction_uuid: Uuid,
@@ -122,7 +144,7 @@ impl Executor for SingleReadExecutor {
         }
         info!("Deploying {}", self.transaction_uuid);
         self.responder_sender
-            .send(self.operation.into_sqlresponse(Ok(Vec::new())))
+            .send(self.operation.into_sqlresponse(Ok(MsqlResult::default())))
             .await
             .map_err(|e| e.to_string())
             .unwrap();
@@ -178,34 +200,70 @@ impl Executor for TransactionExecutor {
         // Default is rollback
         let mut is_finished = false;
         let mut is_ended_by_rollback = true;
+        // Mimic the savepoint stack of a real DBMS,
+        // after an error only a valid rollback to savepoint or the end of transaction is accepted
+        let mut savepoints: Vec<String> = Vec::new();
+        let mut is_aborted = false;
         while let Some(operation) = self.transaction_listener.recv().await {
             let raw = match operation.operation_type {
                 Task::READ => {
                     if let Some(latency) = self.latency_provider.as_ref() {
                         latency.wait_read().await;
                     }
-                    Ok(vec![])
+                    check_not_aborted(is_aborted).map(|_| MsqlResult::default())
                 }
                 Task::WRITE => {
                     if let Some(latency) = self.latency_provider.as_ref() {
                         latency.wait_write().await;
                     }
-                    Ok(vec![])
+                    check_not_aborted(is_aborted).map(|_| MsqlResult::default())
+                }
+                Task::SAVEPOINT => {
+                    if let Some(latency) = self.latency_provider.as_ref() {
+                        latency.wait_write().await;
+                    }
+                    let savepoint = operation
+                        .msql
+                        .try_get_savepoint()
+                        .expect("Task::SAVEPOINT must come with Msql::Savepoint");
+                    let found = savepoints.iter().rposition(|name| name == savepoint.name());
+                    let res = match (savepoint.mode(), found) {
+                        (MsqlSavepointMode::Savepoint, _) => check_not_aborted(is_aborted).map(|_| {
+                            savepoints.push(savepoint.name().to_owned());
+                        }),
+                        (MsqlSavepointMode::RollbackTo, Some(idx)) => {
+                            savepoints.truncate(idx + 1);
+                            is_aborted = false;
+                            Ok(())
+                        }
+                        (MsqlSavepointMode::Release, Some(idx)) => check_not_aborted(is_aborted).map(|_| {
+                            savepoints.truncate(idx);
+                        }),
+                        (_, None) => Err(MsqlError::database(
+                            format!("savepoint \"{}\" does not exist", savepoint.name()),
+                            Some("3B001"),
+                        )),
+                    };
+                    if res.is_err() {
+                        is_aborted = true;
+                    }
+                    res.map(|_| MsqlResult::default())
                 }
                 Task::COMMIT => {
                     if let Some(latency) = self.latency_provider.as_ref() {
                         latency.wait_endtx().await;
                     }
                     is_finished = true;
-                    is_ended_by_rollback = false;
-                    Ok(vec![])
+                    // Committing an aborted transaction ends up as a rollback
+                    is_ended_by_rollback = is_aborted;
+                    Ok(MsqlResult::default())
                 }
                 Task::ABORT => {
                     if let Some(latency) = self.latency_provider.as_ref() {
                         latency.wait_endtx().await;
                     }
                     is_finished = true;
-                    Ok(vec![])
+                    Ok(MsqlResult::default())
                 }
                 _ => panic!("Unexpected operation type"),
             };
@@ -230,3 +288,15 @@ impl Executor for TransactionExecutor {
         );
     }
 }
+
+/// Errors if the mocked transaction has been aborted by a previous failure
+fn check_not_aborted(is_aborted: bool) -> Result<(), MsqlError> {
+    if is_aborted {
+        Err(MsqlError::database(
+            "current transaction is aborted, commands ignored until end of transaction block",
+            Some("25P02"),
+        ))
+    } else {
+        Ok(())
+    }
+}
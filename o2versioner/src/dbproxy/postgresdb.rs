@@ -1,21 +1,47 @@
-use super::core::{QueryResult, QueueMessage, Task};
+use super::core::{QueryResult, QueueMessage, RunningRequests, Task};
+use crate::comm::scheduler_dbproxy::DbSnapshot;
+use crate::comm::{MsqlColumn, MsqlResult};
 use crate::core::*;
 use crate::util::executor::Executor;
+use crate::util::sql_types::{SqlParam, SqlValue};
 use async_trait::async_trait;
 use bb8_postgres::bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
+use bytes::Bytes;
+use futures::pin_mut;
+use futures::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio_postgres::NoTls;
-use tracing::{field, info, instrument, Span};
+use tokio::sync::Mutex;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{CancelToken, Client, GenericClient, IsolationLevel, NoTls, SimpleQueryMessage, Transaction};
+use tracing::{field, info, instrument, warn, Span};
 use uuid::Uuid;
 
+/// Creates a connection pool to the postgre DBMS configured by `sql_conf`
+pub async fn connect(sql_conf: &str, pool_size: u32) -> Pool<PostgresConnectionManager<NoTls>> {
+    Pool::builder()
+        .max_size(pool_size)
+        .build(PostgresConnectionManager::new(
+            tokio_postgres::Config::from_str(sql_conf).unwrap(),
+            NoTls,
+        ))
+        .await
+        .unwrap()
+}
+
 /// Executes a Sql transaction with a postgre DBMS
 pub struct TransactionExecutor {
     transaction_uuid: Uuid,
     client_meta: ClientMeta,
     transaction_listener: mpsc::Receiver<QueueMessage>,
     pool: Pool<PostgresConnectionManager<NoTls>>,
+    columns: ColumnCache,
     responder_sender: mpsc::Sender<QueryResult>,
+    running_requests: Arc<Mutex<RunningRequests>>,
 }
 
 impl TransactionExecutor {
@@ -24,7 +50,9 @@ impl TransactionExecutor {
         client_meta: ClientMeta,
         transaction_channel_queue_size: usize,
         pool: Pool<PostgresConnectionManager<NoTls>>,
+        columns: ColumnCache,
         responder_sender: mpsc::Sender<QueryResult>,
+        running_requests: Arc<Mutex<RunningRequests>>,
     ) -> (mpsc::Sender<QueueMessage>, Self) {
         let (transaction_tx, transaction_listener) = mpsc::channel(transaction_channel_queue_size);
 
@@ -35,7 +63,9 @@ impl TransactionExecutor {
                 client_meta,
                 transaction_listener,
                 pool,
+                columns,
                 responder_sender,
+                running_requests,
             },
         )
     }
@@ -57,22 +87,33 @@ impl Executor for TransactionExecutor {
         while let Some(operation) = self.transaction_listener.recv().await {
             let raw = match operation.operation_type {
                 Task::READ | Task::WRITE => {
-                    transc
-                        .as_ref()
-                        .unwrap()
-                        .simple_query(&MsqlFinalString::from(operation.msql.clone()).into_inner())
+                    let query = operation.msql.try_get_query().unwrap();
+                    let transc = transc.as_ref().unwrap();
+                    self.running_requests
+                        .lock()
                         .await
+                        .start(operation.identifier.clone(), transc.cancel_token());
+                    let raw = execute_statement(transc, &self.columns, query.query(), query.params()).await;
+                    self.running_requests.lock().await.finish(&operation.identifier);
+                    raw
                 }
+                // Savepoints are executed on the open transaction as well
+                Task::SAVEPOINT => transc
+                    .as_ref()
+                    .unwrap()
+                    .batch_execute(&MsqlFinalString::from(operation.msql.clone()).into_inner())
+                    .await
+                    .map(|_| MsqlResult::default()),
                 Task::COMMIT => {
                     is_ended_by_rollback = false;
-                    transc.take().unwrap().commit().await.map(|_| Vec::new())
+                    transc.take().unwrap().commit().await.map(|_| MsqlResult::default())
                 }
-                Task::ABORT => transc.take().unwrap().rollback().await.map(|_| Vec::new()),
+                Task::ABORT => transc.take().unwrap().rollback().await.map(|_| MsqlResult::default()),
                 _ => panic!("Unexpected operation type"),
             };
 
             self.responder_sender
-                .send(operation.into_sqlresponse(raw))
+                .send(operation.into_sqlresponse(raw.map_err(into_msql_error)))
                 .await
                 .map_err(|e| e.to_string())
                 .unwrap();
@@ -97,8 +138,10 @@ pub struct SingleReadExecutor {
     transaction_uuid: Uuid,
     client_meta: ClientMeta,
     pool: Pool<PostgresConnectionManager<NoTls>>,
+    columns: ColumnCache,
     operation: QueueMessage,
     responder_sender: mpsc::Sender<QueryResult>,
+    running_requests: Arc<Mutex<RunningRequests>>,
 }
 
 impl SingleReadExecutor {
@@ -106,15 +149,19 @@ impl SingleReadExecutor {
         transaction_uuid: Uuid,
         client_meta: ClientMeta,
         pool: Pool<PostgresConnectionManager<NoTls>>,
+        columns: ColumnCache,
         operation: QueueMessage,
         responder_sender: mpsc::Sender<QueryResult>,
+        running_requests: Arc<Mutex<RunningRequests>>,
     ) -> Self {
         Self {
             transaction_uuid,
             client_meta,
             pool,
+            columns,
             operation,
             responder_sender,
+            running_requests,
         }
     }
 }
@@ -127,14 +174,357 @@ impl Executor for SingleReadExecutor {
         let conn = self.pool.get().await.unwrap();
         info!("Deploying {}", self.transaction_uuid);
 
-        let raw = conn
-            .simple_query(&MsqlFinalString::from(self.operation.msql.clone()).into_inner())
-            .await;
+        let query = self.operation.msql.try_get_query().unwrap();
+        self.running_requests
+            .lock()
+            .await
+            .start(self.operation.identifier.clone(), conn.cancel_token());
+        let raw = execute_statement(&*conn, &self.columns, query.query(), query.params()).await;
+        self.running_requests.lock().await.finish(&self.operation.identifier);
 
         self.responder_sender
-            .send(self.operation.into_sqlresponse(raw))
+            .send(self.operation.into_sqlresponse(raw.map_err(into_msql_error)))
             .await
             .map_err(|e| e.to_string())
             .unwrap();
     }
 }
+
+/// Interrupts the statement being executed by the postgres backend of `cancel_token`,
+/// which then fails with the SQLSTATE `57014`
+pub async fn cancel(cancel_token: CancelToken) {
+    if let Err(e) = cancel_token.cancel_query(NoTls).await {
+        warn!("Cannot cancel the query: {}", e);
+    }
+}
+
+/// Reads all rows of the `tables`, and the last values of the sequences they own,
+/// within a single read-only transaction
+pub async fn dump(pool: Pool<PostgresConnectionManager<NoTls>>, tables: Vec<String>) -> Result<DbSnapshot, MsqlError> {
+    let mut conn = pool.get().await.map_err(|e| MsqlError::unavailable(e.to_string()))?;
+    let transc = conn
+        .build_transaction()
+        .isolation_level(IsolationLevel::RepeatableRead)
+        .read_only(true)
+        .start()
+        .await
+        .map_err(into_msql_error)?;
+
+    let columns = ColumnCache::default();
+    let mut snapshot = DbSnapshot::default();
+    for table in tables {
+        let select = format!("SELECT * FROM {};", quote_ident(&table));
+        let result = execute_statement(&transc, &columns, &select, &[])
+            .await
+            .map_err(into_msql_error)?;
+        // Sequences owned by the columns, such as of `serial` and identity columns
+        let sequences = transc
+            .query(
+                "SELECT format('%I.%I', seq.schemaname, seq.sequencename), seq.last_value \
+                FROM pg_depend dep \
+                JOIN pg_class cls ON cls.oid = dep.objid AND cls.relkind = 'S' \
+                JOIN pg_namespace nsp ON nsp.oid = cls.relnamespace \
+                JOIN pg_sequences seq ON seq.schemaname = nsp.nspname AND seq.sequencename = cls.relname \
+                WHERE dep.classid = 'pg_class'::regclass AND dep.refclassid = 'pg_class'::regclass \
+                AND dep.deptype IN ('a', 'i') AND dep.refobjid = $1::text::regclass;",
+                &[&quote_ident(&table)],
+            )
+            .await
+            .map_err(into_msql_error)?;
+        snapshot
+            .sequences
+            .extend(sequences.into_iter().map(|row| (row.get(0), row.get(1))));
+        snapshot.tables.insert(table, result);
+    }
+    transc.commit().await.map_err(into_msql_error)?;
+
+    info!(
+        "Dumped {} tables, {} sequences",
+        snapshot.tables.len(),
+        snapshot.sequences.len()
+    );
+    Ok(snapshot)
+}
+
+/// Prepares `sql` without executing it, returning its columns
+pub async fn describe(pool: Pool<PostgresConnectionManager<NoTls>>, sql: String) -> Result<Vec<MsqlColumn>, MsqlError> {
+    let conn = pool.get().await.map_err(|e| MsqlError::unavailable(e.to_string()))?;
+    let statement = conn.prepare(&sql).await.map_err(into_msql_error)?;
+    Ok(statement
+        .columns()
+        .iter()
+        .map(|column| MsqlColumn::new(column.name(), column.type_().name()))
+        .collect())
+}
+
+/// Replaces all rows of the tables in `snapshot`, and the last values of their sequences,
+/// within a single transaction
+///
+/// The tables must already exist, and they are truncated together before copying the rows in the text format,
+/// so that columns of any type are restored. The tables are restored one after another,
+/// so a foreign key between them fails the restore if the referencing table is restored first.
+pub async fn restore(pool: Pool<PostgresConnectionManager<NoTls>>, snapshot: DbSnapshot) -> Result<(), MsqlError> {
+    let mut conn = pool.get().await.map_err(|e| MsqlError::unavailable(e.to_string()))?;
+    let transc = conn.transaction().await.map_err(into_msql_error)?;
+
+    if !snapshot.tables.is_empty() {
+        // Sequences not used yet in the snapshot are restarted along
+        let tables: Vec<_> = snapshot.tables.keys().map(|table| quote_ident(table)).collect();
+        transc
+            .batch_execute(&format!("TRUNCATE {} RESTART IDENTITY;", tables.join(", ")))
+            .await
+            .map_err(into_msql_error)?;
+    }
+    for (table, result) in snapshot.tables.iter().filter(|(_, result)| !result.rows().is_empty()) {
+        let columns: Vec<_> = result
+            .columns()
+            .iter()
+            .map(|column| quote_ident(column.name()))
+            .collect();
+        let rows: String = result
+            .rows()
+            .iter()
+            .map(|row| copy_row(result.columns(), row))
+            .collect();
+        let sink = transc
+            .copy_in(&format!("COPY {} ({}) FROM STDIN;", quote_ident(table), columns.join(", "))[..])
+            .await
+            .map_err(into_msql_error)?;
+        pin_mut!(sink);
+        sink.send(Bytes::from(rows)).await.map_err(into_msql_error)?;
+        sink.finish().await.map_err(into_msql_error)?;
+    }
+    for (sequence, last_value) in snapshot.sequences.iter() {
+        if let Some(last_value) = last_value {
+            transc
+                .execute("SELECT setval($1::text::regclass, $2);", &[sequence, last_value])
+                .await
+                .map_err(into_msql_error)?;
+        }
+    }
+    transc.commit().await.map_err(into_msql_error)?;
+
+    info!(
+        "Restored {} tables, {} sequences",
+        snapshot.tables.len(),
+        snapshot.sequences.len()
+    );
+    Ok(())
+}
+
+/// Quotes a table or column name, so that it is taken as is
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Renders a row in the text format of `COPY`, where `NULL` is `\N`
+///
+/// Values of `json` and `jsonb` columns are rendered as Json, so that a Json string keeps its quotes.
+fn copy_row(columns: &[MsqlColumn], row: &[Value]) -> String {
+    let fields: Vec<_> = columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| {
+            let text = match value {
+                Value::Null => return String::from("\\N"),
+                _ if column.type_name() == "json" || column.type_name() == "jsonb" => value.to_string(),
+                Value::String(s) => s.clone(),
+                Value::Bool(b) => String::from(if *b { "t" } else { "f" }),
+                other => other.to_string(),
+            };
+            text.replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        })
+        .collect();
+    fields.join("\t") + "\n"
+}
+
+/// Converts an error of the DBMS into a `MsqlError`, with the SQLSTATE if the DBMS reported one
+///
+/// A lost connection to the DBMS is `MsqlErrorKind::Unavailable` instead, which is retryable.
+fn into_msql_error(e: tokio_postgres::Error) -> MsqlError {
+    if e.code().is_none() && is_connection_error(&e) {
+        MsqlError::unavailable(e.to_string())
+    } else {
+        MsqlError::database(e.to_string(), e.code().map(|code| code.code()))
+    }
+}
+
+/// Check whether the connection to the DBMS is closed or broken,
+/// rather than the Sql or its parameters being invalid
+fn is_connection_error(e: &tokio_postgres::Error) -> bool {
+    // tokio_postgres does not expose the kind of its errors, only their rendering and their cause
+    e.to_string() == "connection closed"
+        || matches!(std::error::Error::source(e), Some(cause) if cause.is::<std::io::Error>())
+}
+
+/// At most this many statements have their columns cached, the cache is cleared once full
+const COLUMN_CACHE_SIZE: usize = 1024;
+
+/// Names and types of the columns of a statement
+type StatementColumns = Arc<[(String, Type)]>;
+
+/// Names and types of the columns of the statements executed so far, by their Sql,
+/// so that a statement without parameters is prepared only the first time it is executed
+#[derive(Debug, Default, Clone)]
+pub struct ColumnCache(Arc<Mutex<HashMap<String, StatementColumns>>>);
+
+impl ColumnCache {
+    async fn get(&self, query: &str) -> Option<StatementColumns> {
+        self.0.lock().await.get(query).cloned()
+    }
+
+    async fn insert(&self, query: &str, columns: StatementColumns) {
+        let mut cache = self.0.lock().await;
+        if cache.len() >= COLUMN_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(query.to_owned(), columns);
+    }
+
+    async fn remove(&self, query: &str) {
+        self.0.lock().await.remove(query);
+    }
+}
+
+/// A postgres client executing queries through both the simple and the extended query protocols
+#[async_trait]
+pub(crate) trait QueryClient: GenericClient {
+    async fn simple_query(&self, query: &str) -> Result<Vec<SimpleQueryMessage>, tokio_postgres::Error>;
+}
+
+#[async_trait]
+impl QueryClient for Client {
+    async fn simple_query(&self, query: &str) -> Result<Vec<SimpleQueryMessage>, tokio_postgres::Error> {
+        Client::simple_query(self, query).await
+    }
+}
+
+#[async_trait]
+impl QueryClient for Transaction<'_> {
+    async fn simple_query(&self, query: &str) -> Result<Vec<SimpleQueryMessage>, tokio_postgres::Error> {
+        Transaction::simple_query(self, query).await
+    }
+}
+
+/// Executes a Sql query, returning the columns and rows, or the number of affected rows
+/// for a statement without any column, such as an `INSERT`
+///
+/// A query with parameters is a single statement, which is prepared first
+/// and has its parameters bound through the extended query protocol.
+///
+/// A query without parameters is executed through the simple query protocol, so that it can have
+/// several statements and columns of any type. The columns of a single statement are taken from `columns`,
+/// or it is prepared along with its execution the first time. A query with several statements returns
+/// the rows of its last statement in text, like `libpq`.
+pub(crate) async fn execute_statement<C: QueryClient>(
+    client: &C,
+    columns: &ColumnCache,
+    query: &str,
+    params: &[Value],
+) -> Result<MsqlResult, tokio_postgres::Error> {
+    if params.is_empty() {
+        if is_multi_statement(query) {
+            return simple_query_result(client.simple_query(query).await?, None);
+        }
+        if let Some(cached) = columns.get(query).await {
+            let messages = client.simple_query(query).await;
+            if messages.is_err() {
+                // The columns may have changed along with the schema
+                columns.remove(query).await;
+            }
+            return simple_query_result(messages?, Some(&cached));
+        }
+
+        // Pipelined on the connection, so that preparing does not take another round trip
+        let (statement, messages) = future::join(client.prepare(query), client.simple_query(query)).await;
+        let messages = messages?;
+        return match statement {
+            Ok(statement) => {
+                let statement_columns: StatementColumns = statement
+                    .columns()
+                    .iter()
+                    .map(|column| (column.name().to_owned(), column.type_().clone()))
+                    .collect();
+                columns.insert(query, statement_columns.clone()).await;
+                simple_query_result(messages, Some(&statement_columns))
+            }
+            Err(_) => simple_query_result(messages, None),
+        };
+    }
+
+    let params: Vec<_> = params.iter().map(SqlParam).collect();
+    let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| param as &(dyn ToSql + Sync)).collect();
+
+    let statement = client.prepare(query).await?;
+    if statement.columns().is_empty() {
+        return client.execute(&statement, &params).await.map(MsqlResult::affected);
+    }
+
+    let columns = statement
+        .columns()
+        .iter()
+        .map(|column| MsqlColumn::new(column.name(), column.type_().name()))
+        .collect();
+    let rows = client
+        .query(&statement, &params)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (0..row.len())
+                .map(|idx| row.try_get::<_, SqlValue>(idx).map(SqlValue::into_inner))
+                .collect()
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(MsqlResult::new(columns, rows))
+}
+
+/// Collects the result of a Sql query executed through the simple query protocol,
+/// with the names and types of its `columns` if known
+///
+/// Cells are converted from their text format according to the types of the `columns`.
+fn simple_query_result(
+    messages: Vec<SimpleQueryMessage>,
+    columns: Option<&[(String, Type)]>,
+) -> Result<MsqlResult, tokio_postgres::Error> {
+    // Rows and the number of affected rows of the last statement
+    let mut rows: Vec<Vec<Value>> = Vec::new();
+    let mut last = (Vec::new(), 0);
+    for message in messages {
+        match message {
+            SimpleQueryMessage::Row(row) => rows.push(
+                (0..row.len())
+                    .map(|idx| {
+                        let text = row.try_get(idx)?;
+                        Ok(match columns.and_then(|columns| columns.get(idx)) {
+                            Some((_, ty)) => SqlValue::from_text(ty, text).into_inner(),
+                            None => text.map_or(Value::Null, Value::from),
+                        })
+                    })
+                    .collect::<Result<_, tokio_postgres::Error>>()?,
+            ),
+            SimpleQueryMessage::CommandComplete(affected_rows) => last = (std::mem::take(&mut rows), affected_rows),
+            _ => (),
+        }
+    }
+    let (rows, affected_rows) = last;
+
+    let columns: Vec<_> = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|(name, ty)| MsqlColumn::new(name, ty.name()))
+            .collect(),
+        // Names and types of the columns are not reported through the simple query protocol
+        None => (0..rows.first().map_or(0, |row| row.len()))
+            .map(|_| MsqlColumn::new("?column?", "text"))
+            .collect(),
+    };
+    if columns.is_empty() {
+        Ok(MsqlResult::affected(affected_rows))
+    } else {
+        Ok(MsqlResult::new(columns, rows))
+    }
+}
@@ -1,53 +1,191 @@
 use super::core::PendingQueue;
-use super::core::{DbVersion, QueryResult, QueryResultType};
+use super::core::{DbVersion, QueryResult, QueryResultType, RunningRequests};
+use super::{mockdb, postgresdb};
 use crate::comm::scheduler_dbproxy::Message;
+use crate::core::{DbVN, MsqlError, RequestMeta, VN};
 use crate::util::executor::Executor;
+use crate::util::tcp::backoff;
 use async_trait::async_trait;
+use bb8_postgres::bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
 use futures::prelude::*;
 use futures::SinkExt;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpListener, ToSocketAddrs};
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tokio::time::sleep;
+use tokio_postgres::NoTls;
 use tokio_serde::formats::SymmetricalJson;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
-use tracing::{debug, field, info, info_span, instrument, Instrument, Span};
+use tracing::{debug, error, field, info, info_span, instrument, warn, Instrument, Span};
 
-/// Creates a tcp connection and splits it into
-/// `Receiver` and `Responder`
+/// Replies kept for resynchronizing with a reconnected scheduler, must be more than
+/// the requests that can be in flight at once
+const REPLY_CACHE_SIZE: usize = 1024;
+
+type SchedulerReader =
+    SymmetricallyFramed<FramedRead<OwnedReadHalf, LengthDelimitedCodec>, Message, SymmetricalJson<Message>>;
+
+/// Binds to `addr` for the scheduler to connect, and creates
+/// `Receiver` and `Responder` sharing the same `Link`
 pub async fn connection<A>(
     addr: A,
     pending_queue: Arc<Mutex<PendingQueue>>,
+    responder_sender: mpsc::Sender<QueryResult>,
     responder_receiver: mpsc::Receiver<QueryResult>,
     version: Arc<Mutex<DbVersion>>,
+    pool_opt: Option<Pool<PostgresConnectionManager<NoTls>>>,
+    running_requests: Arc<Mutex<RunningRequests>>,
 ) -> (Receiver, Responder)
 where
     A: ToSocketAddrs,
 {
     let listener = TcpListener::bind(addr).await.unwrap();
     info!("Binded to port");
-    let (tcp_stream, _) = listener.accept().await.unwrap();
-    info!("Connection established with scheduler...");
-    let (tcp_read, tcp_write) = tcp_stream.into_split();
 
-    let receiver = Receiver::new(pending_queue.clone(), tcp_read);
-    let responder = Responder::new(responder_receiver, version, tcp_write);
+    let link = Arc::new(Mutex::new(Link::default()));
+    let receiver = Receiver::new(
+        pending_queue,
+        responder_sender,
+        version.clone(),
+        pool_opt,
+        running_requests,
+        link.clone(),
+        listener,
+    );
+    let responder = Responder::new(responder_receiver, version, link);
     (receiver, responder)
 }
 
+/// The connection to the scheduler shared by `Receiver` and `Responder`,
+/// which is replaced whenever the scheduler reconnects
+///
+/// The replies of the recent requests are kept, so that the ones lost with a broken connection
+/// are replied again once the scheduler resynchronizes, instead of executing the requests twice.
+#[derive(Debug, Default)]
+pub struct Link {
+    writer: Option<mpsc::UnboundedSender<Message>>,
+    /// `None` until replied
+    replies: HashMap<RequestMeta, Option<Message>>,
+    /// Replied requests, oldest first
+    replied: VecDeque<RequestMeta>,
+}
+
+impl Link {
+    /// Send via `tcp_write` from now on, the previous connection is dropped
+    ///
+    /// Messages are sent in a separate task, so that a stuck connection never blocks the `Link`.
+    pub fn connect(&mut self, tcp_write: OwnedWriteHalf) {
+        let (writer, mut messages) = mpsc::unbounded_channel();
+        let mut serializer = SymmetricallyFramed::new(
+            FramedWrite::new(tcp_write, LengthDelimitedCodec::new()),
+            SymmetricalJson::<Message>::default(),
+        );
+        tokio::spawn(
+            async move {
+                while let Some(msg) = messages.recv().await {
+                    if let Err(e) = serializer.send(msg).await {
+                        warn!("Cannot send to scheduler: {}", e);
+                        break;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+        self.writer = Some(writer);
+    }
+
+    pub fn disconnect(&mut self) {
+        self.writer = None;
+    }
+
+    /// Send to the scheduler, the message is dropped if not connected
+    pub fn send(&mut self, msg: Message) {
+        let sent = self.writer.as_ref().map(|writer| writer.send(msg).is_ok());
+        match sent {
+            Some(true) => (),
+            Some(false) => self.disconnect(),
+            None => debug!("Not connected to scheduler, dropping the message"),
+        }
+    }
+
+    /// Reply to the request received, and keep the reply for `Link::resync`
+    pub fn reply(&mut self, msg: Message) {
+        let meta = msg.try_get_request_meta().unwrap().clone();
+        self.replies.insert(meta.clone(), Some(msg.clone()));
+        self.replied.push_back(meta);
+        while self.replied.len() > REPLY_CACHE_SIZE {
+            // Unless the request is received again
+            if let Entry::Occupied(entry) = self.replies.entry(self.replied.pop_front().unwrap()) {
+                if entry.get().is_some() {
+                    entry.remove();
+                }
+            }
+        }
+
+        self.send(msg);
+    }
+
+    fn received(&mut self, meta: RequestMeta) {
+        self.replies.insert(meta, None);
+    }
+
+    /// Reply again the requests in `metas` that are already replied, and then
+    /// reply `Resynced` with the requests in `metas` that are never received
+    fn resync(&mut self, metas: Vec<RequestMeta>, version_vector: HashMap<String, VN>) {
+        let mut missing = Vec::new();
+        for meta in metas {
+            match self.replies.get(&meta).cloned() {
+                Some(Some(reply)) => self.send(reply),
+                // Replied once executed
+                Some(None) => (),
+                None => missing.push(meta),
+            }
+        }
+
+        info!("Resynchronized, with {} requests never received", missing.len());
+        self.send(Message::Resynced(missing, version_vector));
+    }
+}
+
 /// Handles the incoming tcp request from scheduler
+///
+/// A new connection from the scheduler replaces the current one. The scheduler reconnects after
+/// resetting a broken connection, while closing the connection means the scheduler is stopping.
 pub struct Receiver {
     pending_queue: Arc<Mutex<PendingQueue>>,
-    tcp_read: OwnedReadHalf,
+    /// For replying the requests cancelled before being executed
+    responder_sender: mpsc::Sender<QueryResult>,
+    version: Arc<Mutex<DbVersion>>,
+    pool_opt: Option<Pool<PostgresConnectionManager<NoTls>>>,
+    running_requests: Arc<Mutex<RunningRequests>>,
+    link: Arc<Mutex<Link>>,
+    listener: TcpListener,
 }
 
 impl Receiver {
-    pub fn new(pending_queue: Arc<Mutex<PendingQueue>>, tcp_read: OwnedReadHalf) -> Self {
+    pub fn new(
+        pending_queue: Arc<Mutex<PendingQueue>>,
+        responder_sender: mpsc::Sender<QueryResult>,
+        version: Arc<Mutex<DbVersion>>,
+        pool_opt: Option<Pool<PostgresConnectionManager<NoTls>>>,
+        running_requests: Arc<Mutex<RunningRequests>>,
+        link: Arc<Mutex<Link>>,
+        listener: TcpListener,
+    ) -> Self {
         Self {
             pending_queue,
-            tcp_read,
+            responder_sender,
+            version,
+            pool_opt,
+            running_requests,
+            link,
+            listener,
         }
     }
 }
@@ -58,17 +196,60 @@ impl Executor for Receiver {
     async fn run(mut self: Box<Self>) {
         let Self {
             pending_queue,
-            tcp_read,
+            responder_sender,
+            version,
+            pool_opt,
+            running_requests,
+            link,
+            listener,
         } = *self;
 
-        let mut deserializer = SymmetricallyFramed::new(
-            FramedRead::new(tcp_read, LengthDelimitedCodec::new()),
-            SymmetricalJson::<Message>::default(),
-        );
-
         info!("started");
 
-        while let Some(msg) = deserializer.try_next().await.unwrap() {
+        let mut deserializer: Option<SchedulerReader> = None;
+        let mut accept_failures = 0;
+        loop {
+            let msg = tokio::select! {
+                accepted = listener.accept() => {
+                    match accepted {
+                        Ok((tcp_stream, scheduler_addr)) => {
+                            if deserializer.is_some() {
+                                warn!("Connection replaced by scheduler {}", scheduler_addr);
+                            } else {
+                                info!("Connection established with scheduler {}", scheduler_addr);
+                            }
+                            accept_failures = 0;
+                            let (tcp_read, tcp_write) = tcp_stream.into_split();
+                            link.lock().await.connect(tcp_write);
+                            deserializer = Some(SymmetricallyFramed::new(
+                                FramedRead::new(tcp_read, LengthDelimitedCodec::new()),
+                                SymmetricalJson::<Message>::default(),
+                            ));
+                        }
+                        Err(e) => {
+                            warn!("Cannot accept scheduler: {}", e);
+                            sleep(backoff(accept_failures)).await;
+                            accept_failures += 1;
+                        }
+                    }
+                    continue;
+                }
+                msg = next(&mut deserializer) => msg,
+            };
+
+            let msg = match msg {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!(
+                        "Connection with scheduler is broken, waiting for it to reconnect: {}",
+                        e
+                    );
+                    deserializer = None;
+                    link.lock().await.disconnect();
+                    continue;
+                }
+            };
             debug!("Receiver received a new request from scheduler");
 
             async {
@@ -78,8 +259,93 @@ impl Executor for Receiver {
                         Span::current().record("type", &request.as_ref());
                         debug!("Txvn: {:?}", versions.clone());
                         debug!("Request content is: {:?}", request.clone());
+                        link.lock().await.received(meta.clone());
                         pending_queue.lock().await.emplace(meta, request, versions);
                     }
+                    // Replied through the responder as if it failed, unless it is already done
+                    Message::Cancel(meta) => {
+                        Span::current().record("message", &&meta.to_string()[..]);
+                        let cancelled = pending_queue.lock().await.cancel(&meta);
+                        if let Some(operation) = cancelled {
+                            info!("Cancelled while waiting for versions");
+                            let result = operation.into_sqlresponse(Err(MsqlError::cancelled("Request cancelled")));
+                            responder_sender.send(result).await.map_err(|e| e.to_string()).unwrap();
+                        } else if let Some(cancel_token) = running_requests.lock().await.get_cancel_token(&meta) {
+                            info!("Cancelling the query being executed");
+                            tokio::spawn(postgresdb::cancel(cancel_token).in_current_span());
+                        } else {
+                            debug!("Nothing to cancel");
+                        }
+                    }
+                    Message::InitVersion(version_vector) => {
+                        info!("Initializing versions to {:?}", version_vector);
+                        version.lock().await.db_version = DbVN::from(version_vector);
+                    }
+                    Message::Heartbeat => {
+                        let waiting = pending_queue.lock().await.waiting_requests();
+                        link.lock().await.send(Message::HeartbeatAck(waiting))
+                    }
+                    Message::SnapshotRequest(meta, tables) => {
+                        info!("Dumping {:?}", tables);
+                        link.lock().await.received(meta.clone());
+                        let pool_opt = pool_opt.clone();
+                        let link = link.clone();
+                        tokio::spawn(
+                            async move {
+                                let snapshot = match pool_opt {
+                                    Some(pool) => postgresdb::dump(pool, tables).await,
+                                    None => mockdb::dump(tables).await,
+                                };
+                                link.lock().await.reply(Message::Snapshot(meta, snapshot));
+                            }
+                            .in_current_span(),
+                        );
+                    }
+                    Message::DescribeRequest(meta, sql) => {
+                        debug!("Describing {}", sql);
+                        link.lock().await.received(meta.clone());
+                        let pool_opt = pool_opt.clone();
+                        let link = link.clone();
+                        tokio::spawn(
+                            async move {
+                                let columns = match pool_opt {
+                                    Some(pool) => postgresdb::describe(pool, sql).await,
+                                    None => mockdb::describe(sql).await,
+                                };
+                                link.lock().await.reply(Message::Description(meta, columns));
+                            }
+                            .in_current_span(),
+                        );
+                    }
+                    // Restored in the background so that heartbeats are still acknowledged,
+                    // while the requests received afterwards are held until it is restored
+                    Message::Restore(meta, version_vector, snapshot) => {
+                        info!("Restoring to {:?}", version_vector);
+                        link.lock().await.received(meta.clone());
+                        version.lock().await.restoring();
+                        let pool_opt = pool_opt.clone();
+                        let version = version.clone();
+                        let link = link.clone();
+                        tokio::spawn(
+                            async move {
+                                let restored = match pool_opt {
+                                    Some(pool) => postgresdb::restore(pool, snapshot).await,
+                                    None => mockdb::restore(snapshot).await,
+                                };
+                                match &restored {
+                                    Ok(_) => version.lock().await.restored(DbVN::from(version_vector)),
+                                    Err(e) => error!("Cannot restore, requests are held: {:?}", e),
+                                }
+                                link.lock().await.reply(Message::Restored(meta, restored));
+                            }
+                            .in_current_span(),
+                        );
+                    }
+                    Message::Resync(metas) => {
+                        info!("Resynchronizing {} requests not replied", metas.len());
+                        let version_vector = version.lock().await.db_version.to_version_vector();
+                        link.lock().await.resync(metas, version_vector);
+                    }
                     _ => debug!("nope"),
                 }
             }
@@ -91,23 +357,27 @@ impl Executor for Receiver {
     }
 }
 
+/// Receive from the current connection, never resolves if not connected
+async fn next(deserializer: &mut Option<SchedulerReader>) -> Result<Option<Message>, std::io::Error> {
+    match deserializer {
+        Some(deserializer) => deserializer.try_next().await,
+        None => future::pending().await,
+    }
+}
+
 /// Sends the tcp reply messages back to scheduler
 pub struct Responder {
     receiver: mpsc::Receiver<QueryResult>,
     version: Arc<Mutex<DbVersion>>,
-    tcp_write: OwnedWriteHalf,
+    link: Arc<Mutex<Link>>,
 }
 
 impl Responder {
-    pub fn new(
-        receiver: mpsc::Receiver<QueryResult>,
-        version: Arc<Mutex<DbVersion>>,
-        tcp_write: OwnedWriteHalf,
-    ) -> Self {
+    pub fn new(receiver: mpsc::Receiver<QueryResult>, version: Arc<Mutex<DbVersion>>, link: Arc<Mutex<Link>>) -> Self {
         Self {
             receiver,
             version,
-            tcp_write,
+            link,
         }
     }
 }
@@ -119,14 +389,10 @@ impl Executor for Responder {
         let Self {
             mut receiver,
             version,
-            tcp_write,
+            link,
         } = *self;
 
         info!("started");
-        let mut serializer = SymmetricallyFramed::new(
-            FramedWrite::new(tcp_write, LengthDelimitedCodec::new()),
-            SymmetricalJson::<Message>::default(),
-        );
 
         while let Some(mut result) = receiver.recv().await {
             debug!("Responder got a result to return");
@@ -149,13 +415,10 @@ impl Executor for Responder {
                 _ => {}
             }
 
-            serializer
-                .send(Message::MsqlResponse(
-                    result.identifier.clone(),
-                    result.into_msql_response(),
-                ))
-                .await
-                .unwrap();
+            link.lock().await.reply(Message::MsqlResponse(
+                result.identifier.clone(),
+                result.into_msql_response(),
+            ));
         }
 
         info!("Responder finishes its job");
@@ -168,7 +431,7 @@ mod tests_receiver {
     use super::*;
     use crate::comm::scheduler_dbproxy::Message;
     use crate::core::*;
-    use crate::dbproxy::core::PendingQueue;
+    use crate::dbproxy::core::{DbVersion, PendingQueue};
     use futures::SinkExt;
     use std::net::*;
     use std::sync::Arc;
@@ -263,10 +526,23 @@ mod tests_receiver {
 
     async fn helper_spawn_receiver(pending_queue: Arc<Mutex<PendingQueue>>, addr: SocketAddr) {
         let listener = TcpListener::bind(addr).await.unwrap();
-        let (tcp_stream, _) = listener.accept().await.unwrap();
-        let (tcp_read, _) = tcp_stream.into_split();
+        let version = Arc::new(Mutex::new(DbVersion::new(Default::default())));
+        let link = Arc::new(Mutex::new(Link::default()));
+
+        let (responder_sender, _) = mpsc::channel(1);
+        let running_requests = Arc::new(Mutex::new(RunningRequests::new()));
 
-        Box::new(Receiver::new(pending_queue, tcp_read)).run().await;
+        Box::new(Receiver::new(
+            pending_queue,
+            responder_sender,
+            version,
+            None,
+            running_requests,
+            link,
+            listener,
+        ))
+        .run()
+        .await;
     }
 
     async fn helper_spawn_mock_client(mut items: Vec<Message>, addr: SocketAddr) {
@@ -284,7 +560,7 @@ mod tests_receiver {
 mod tests_responder {
     use super::Responder;
     use super::*;
-    use crate::comm::MsqlResponse;
+    use crate::comm::{MsqlResponse, MsqlResult};
     use crate::core::*;
     use crate::dbproxy::core::{DbVersion, QueryResult, QueryResultType};
     use crate::{comm::scheduler_dbproxy::Message, core::RequestMeta};
@@ -316,8 +592,7 @@ mod tests_responder {
         // Prepare - Data
         let r = QueryResult {
             identifier: addr.clone(),
-            result: "r".to_string(),
-            succeed: true,
+            result: Ok(MsqlResult::default()),
             result_type: QueryResultType::BEGIN,
             contained_newer_versions: TxVN::new(),
             contained_early_release_version: None,
@@ -351,8 +626,10 @@ mod tests_responder {
         let listener = TcpListener::bind(addr).await.unwrap();
         let (tcp_stream, _) = listener.accept().await.unwrap();
         let (_, tcp_write) = tcp_stream.into_split();
+        let link = Arc::new(Mutex::new(Link::default()));
+        link.lock().await.connect(tcp_write);
 
-        Box::new(Responder::new(receiver, version, tcp_write)).run().await;
+        Box::new(Responder::new(receiver, version, link)).run().await;
     }
 
     async fn helper_spawn_mock_client(vertifying_queue: Arc<Mutex<Vec<MsqlResponse>>>, addr: SocketAddr) {
@@ -383,3 +660,49 @@ mod tests_responder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests_link {
+    use super::*;
+    use crate::comm::MsqlResponse;
+    use tokio::net::TcpStream;
+
+    /// Unit test for `Link::resync`
+    #[tokio::test]
+    async fn test_resync() {
+        let metas: Vec<_> = (0..3)
+            .map(|request_id| RequestMeta {
+                client_addr: "127.0.0.1:8080".parse().unwrap(),
+                cur_txid: 0,
+                request_id,
+            })
+            .collect();
+        let reply = Message::MsqlResponse(metas[0].clone(), MsqlResponse::endtx_ok("commit"));
+
+        // Replied while disconnected, still executing, and never received
+        let mut link = Link::default();
+        link.received(metas[0].clone());
+        link.reply(reply.clone());
+        link.received(metas[1].clone());
+
+        let listener = TcpListener::bind("127.0.0.1:2346").await.unwrap();
+        let tcp_stream = TcpStream::connect("127.0.0.1:2346").await.unwrap();
+        let (scheduler, _) = listener.accept().await.unwrap();
+        link.connect(tcp_stream.into_split().1);
+        let mut serded_read = SymmetricallyFramed::new(
+            FramedRead::new(scheduler, LengthDelimitedCodec::new()),
+            SymmetricalJson::<Message>::default(),
+        );
+
+        let version_vector: HashMap<_, _> = vec![(String::from("t0"), 1)].into_iter().collect();
+        link.resync(metas.clone(), version_vector.clone());
+        assert_eq!(
+            format!("{:?}", serded_read.try_next().await.unwrap().unwrap()),
+            format!("{:?}", reply)
+        );
+        assert_eq!(
+            format!("{:?}", serded_read.try_next().await.unwrap().unwrap()),
+            format!("{:?}", Message::Resynced(vec![metas[2].clone()], version_vector))
+        );
+    }
+}
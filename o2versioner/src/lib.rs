@@ -1,3 +1,4 @@
+pub mod client;
 pub mod comm;
 pub mod core;
 mod dbproxy;
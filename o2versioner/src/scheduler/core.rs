@@ -4,12 +4,16 @@ use crate::core::*;
 use crate::util::common::{create_zip_csv_writer, prepare_logging_dir};
 use crate::util::conf::*;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
 #[derive(Debug, Clone)]
@@ -18,6 +22,8 @@ use tracing::{info, warn};
 pub struct State {
     dbvn_manager: Arc<RwLock<DbVNManager>>,
     client_records: ClientRecords,
+    divergence_records: DivergenceRecords,
+    in_flight_txvns: InFlightTxVNs,
     conf: Arc<Conf>,
 }
 
@@ -26,6 +32,8 @@ impl State {
         Self {
             dbvn_manager: Arc::new(RwLock::new(dbvn_manager)),
             client_records: ClientRecords::new(conf.scheduler.detailed_logging.is_some()),
+            divergence_records: DivergenceRecords::default(),
+            in_flight_txvns: InFlightTxVNs::default(),
             conf: Arc::new(conf),
         }
     }
@@ -40,6 +48,16 @@ impl State {
         self.client_records.share_client_record(client).await
     }
 
+    /// Share `DivergenceRecords` by cloning the shared reference
+    pub fn share_divergence_records(&self) -> DivergenceRecords {
+        self.divergence_records.clone()
+    }
+
+    /// Share `InFlightTxVNs` by cloning the shared reference
+    pub fn share_in_flight_txvns(&self) -> InFlightTxVNs {
+        self.in_flight_txvns.clone()
+    }
+
     /// Dump the performance logging, returns the path of logging dumps if dumped
     pub async fn dump_perf_log(&self) -> Option<String> {
         if let Some(perf_log_path) = self.conf.scheduler.performance_logging.as_ref() {
@@ -58,6 +76,18 @@ impl State {
                 .for_each(|r| wrt.serialize(r).unwrap());
             info!("Dumped performance logging to {}", perf_csv_path.display());
 
+            // Divergence logging
+            let mut divergence_csv_path_builder = PathBuf::from(&cur_log_dir);
+            divergence_csv_path_builder.push("divergence.csv.gz");
+            let divergence_csv_path = divergence_csv_path_builder.as_path();
+            let mut wrt = create_zip_csv_writer(divergence_csv_path).unwrap();
+            self.divergence_records
+                .collect()
+                .await
+                .into_iter()
+                .for_each(|r| wrt.serialize(r).unwrap());
+            info!("Dumped divergence logging to {}", divergence_csv_path.display());
+
             // Dbvn logging
             let mut dbproxy_stats_path_builder = PathBuf::from(&cur_log_dir);
             dbproxy_stats_path_builder.push("dbproxy_stats.csv.gz");
@@ -91,15 +121,30 @@ impl State {
 pub struct ConnectionState {
     client_meta: ClientMeta,
     cur_txvn: Option<TxVN>,
+    has_savepoint: bool,
+    tx_begin_time: Option<Instant>,
+    /// The error to fail the next request with, once the transaction is rolled back for timing out
+    tx_timeout: Option<MsqlError>,
+    request_canceller: RequestCanceller,
     client_record: Arc<RwLock<ClientRecord>>,
+    in_flight_txvns: InFlightTxVNs,
 }
 
 impl ConnectionState {
-    pub fn new(client_addr: SocketAddr, client_record: Arc<RwLock<ClientRecord>>) -> Self {
+    pub fn new(
+        client_addr: SocketAddr,
+        client_record: Arc<RwLock<ClientRecord>>,
+        in_flight_txvns: InFlightTxVNs,
+    ) -> Self {
         Self {
             client_meta: ClientMeta::new(client_addr),
             cur_txvn: None,
+            has_savepoint: false,
+            tx_begin_time: None,
+            tx_timeout: None,
+            request_canceller: RequestCanceller::default(),
             client_record,
+            in_flight_txvns,
         }
     }
 
@@ -115,12 +160,75 @@ impl ConnectionState {
         &self.cur_txvn
     }
 
+    /// Replace the current `TxVN`, setting it to `None` also clears the savepoint state
     pub fn replace_txvn(&mut self, new_txvn: Option<TxVN>) -> Option<TxVN> {
+        if new_txvn.is_none() {
+            self.has_savepoint = false;
+            self.tx_begin_time = None;
+        } else if self.cur_txvn.is_none() {
+            self.tx_begin_time = Some(Instant::now());
+        }
+        self.in_flight_txvns
+            .replace(self.client_meta.client_addr(), new_txvn.clone());
         let old_txvn = self.cur_txvn.take();
         self.cur_txvn = new_txvn;
         old_txvn
     }
 
+    /// Whether a savepoint has been set within the current transaction
+    pub fn has_savepoint(&self) -> bool {
+        self.has_savepoint
+    }
+
+    /// Mark that a savepoint has been set within the current transaction
+    pub fn set_savepoint(&mut self) {
+        self.has_savepoint = true;
+    }
+
+    /// When the current transaction expires if the client stays idle from `now` on,
+    /// along with the error for the client. Returns `None` if there is no transaction,
+    /// or neither `SchedulerConf::tx_idle_timeout` nor `SchedulerConf::tx_max_lifetime` is set
+    pub fn tx_expiry(&self, conf: &SchedulerConf, now: Instant) -> Option<(Instant, MsqlError)> {
+        let tx_begin_time = self.tx_begin_time?;
+        let idle = conf.tx_idle_timeout.map(|timeout| {
+            (
+                now + Duration::from_millis(timeout),
+                MsqlError::timeout(format!("transaction was idle for more than {} ms", timeout)),
+            )
+        });
+        let lifetime = conf.tx_max_lifetime.map(|lifetime| {
+            (
+                tx_begin_time + Duration::from_millis(lifetime),
+                MsqlError::timeout(format!("transaction exceeded the maximum lifetime of {} ms", lifetime)),
+            )
+        });
+        idle.into_iter().chain(lifetime).min_by_key(|(deadline, _)| *deadline)
+    }
+
+    /// Fail the next request with `err`, as the transaction is rolled back for timing out
+    pub fn set_tx_timeout(&mut self, err: MsqlError) {
+        self.tx_timeout = Some(err);
+    }
+
+    /// The error to fail the next request with, if the transaction was rolled back for timing out
+    pub fn tx_timeout(&self) -> Option<&MsqlError> {
+        self.tx_timeout.as_ref()
+    }
+
+    /// Take the error to fail the current request with, if the transaction was rolled back for timing out
+    pub fn take_tx_timeout(&mut self) -> Option<MsqlError> {
+        self.tx_timeout.take()
+    }
+
+    /// Set the `RequestCanceller` of the current request, a new one must be set for every request
+    pub fn set_request_canceller(&mut self, request_canceller: RequestCanceller) {
+        self.request_canceller = request_canceller;
+    }
+
+    pub fn request_canceller(&self) -> &RequestCanceller {
+        &self.request_canceller
+    }
+
     pub async fn current_request_id(&self) -> usize {
         self.client_record.read().await.len()
     }
@@ -130,16 +238,78 @@ impl ConnectionState {
     }
 }
 
+/// `TxVN`s of all transactions in flight, keyed by the client address of their connections
+#[derive(Debug, Clone, Default)]
+pub struct InFlightTxVNs(Arc<std::sync::Mutex<HashMap<SocketAddr, TxVN>>>);
+
+impl InFlightTxVNs {
+    fn replace(&self, client_addr: SocketAddr, txvn: Option<TxVN>) {
+        let mut txvns = self.0.lock().unwrap();
+        match txvn {
+            Some(txvn) => txvns.insert(client_addr, txvn),
+            None => txvns.remove(&client_addr),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Cancels a request of a connection, shared by the handler and the dispatcher
+///
+/// A request is cancelled at most once, along with the error for the client
+#[derive(Debug, Clone, Default)]
+pub struct RequestCanceller {
+    token: CancellationToken,
+    err: Arc<std::sync::Mutex<Option<MsqlError>>>,
+}
+
+impl RequestCanceller {
+    /// Cancel the request with `err`, does nothing if it is already cancelled
+    pub fn cancel(&self, err: MsqlError) {
+        let mut cur_err = self.err.lock().unwrap();
+        if cur_err.is_none() {
+            *cur_err = Some(err);
+            self.token.cancel();
+        }
+    }
+
+    /// Returns the error for the client if the request is cancelled
+    pub fn cancelled_err(&self) -> Option<MsqlError> {
+        self.err.lock().unwrap().clone()
+    }
+
+    /// Wait until the request is cancelled, returns the error for the client
+    pub async fn cancelled(&self) -> MsqlError {
+        self.token.cancelled().await;
+        self.cancelled_err().unwrap()
+    }
+}
+
 /// `Dbproxy_addr` -> `DvVN`
+///
+/// Dbproxies that are catching up still have their versions released,
+/// but are not chosen for read queries until they are caught up.
 #[derive(Debug)]
-pub struct DbVNManager(HashMap<SocketAddr, DbVN>);
+pub struct DbVNManager {
+    dbvns: HashMap<SocketAddr, DbVN>,
+    catching_up: HashSet<SocketAddr>,
+}
 
 impl FromIterator<SocketAddr> for DbVNManager {
     fn from_iter<T>(iter: T) -> Self
     where
         T: IntoIterator<Item = SocketAddr>,
     {
-        Self(iter.into_iter().map(|addr| (addr, DbVN::default())).collect())
+        Self {
+            dbvns: iter.into_iter().map(|addr| (addr, DbVN::default())).collect(),
+            catching_up: HashSet::new(),
+        }
     }
 }
 
@@ -161,54 +331,104 @@ impl DbVNManager {
             .get_from_tableops(tableops)
             .expect("Mismatching between TableOps and TxVN");
 
-        self.0
+        self.dbvns
             .iter()
+            .filter(|(addr, _)| !self.catching_up.contains(addr))
             .filter(|(_, dbvn)| dbvn.can_execute_query(&txtablevns))
             .map(|(addr, dbvn)| (addr.clone(), dbvn.get_from_tableops(tableops)))
             .sorted_by_key(|(addr, _)| *addr)
             .collect()
     }
 
-    /// Uses the sum of VNs of interest to determine the most updated
-    pub fn get_most_updated_version_for_read_query(&self, tableops: &TableOps) -> (SocketAddr, Vec<DbTableVN>) {
+    /// Uses the sum of VNs of interest to determine the most updated, returns all of the equally
+    /// most updated dbproxies, which is empty if there is no dbproxy that is caught up
+    pub fn get_most_updated_version_for_read_query(&self, tableops: &TableOps) -> Vec<(SocketAddr, Vec<DbTableVN>)> {
         assert_eq!(
             tableops.access_pattern(),
             AccessPattern::ReadOnly,
             "Expecting ReadOnly access pattern for the query"
         );
 
-        self.0
-            .iter()
-            .max_by_key(|(_, dbvn)| {
-                dbvn.get_from_tableops(tableops)
-                    .iter()
-                    .map(|dbtablevn| dbtablevn.vn)
-                    .sum::<VN>()
-            })
+        let vn_sum = |dbvn: &DbVN| {
+            dbvn.get_from_tableops(tableops)
+                .iter()
+                .map(|dbtablevn| dbtablevn.vn)
+                .sum::<VN>()
+        };
+        let candidates = self.dbvns.iter().filter(|(addr, _)| !self.catching_up.contains(addr));
+        let max_vn_sum = match candidates.clone().map(|(_, dbvn)| vn_sum(dbvn)).max() {
+            Some(max_vn_sum) => max_vn_sum,
+            None => return Vec::new(),
+        };
+        candidates
+            .filter(|(_, dbvn)| vn_sum(dbvn) == max_vn_sum)
             .map(|(addr, dbvn)| (addr.clone(), dbvn.get_from_tableops(tableops)))
-            .unwrap()
+            .sorted_by_key(|(addr, _)| *addr)
+            .collect()
     }
 
     /// Performa a version release on the argument dbproxy
     pub fn release_version(&mut self, dbproxy_addr: &SocketAddr, release_request: DbVNReleaseRequest) {
-        if !self.0.contains_key(dbproxy_addr) {
+        if let Some(dbvn) = self.dbvns.get_mut(dbproxy_addr) {
+            dbvn.release_version(release_request);
+        } else {
             warn!(
-                "DbVNManager does not have a DbVN for {} yet, is this a newly added dbproxy?",
+                "DbVNManager does not have a DbVN for {}, is this a removed dbproxy?",
                 dbproxy_addr
             );
         }
-        self.0
-            .entry(dbproxy_addr.clone())
-            .or_default()
-            .release_version(release_request);
+    }
+
+    /// Add a dbproxy with its current `DbVN`, returns `false` if it already exists
+    pub fn insert(&mut self, dbproxy_addr: SocketAddr, dbvn: DbVN) -> bool {
+        match self.dbvns.entry(dbproxy_addr) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(dbvn);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, dbproxy_addr: &SocketAddr) -> Option<DbVN> {
+        self.catching_up.remove(dbproxy_addr);
+        self.dbvns.remove(dbproxy_addr)
+    }
+
+    /// Mark whether the dbproxy is catching up, returns `false` if it does not exist
+    pub fn set_catching_up(&mut self, dbproxy_addr: &SocketAddr, catching_up: bool) -> bool {
+        if !self.dbvns.contains_key(dbproxy_addr) {
+            false
+        } else {
+            if catching_up {
+                self.catching_up.insert(*dbproxy_addr);
+            } else {
+                self.catching_up.remove(dbproxy_addr);
+            }
+            true
+        }
+    }
+
+    pub fn is_catching_up(&self, dbproxy_addr: &SocketAddr) -> bool {
+        self.catching_up.contains(dbproxy_addr)
+    }
+
+    /// Find all dbproxies whose versions have not reached `version_vector` yet
+    pub fn get_all_behind(&self, version_vector: &HashMap<String, VN>) -> Vec<SocketAddr> {
+        self.dbvns
+            .iter()
+            .filter(|(_, dbvn)| !dbvn.has_reached(version_vector))
+            .map(|(addr, _)| *addr)
+            .sorted()
+            .collect()
     }
 
     pub fn inner(&self) -> &HashMap<SocketAddr, DbVN> {
-        &self.0
+        &self.dbvns
     }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct DbproxyManager(HashMap<SocketAddr, TransceiverAddr>);
 
 impl FromIterator<(SocketAddr, TransceiverAddr)> for DbproxyManager {
@@ -222,11 +442,25 @@ impl DbproxyManager {
         &self.0
     }
 
-    pub fn get(&self, dbproxy_addr: &SocketAddr) -> TransceiverAddr {
-        self.0
-            .get(dbproxy_addr)
-            .expect(&format!("{} is not in the DbproxyManager", dbproxy_addr))
-            .clone()
+    /// Returns `None` if the dbproxy has been removed
+    pub fn get(&self, dbproxy_addr: &SocketAddr) -> Option<TransceiverAddr> {
+        self.0.get(dbproxy_addr).cloned()
+    }
+
+    /// Add a dbproxy, returns `false` if it already exists
+    pub fn insert(&mut self, dbproxy_addr: SocketAddr, transceiver_addr: TransceiverAddr) -> bool {
+        match self.0.entry(dbproxy_addr) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(transceiver_addr);
+                true
+            }
+        }
+    }
+
+    /// Remove a dbproxy, its `Transceiver` stops once all the returned and cloned `TransceiverAddr` are dropped
+    pub fn remove(&mut self, dbproxy_addr: &SocketAddr) -> Option<TransceiverAddr> {
+        self.0.remove(dbproxy_addr)
     }
 
     pub fn to_vec(&self) -> Vec<(SocketAddr, TransceiverAddr)> {
@@ -245,22 +479,109 @@ mod tests_connection_state {
     #[test]
     fn test_replace_txvn() {
         let client_addr: SocketAddr = "127.0.0.1:6666".parse().unwrap();
+        let in_flight_txvns = InFlightTxVNs::default();
         let mut conn_state = ConnectionState::new(
             client_addr.clone(),
             Arc::new(RwLock::new(ClientRecord::new(client_addr, false))),
+            in_flight_txvns.clone(),
         );
         assert_eq!(*conn_state.current_txvn(), None);
+        assert!(in_flight_txvns.is_empty());
 
         assert_eq!(conn_state.replace_txvn(Some(TxVN::new().erase_uuid())), None);
         assert_eq!(
             *conn_state.current_txvn(),
             Some(TxVN::new()).map(|txvn| txvn.erase_uuid())
         );
+        assert_eq!(in_flight_txvns.len(), 1);
         assert_eq!(
             conn_state.replace_txvn(None),
             Some(TxVN::new()).map(|txvn| txvn.erase_uuid())
         );
         assert_eq!(*conn_state.current_txvn(), None);
+        assert!(in_flight_txvns.is_empty());
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let client_addr: SocketAddr = "127.0.0.1:6666".parse().unwrap();
+        let mut conn_state = ConnectionState::new(
+            client_addr,
+            Arc::new(RwLock::new(ClientRecord::new(client_addr, false))),
+            InFlightTxVNs::default(),
+        );
+        assert!(!conn_state.has_savepoint());
+
+        conn_state.replace_txvn(Some(TxVN::new()));
+        conn_state.set_savepoint();
+        assert!(conn_state.has_savepoint());
+
+        conn_state.replace_txvn(Some(TxVN::new()));
+        assert!(conn_state.has_savepoint());
+
+        conn_state.replace_txvn(None);
+        assert!(!conn_state.has_savepoint());
+    }
+
+    #[test]
+    fn test_tx_expiry() {
+        let client_addr: SocketAddr = "127.0.0.1:6666".parse().unwrap();
+        let mut conn_state = ConnectionState::new(
+            client_addr,
+            Arc::new(RwLock::new(ClientRecord::new(client_addr, false))),
+            InFlightTxVNs::default(),
+        );
+        let conf = SchedulerConf::default()
+            .set_tx_idle_timeout(Some(100))
+            .set_tx_max_lifetime(Some(1000));
+        assert_eq!(conn_state.tx_expiry(&conf, Instant::now()), None);
+
+        conn_state.replace_txvn(Some(TxVN::new()));
+        let begin_time = Instant::now();
+        let (deadline, err) = conn_state.tx_expiry(&conf, begin_time).unwrap();
+        assert_eq!(deadline, begin_time + Duration::from_millis(100));
+        assert_eq!(err, MsqlError::timeout("transaction was idle for more than 100 ms"));
+
+        // The lifetime is not renewed by the following queries
+        conn_state.replace_txvn(Some(TxVN::new()));
+        let (deadline, err) = conn_state
+            .tx_expiry(&conf, begin_time + Duration::from_millis(950))
+            .unwrap();
+        assert!(deadline <= begin_time + Duration::from_millis(1000));
+        assert_eq!(
+            err,
+            MsqlError::timeout("transaction exceeded the maximum lifetime of 1000 ms")
+        );
+        assert_eq!(conn_state.tx_expiry(&SchedulerConf::default(), begin_time), None);
+
+        conn_state.replace_txvn(None);
+        assert_eq!(conn_state.tx_expiry(&conf, Instant::now()), None);
+
+        assert_eq!(conn_state.take_tx_timeout(), None);
+        conn_state.set_tx_timeout(err.clone());
+        assert_eq!(conn_state.take_tx_timeout(), Some(err));
+        assert_eq!(conn_state.take_tx_timeout(), None);
+    }
+}
+
+/// Unit test for `RequestCanceller`
+#[cfg(test)]
+mod tests_request_canceller {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let canceller = RequestCanceller::default();
+        assert_eq!(canceller.cancelled_err(), None);
+        assert!(timeout(Duration::from_millis(50), canceller.cancelled()).await.is_err());
+
+        // Only the first cancellation counts
+        let canceller_cloned = canceller.clone();
+        canceller_cloned.cancel(MsqlError::cancelled("first"));
+        canceller_cloned.cancel(MsqlError::cancelled("second"));
+        assert_eq!(canceller.cancelled_err(), Some(MsqlError::cancelled("first")));
+        assert_eq!(canceller.cancelled().await, MsqlError::cancelled("first"));
     }
 }
 
@@ -283,6 +604,81 @@ mod tests_dbvnmanager {
         assert!(!dbvnmanager.inner().contains_key(&"127.0.0.1:10003".parse().unwrap()));
     }
 
+    #[test]
+    fn test_insert_remove() {
+        let mut dbvnmanager = DbVNManager::from_iter(vec!["127.0.0.1:10000".parse().unwrap()]);
+        let dbproxy_addr: SocketAddr = "127.0.0.1:10001".parse().unwrap();
+        let version_vector: HashMap<_, _> = vec![(String::from("t0"), 3)].into_iter().collect();
+
+        assert!(dbvnmanager.insert(dbproxy_addr, DbVN::from(version_vector.clone())));
+        assert!(!dbvnmanager.insert(dbproxy_addr, DbVN::default()));
+        assert!(dbvnmanager
+            .inner()
+            .get(&dbproxy_addr)
+            .unwrap()
+            .has_reached(&version_vector));
+        assert_eq!(
+            dbvnmanager.get_all_behind(&version_vector),
+            vec!["127.0.0.1:10000".parse::<SocketAddr>().unwrap()]
+        );
+
+        assert!(dbvnmanager.remove(&dbproxy_addr).is_some());
+        assert!(dbvnmanager.remove(&dbproxy_addr).is_none());
+
+        // Releasing on a removed dbproxy is ignored
+        dbvnmanager.release_version(
+            &dbproxy_addr,
+            TxVN::new()
+                .set_txtablevns(vec![TxTableVN::new("t0", 3, RWOperation::W)])
+                .into_dbvn_release_request(),
+        );
+        assert!(!dbvnmanager.inner().contains_key(&dbproxy_addr));
+    }
+
+    #[test]
+    fn test_catching_up() {
+        let mut dbvnmanager = DbVNManager::from_iter(vec![
+            "127.0.0.1:10000".parse().unwrap(),
+            "127.0.0.1:10001".parse().unwrap(),
+        ]);
+        let dbproxy_addr: SocketAddr = "127.0.0.1:10001".parse().unwrap();
+        let tableops = TableOps::from_iter(vec![TableOp::new("t0", RWOperation::R)]);
+        let txvn = TxVN::new().set_txtablevns(vec![TxTableVN::new("t0", 0, RWOperation::R)]);
+
+        assert!(dbvnmanager.set_catching_up(&dbproxy_addr, true));
+        assert!(!dbvnmanager.set_catching_up(&"127.0.0.1:10002".parse().unwrap(), true));
+        assert!(dbvnmanager.is_catching_up(&dbproxy_addr));
+
+        // Versions are still released, but reads are not sent to it
+        dbvnmanager.release_version(
+            &dbproxy_addr,
+            TxVN::new()
+                .set_txtablevns(vec![TxTableVN::new("t0", 0, RWOperation::W)])
+                .into_dbvn_release_request(),
+        );
+        assert_eq!(
+            dbvnmanager.get_all_that_can_execute_read_query(&tableops, &txvn),
+            vec![("127.0.0.1:10000".parse().unwrap(), vec![DbTableVN::new("t0", 0)])]
+        );
+        assert_eq!(
+            dbvnmanager.get_most_updated_version_for_read_query(&tableops),
+            vec![("127.0.0.1:10000".parse().unwrap(), vec![DbTableVN::new("t0", 0)])]
+        );
+        assert!(dbvnmanager.remove(&"127.0.0.1:10000".parse().unwrap()).is_some());
+        assert_eq!(dbvnmanager.get_most_updated_version_for_read_query(&tableops), vec![]);
+
+        assert!(dbvnmanager.set_catching_up(&dbproxy_addr, false));
+        assert_eq!(
+            dbvnmanager.get_most_updated_version_for_read_query(&tableops),
+            vec![(dbproxy_addr, vec![DbTableVN::new("t0", 1)])]
+        );
+
+        // Removing also clears it
+        assert!(dbvnmanager.set_catching_up(&dbproxy_addr, true));
+        assert!(dbvnmanager.remove(&dbproxy_addr).is_some());
+        assert!(!dbvnmanager.is_catching_up(&dbproxy_addr));
+    }
+
     #[test]
     fn test_get_all_that_can_execute_read_query() {
         let dbvnmanager = DbVNManager::from_iter(vec![
@@ -386,10 +782,10 @@ mod tests_dbvnmanager {
                 TableOp::new("t0", RWOperation::R),
                 TableOp::new("t1", RWOperation::R)
             ])),
-            (
+            vec![(
                 "127.0.0.1:10002".parse().unwrap(),
                 vec![DbTableVN::new("t0", 1), DbTableVN::new("t1", 1)]
-            )
+            )]
         );
 
         dbvnmanager.release_version(
@@ -407,10 +803,30 @@ mod tests_dbvnmanager {
                 TableOp::new("t0", RWOperation::R),
                 TableOp::new("t1", RWOperation::R)
             ])),
-            (
+            vec![(
                 "127.0.0.1:10001".parse().unwrap(),
                 vec![DbTableVN::new("t0", 2), DbTableVN::new("t1", 1)]
-            )
+            )]
+        );
+
+        // All of the equally most updated
+        assert_eq!(
+            dbvnmanager.get_most_updated_version_for_read_query(&TableOps::from_iter(vec![TableOp::new(
+                "t2",
+                RWOperation::R
+            )])),
+            vec![("127.0.0.1:10001".parse().unwrap(), vec![DbTableVN::new("t2", 1)])]
+        );
+        assert_eq!(
+            dbvnmanager.get_most_updated_version_for_read_query(&TableOps::from_iter(vec![TableOp::new(
+                "t3",
+                RWOperation::R
+            )])),
+            vec![
+                ("127.0.0.1:10000".parse().unwrap(), vec![DbTableVN::new("t3", 0)]),
+                ("127.0.0.1:10001".parse().unwrap(), vec![DbTableVN::new("t3", 0)]),
+                ("127.0.0.1:10002".parse().unwrap(), vec![DbTableVN::new("t3", 0)])
+            ]
         );
     }
 
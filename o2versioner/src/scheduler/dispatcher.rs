@@ -1,15 +1,21 @@
-use super::core::{DbVNManager, DbproxyManager};
+use super::core::{DbVNManager, DbproxyManager, RequestCanceller};
+use super::logging::{DivergenceRecord, DivergenceRecords};
+use super::membership::Evictor;
+use super::replica_selector::{Candidate, ReplicaSelector};
 use super::transceiver::*;
 use crate::comm::scheduler_dbproxy::*;
 use crate::comm::MsqlResponse;
 use crate::core::*;
+use crate::util::conf::SchedulerConf;
 use crate::util::executor::Executor;
 use crate::util::executor_addr::*;
 use async_trait::async_trait;
 use futures::prelude::*;
+use std::cmp::Reverse;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::sync::{oneshot, Mutex, Notify, RwLock};
+use tokio::time::Instant;
 use tracing::{debug, error, field, info, info_span, instrument, trace, warn, Instrument, Span};
 
 /// Response sent from dispatcher to handler
@@ -33,6 +39,7 @@ pub struct DispatcherRequest {
     request_meta: RequestMeta,
     command: Msql,
     txvn: Option<TxVN>,
+    canceller: RequestCanceller,
 }
 
 impl ExecutorRequest for DispatcherRequest {
@@ -45,8 +52,17 @@ impl DispatcherRequest {
             request_meta: RequestMeta::new(&client_meta, current_request_id),
             command,
             txvn,
+            canceller: RequestCanceller::default(),
         }
     }
+
+    /// Set the `RequestCanceller` to cancel the request with, which is never cancelled by default.
+    /// The cancelled request is replied with the error of the `RequestCanceller`,
+    /// unless it is already done by the dbproxies
+    pub fn set_canceller(mut self, canceller: RequestCanceller) -> Self {
+        self.canceller = canceller;
+        self
+    }
 }
 
 /// A state containing shared variables
@@ -54,15 +70,31 @@ impl DispatcherRequest {
 struct State {
     dbvn_manager: Arc<RwLock<DbVNManager>>,
     dbvn_manager_notify: Arc<Notify>,
-    dbproxy_manager: DbproxyManager,
+    dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+    replica_selector: Arc<dyn ReplicaSelector>,
+    evictor: Evictor,
+    divergence_records: DivergenceRecords,
+    commit_ack: CommitAck,
 }
 
 impl State {
-    fn new(dbvn_manager: Arc<RwLock<DbVNManager>>, dbproxy_manager: DbproxyManager) -> Self {
+    fn new(
+        dbvn_manager: Arc<RwLock<DbVNManager>>,
+        dbvn_manager_notify: Arc<Notify>,
+        dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+        replica_selector: Arc<dyn ReplicaSelector>,
+        evictor: Evictor,
+        divergence_records: DivergenceRecords,
+        commit_ack: CommitAck,
+    ) -> Self {
         Self {
             dbvn_manager,
-            dbvn_manager_notify: Arc::new(Notify::new()),
+            dbvn_manager_notify,
             dbproxy_manager,
+            replica_selector,
+            evictor,
+            divergence_records,
+            commit_ack,
         }
     }
 
@@ -74,57 +106,160 @@ impl State {
         Span::current().record("cmd", &request.command.as_ref());
         trace!("<- {:?} {:?}", request.command, request.txvn);
 
-        // Check whether there are no dbproxies in managers at all,
-        // if such case, early exit
-        if self.dbproxy_manager.inner().is_empty() {
-            warn!("There are currently no dbproxy servers online, Scheduler dispatcher skipped the work");
-
-            reply_ch
-                .unwrap()
-                .send(DispatcherReply::new(
-                    MsqlResponse::err("Dbproxy servers are all offline", &request.command),
-                    request.txvn,
-                ))
-                .expect("Dispatcher cannot reply response to handler");
-
-            return;
-        }
+        let DispatcherRequest {
+            request_meta,
+            command,
+            txvn,
+            canceller,
+        } = request;
+        // Msql::EndTx and Msql::Savepoint must be applied on all replicas alike once requested
+        let canceller = match &command {
+            Msql::Query(_) => canceller,
+            _ => RequestCanceller::default(),
+        };
+        let shared_reply_channel = Arc::new(Mutex::new(reply_ch));
+        let is_read_only = command
+            .try_get_query()
+            .is_ok_and(|query| query.access_pattern().is_read_only());
+
+        // A ReadOnly query is retried on another dbproxy if its dbproxy fails,
+        // while other commands are sent to all dbproxies and only fail if all of them fail
+        loop {
+            // A cancelled request is not sent to any dbproxy
+            if let Some(err) = canceller.cancelled_err() {
+                warn!("Request cancelled: {}", err.message());
+                Self::reply(&shared_reply_channel, MsqlResponse::err(err, &command), txvn).await;
+                return;
+            }
 
-        let dbproxy_addrs = match &request.command {
-            Msql::BeginTx(_) => panic!("Dispatcher does not support Msql::BeginTx command"),
-            Msql::Query(msqlquery) => {
-                Span::current().record("op", &&format!("{:?}", msqlquery.tableops().access_pattern())[..]);
-                match msqlquery.tableops().access_pattern() {
-                    AccessPattern::Mixed => panic!("Does not support query with mixed R and W"),
-                    AccessPattern::ReadOnly => {
-                        vec![self.wait_on_version_for_read_only_query(msqlquery, &request.txvn).await]
+            let dbproxy_addrs = match &command {
+                Msql::BeginTx(_) => panic!("Dispatcher does not support Msql::BeginTx command"),
+                Msql::Query(msqlquery) => {
+                    Span::current().record("op", &&format!("{:?}", msqlquery.tableops().access_pattern())[..]);
+                    match msqlquery.tableops().access_pattern() {
+                        AccessPattern::ReadOnly => {
+                            let dbproxy = tokio::select! {
+                                dbproxy = self.wait_on_version_for_read_only_query(msqlquery, &txvn) => dbproxy,
+                                _ = canceller.cancelled() => continue,
+                            };
+                            dbproxy.into_iter().collect()
+                        }
+                        // Mixed queries are routed like writes
+                        AccessPattern::WriteOnly | AccessPattern::Mixed => {
+                            self.dbproxy_manager.read().await.to_vec()
+                        }
                     }
-                    AccessPattern::WriteOnly => self.dbproxy_manager.to_vec(),
                 }
+                Msql::EndTx(msqlendtx) => {
+                    Span::current().record("op", &&format!("{:?}", msqlendtx.mode())[..]);
+                    self.dbproxy_manager.read().await.to_vec()
+                }
+                // Savepoints must be kept in sync on all replicas
+                Msql::Savepoint(msqlsavepoint) => {
+                    Span::current().record("op", &&format!("{:?}", msqlsavepoint.mode())[..]);
+                    self.dbproxy_manager.read().await.to_vec()
+                }
+            };
+
+            // Check whether there are no dbproxies in managers at all,
+            // if such case, early exit
+            if dbproxy_addrs.is_empty() {
+                warn!("There are currently no dbproxy servers online, Scheduler dispatcher skipped the work");
+                let msqlresponse =
+                    MsqlResponse::err(MsqlError::unavailable("Dbproxy servers are all offline"), &command);
+                Self::reply(&shared_reply_channel, msqlresponse, txvn).await;
+                return;
             }
-            Msql::EndTx(msqlendtx) => {
-                Span::current().record("op", &&format!("{:?}", msqlendtx.mode())[..]);
-                self.dbproxy_manager.to_vec()
+
+            let result = self
+                .dispatch(
+                    dbproxy_addrs,
+                    &request_meta,
+                    &command,
+                    &txvn,
+                    &canceller,
+                    &shared_reply_channel,
+                )
+                .await;
+            match result {
+                Ok(()) => break,
+                Err(DispatchFailure::Unavailable(e)) if is_read_only => {
+                    warn!("Retrying on another dbproxy: {}", e);
+                }
+                Err(DispatchFailure::Unavailable(e)) => {
+                    let msqlresponse = MsqlResponse::err(MsqlError::unavailable(e), &command);
+                    Self::reply(&shared_reply_channel, msqlresponse, txvn).await;
+                    return;
+                }
+                Err(DispatchFailure::ReplicaFailure(txvn)) => {
+                    self.abort(&request_meta, txvn).await;
+                    let msqlresponse = MsqlResponse::err(
+                        MsqlError::unavailable("transaction aborted due to replica failure"),
+                        &command,
+                    );
+                    Self::reply(&shared_reply_channel, msqlresponse, None).await;
+                    return;
+                }
             }
-        };
+        }
 
+        debug!("all tasks done");
+    }
+
+    /// Send the request to all `dbproxy_addrs` and reply the first response back to handler,
+    /// returns the error of the last failed dbproxy if none of them responds
+    ///
+    /// A write query in a transaction is only replied once all dbproxies respond. If it fails on some of them only,
+    /// nothing is replied and the transaction must be aborted.
+    /// A commit is only replied once the number of dbproxies required by its `CommitAck` have applied it,
+    /// and the reply reports that number. The required number is recomputed against the live dbproxies
+    /// once any of them fails.
+    /// The responses to a write query or `Msql::EndTx` are compared once all dbproxies respond.
+    /// Once `canceller` is cancelled, the dbproxies are told to cancel the request if it is a query,
+    /// and all errors they respond are replaced by the error of the `canceller`
+    async fn dispatch(
+        &self,
+        dbproxy_addrs: Vec<(SocketAddr, TransceiverAddr)>,
+        request_meta: &RequestMeta,
+        command: &Msql,
+        txvn: &Option<TxVN>,
+        canceller: &RequestCanceller,
+        shared_reply_channel: &Arc<Mutex<Option<oneshot::Sender<DispatcherReply>>>>,
+    ) -> Result<(), DispatchFailure> {
         let num_dbproxy = dbproxy_addrs.len();
+        // Msql::EndTx and Msql::Savepoint are never cancelled
+        let cancelled_transceiver_addrs: Vec<_> = match command {
+            Msql::Query(_) => dbproxy_addrs
+                .iter()
+                .map(|(_, transceiver_addr)| transceiver_addr.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
         let dbproxy_tasks_stream = stream::iter(dbproxy_addrs);
 
-        let DispatcherRequest {
-            request_meta,
-            command,
-            txvn,
-        } = request;
-
-        let msg = Message::MsqlRequest(request_meta, command.clone(), txvn.clone());
-        let shared_reply_channel = Arc::new(Mutex::new(reply_ch));
+        let msg = Message::MsqlRequest(request_meta.clone(), command.clone(), txvn.clone());
+        let failure = Arc::new(Mutex::new(None));
+        let sent = Instant::now();
+        let is_write = match command {
+            Msql::Query(query) => !query.access_pattern().is_read_only(),
+            Msql::EndTx(_) => true,
+            _ => false,
+        };
+        let responses = Mutex::new(Vec::new());
+        let commit_ack = match command {
+            Msql::EndTx(endtx) if endtx.mode() == MsqlEndTxMode::Commit => endtx.ack().unwrap_or(self.commit_ack),
+            _ => CommitAck::First,
+        };
+        let held_reply = Mutex::new(None);
+        let holds_reply = is_write && txvn.is_some() && command.try_get_query().is_ok();
 
         // Send all requests to transceivers
+        let failure_cloned = failure.clone();
         let addr_receipts: Vec<_> = dbproxy_tasks_stream
             .then(move |(dbproxy_addr, transceiver_addr)| {
                 let msg = msg.clone();
                 let dbproxy_addr_clone = dbproxy_addr.clone();
+                let failure_cloned = failure_cloned.clone();
                 trace!("-> {:?}", msg);
                 async move {
                     transceiver_addr
@@ -132,7 +267,11 @@ impl State {
                             dbproxy_addr: dbproxy_addr.clone(),
                             dbproxy_msg: msg,
                         })
-                        .inspect_err(|e| error!("Cannot send: {:?}", e))
+                        .or_else(|e| async move {
+                            error!("Cannot send: {:?}", e);
+                            failure_cloned.lock().await.replace(e.clone());
+                            Err(e)
+                        })
                         .map_ok(|receipt| (dbproxy_addr, receipt))
                         .await
                 }
@@ -149,15 +288,21 @@ impl State {
 
         // Must join here before continue, so that the next query from the same user in the same transaction won't
         // get ahead of current query by any chances
+        let progress = Mutex::new(CommitProgress {
+            applied: 0,
+            live: addr_receipts.len(),
+        });
 
         // Wait for responses from transceivers concurrently
-        stream::iter(addr_receipts)
-            .for_each_concurrent(None, move |(dbproxy_addr, transceiver_receipt)| {
+        let wait_responses = stream::iter(addr_receipts)
+            .for_each_concurrent(None, |(dbproxy_addr, transceiver_receipt)| {
                 let command_cloned = command.clone();
                 let txvn_cloned = txvn.clone();
-                let shared_reply_channel_cloned = shared_reply_channel.clone();
+                let failure_cloned = failure.clone();
+                let responses = &responses;
+                let progress = &progress;
+                let held_reply = &held_reply;
                 async move {
-                    let is_endtx = command_cloned.is_endtx();
                     let msqlresponse = transceiver_receipt
                         .wait_request()
                         .and_then(|res| match res {
@@ -167,15 +312,39 @@ impl State {
                             },
                             _ => panic!("Invalid TransceiverReply message"),
                         })
-                        .unwrap_or_else(|e| {
-                            if is_endtx {
-                                MsqlResponse::endtx_err(e)
-                            } else {
-                                MsqlResponse::query_err(e)
-                            }
-                        })
                         .await;
 
+                    // The failed dbproxy is evicted, and the request is completed by the others
+                    let mut msqlresponse = match msqlresponse {
+                        Ok(msqlresponse) => msqlresponse,
+                        Err(e) => {
+                            warn!("Dbproxy failed: {}", e);
+                            failure_cloned.lock().await.replace(e);
+                            // The Msql::EndTx may only be waiting for the failed dbproxy
+                            if command_cloned.is_endtx() {
+                                let mut progress = progress.lock().await;
+                                progress.live -= 1;
+                                if progress.applied > 0 && progress.applied == commit_ack.required(progress.live) {
+                                    let msqlresponse = MsqlResponse::endtx_ok(format!(
+                                        "Applied on {} of {} replicas",
+                                        progress.applied, num_dbproxy
+                                    ));
+                                    Self::reply(shared_reply_channel, msqlresponse, None).await;
+                                }
+                            }
+                            return;
+                        }
+                    };
+                    self.replica_selector.record_latency(&dbproxy_addr, sent.elapsed());
+                    if msqlresponse.is_err() {
+                        if let Some(err) = canceller.cancelled_err() {
+                            msqlresponse = MsqlResponse::err(err, &command_cloned);
+                        }
+                    }
+                    if is_write {
+                        responses.lock().await.push((dbproxy_addr, msqlresponse.clone()));
+                    }
+
                     // Release table versions
                     let txvn = match command_cloned {
                         Msql::Query(query) if query.has_early_release() && txvn_cloned.is_some() => {
@@ -199,29 +368,185 @@ impl State {
                         _ => txvn_cloned,
                     };
 
-                    // If the oneshot channel is not consumed, consume it to send the reply back to handler
-                    if let Some(reply) = shared_reply_channel_cloned.lock().await.take() {
-                        trace!("~~ {:?}", msqlresponse);
-                        reply
-                            .send(DispatcherReply::new(msqlresponse, txvn))
-                            .expect(&format!("Cannot reply response to handler"));
-                    } else {
-                        trace!("Not reply to handler: {:?}", msqlresponse);
+                    // Errors of Msql::EndTx are only replied if no dbproxy applies it
+                    if msqlresponse.is_endtx() {
+                        if msqlresponse.is_err() {
+                            return;
+                        }
+                        let mut progress = progress.lock().await;
+                        progress.applied += 1;
+                        if progress.applied != commit_ack.required(progress.live) {
+                            return;
+                        }
+                        msqlresponse = MsqlResponse::endtx_ok(format!(
+                            "Applied on {} of {} replicas",
+                            progress.applied, num_dbproxy
+                        ));
+                    }
+
+                    if holds_reply {
+                        held_reply.lock().await.get_or_insert((msqlresponse, txvn));
+                        return;
                     }
+                    Self::reply(shared_reply_channel, msqlresponse, txvn).await;
                 }
                 .instrument(info_span!("<-dbproxy", N = num_dbproxy, message = %dbproxy_addr))
-            })
+            });
+
+        // The cancelled request is still replied by the dbproxies
+        let cancel_dbproxies = async {
+            canceller.cancelled().await;
+            if cancelled_transceiver_addrs.is_empty() {
+                return future::pending::<()>().await;
+            }
+            info!("Cancelling on {} dbproxies", cancelled_transceiver_addrs.len());
+            for transceiver_addr in cancelled_transceiver_addrs {
+                let request = TransceiverRequest::Cancel(request_meta.clone());
+                if let Err(e) = transceiver_addr.request_nowait(request).await {
+                    warn!("Cannot cancel: {:?}", e);
+                }
+            }
+            future::pending::<()>().await
+        };
+        tokio::select! {
+            _ = wait_responses => {}
+            _ = cancel_dbproxies => {}
+        }
+
+        if let Msql::EndTx(_) = command {
+            let CommitProgress { applied, live } = progress.into_inner();
+            let first_err = responses
+                .lock()
+                .await
+                .iter()
+                .map(|(_, msqlresponse)| msqlresponse)
+                .find(|msqlresponse| msqlresponse.is_err())
+                .cloned();
+            let msqlresponse = match first_err {
+                Some(msqlresponse) if applied == 0 => Some(msqlresponse),
+                // The commit cannot be retried, as it is applied on some dbproxies
+                _ if applied > 0 => Some(MsqlResponse::endtx_err(
+                    MsqlError::unavailable(format!(
+                        "Applied on {} of {} replicas, {} required",
+                        applied,
+                        num_dbproxy,
+                        commit_ack.required(live)
+                    ))
+                    .set_retryable(false),
+                )),
+                _ => None,
+            };
+            if let Some(msqlresponse) = msqlresponse {
+                Self::reply(shared_reply_channel, msqlresponse, None).await;
+            }
+        }
+
+        let responses = responses.into_inner();
+        let failed_dbproxy_addrs: Vec<_> = responses
+            .iter()
+            .filter(|(_, msqlresponse)| msqlresponse.is_err())
+            .map(|(dbproxy_addr, _)| *dbproxy_addr)
+            .collect();
+        let has_replica_failure = !failed_dbproxy_addrs.is_empty() && failed_dbproxy_addrs.len() < responses.len();
+
+        // The dbproxies do not diverge if the transaction is aborted on all of them
+        if is_write && !(holds_reply && has_replica_failure) {
+            self.check_divergence(request_meta, command, responses).await;
+        }
+
+        if let Some((msqlresponse, txvn)) = held_reply.into_inner() {
+            if has_replica_failure {
+                warn!("Write query failed on dbproxies {:?}", failed_dbproxy_addrs);
+                return Err(DispatchFailure::ReplicaFailure(
+                    txvn.expect("Write query in a transaction must include Some(TxVN)"),
+                ));
+            }
+            Self::reply(shared_reply_channel, msqlresponse, txvn).await;
+        }
+
+        if shared_reply_channel.lock().await.is_some() {
+            let failure = failure.lock().await.take();
+            Err(DispatchFailure::Unavailable(
+                failure.unwrap_or_else(|| String::from("Dbproxy servers are all offline")),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Roll back the transaction on all dbproxies, which releases all versions of `txvn`
+    async fn abort(&self, request_meta: &RequestMeta, txvn: TxVN) {
+        warn!("Aborting the transaction due to replica failure");
+        let dbproxy_addrs = self.dbproxy_manager.read().await.to_vec();
+        let rollback = Msql::EndTx(MsqlEndTx::rollback());
+        // Nothing to reply back to handler
+        let reply_channel = Arc::new(Mutex::new(None));
+        let _ = self
+            .dispatch(
+                dbproxy_addrs,
+                request_meta,
+                &rollback,
+                &Some(txvn),
+                &RequestCanceller::default(),
+                &reply_channel,
+            )
             .await;
+    }
 
-        debug!("all tasks done");
+    /// Compare the responses of all dbproxies to the same write query or `Msql::EndTx`. If they differ,
+    /// the divergence is recorded, and the dbproxies that responded differently from the majority are quarantined
+    async fn check_divergence(
+        &self,
+        request_meta: &RequestMeta,
+        command: &Msql,
+        responses: Vec<(SocketAddr, MsqlResponse)>,
+    ) {
+        let groups = group_responses(responses);
+        if groups.len() <= 1 {
+            return;
+        }
+
+        // Nobody is quarantined if there is a tie
+        let has_majority = groups[0].1.len() > groups[1].1.len();
+        let request_type = match command {
+            Msql::Query(query) => query.access_pattern().as_ref().to_owned(),
+            Msql::EndTx(endtx) => endtx.mode().as_ref().to_owned(),
+            other => other.as_ref().to_owned(),
+        };
+        let record = DivergenceRecord::new(request_meta.client_addr, request_type, &groups, has_majority);
+        error!("Dbproxies diverged: {:?}", record);
+        self.divergence_records.push(record).await;
+
+        if has_majority {
+            for dbproxy_addr in groups[1..].iter().flat_map(|(_, dbproxy_addrs)| dbproxy_addrs) {
+                self.evictor.quarantine(dbproxy_addr).await;
+            }
+        }
+    }
+
+    /// If the oneshot channel is not consumed, consume it to send the reply back to handler
+    async fn reply(
+        shared_reply_channel: &Arc<Mutex<Option<oneshot::Sender<DispatcherReply>>>>,
+        msqlresponse: MsqlResponse,
+        txvn: Option<TxVN>,
+    ) {
+        if let Some(reply) = shared_reply_channel.lock().await.take() {
+            trace!("~~ {:?}", msqlresponse);
+            reply
+                .send(DispatcherReply::new(msqlresponse, txvn))
+                .expect(&format!("Cannot reply response to handler"));
+        } else {
+            trace!("Not reply to handler: {:?}", msqlresponse);
+        }
     }
 
+    /// Returns `None` if there are no dbproxies left
     #[instrument(skip(self), fields(msqlquery, txvn))]
     async fn wait_on_version_for_read_only_query(
         &self,
         msqlquery: &MsqlQuery,
         txvn: &Option<TxVN>,
-    ) -> (SocketAddr, TransceiverAddr) {
+    ) -> Option<(SocketAddr, TransceiverAddr)> {
         assert_eq!(
             msqlquery.tableops().access_pattern(),
             AccessPattern::ReadOnly,
@@ -235,90 +560,95 @@ impl State {
             self.dbvn_manager.read().await.inner()
         );
 
-        if let Some(txvn) = txvn {
-            // The scheduler blocks read queries until at least one database has, for all tables in the query,
-            // version numbers that are greater than or equal to the version numbers assigned to the transaction
-            // for these tables. If there are several such replicas, the least loaded replica is chosen
-
-            let mut avail_dbproxy;
-            // Need to wait on version
-            loop {
-                avail_dbproxy = self
-                    .dbvn_manager
-                    .read()
-                    .await
-                    .get_all_that_can_execute_read_query(msqlquery.tableops(), txvn);
-
-                // Found a dbproxy that can execute the read query
-                if avail_dbproxy.len() > 0 {
+        // The scheduler blocks read queries until at least one database has, for all tables in the query,
+        // version numbers that are greater than or equal to the version numbers assigned to the transaction
+        // for these tables. If there are several such replicas, one of them is chosen by the `ReplicaSelector`
+        //
+        // Single read operation that does not have a TxVN
+        // Since a single-read transaction executes only at one replica,
+        // there is no need to assign cluster-wide version numbers to such a transaction. Instead,
+        // the scheduler forwards the transaction to the chosen replica, without assigning version
+        // numbers. Because the order of execution for a single-read transaction is ultimately decided
+        // by the database proxy, the scheduler does not block such queries.
+        // The scheduler attempts to reduce this wait by selecting a replica that has an up-to-date
+        // version of each table needed by the query. In this case, up-to-date version means that the
+        // table has a version number greater than or equal to the highest version number assigned to
+        // any previous transaction on that table. Such a replica may not necessarily exist.
+        let mut avail_dbproxy_load: Vec<_>;
+        // Need to wait on version
+        loop {
+            let avail_dbproxy = self
+                .get_transceivers(|dbvn_manager| match txvn {
+                    Some(txvn) => dbvn_manager.get_all_that_can_execute_read_query(msqlquery.tableops(), txvn),
+                    // Find the replicas that have the highest version number for the query
+                    None => dbvn_manager.get_most_updated_version_for_read_query(msqlquery.tableops()),
+                })
+                .await;
+
+            // Found a dbproxy that can execute the read query
+            if avail_dbproxy.len() > 0 {
+                // Failed dbproxies cannot reply the load, and they are already evicted
+                avail_dbproxy_load = stream::iter(avail_dbproxy)
+                    .filter_map(|(dbproxy_addr, transceiver_addr, dbtablevns)| async move {
+                        match transceiver_addr.request(TransceiverRequest::DbproxyLoad).await {
+                            Ok(TransceiverReply::DbproxyLoad(load)) => {
+                                Some((dbproxy_addr, transceiver_addr, load, dbtablevns))
+                            }
+                            Ok(_) => panic!("Unexpected TransceiverReply"),
+                            Err(_) => None,
+                        }
+                    })
+                    .collect()
+                    .await;
+                if !avail_dbproxy_load.is_empty() {
                     break;
-                } else {
-                    // Did not find any dbproxy that can execute the read quer, need to wait on version
-                    // Wait for any updates on dbvn_manager
-                    self.dbvn_manager_notify.notified().await;
                 }
+            } else if self.dbvn_manager.read().await.inner().is_empty() {
+                return None;
+            } else {
+                // Did not find any dbproxy that can execute the read quer, need to wait on version
+                // Wait for any updates on dbvn_manager
+                self.dbvn_manager_notify.notified().await;
             }
+        }
 
-            // Choose the dbproxy with least load
-            let avail_dbproxy_load: Vec<_> =
-                stream::iter(avail_dbproxy.into_iter().map(|(dbproxy_addr, dbtablevns)| {
-                    (
-                        dbproxy_addr.clone(),
-                        self.dbproxy_manager.get(&dbproxy_addr),
-                        dbtablevns,
-                    )
-                }))
-                .then(|(dbproxy_addr, transceiver_addr, dbtablevns)| async move {
-                    match transceiver_addr.request(TransceiverRequest::DbproxyLoad).await.unwrap() {
-                        TransceiverReply::DbproxyLoad(load) => (dbproxy_addr, load, dbtablevns),
-                        _ => panic!("Unexpected TransceiverReply"),
-                    }
-                })
-                .collect()
-                .await;
+        let candidates: Vec<_> = avail_dbproxy_load
+            .iter()
+            .map(|(dbproxy_addr, _transceiver_addr, load, _dbtablevns)| Candidate::new(*dbproxy_addr, *load))
+            .collect();
+        let selected = self.replica_selector.select(msqlquery.tableops(), &candidates);
+        let (dbproxy_addr, transceiver_addr, load, dbtablevns) = avail_dbproxy_load
+            .into_iter()
+            .find(|(dbproxy_addr, _transceiver_addr, _load, _dbtablevns)| *dbproxy_addr == selected)
+            .expect("ReplicaSelector selected a dbproxy not in candidates");
+        trace!(
+            "Found dbproxy {} with load {} for executing the ReadOnly query: {:?}",
+            dbproxy_addr,
+            load,
+            dbtablevns,
+        );
+        Some((dbproxy_addr, transceiver_addr))
+    }
 
-            let selected_dbproxy = avail_dbproxy_load
-                .into_iter()
-                .min_by_key(|(_dbproxy_addr, load, _dbtablevns)| load.clone())
-                .unwrap();
-            trace!(
-                "Found dbproxy {} with load {} for executing the ReadOnly query: {:?}",
-                selected_dbproxy.0,
-                selected_dbproxy.1,
-                selected_dbproxy.2,
-            );
-            (
-                selected_dbproxy.0.clone(),
-                self.dbproxy_manager.get(&selected_dbproxy.0),
-            )
-        } else {
-            // Single read operation that does not have a TxVN
-            // Since a single-read transaction executes only at one replica,
-            // there is no need to assign cluster-wide version numbers to such a transaction. Instead,
-            // the scheduler forwards the transaction to the chosen replica, without assigning version
-            // numbers. Because the order of execution for a single-read transaction is ultimately decided
-            // by the database proxy, the scheduler does not block such queries.
-            // The scheduler attempts to reduce this wait by selecting a replica that has an up-to-date
-            // version of each table needed by the query. In this case, up-to-date version means that the
-            // table has a version number greater than or equal to the highest version number assigned to
-            // any previous transaction on that table. Such a replica may not necessarily exist.
-
-            // Find the replica that has the highest version number for the query
-            let selected_dbproxy = self
-                .dbvn_manager
-                .read()
-                .await
-                .get_most_updated_version_for_read_query(msqlquery.tableops());
-            trace!(
-                "Found dbproxy {} for executing the single ReadOnly query: {:?}",
-                selected_dbproxy.0,
-                selected_dbproxy.1
-            );
-            (
-                selected_dbproxy.0.clone(),
-                self.dbproxy_manager.get(&selected_dbproxy.0),
-            )
-        }
+    /// Pair the dbproxies found by `find` in `DbVNManager` with their `TransceiverAddr`
+    ///
+    /// Both managers are locked in the same order as the admin adding or removing a dbproxy,
+    /// so that they always have the same set of dbproxies here
+    async fn get_transceivers<F>(&self, find: F) -> Vec<(SocketAddr, TransceiverAddr, Vec<DbTableVN>)>
+    where
+        F: FnOnce(&DbVNManager) -> Vec<(SocketAddr, Vec<DbTableVN>)>,
+    {
+        let dbvn_manager = self.dbvn_manager.read().await;
+        let dbproxy_manager = self.dbproxy_manager.read().await;
+        find(&dbvn_manager)
+            .into_iter()
+            .map(|(dbproxy_addr, dbtablevns)| {
+                let transceiver_addr = dbproxy_manager
+                    .get(&dbproxy_addr)
+                    .expect("DbproxyManager does not match with DbVNManager");
+                (dbproxy_addr, transceiver_addr, dbtablevns)
+            })
+            .collect()
     }
 
     /// This should be called whenever dbproxy sent a response back for a `Msql::EndTx`
@@ -333,6 +663,34 @@ impl State {
     }
 }
 
+/// Group the dbproxies by their responses, the largest group first
+fn group_responses(responses: Vec<(SocketAddr, MsqlResponse)>) -> Vec<(MsqlResponse, Vec<SocketAddr>)> {
+    let mut groups: Vec<(MsqlResponse, Vec<SocketAddr>)> = Vec::new();
+    for (dbproxy_addr, response) in responses {
+        match groups.iter_mut().find(|(group_response, _)| *group_response == response) {
+            Some((_, dbproxy_addrs)) => dbproxy_addrs.push(dbproxy_addr),
+            None => groups.push((response, vec![dbproxy_addr])),
+        }
+    }
+    groups.iter_mut().for_each(|(_, dbproxy_addrs)| dbproxy_addrs.sort());
+    groups.sort_by_key(|(_, dbproxy_addrs)| (Reverse(dbproxy_addrs.len()), dbproxy_addrs[0]));
+    groups
+}
+
+/// Number of dbproxies that applied a `Msql::EndTx`, and that have not failed
+struct CommitProgress {
+    applied: usize,
+    live: usize,
+}
+
+/// Why the dbproxies cannot complete a request
+enum DispatchFailure {
+    /// None of the dbproxies responds, with the error of the last failed dbproxy
+    Unavailable(String),
+    /// A write query in a transaction failed on some of the dbproxies only, with the `TxVN` of the transaction
+    ReplicaFailure(TxVN),
+}
+
 pub type DispatcherAddr = ExecutorAddr<DispatcherRequest>;
 
 /// An executor for dispatching Sql requests to the corresponding
@@ -340,7 +698,7 @@ pub type DispatcherAddr = ExecutorAddr<DispatcherRequest>;
 /// 
 /// Write queries are sent to all dbproxies, while read queries
 /// are gated here until their versions are ready and are only
-/// sent to a single dbproxy
+/// sent to a single dbproxy, chosen by the `ReplicaSelector`
 pub struct Dispatcher {
     state: State,
     request_rx: RequestReceiver<DispatcherRequest>,
@@ -348,13 +706,25 @@ pub struct Dispatcher {
 
 impl Dispatcher {
     pub fn new(
-        queue_size: usize,
+        conf: &SchedulerConf,
         dbvn_manager: Arc<RwLock<DbVNManager>>,
-        dbproxy_manager: DbproxyManager,
+        dbvn_manager_notify: Arc<Notify>,
+        dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+        replica_selector: Arc<dyn ReplicaSelector>,
+        evictor: Evictor,
+        divergence_records: DivergenceRecords,
     ) -> (DispatcherAddr, Dispatcher) {
-        let state = State::new(dbvn_manager, dbproxy_manager);
+        let state = State::new(
+            dbvn_manager,
+            dbvn_manager_notify,
+            dbproxy_manager,
+            replica_selector,
+            evictor,
+            divergence_records,
+            conf.commit_ack,
+        );
 
-        let (addr, request_rx) = DispatcherAddr::new(queue_size);
+        let (addr, request_rx) = DispatcherAddr::new(conf.dispatcher_queue_size);
         (addr, Dispatcher { state, request_rx })
     }
 }
@@ -378,3 +748,42 @@ impl Executor for Dispatcher {
         info!("DIES");
     }
 }
+
+#[cfg(test)]
+mod tests_dispatcher {
+    use super::*;
+    use crate::comm::MsqlResult;
+
+    /// Unit test for `group_responses`
+    #[test]
+    fn test_group_responses() {
+        let addr = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+        let ok = MsqlResponse::Query(Ok(MsqlResult::affected(1)));
+        let diverged = MsqlResponse::Query(Ok(MsqlResult::affected(0)));
+        let err = MsqlResponse::Query(Err(MsqlError::database("deadlock detected", Some("40P01"))));
+
+        assert_eq!(
+            group_responses(vec![(addr(3), ok.clone()), (addr(1), ok.clone())]),
+            vec![(ok.clone(), vec![addr(1), addr(3)])]
+        );
+        assert_eq!(
+            group_responses(vec![
+                (addr(1), diverged.clone()),
+                (addr(4), err.clone()),
+                (addr(3), ok.clone()),
+                (addr(2), ok.clone())
+            ]),
+            vec![
+                (ok.clone(), vec![addr(2), addr(3)]),
+                (diverged.clone(), vec![addr(1)]),
+                (err, vec![addr(4)])
+            ]
+        );
+
+        // Ties are ordered by the addresses
+        assert_eq!(
+            group_responses(vec![(addr(2), ok.clone()), (addr(1), diverged.clone())]),
+            vec![(diverged, vec![addr(1)]), (ok, vec![addr(2)])]
+        );
+    }
+}
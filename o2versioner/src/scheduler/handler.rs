@@ -2,32 +2,39 @@ use super::admin_handler::*;
 use super::core::*;
 use super::dispatcher::*;
 use super::logging::*;
-use super::transceiver::*;
+use super::membership::*;
+use super::pg_handler::*;
+use super::replica_selector::new_replica_selector;
+use super::sequencer_pool::*;
 use crate::comm::MsqlResponse;
 use crate::comm::{scheduler_api, scheduler_sequencer};
 use crate::core::*;
 use crate::util::conf::*;
 use crate::util::executor::Executor;
 use crate::util::tcp;
-use bb8::Pool;
 use futures::future::Either;
 use futures::pin_mut;
 use futures::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::signal;
-use tokio::sync::oneshot;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
 use tokio_serde::formats::SymmetricalJson;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 use tracing::{error, field, info, info_span, instrument, trace, warn, Instrument, Span};
 use unicase::UniCase;
 
+/// Maximum time for the admin `quiesce` command to wait for all in-flight transactions
+const QUIESCE_TIMEOUT: Duration = Duration::from_secs(60);
+const QUIESCE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Main entrance for the Scheduler
 ///
 /// # Modes
@@ -38,6 +45,10 @@ use unicase::UniCase;
 /// to the admin port, which will then force to not accept any new
 /// connections.
 ///
+/// If `SchedulerConf::pg_addr` is set, clients speaking the PostgreSQL
+/// frontend/backend protocol are accepted as well, and the limit of
+/// the total maximum number of input connections applies to each port.
+///
 /// # Notes
 /// Upon receiving CTRL-C signal, scheduler will shutdown with
 /// possible *INCONSISTEN* state. Please use the above mentioned modes
@@ -47,39 +58,38 @@ pub async fn main(conf: Conf) {
     // Create the main state
     let state = State::new(DbVNManager::from_iter(conf.to_dbproxy_addrs()), conf.clone());
 
-    // The current task completes as soon as start_tcplistener finishes,
-    // which happens when it reaches the max_conn_till_dropped if not None,
-    // which is really depending on the incoming connections into Scheduler.
-    // So the sequencer_socket_pool here does not require an explicit
-    // max_lifetime being set.
-    // Prepare sequencer pool
-    let sequencer_socket_pool = Pool::builder()
-        .max_size(conf.scheduler.sequencer_pool_size)
-        .min_idle(Some(1))
-        .build(tcp::TcpStreamConnectionManager::new(conf.sequencer.to_addr()).await)
+    // Prepare sequencer pool, all connections are closed once it is dropped
+    let sequencer_socket_pool = SequencerPool::new(conf.sequencer.to_addrs(), conf.scheduler.sequencer_pool_size);
+    sequencer_socket_pool
+        .connect_all(Duration::from_secs(30), Duration::from_millis(100))
         .await
         .unwrap();
 
-    // Prepare transceiver
-    let (transceiver_addrs, transceivers): (Vec<_>, Vec<_>) = conf
-        .to_dbproxy_addrs()
-        .into_iter()
-        .map(|dbproxy_addr| {
-            let (trscaddrs, trsc) = Transceiver::new(conf.scheduler.transceiver_queue_size, dbproxy_addr);
-            ((dbproxy_addr, trscaddrs), trsc)
-        })
-        .unzip();
+    // Prepare transceiver, launched as a new task including the ones of dbproxies added later by admin
+    let dbvn_manager_notify = Arc::new(Notify::new());
+    let (transceiver_tx, transceiver_rx) = mpsc::unbounded_channel();
+    let membership = Membership::new(
+        state.share_dbvn_manager(),
+        dbvn_manager_notify.clone(),
+        conf.to_dbproxy_addrs(),
+        transceiver_tx,
+        conf.scheduler.clone(),
+    )
+    .await;
 
     // Prepare dispatcher
     let (dispatcher_addr, dispatcher) = Dispatcher::new(
-        conf.scheduler.dispatcher_queue_size,
+        &conf.scheduler,
         state.share_dbvn_manager(),
-        DbproxyManager::from_iter(transceiver_addrs),
+        dbvn_manager_notify,
+        membership.share_dbproxy_manager(),
+        new_replica_selector(conf.scheduler.replica_selection),
+        membership.share_evictor(),
+        state.share_divergence_records(),
     );
 
-    // Launch transceiver as a new task
     let transceiver_handle = tokio::spawn(
-        stream::iter(transceivers)
+        transceiver_rx
             .for_each_concurrent(None, |transceiver| Box::new(transceiver).run())
             .in_current_span(),
     );
@@ -87,12 +97,61 @@ pub async fn main(conf: Conf) {
     // Launch dispatcher as a new task
     let dispatcher_handle = tokio::spawn(Box::new(dispatcher).run().in_current_span());
 
-    // Create a stop_signal channel if admin mode is turned on
-    let (stop_tx, stop_rx) = if conf.scheduler.admin_addr.is_some() {
-        let (tx, rx) = oneshot::channel();
-        (Some(tx), Some(rx))
+    // Create a stop_signal channel for each listener if admin mode is turned on
+    let new_stop_channel = || {
+        if conf.scheduler.admin_addr.is_some() {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        }
+    };
+    let (stop_tx, stop_rx) = new_stop_channel();
+    let mut stop_txs: Vec<_> = stop_tx.into_iter().collect();
+
+    // Launch PostgreSQL protocol handler as a new task if enabled
+    let pg_handler_handle = if let Some(pg_addr) = conf.scheduler.pg_addr.clone() {
+        let (pg_stop_tx, pg_stop_rx) = new_stop_channel();
+        stop_txs.extend(pg_stop_tx);
+        let conf_clone = conf.scheduler.clone();
+        let sequencer_socket_pool_clone = sequencer_socket_pool.clone();
+        let state_clone = state.clone();
+        let dispatcher_addr_clone = dispatcher_addr.clone();
+        let dbproxy_manager_clone = membership.share_dbproxy_manager();
+        tokio::spawn(
+            tcp::start_tcplistener(
+                pg_addr,
+                move |tcp_stream| {
+                    let sequencer_socket_pool = sequencer_socket_pool_clone.clone();
+                    let state_cloned = state_clone.clone();
+                    let conf = conf_clone.clone();
+                    let dispatcher_addr = Arc::new(dispatcher_addr_clone.clone());
+                    let dbproxy_manager = dbproxy_manager_clone.clone();
+                    async move {
+                        let client_addr = tcp_stream.peer_addr().unwrap();
+                        let conn_state = ConnectionState::new(
+                            client_addr,
+                            state_cloned.share_client_record(client_addr).await,
+                            state_cloned.share_in_flight_txvns(),
+                        );
+                        process_pg_connection(
+                            conf,
+                            tcp_stream,
+                            conn_state,
+                            sequencer_socket_pool,
+                            dispatcher_addr,
+                            dbproxy_manager,
+                        )
+                        .await;
+                    }
+                },
+                conf.scheduler.max_connection,
+                pg_stop_rx,
+            )
+            .in_current_span(),
+        )
     } else {
-        (None, None)
+        tokio::spawn(future::ready(()))
     };
 
     // Launch main handler as a new task
@@ -112,8 +171,11 @@ pub async fn main(conf: Conf) {
                 let dispatcher_addr = Arc::new(dispatcher_addr.clone());
                 async move {
                     let client_addr = tcp_stream.peer_addr().unwrap();
-                    let conn_state =
-                        ConnectionState::new(client_addr.clone(), state_cloned.share_client_record(client_addr).await);
+                    let conn_state = ConnectionState::new(
+                        client_addr.clone(),
+                        state_cloned.share_client_record(client_addr).await,
+                        state_cloned.share_in_flight_txvns(),
+                    );
                     process_connection(conf, tcp_stream, conn_state, sequencer_socket_pool, dispatcher_addr).await;
                 }
             },
@@ -124,7 +186,7 @@ pub async fn main(conf: Conf) {
     );
 
     // Combine the dispatcher handle and main handler handle into a main_handle
-    let main_handle = future::try_join3(transceiver_handle, dispatcher_handle, handler_handle);
+    let main_handle = future::try_join4(transceiver_handle, dispatcher_handle, handler_handle, pg_handler_handle);
     let ctrl_c_handle = signal::ctrl_c();
 
     pin_mut!(ctrl_c_handle);
@@ -143,9 +205,10 @@ pub async fn main(conf: Conf) {
         let admin_handle = tokio::spawn(
             admin(
                 admin_addr.parse().unwrap(),
-                stop_tx,
+                stop_txs,
                 sequencer_socket_pool,
                 state.clone(),
+                membership,
             )
             .in_current_span(),
         );
@@ -160,6 +223,7 @@ pub async fn main(conf: Conf) {
             _ = admin_handle => {}
         };
     } else {
+        drop(membership);
         main_handle_with_signal.await.unwrap();
     }
 
@@ -169,27 +233,36 @@ pub async fn main(conf: Conf) {
     info!("DIES");
 }
 
-#[instrument(name = "admin", skip(admin_addr, stop_tx, sequencer_socket_pool, state))]
+#[instrument(name = "admin", skip(admin_addr, stop_txs, sequencer_socket_pool, state, membership))]
 async fn admin(
     admin_addr: SocketAddr,
-    stop_tx: Option<oneshot::Sender<()>>,
-    sequencer_socket_pool: Pool<tcp::TcpStreamConnectionManager>,
+    stop_txs: Vec<oneshot::Sender<()>>,
+    sequencer_socket_pool: SequencerPool,
     state: State,
+    membership: Membership,
 ) {
     start_admin_tcplistener(admin_addr, move |msg| {
         let sequencer_socket_pool = sequencer_socket_pool.clone();
         let state = state.clone();
+        let membership = membership.clone();
         async move {
             let cmd_registry: HashMap<_, Vec<_>> = vec![
                 ("block_unblock", vec!["block", "unblock"]),
                 ("kill", vec!["kill", "exit", "quit"]),
                 ("perf", vec!["perf"]),
+                ("quiesce", vec!["quiesce"]),
+                ("dbproxies", vec!["dbproxies"]),
+                ("add_remove", vec!["add", "remove"]),
+                ("catchup", vec!["catchup"]),
             ]
             .into_iter()
             .map(|(k, vs)| (k, vs.into_iter().map(|v| UniCase::new(String::from(v))).collect()))
             .collect();
 
-            let command = UniCase::new(msg);
+            // Some commands take an argument after the command, such as `add 127.0.0.1:12345`
+            let mut args = msg.split_whitespace().map(String::from);
+            let command = UniCase::new(args.next().unwrap_or_default());
+            let arg = args.next();
 
             if cmd_registry.get("block_unblock").unwrap().contains(&command) {
                 let m = if command == UniCase::new("block") {
@@ -197,7 +270,8 @@ async fn admin(
                 } else {
                     scheduler_sequencer::Message::RequestUnblock
                 };
-                let reply = tcp::send_and_receive_single_as_json(&mut sequencer_socket_pool.get().await.unwrap(), m)
+                let reply = sequencer_socket_pool
+                    .request(m)
                     .map_err(|e| e.to_string())
                     .and_then(|res| match res {
                         scheduler_sequencer::Message::ReplyBlockUnblock(m) => future::ok(m),
@@ -209,21 +283,54 @@ async fn admin(
             } else if cmd_registry.get("kill").unwrap().contains(&command) {
                 let reply = format!(
                     "Scheduler is going to Stop. {}",
-                    tcp::send_and_receive_single_as_json(
-                        &mut sequencer_socket_pool.get().await.unwrap(),
-                        scheduler_sequencer::Message::RequestStop,
-                    )
-                    .map_err(|e| e.to_string())
-                    .and_then(|res| match res {
-                        scheduler_sequencer::Message::ReplyStop => {
-                            future::ok(String::from("Sequencer received Stop"))
-                        }
-                        _ => future::ok(String::from("Invalid response from Sequencer")),
-                    })
-                    .map_ok_or_else(|e| e, |m| m)
-                    .await
+                    sequencer_socket_pool
+                        .request(scheduler_sequencer::Message::RequestStop)
+                        .map_err(|e| e.to_string())
+                        .and_then(|res| match res {
+                            scheduler_sequencer::Message::ReplyStop => {
+                                future::ok(String::from("Sequencer received Stop"))
+                            }
+                            _ => future::ok(String::from("Invalid response from Sequencer")),
+                        })
+                        .map_ok_or_else(|e| e, |m| m)
+                        .await
                 );
                 (reply, false)
+            } else if cmd_registry.get("quiesce").unwrap().contains(&command) {
+                (quiesce(&sequencer_socket_pool, &state).await, true)
+            } else if cmd_registry.get("dbproxies").unwrap().contains(&command) {
+                (
+                    format!(
+                        "Dbproxies: {:?}, evicted: {:?}, quarantined: {:?}",
+                        membership.list().await,
+                        membership.list_evicted().await,
+                        membership.list_quarantined().await
+                    ),
+                    true,
+                )
+            } else if cmd_registry.get("add_remove").unwrap().contains(&command) {
+                let reply = match arg.as_ref().map(|arg| arg.parse::<SocketAddr>()) {
+                    Some(Ok(dbproxy_addr)) if command == UniCase::new("add") => {
+                        add_dbproxy(&sequencer_socket_pool, &state, &membership, dbproxy_addr).await
+                    }
+                    Some(Ok(dbproxy_addr)) => {
+                        remove_dbproxy(&sequencer_socket_pool, &state, &membership, dbproxy_addr).await
+                    }
+                    _ => format!("Usage: {} <dbproxy_addr>", command),
+                };
+                (reply, true)
+            } else if cmd_registry.get("catchup").unwrap().contains(&command) {
+                let reply = match arg.as_ref().map(|arg| arg.parse::<SocketAddr>()) {
+                    Some(Ok(dbproxy_addr)) => {
+                        catch_up_dbproxy(&sequencer_socket_pool, &state, &membership, dbproxy_addr).await
+                    }
+                    _ => format!(
+                        "Usage: {} <dbproxy_addr>. The dbproxy must already have the schema of all tables, \
+                        and foreign keys between the tables are not supported",
+                        command
+                    ),
+                };
+                (reply, true)
             } else if cmd_registry.get("perf").unwrap().contains(&command) {
                 let location_dumped = state.dump_perf_log().await;
                 (format!("Perf logging dumped to {:?}", location_dumped), true)
@@ -237,11 +344,184 @@ async fn admin(
     })
     .await;
 
-    stop_tx.unwrap().send(()).unwrap();
+    stop_txs.into_iter().for_each(|stop_tx| stop_tx.send(()).unwrap());
 
     info!("DIES");
 }
 
+/// Block the Sequencer, and then wait for all in-flight transactions to finish
+///
+/// The Sequencer is left blocked, even if it times out after `QUIESCE_TIMEOUT`.
+async fn quiesce(sequencer_socket_pool: &SequencerPool, state: &State) -> String {
+    quiesced_version_vector(sequencer_socket_pool, state).await.map_or_else(
+        |e| format!("{}. Sequencer is still blocked", e),
+        |version_vector| format!("Quiesced at version vector {:?}", sorted(version_vector)),
+    )
+}
+
+/// Block the Sequencer, wait for all in-flight transactions to finish, and then return the version vector
+///
+/// Quiesced once no `ConnectionState` has a `TxVN`, and every dbproxy has reached the
+/// `next_for_write` of the Sequencer for all tables, which is the consistent version vector.
+async fn quiesced_version_vector(
+    sequencer_socket_pool: &SequencerPool,
+    state: &State,
+) -> Result<HashMap<String, VN>, String> {
+    let version_vector: HashMap<_, _> = match sequencer_socket_pool
+        .request(scheduler_sequencer::Message::RequestBlock)
+        .and_then(|_| sequencer_socket_pool.request(scheduler_sequencer::Message::RequestVNRecords))
+        .await
+    {
+        Ok(scheduler_sequencer::Message::ReplyVNRecords(vn_records)) => vn_records
+            .into_iter()
+            .map(|(table, vn_record)| (table, vn_record.next_for_write))
+            .collect(),
+        Ok(_) => return Err(String::from("Invalid response from Sequencer")),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let deadline = Instant::now() + QUIESCE_TIMEOUT;
+    loop {
+        let in_flight_txvns = state.share_in_flight_txvns();
+        let dbproxies_behind = state.share_dbvn_manager().read().await.get_all_behind(&version_vector);
+        if in_flight_txvns.is_empty() && dbproxies_behind.is_empty() {
+            return Ok(version_vector);
+        } else if Instant::now() >= deadline {
+            return Err(format!(
+                "Timed out quiescing, with {} transactions in flight and dbproxies {:?} behind",
+                in_flight_txvns.len(),
+                dbproxies_behind
+            ));
+        }
+        sleep(QUIESCE_POLL_INTERVAL).await;
+    }
+}
+
+/// Add a dbproxy whose database holds the same data as the others, at the quiesced version vector
+async fn add_dbproxy(
+    sequencer_socket_pool: &SequencerPool,
+    state: &State,
+    membership: &Membership,
+    dbproxy_addr: SocketAddr,
+) -> String {
+    if membership.contains(&dbproxy_addr).await {
+        return format!("Dbproxy {} already exists", dbproxy_addr);
+    }
+    let tcp_stream = match TcpStream::connect(dbproxy_addr).await {
+        Ok(tcp_stream) => tcp_stream,
+        Err(e) => return format!("Cannot connect to dbproxy {}: {}", dbproxy_addr, e),
+    };
+
+    change_membership(sequencer_socket_pool, state, |version_vector| async move {
+        membership
+            .add(dbproxy_addr, tcp_stream, version_vector.clone())
+            .await
+            .map(|_| {
+                format!(
+                    "Added dbproxy {} at version vector {:?}",
+                    dbproxy_addr,
+                    sorted(version_vector)
+                )
+            })
+    })
+    .await
+}
+
+/// Add a new or recovered dbproxy by copying the data of a live dbproxy at the quiesced version vector
+///
+/// The Sequencer is unblocked once the donor is dumped, and the reply is sent once the dbproxy is caught up.
+async fn catch_up_dbproxy(
+    sequencer_socket_pool: &SequencerPool,
+    state: &State,
+    membership: &Membership,
+    dbproxy_addr: SocketAddr,
+) -> String {
+    if membership.contains(&dbproxy_addr).await {
+        return format!("Dbproxy {} already exists", dbproxy_addr);
+    }
+    let tcp_stream = match TcpStream::connect(dbproxy_addr).await {
+        Ok(tcp_stream) => tcp_stream,
+        Err(e) => return format!("Cannot connect to dbproxy {}: {}", dbproxy_addr, e),
+    };
+
+    let mut receipt = None;
+    let receipt_mut = &mut receipt;
+    let reply = change_membership(sequencer_socket_pool, state, |version_vector| async move {
+        membership
+            .catch_up(dbproxy_addr, tcp_stream, version_vector.clone())
+            .await
+            .map(|(donor_addr, restoring_receipt)| {
+                *receipt_mut = Some(restoring_receipt);
+                format!(
+                    "Catching up dbproxy {} from donor {} at version vector {:?}",
+                    dbproxy_addr,
+                    donor_addr,
+                    sorted(version_vector)
+                )
+            })
+    })
+    .await;
+
+    match receipt {
+        Some(receipt) => format!(
+            "{}. {}",
+            reply,
+            membership.finish_catch_up(&dbproxy_addr, receipt).await.map_or_else(
+                |e| format!("Cannot catch up dbproxy {}: {}", dbproxy_addr, e),
+                |_| format!("Caught up dbproxy {}", dbproxy_addr)
+            )
+        ),
+        None => reply,
+    }
+}
+
+/// Remove a dbproxy once quiesced, so that it does not have any unfinished transaction
+async fn remove_dbproxy(
+    sequencer_socket_pool: &SequencerPool,
+    state: &State,
+    membership: &Membership,
+    dbproxy_addr: SocketAddr,
+) -> String {
+    if !membership.contains(&dbproxy_addr).await {
+        return format!("Dbproxy {} does not exist", dbproxy_addr);
+    }
+
+    change_membership(sequencer_socket_pool, state, |version_vector| async move {
+        membership.remove(&dbproxy_addr).await.map(|_| {
+            format!(
+                "Removed dbproxy {} at version vector {:?}",
+                dbproxy_addr,
+                sorted(version_vector)
+            )
+        })
+    })
+    .await
+}
+
+/// Quiesce, apply `change` at the quiesced version vector, and then unblock the Sequencer
+///
+/// The Sequencer is unblocked afterwards, whether `change` is applied or not.
+async fn change_membership<F, Fut>(sequencer_socket_pool: &SequencerPool, state: &State, change: F) -> String
+where
+    F: FnOnce(HashMap<String, VN>) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+{
+    let reply = match quiesced_version_vector(sequencer_socket_pool, state).await {
+        Ok(version_vector) => change(version_vector).await,
+        Err(e) => Err(e),
+    };
+
+    let unblocked = sequencer_socket_pool
+        .request(scheduler_sequencer::Message::RequestUnblock)
+        .await
+        .map_or_else(|e| e.to_string(), |_| String::from("Sequencer is unblocked"));
+    format!("{}. {}", reply.unwrap_or_else(|e| e), unblocked)
+}
+
+fn sorted(version_vector: HashMap<String, VN>) -> BTreeMap<String, VN> {
+    version_vector.into_iter().collect()
+}
+
 /// Process the `tcp_stream` for a single connection
 ///
 /// Will process all messages sent via this `tcp_stream` on this tcp connection.
@@ -251,7 +531,7 @@ async fn process_connection(
     conf: SchedulerConf,
     mut socket: TcpStream,
     conn_state: ConnectionState,
-    sequencer_socket_pool: Pool<tcp::TcpStreamConnectionManager>,
+    sequencer_socket_pool: SequencerPool,
     dispatcher_addr: Arc<DispatcherAddr>,
 ) {
     let client_addr = socket.peer_addr().unwrap();
@@ -265,57 +545,152 @@ async fn process_connection(
     let delimited_write = FramedWrite::new(tcp_write, LengthDelimitedCodec::new());
 
     // Deserialize/Serialize frames using JSON codec
-    let serded_read = SymmetricallyFramed::new(delimited_read, SymmetricalJson::<scheduler_api::Message>::default());
-    let serded_write = SymmetricallyFramed::new(delimited_write, SymmetricalJson::<scheduler_api::Message>::default());
+    let mut serded_read =
+        SymmetricallyFramed::new(delimited_read, SymmetricalJson::<scheduler_api::Message>::default());
+    let mut serded_write =
+        SymmetricallyFramed::new(delimited_write, SymmetricalJson::<scheduler_api::Message>::default());
 
     // Each individual connection communication is executed in blocking order,
     // the socket is dedicated for one session only, opposed to being shared for multiple sessions.
     // At any given point, there is at most one transaction.
     // Connection/session specific storage
     let conn_state = Arc::new(Mutex::new(conn_state));
-    let conn_state_cloned = conn_state.clone();
-
-    // Process a stream of incoming messages from a single tcp connection
-    let dispatcher_addr_cloned = dispatcher_addr.clone();
-    serded_read
-        .and_then(move |msg| {
-            let conf_cloned = conf.clone();
-            let conn_state_cloned = conn_state_cloned.clone();
-            let sequencer_socket_pool_cloned = sequencer_socket_pool.clone();
-            let dispatcher_addr_cloned = dispatcher_addr_cloned.clone();
-            trace!("<- {:?}", msg);
-
-            async move {
-                Ok(process_request(
-                    conf_cloned,
-                    msg,
-                    conn_state_cloned,
-                    sequencer_socket_pool_cloned,
-                    dispatcher_addr_cloned,
-                )
-                .await)
+
+    // Process a stream of incoming messages from a single tcp connection,
+    // the transaction is rolled back if it expires while waiting for the next message.
+    // The next message is read while a request is ongoing, in case it cancels the request
+    let mut next_msg = None;
+    loop {
+        let msg = match next_msg.take() {
+            Some(msg) => msg,
+            None => {
+                let tx_expiry = conn_state.lock().await.tx_expiry(&conf, Instant::now());
+                let expired = matches!(&tx_expiry, Some((deadline, _)) if *deadline <= Instant::now());
+                tokio::select! {
+                    msg = serded_read.next(), if !expired => msg,
+                    err = wait_tx_expiry(tx_expiry) => {
+                        process_expired_tx(&conf, err, &mut *conn_state.lock().await, &dispatcher_addr).await;
+                        continue;
+                    }
+                }
             }
-        })
-        .inspect_err(|err| {
-            warn!("Can not decode input bytes: {:?}", err);
-        })
-        .forward(serded_write)
-        .map(|_| ())
-        .await;
+        };
+
+        let msg = match msg {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
+                warn!("Can not decode input bytes: {:?}", err);
+                break;
+            }
+            None => break,
+        };
+        trace!("<- {:?}", msg);
+
+        let (msg, timeout) = match msg {
+            scheduler_api::Message::RequestWithTimeout { timeout, request } => (*request, Some(timeout)),
+            scheduler_api::Message::RequestCancel => {
+                trace!("No ongoing request to cancel");
+                continue;
+            }
+            msg => (msg, None),
+        };
+
+        let request_canceller = RequestCanceller::default();
+        conn_state.lock().await.set_request_canceller(request_canceller.clone());
+        let request = process_request(
+            conf.clone(),
+            msg,
+            conn_state.clone(),
+            sequencer_socket_pool.clone(),
+            dispatcher_addr.clone(),
+        );
+        let request_timeout = wait_request_timeout(timeout);
+        pin_mut!(request, request_timeout);
+        let mut timed_out = false;
+        let response = loop {
+            tokio::select! {
+                response = &mut request => break response,
+                msg = serded_read.next(), if next_msg.is_none() => match msg {
+                    Some(Ok(scheduler_api::Message::RequestCancel)) => {
+                        request_canceller.cancel(MsqlError::cancelled("request was cancelled by the client"))
+                    }
+                    // Processed once the ongoing request is replied
+                    msg => next_msg = Some(msg),
+                },
+                err = &mut request_timeout, if !timed_out => {
+                    timed_out = true;
+                    request_canceller.cancel(err);
+                }
+            }
+        };
+        if serded_write.send(response).await.is_err() {
+            break;
+        }
+    }
 
     let mut conn_state = Arc::try_unwrap(conn_state).unwrap().into_inner();
+    process_unclosed_tx(&conf, &mut conn_state, &dispatcher_addr).await;
 
+    info!("Connection dropped. {:?}", conn_state);
+}
+
+/// Abort the transaction left unclosed by a dropped connection, if there is any
+pub(super) async fn process_unclosed_tx(
+    conf: &SchedulerConf,
+    conn_state: &mut ConnectionState,
+    dispatcher_addr: &Arc<DispatcherAddr>,
+) {
     if conn_state.current_txvn().is_some() {
         warn!(
             "Unclosed transaction. Aborting the transaction.. {:?}",
             conn_state.current_txvn()
         );
 
-        let response = process_endtx(Msql::EndTx(MsqlEndTx::rollback()), &mut conn_state, &dispatcher_addr).await;
+        let response = process_endtx(conf, Msql::EndTx(MsqlEndTx::rollback()), conn_state, dispatcher_addr).await;
         warn!("Aborting unclosed transaction successfully. {:?}", response);
     }
+}
 
-    info!("Connection dropped. {:?}", conn_state);
+/// Wait until the transaction expires at the deadline of `ConnectionState::tx_expiry`,
+/// returns the error for the client, or never returns if there is no deadline
+pub(super) async fn wait_tx_expiry(tx_expiry: Option<(Instant, MsqlError)>) -> MsqlError {
+    match tx_expiry {
+        Some((deadline, err)) => {
+            sleep_until(deadline).await;
+            err
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Wait until the request is not done within `timeout` ms, returns the error for the client,
+/// or never returns if there is no timeout
+async fn wait_request_timeout(timeout: Option<u64>) -> MsqlError {
+    match timeout {
+        Some(timeout) => {
+            sleep(Duration::from_millis(timeout)).await;
+            MsqlError::cancelled(format!("request was not done within its timeout of {} ms", timeout))
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Abort the expired transaction, and fail the next request of the connection with `err`
+pub(super) async fn process_expired_tx(
+    conf: &SchedulerConf,
+    err: MsqlError,
+    conn_state: &mut ConnectionState,
+    dispatcher_addr: &Arc<DispatcherAddr>,
+) {
+    warn!(
+        "Expired transaction, {}. Aborting the transaction.. {:?}",
+        err.message(),
+        conn_state.current_txvn()
+    );
+
+    let response = process_endtx(conf, Msql::EndTx(MsqlEndTx::rollback()), conn_state, dispatcher_addr).await;
+    warn!("Aborting expired transaction successfully. {:?}", response);
+    conn_state.set_tx_timeout(err);
 }
 
 #[instrument(name="request", skip(conf, msg, conn_state, sequencer_socket_pool, dispatcher_addr), fields(message=field::Empty, id=field::Empty, txid=field::Empty, cmd=field::Empty))]
@@ -323,7 +698,7 @@ async fn process_request(
     conf: SchedulerConf,
     msg: scheduler_api::Message,
     conn_state: Arc<Mutex<ConnectionState>>,
-    sequencer_socket_pool: Pool<tcp::TcpStreamConnectionManager>,
+    sequencer_socket_pool: SequencerPool,
     dispatcher_addr: Arc<DispatcherAddr>,
 ) -> scheduler_api::Message {
     // Not creating any critical session indeed, process_msql will always be executing in serial
@@ -331,7 +706,7 @@ async fn process_request(
     Span::current().record("message", &msg.as_ref());
 
     let response = match msg {
-        scheduler_api::Message::RequestMsql(msql) => {
+        scheduler_api::Message::RequestMsql(msql) => scheduler_api::Message::Reply(
             process_msql(
                 conf,
                 msql,
@@ -339,11 +714,11 @@ async fn process_request(
                 sequencer_socket_pool,
                 dispatcher_addr,
             )
-            .await
-        }
+            .await,
+        ),
         scheduler_api::Message::RequestMsqlText(msqltext) => match Msql::try_from(msqltext) {
             // Try to convert MsqlText to Msql first
-            Ok(msql) => {
+            Ok(msql) => scheduler_api::Message::Reply(
                 process_msql(
                     conf,
                     msql,
@@ -351,8 +726,8 @@ async fn process_request(
                     sequencer_socket_pool,
                     dispatcher_addr,
                 )
-                .await
-            }
+                .await,
+            ),
             Err(e) => scheduler_api::Message::InvalidMsqlText(e.to_owned()),
         },
         scheduler_api::Message::RequestCrash(reason) => {
@@ -368,26 +743,37 @@ async fn process_request(
     // conn_state_guard should be dropped here
 }
 
-async fn process_msql(
+/// Process a single `Msql` request of the connection
+pub(super) async fn process_msql(
     conf: SchedulerConf,
     msql: Msql,
     conn_state: &mut ConnectionState,
-    sequencer_socket_pool: Pool<tcp::TcpStreamConnectionManager>,
+    sequencer_socket_pool: SequencerPool,
     dispatcher_addr: Arc<DispatcherAddr>,
-) -> scheduler_api::Message {
+) -> MsqlResponse {
     Span::current().record("cmd", &msql.as_ref());
     Span::current().record("txid", &conn_state.client_meta().current_txid());
     Span::current().record("id", &conn_state.current_request_id().await);
 
+    // The client is notified once its transaction is aborted for timing out
+    if let Some(err) = conn_state.take_tx_timeout() {
+        warn!("{:?}", err);
+        return MsqlResponse::err(err, &msql);
+    }
+
     // Start the RequestRecord
     let reqrecord = RequestRecord::start(&msql, conn_state.current_txvn());
     let msqlresponse = match msql {
-        Msql::BeginTx(msqlbegintx) => process_begintx(msqlbegintx, conn_state, &sequencer_socket_pool).await,
+        Msql::BeginTx(msqlbegintx) => process_begintx(&conf, msqlbegintx, conn_state, &sequencer_socket_pool).await,
         Msql::Query(mut query) => {
             if query.has_early_release() {
-                if conf.disable_early_release || query.tableops().access_pattern().is_read_only() {
+                // Writes after a savepoint may still be rolled back, so they cannot be early released
+                if conf.disable_early_release
+                    || query.tableops().access_pattern().is_read_only()
+                    || conn_state.has_savepoint()
+                {
                     warn!(
-                        "Removing early release annotation due to settings or ReadOnly query. {:?} {:?}",
+                        "Removing early release annotation due to settings, ReadOnly query or savepoint. {:?} {:?}",
                         query.tableops(),
                         query.early_release_tables()
                     );
@@ -396,7 +782,7 @@ async fn process_msql(
             }
 
             if conn_state.current_txvn().is_none()
-                && (query.access_pattern().is_write_only() || conf.disable_single_read_optimization)
+                && (!query.access_pattern().is_read_only() || conf.disable_single_read_optimization)
             {
                 if query.access_pattern().is_read_only() {
                     info!("Unoptimized Single Read query");
@@ -404,20 +790,27 @@ async fn process_msql(
                     info!("Single Write query");
                 }
 
-                // Construct a new MsqlBeginTx
+                // Construct a new MsqlBeginTx, the query is not executed without its TxVN
                 let msqlbegintx = MsqlBeginTx::from(query.tableops().clone());
-                process_begintx(msqlbegintx, conn_state, &sequencer_socket_pool).await;
-                // Execute the query
-                let resp = process_query(Msql::Query(query), conn_state, &dispatcher_addr).await;
-                // Construct a new MsqlEndTx
-                let msqlendtx = Msql::EndTx(MsqlEndTx::commit());
-                process_endtx(msqlendtx, conn_state, &dispatcher_addr).await;
-                resp
+                match process_begintx(&conf, msqlbegintx, conn_state, &sequencer_socket_pool).await {
+                    MsqlResponse::BeginTx(Err(err)) => MsqlResponse::query_err(err),
+                    _ => {
+                        // Execute the query
+                        let resp = process_query(&conf, Msql::Query(query), conn_state, &dispatcher_addr).await;
+                        // Construct a new MsqlEndTx, unless the transaction is already aborted
+                        if conn_state.current_txvn().is_some() {
+                            let msqlendtx = Msql::EndTx(MsqlEndTx::commit());
+                            process_endtx(&conf, msqlendtx, conn_state, &dispatcher_addr).await;
+                        }
+                        resp
+                    }
+                }
             } else {
-                process_query(Msql::Query(query), conn_state, &dispatcher_addr).await
+                process_query(&conf, Msql::Query(query), conn_state, &dispatcher_addr).await
             }
         }
-        Msql::EndTx(_) => process_endtx(msql, conn_state, &dispatcher_addr).await,
+        Msql::EndTx(_) => process_endtx(&conf, msql, conn_state, &dispatcher_addr).await,
+        Msql::Savepoint(_) => process_savepoint(&conf, msql, conn_state, &dispatcher_addr).await,
     };
 
     // Store the RequestRecord
@@ -425,13 +818,13 @@ async fn process_msql(
         .push_request_record(reqrecord.finish(&msqlresponse, conn_state.current_txvn()))
         .await;
 
-    scheduler_api::Message::Reply(msqlresponse)
+    msqlresponse
 }
 
 /// Helper function to check the legality of the current `Msql` request,
 /// this should be called after legalization
-fn process_msql_legality(msql: &Msql, txvn_opt: &Option<TxVN>) -> Result<(), MsqlResponse> {
-    match Legality::final_check(msql, txvn_opt) {
+fn process_msql_legality(msql: &Msql, txvn_opt: &Option<TxVN>, conf: &SchedulerConf) -> Result<(), MsqlResponse> {
+    match Legality::final_check(msql, txvn_opt, conf.strict_annotation) {
         Legality::Critical(e) => {
             warn!("{} {:?} {:?}", e, msql, txvn_opt);
             Err(MsqlResponse::err(e, msql))
@@ -445,20 +838,23 @@ fn process_msql_legality(msql: &Msql, txvn_opt: &Option<TxVN>) -> Result<(), Msq
 }
 
 async fn process_begintx(
+    conf: &SchedulerConf,
     msqlbegintx: MsqlBeginTx,
     conn_state: &mut ConnectionState,
-    sequencer_socket_pool: &Pool<tcp::TcpStreamConnectionManager>,
+    sequencer_socket_pool: &SequencerPool,
 ) -> MsqlResponse {
-    if let Err(msqlresponse) = process_msql_legality(&Msql::BeginTx(msqlbegintx.clone()), conn_state.current_txvn()) {
+    if let Err(msqlresponse) =
+        process_msql_legality(&Msql::BeginTx(msqlbegintx.clone()), conn_state.current_txvn(), conf)
+    {
         return msqlresponse;
     }
 
     assert!(conn_state.current_txvn().is_none());
 
-    let mut sequencer_socket = sequencer_socket_pool.get().await.unwrap();
     let msg = scheduler_sequencer::Message::RequestTxVN(conn_state.client_meta().clone(), msqlbegintx);
 
-    tcp::send_and_receive_single_as_json(&mut sequencer_socket, msg)
+    sequencer_socket_pool
+        .request(msg)
         .map_err(|e| e.to_string())
         .and_then(|res| async {
             match res {
@@ -474,17 +870,21 @@ async fn process_begintx(
                 _ => Err(String::from("Invalid response from Sequencer")),
             }
         })
-        .map_ok_or_else(|e| MsqlResponse::begintx_err(e), |_| MsqlResponse::begintx_ok())
+        .map_ok_or_else(
+            |e| MsqlResponse::begintx_err(MsqlError::sequencer(e)),
+            |_| MsqlResponse::begintx_ok(),
+        )
         .instrument(info_span!("<->sequencer"))
         .await
 }
 
 async fn process_query(
+    conf: &SchedulerConf,
     msql: Msql,
     conn_state: &mut ConnectionState,
     dispatcher_addr: &Arc<DispatcherAddr>,
 ) -> MsqlResponse {
-    if let Err(msqlresponse) = process_msql_legality(&msql, conn_state.current_txvn()) {
+    if let Err(msqlresponse) = process_msql_legality(&msql, conn_state.current_txvn(), conf) {
         return msqlresponse;
     }
 
@@ -496,17 +896,24 @@ async fn process_query(
         }
     }
 
+    let request = DispatcherRequest::new(
+        conn_state.client_meta().clone(),
+        msql,
+        conn_state.current_txvn().clone(),
+        conn_state.current_request_id().await,
+    )
+    .set_canceller(conn_state.request_canceller().clone());
     dispatcher_addr
-        .request(DispatcherRequest::new(
-            conn_state.client_meta().clone(),
-            msql,
-            conn_state.current_txvn().clone(),
-            conn_state.current_request_id().await,
-        ))
+        .request(request)
         .map_ok_or_else(
-            |e| MsqlResponse::query_err(e),
+            |e| MsqlResponse::query_err(MsqlError::internal(e)),
             |res| {
                 let DispatcherReply { msql_res, txvn_res } = res;
+                // The transaction is aborted by the dispatcher if the query fails on some of the dbproxies only
+                if txvn_res.is_none() && conn_state.replace_txvn(None).is_some() {
+                    warn!("Transaction aborted due to replica failure");
+                    conn_state.client_meta_as_mut().transaction_finished();
+                }
                 conn_state.replace_txvn(txvn_res);
                 msql_res
             },
@@ -515,11 +922,12 @@ async fn process_query(
 }
 
 async fn process_endtx(
+    conf: &SchedulerConf,
     msql: Msql,
     conn_state: &mut ConnectionState,
     dispatcher_addr: &Arc<DispatcherAddr>,
 ) -> MsqlResponse {
-    if let Err(msqlresponse) = process_msql_legality(&msql, conn_state.current_txvn()) {
+    if let Err(msqlresponse) = process_msql_legality(&msql, conn_state.current_txvn(), conf) {
         return msqlresponse;
     }
 
@@ -534,7 +942,7 @@ async fn process_endtx(
             conn_state.current_request_id().await,
         ))
         .map_ok_or_else(
-            |e| MsqlResponse::endtx_err(e),
+            |e| MsqlResponse::endtx_err(MsqlError::internal(e)),
             |res| {
                 let DispatcherReply { msql_res, txvn_res } = res;
                 let existing = conn_state.replace_txvn(txvn_res);
@@ -545,3 +953,36 @@ async fn process_endtx(
         )
         .await
 }
+
+async fn process_savepoint(
+    conf: &SchedulerConf,
+    msql: Msql,
+    conn_state: &mut ConnectionState,
+    dispatcher_addr: &Arc<DispatcherAddr>,
+) -> MsqlResponse {
+    if let Err(msqlresponse) = process_msql_legality(&msql, conn_state.current_txvn(), conf) {
+        return msqlresponse;
+    }
+
+    assert!(conn_state.current_txvn().is_some());
+
+    // Versions are still held by the transaction, the savepoint does not release anything
+    conn_state.set_savepoint();
+
+    dispatcher_addr
+        .request(DispatcherRequest::new(
+            conn_state.client_meta().clone(),
+            msql,
+            conn_state.current_txvn().clone(),
+            conn_state.current_request_id().await,
+        ))
+        .map_ok_or_else(
+            |e| MsqlResponse::savepoint_err(MsqlError::internal(e)),
+            |res| {
+                let DispatcherReply { msql_res, txvn_res } = res;
+                conn_state.replace_txvn(txvn_res);
+                msql_res
+            },
+        )
+        .await
+}
@@ -29,7 +29,8 @@ impl RequestRecordStart {
         assert!(
             (req.is_begintx() && res.is_begintx())
                 || (req.is_query() && res.is_query())
-                || (req.is_endtx() && res.is_endtx()),
+                || (req.is_endtx() && res.is_endtx())
+                || (req.is_savepoint() && res.is_savepoint()),
             "Request must match with Response type"
         );
 
@@ -101,6 +102,10 @@ impl RequestRecord {
     pub fn is_endtx(&self) -> bool {
         self.req.is_endtx()
     }
+
+    pub fn is_savepoint(&self) -> bool {
+        self.req.is_savepoint()
+    }
 }
 
 /// For performance benchmarking, converted from `RequestRecord`
@@ -129,6 +134,7 @@ impl From<RequestRecord> for PerformanceRequestRecord {
                 }
             }
             Msql::EndTx(endtx) => endtx.mode().as_ref().to_owned(),
+            Msql::Savepoint(savepoint) => savepoint.mode().as_ref().to_owned(),
         };
 
         let request_result = if r.res.is_ok() { "Ok" } else { "Err" };
@@ -259,3 +265,69 @@ impl ClientRecords {
             .collect()
     }
 }
+
+/// Dbproxies replied differently to the same write query or `Msql::EndTx`, which means they diverged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DivergenceRecord {
+    client_addr: SocketAddr,
+    request_type: String,
+    timestamp: DateTime<Utc>,
+    /// Dbproxies replied the most common response
+    majority: String,
+    /// Dbproxies quarantined for replying differently from the majority
+    quarantined: String,
+    /// All distinct responses, in the same order as the dbproxies above
+    responses: String,
+}
+
+impl DivergenceRecord {
+    /// `groups` are the dbproxies grouped by their responses, where the first group is the majority if
+    /// `has_majority`, and the rest are quarantined
+    pub fn new(
+        client_addr: SocketAddr,
+        request_type: String,
+        groups: &[(MsqlResponse, Vec<SocketAddr>)],
+        has_majority: bool,
+    ) -> Self {
+        let join = |groups: &[(MsqlResponse, Vec<SocketAddr>)]| {
+            groups
+                .iter()
+                .flat_map(|(_, dbproxy_addrs)| dbproxy_addrs.iter().map(|dbproxy_addr| dbproxy_addr.to_string()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let (majority, quarantined) = if has_majority {
+            (join(&groups[..1]), join(&groups[1..]))
+        } else {
+            (String::new(), String::new())
+        };
+
+        Self {
+            client_addr,
+            request_type,
+            timestamp: Utc::now(),
+            majority,
+            quarantined,
+            responses: groups
+                .iter()
+                .map(|(response, _)| format!("{:?}", response))
+                .collect::<Vec<_>>()
+                .join(" | "),
+        }
+    }
+}
+
+/// Shareable list of `DivergenceRecord`
+#[derive(Debug, Clone, Default)]
+pub struct DivergenceRecords(Arc<Mutex<Vec<DivergenceRecord>>>);
+
+impl DivergenceRecords {
+    pub async fn push(&self, record: DivergenceRecord) {
+        self.0.lock().await.push(record);
+    }
+
+    /// Takes a snapshot of all current `DivergenceRecord`s
+    pub async fn collect(&self) -> Vec<DivergenceRecord> {
+        self.0.lock().await.clone()
+    }
+}
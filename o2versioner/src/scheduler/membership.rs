@@ -0,0 +1,357 @@
+use super::core::{DbVNManager, DbproxyManager};
+use super::transceiver::{Transceiver, TransceiverAddr, TransceiverReply, TransceiverRequest};
+use crate::comm::scheduler_dbproxy::Message;
+use crate::core::{DbVN, RequestMeta, VN};
+use crate::util::conf::SchedulerConf;
+use crate::util::executor_addr::ExecutorAddrRequestReceipt;
+use futures::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::iter::FromIterator;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex, Notify, RwLock};
+use tokio::time::Duration;
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{FramedWrite, LengthDelimitedCodec};
+use tracing::{info, warn};
+
+/// Adds and removes dbproxies while the Scheduler is running
+///
+/// Both `DbVNManager` and `DbproxyManager` are updated while holding both of their locks,
+/// in the same order as the dispatcher reading them, so that they always have the same set of dbproxies.
+///
+/// # Notes
+/// 1. `Transceiver`s of the added dbproxies are launched by sending them to `transceiver_tx`
+/// 2. Both adding and removing should be done while quiesced, so that no transaction is left
+///    unfinished on a dbproxy
+/// 3. A removed dbproxy keeps receiving replies of its outstanding requests, and is disconnected
+///    once they are all drained
+/// 4. A failed dbproxy is evicted by its `Transceiver` via `Evictor`, and can be added back later.
+///    The last live dbproxy is never evicted
+/// 5. A diverged dbproxy is quarantined by the dispatcher via `Evictor`, and can catch up later
+/// 6. A new or recovered dbproxy can catch up from a live dbproxy instead, see `Membership::catch_up`
+#[derive(Clone)]
+pub struct Membership {
+    dbvn_manager: Arc<RwLock<DbVNManager>>,
+    dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+    evictor: Evictor,
+    transceiver_tx: mpsc::UnboundedSender<Transceiver>,
+    conf: SchedulerConf,
+}
+
+impl Membership {
+    /// Launch the `Transceiver`s of the dbproxies in `dbproxy_addrs` by sending them to `transceiver_tx`,
+    /// their `DbVN`s must be already in `dbvn_manager`
+    ///
+    /// `dbvn_manager_notify` is notified whenever a dbproxy is evicted.
+    pub async fn new(
+        dbvn_manager: Arc<RwLock<DbVNManager>>,
+        dbvn_manager_notify: Arc<Notify>,
+        dbproxy_addrs: Vec<SocketAddr>,
+        transceiver_tx: mpsc::UnboundedSender<Transceiver>,
+        conf: SchedulerConf,
+    ) -> Self {
+        let dbproxy_manager = Arc::new(RwLock::new(DbproxyManager::from_iter(Vec::new())));
+        let evictor = Evictor {
+            dbvn_manager: dbvn_manager.clone(),
+            dbvn_manager_notify,
+            dbproxy_manager: Arc::downgrade(&dbproxy_manager),
+            evicted: Default::default(),
+            quarantined: Default::default(),
+        };
+        let membership = Self {
+            dbvn_manager,
+            dbproxy_manager,
+            evictor,
+            transceiver_tx,
+            conf,
+        };
+
+        let mut dbproxy_manager = membership.dbproxy_manager.write().await;
+        for dbproxy_addr in dbproxy_addrs {
+            let (transceiver_addr, transceiver) = membership.new_transceiver(dbproxy_addr);
+            membership.transceiver_tx.send(transceiver).unwrap();
+            assert!(dbproxy_manager.insert(dbproxy_addr, transceiver_addr));
+        }
+        drop(dbproxy_manager);
+
+        membership
+    }
+
+    pub fn share_dbproxy_manager(&self) -> Arc<RwLock<DbproxyManager>> {
+        self.dbproxy_manager.clone()
+    }
+
+    pub fn share_evictor(&self) -> Evictor {
+        self.evictor.clone()
+    }
+
+    /// All live dbproxies, sorted by their addresses
+    pub async fn list(&self) -> Vec<SocketAddr> {
+        let mut dbproxy_addrs: Vec<_> = self.dbproxy_manager.read().await.inner().keys().cloned().collect();
+        dbproxy_addrs.sort();
+        dbproxy_addrs
+    }
+
+    /// All evicted dbproxies that are not added back, sorted by their addresses
+    pub async fn list_evicted(&self) -> Vec<SocketAddr> {
+        self.evictor.evicted.lock().await.iter().cloned().collect()
+    }
+
+    /// All quarantined dbproxies that are not added back, sorted by their addresses
+    pub async fn list_quarantined(&self) -> Vec<SocketAddr> {
+        self.evictor.quarantined.lock().await.iter().cloned().collect()
+    }
+
+    pub async fn contains(&self, dbproxy_addr: &SocketAddr) -> bool {
+        self.dbproxy_manager.read().await.inner().contains_key(dbproxy_addr)
+    }
+
+    /// Add the dbproxy connected via `tcp_stream`, whose database holds the data at `version_vector`
+    ///
+    /// The dbproxy is told its versions before receiving any request, so `version_vector` must not change
+    /// until the dbproxy is added.
+    pub async fn add(
+        &self,
+        dbproxy_addr: SocketAddr,
+        mut tcp_stream: TcpStream,
+        version_vector: HashMap<String, VN>,
+    ) -> Result<(), String> {
+        {
+            let delimited_write = FramedWrite::new(&mut tcp_stream, LengthDelimitedCodec::new());
+            let mut serded_write = SymmetricallyFramed::new(delimited_write, SymmetricalJson::<Message>::default());
+            serded_write
+                .send(Message::InitVersion(version_vector.clone()))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let mut dbvn_manager = self.dbvn_manager.write().await;
+        let mut dbproxy_manager = self.dbproxy_manager.write().await;
+        if dbproxy_manager.inner().contains_key(&dbproxy_addr) {
+            return Err(format!("Dbproxy {} already exists", dbproxy_addr));
+        }
+
+        let (transceiver_addr, transceiver) = self.new_transceiver(dbproxy_addr);
+        self.transceiver_tx
+            .send(transceiver.set_tcp_stream(tcp_stream))
+            .map_err(|_| String::from("Scheduler is stopping"))?;
+        assert!(dbproxy_manager.insert(dbproxy_addr, transceiver_addr));
+        assert!(dbvn_manager.insert(dbproxy_addr, DbVN::from(version_vector)));
+        self.evictor.forget(&dbproxy_addr).await;
+
+        info!("Added dbproxy {}", dbproxy_addr);
+        Ok(())
+    }
+
+    /// Add the dbproxy connected via `tcp_stream` by copying the data of a live dbproxy, the donor,
+    /// at `version_vector`, returns the donor and the receipt of the restoring
+    ///
+    /// The donor is dumped right away, so `version_vector` must not change until this returns.
+    /// The dbproxy must already have the schema of all tables. It receives the write queries
+    /// once added, and holds them until restored, but it is not chosen for read queries
+    /// until `Membership::finish_catch_up`.
+    pub async fn catch_up(
+        &self,
+        dbproxy_addr: SocketAddr,
+        tcp_stream: TcpStream,
+        version_vector: HashMap<String, VN>,
+    ) -> Result<(SocketAddr, ExecutorAddrRequestReceipt<TransceiverRequest>), String> {
+        // Any request meta works, since the dbproxy has no other request at this point
+        let meta = RequestMeta {
+            client_addr: dbproxy_addr,
+            cur_txid: 0,
+            request_id: 0,
+        };
+
+        // A dbproxy that is still catching up may not be restored yet
+        let (donor_addr, donor_transceiver_addr) = {
+            let dbvn_manager = self.dbvn_manager.read().await;
+            let dbproxy_manager = self.dbproxy_manager.read().await;
+            dbproxy_manager
+                .to_vec()
+                .into_iter()
+                .filter(|(donor_addr, _)| !dbvn_manager.is_catching_up(donor_addr))
+                .min_by_key(|(donor_addr, _)| *donor_addr)
+                .ok_or_else(|| String::from("No dbproxy to catch up from"))?
+        };
+        let snapshot = match donor_transceiver_addr
+            .request(TransceiverRequest::DbproxyMsg {
+                dbproxy_addr: donor_addr,
+                dbproxy_msg: Message::SnapshotRequest(meta.clone(), version_vector.keys().cloned().collect()),
+            })
+            .await
+        {
+            Ok(TransceiverReply::DbproxyMsg(Message::Snapshot(_, Ok(snapshot)))) => snapshot,
+            Ok(TransceiverReply::DbproxyMsg(Message::Snapshot(_, Err(e)))) => {
+                return Err(format!("Cannot dump donor {}: {}", donor_addr, e))
+            }
+            Ok(_) => return Err(format!("Invalid response from donor {}", donor_addr)),
+            Err(e) => return Err(format!("Donor {} failed: {}", donor_addr, e)),
+        };
+
+        let mut dbvn_manager = self.dbvn_manager.write().await;
+        let mut dbproxy_manager = self.dbproxy_manager.write().await;
+        if dbproxy_manager.inner().contains_key(&dbproxy_addr) {
+            return Err(format!("Dbproxy {} already exists", dbproxy_addr));
+        }
+
+        // Restore is the first request on the connection, ahead of any write query
+        let (transceiver_addr, transceiver) = self.new_transceiver(dbproxy_addr);
+        let receipt = transceiver_addr
+            .request_nowait(TransceiverRequest::DbproxyMsg {
+                dbproxy_addr,
+                dbproxy_msg: Message::Restore(meta, version_vector.clone(), snapshot),
+            })
+            .await?;
+        self.transceiver_tx
+            .send(transceiver.set_tcp_stream(tcp_stream))
+            .map_err(|_| String::from("Scheduler is stopping"))?;
+        assert!(dbproxy_manager.insert(dbproxy_addr, transceiver_addr));
+        assert!(dbvn_manager.insert(dbproxy_addr, DbVN::from(version_vector)));
+        assert!(dbvn_manager.set_catching_up(&dbproxy_addr, true));
+        self.evictor.forget(&dbproxy_addr).await;
+
+        info!("Catching up dbproxy {} from donor {}", dbproxy_addr, donor_addr);
+        Ok((donor_addr, receipt))
+    }
+
+    /// Wait for the dbproxy added by `Membership::catch_up` to be restored, and then let it serve read queries
+    ///
+    /// A dbproxy that fails to restore is evicted.
+    pub async fn finish_catch_up(
+        &self,
+        dbproxy_addr: &SocketAddr,
+        receipt: ExecutorAddrRequestReceipt<TransceiverRequest>,
+    ) -> Result<(), String> {
+        match receipt.wait_request().await {
+            Ok(TransceiverReply::DbproxyMsg(Message::Restored(_, Ok(())))) => (),
+            Ok(TransceiverReply::DbproxyMsg(Message::Restored(_, Err(e)))) => return Err(e.to_string()),
+            Ok(_) => return Err(String::from("Invalid response from dbproxy")),
+            Err(e) => return Err(e),
+        }
+
+        if !self.dbvn_manager.write().await.set_catching_up(dbproxy_addr, false) {
+            return Err(format!("Dbproxy {} is removed", dbproxy_addr));
+        }
+        self.evictor.dbvn_manager_notify.notify_waiters();
+
+        info!("Caught up dbproxy {}", dbproxy_addr);
+        Ok(())
+    }
+
+    /// Remove the dbproxy, at least one dbproxy is kept
+    pub async fn remove(&self, dbproxy_addr: &SocketAddr) -> Result<(), String> {
+        let mut dbvn_manager = self.dbvn_manager.write().await;
+        let mut dbproxy_manager = self.dbproxy_manager.write().await;
+        if !dbproxy_manager.inner().contains_key(dbproxy_addr) {
+            return Err(format!("Dbproxy {} does not exist", dbproxy_addr));
+        } else if dbproxy_manager.inner().len() == 1 {
+            return Err(format!("Dbproxy {} is the last one", dbproxy_addr));
+        }
+        dbproxy_manager.remove(dbproxy_addr);
+        dbvn_manager.remove(dbproxy_addr);
+
+        info!("Removed dbproxy {}", dbproxy_addr);
+        Ok(())
+    }
+
+    fn new_transceiver(&self, dbproxy_addr: SocketAddr) -> (TransceiverAddr, Transceiver) {
+        let (transceiver_addr, transceiver) = Transceiver::new(self.conf.transceiver_queue_size, dbproxy_addr);
+        let transceiver = transceiver
+            .set_heartbeat_interval(Duration::from_millis(self.conf.dbproxy_heartbeat_interval))
+            .set_request_timeout(self.conf.dbproxy_request_timeout.map(Duration::from_millis))
+            .set_reconnect_attempts(self.conf.dbproxy_reconnect_attempts)
+            .set_evictor(self.evictor.clone());
+        (transceiver_addr, transceiver)
+    }
+}
+
+/// Evicts failed dbproxies and quarantines diverged dbproxies, removing them from both `DbVNManager`
+/// and `DbproxyManager`
+///
+/// Only a weak reference to `DbproxyManager` is kept, so that the `Transceiver`s holding
+/// an `Evictor` can still stop once `DbproxyManager` is dropped.
+#[derive(Debug, Clone)]
+pub struct Evictor {
+    dbvn_manager: Arc<RwLock<DbVNManager>>,
+    dbvn_manager_notify: Arc<Notify>,
+    dbproxy_manager: Weak<RwLock<DbproxyManager>>,
+    evicted: Arc<Mutex<BTreeSet<SocketAddr>>>,
+    quarantined: Arc<Mutex<BTreeSet<SocketAddr>>>,
+}
+
+impl Evictor {
+    /// Returns `false` if the dbproxy is kept instead, since it is the last live dbproxy
+    /// that is not catching up, and evicting it would leave nothing to serve the reads
+    pub async fn evict(&self, dbproxy_addr: &SocketAddr) -> bool {
+        self.remove(dbproxy_addr, &self.evicted, "Evicted", true).await
+    }
+
+    /// Stop sending anything to the dbproxy, since its data has diverged from the others
+    pub async fn quarantine(&self, dbproxy_addr: &SocketAddr) {
+        self.remove(dbproxy_addr, &self.quarantined, "Quarantined", false).await;
+    }
+
+    /// Forget the dbproxy once it is added back
+    async fn forget(&self, dbproxy_addr: &SocketAddr) {
+        self.evicted.lock().await.remove(dbproxy_addr);
+        self.quarantined.lock().await.remove(dbproxy_addr);
+    }
+
+    /// Remove the dbproxy, and then record it in `removed`. Returns `false` if it is kept when `keep_last`
+    async fn remove(
+        &self,
+        dbproxy_addr: &SocketAddr,
+        removed: &Mutex<BTreeSet<SocketAddr>>,
+        action: &str,
+        keep_last: bool,
+    ) -> bool {
+        let dbproxy_manager = match self.dbproxy_manager.upgrade() {
+            Some(dbproxy_manager) => dbproxy_manager,
+            None => return true,
+        };
+
+        let mut dbvn_manager = self.dbvn_manager.write().await;
+        let mut dbproxy_manager = dbproxy_manager.write().await;
+        if keep_last
+            && dbproxy_manager.inner().contains_key(dbproxy_addr)
+            && !dbvn_manager.is_catching_up(dbproxy_addr)
+            && dbproxy_manager
+                .inner()
+                .keys()
+                .all(|other| other == dbproxy_addr || dbvn_manager.is_catching_up(other))
+        {
+            return false;
+        }
+        if dbproxy_manager.remove(dbproxy_addr).is_some() {
+            dbvn_manager.remove(dbproxy_addr);
+            removed.lock().await.insert(dbproxy_addr.clone());
+            warn!(
+                "{} dbproxy {}, {} dbproxies left",
+                action,
+                dbproxy_addr,
+                dbproxy_manager.inner().len()
+            );
+        }
+
+        // Wake up the reads waiting on versions, in case there are no dbproxies left
+        self.dbvn_manager_notify.notify_waiters();
+        true
+    }
+
+    /// Whether the reconnected dbproxy at `version_vector` is behind the versions it has released,
+    /// such as after it restarted with an empty database
+    pub async fn has_lost_versions(&self, dbproxy_addr: &SocketAddr, version_vector: &HashMap<String, VN>) -> bool {
+        let dbvn_manager = self.dbvn_manager.read().await;
+        if dbvn_manager.is_catching_up(dbproxy_addr) {
+            return false;
+        }
+        match dbvn_manager.inner().get(dbproxy_addr) {
+            Some(dbvn) => !DbVN::from(version_vector.clone()).has_passed(&dbvn.to_version_vector()),
+            None => false,
+        }
+    }
+}
@@ -3,6 +3,10 @@ mod core;
 mod dispatcher;
 mod handler;
 mod logging;
+mod membership;
+mod pg_handler;
+mod replica_selector;
+mod sequencer_pool;
 mod transceiver;
 
 pub use handler::main;
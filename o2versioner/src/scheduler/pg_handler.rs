@@ -0,0 +1,1078 @@
+//! Scheduler handler for clients speaking the PostgreSQL frontend/backend protocol
+//!
+//! Each Sql statement is mapped onto a `Msql` request:
+//! 1. `BEGIN` and `START TRANSACTION` are mapped onto `Msql::BeginTx`,
+//!    the tables of the transaction must be annotated as `BEGIN /*o2v read t0 write t1*/`
+//! 2. `COMMIT`, `END`, `ROLLBACK` and `ABORT` are mapped onto `Msql::EndTx`
+//! 3. `SAVEPOINT`, `ROLLBACK TO` and `RELEASE` are mapped onto `Msql::Savepoint`
+//! 4. `SET` and `RESET` are acknowledged without being forwarded
+//! 5. Any other statement is mapped onto `Msql::Query`, which is annotated by
+//!    `/*o2v read t0 write t1 release t1*/` if present, or else automatically annotated
+//!
+//! The columns of a statement or a portal are described by preparing its query on a dbproxy,
+//! so that it is only executed once by `FrontendMessage::Execute`.
+
+use super::core::*;
+use super::dispatcher::*;
+use super::handler::{process_expired_tx, process_msql, process_unclosed_tx, wait_tx_expiry};
+use super::sequencer_pool::SequencerPool;
+use super::transceiver::{TransceiverReply, TransceiverRequest};
+use crate::comm::pg_protocol::*;
+use crate::comm::scheduler_dbproxy::Message;
+use crate::comm::{MsqlColumn, MsqlResponse, MsqlResult};
+use crate::core::*;
+use crate::util::conf::*;
+use crate::util::sql_types::{SqlParam, SqlValue};
+use bytes::{Bytes, BytesMut};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tokio_postgres::types::{FromSql, IsNull, ToSql, Type};
+use tracing::{field, info, instrument, trace, warn, Span};
+
+/// Process the `socket` for a single connection speaking the PostgreSQL frontend/backend protocol
+///
+/// Once this tcp connection is closed, any unclosed transaction is aborted and this function will return
+#[instrument(name="pg_conn", skip(conf, socket, conn_state, sequencer_socket_pool, dispatcher_addr, dbproxy_manager), fields(message=field::Empty))]
+pub(super) async fn process_pg_connection(
+    conf: SchedulerConf,
+    socket: TcpStream,
+    conn_state: ConnectionState,
+    sequencer_socket_pool: SequencerPool,
+    dispatcher_addr: Arc<DispatcherAddr>,
+    dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+) {
+    let client_addr = socket.peer_addr().unwrap();
+
+    Span::current().record("message", &&client_addr.to_string()[..]);
+
+    let mut session = PgSession {
+        conf,
+        conn_state,
+        sequencer_socket_pool,
+        dispatcher_addr,
+        dbproxy_manager,
+        tx_failed: false,
+        skip_until_sync: false,
+        num_describes: 0,
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+    };
+
+    if let Err(e) = session.run(&mut PgServerStream::new(socket)).await {
+        warn!("Connection error: {:?}", e);
+    }
+
+    process_unclosed_tx(&session.conf, &mut session.conn_state, &session.dispatcher_addr).await;
+
+    info!("Connection dropped. {:?}", session.conn_state);
+}
+
+/// A statement prepared by `FrontendMessage::Parse`
+#[derive(Debug)]
+struct PgPreparedStatement {
+    query: String,
+    param_types: Vec<u32>,
+}
+
+/// A statement with its parameters bound by `FrontendMessage::Bind`
+#[derive(Debug)]
+struct PgPortal {
+    query: String,
+    params: Vec<Value>,
+    result_formats: Vec<i16>,
+}
+
+/// A statement sent by the client
+#[derive(Debug, Clone, PartialEq)]
+enum PgStatement {
+    /// The statement does not contain any Sql
+    Empty,
+    /// Session settings are acknowledged with the command tag without being forwarded
+    Set(String),
+    Msql(Msql),
+}
+
+impl PgStatement {
+    /// Whether the statement can be executed after a failure within the current transaction
+    fn is_allowed_in_failed_tx(&self) -> bool {
+        match self {
+            Self::Empty | Self::Msql(Msql::EndTx(_)) => true,
+            Self::Msql(Msql::Savepoint(savepoint)) => savepoint.mode() == MsqlSavepointMode::RollbackTo,
+            _ => false,
+        }
+    }
+}
+
+/// The outcome of a statement executed successfully
+#[derive(Debug)]
+enum PgOutcome {
+    Empty,
+    Complete { result: Option<MsqlResult>, tag: String },
+}
+
+struct PgSession {
+    conf: SchedulerConf,
+    conn_state: ConnectionState,
+    sequencer_socket_pool: SequencerPool,
+    dispatcher_addr: Arc<DispatcherAddr>,
+    /// Dbproxies to prepare the queries on for `FrontendMessage::Describe`
+    dbproxy_manager: Arc<RwLock<DbproxyManager>>,
+    /// Whether a statement failed within the current transaction
+    tx_failed: bool,
+    /// Whether an error occurred in the extended query protocol,
+    /// all messages are then discarded until `FrontendMessage::Sync`
+    skip_until_sync: bool,
+    /// Number of queries described so far, which is the request id of the next `Message::DescribeRequest`
+    num_describes: usize,
+    statements: HashMap<String, PgPreparedStatement>,
+    portals: HashMap<String, PgPortal>,
+}
+
+impl PgSession {
+    async fn run<S>(&mut self, stream: &mut PgServerStream<S>) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if !startup(stream).await? {
+            return Ok(());
+        }
+
+        // The transaction is rolled back if it expires while waiting for the next message
+        loop {
+            let tx_expiry = self.conn_state.tx_expiry(&self.conf, Instant::now());
+            let expired = matches!(&tx_expiry, Some((deadline, _)) if *deadline <= Instant::now());
+            let msg = tokio::select! {
+                msg = stream.read(), if !expired => msg?,
+                err = wait_tx_expiry(tx_expiry) => {
+                    process_expired_tx(&self.conf, err, &mut self.conn_state, &self.dispatcher_addr).await;
+                    continue;
+                }
+            };
+            let msg = match msg {
+                Some(msg) => msg,
+                None => break,
+            };
+            trace!("<- {:?}", msg);
+
+            if self.skip_until_sync && !matches!(msg, FrontendMessage::Sync | FrontendMessage::Terminate) {
+                continue;
+            }
+
+            match msg {
+                FrontendMessage::Query(sql) => {
+                    self.simple_query(stream, &sql).await?;
+                    stream.send(BackendMessage::ReadyForQuery(self.transaction_status()))?;
+                    stream.flush().await?;
+                }
+                FrontendMessage::Parse {
+                    name,
+                    query,
+                    param_types,
+                } => {
+                    self.statements.insert(name, PgPreparedStatement { query, param_types });
+                    stream.send(BackendMessage::ParseComplete)?;
+                }
+                FrontendMessage::Bind {
+                    portal,
+                    statement,
+                    param_formats,
+                    params,
+                    result_formats,
+                } => {
+                    let bound = match self.statements.get(&statement) {
+                        Some(prepared) => bind_params(&prepared.param_types, &param_formats, params)
+                            .map(|params| PgPortal {
+                                query: prepared.query.clone(),
+                                params,
+                                result_formats,
+                            })
+                            .map_err(|e| ("22P02", e)),
+                        None => Err(("26000", format!("prepared statement \"{}\" does not exist", statement))),
+                    };
+                    match bound {
+                        Ok(bound) => {
+                            self.portals.insert(portal, bound);
+                            stream.send(BackendMessage::BindComplete)?;
+                        }
+                        Err((code, message)) => self.send_extended_error(stream, code, message)?,
+                    }
+                }
+                FrontendMessage::Describe {
+                    target: PgTarget::Statement,
+                    name,
+                } => match self.statements.get(&name) {
+                    Some(prepared) => {
+                        stream.send(BackendMessage::ParameterDescription(describe_params(prepared)))?;
+                        // The result formats are unknown until bound
+                        let query = prepared.query.clone();
+                        self.describe(stream, &query, &[]).await?;
+                    }
+                    None => self.send_extended_error(
+                        stream,
+                        "26000",
+                        format!("prepared statement \"{}\" does not exist", name),
+                    )?,
+                },
+                FrontendMessage::Describe {
+                    target: PgTarget::Portal,
+                    name,
+                } => self.describe_portal(stream, name).await?,
+                FrontendMessage::Execute { portal, .. } => self.execute_portal(stream, portal).await?,
+                FrontendMessage::Close { target, name } => {
+                    match target {
+                        PgTarget::Statement => {
+                            self.statements.remove(&name);
+                        }
+                        PgTarget::Portal => {
+                            self.portals.remove(&name);
+                        }
+                    }
+                    stream.send(BackendMessage::CloseComplete)?;
+                }
+                FrontendMessage::Sync => {
+                    self.skip_until_sync = false;
+                    stream.send(BackendMessage::ReadyForQuery(self.transaction_status()))?;
+                    stream.flush().await?;
+                }
+                FrontendMessage::Flush => stream.flush().await?,
+                FrontendMessage::Terminate => return Ok(()),
+                other => {
+                    warn!("Unsupported message: {:?}", other);
+                    stream.send(BackendMessage::ErrorResponse {
+                        code: String::from("0A000"),
+                        message: format!("Unsupported message: {:?}", other),
+                    })?;
+                    stream.flush().await?;
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn transaction_status(&self) -> TransactionStatus {
+        match (self.conn_state.current_txvn().is_some(), self.tx_failed) {
+            (false, _) => TransactionStatus::Idle,
+            (true, false) => TransactionStatus::InTransaction,
+            (true, true) => TransactionStatus::Failed,
+        }
+    }
+
+    fn send_extended_error<S, C>(&mut self, stream: &mut PgServerStream<S>, code: C, message: String) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        C: Into<String>,
+    {
+        warn!("{}", message);
+        self.skip_until_sync = true;
+        stream.send(BackendMessage::ErrorResponse {
+            code: code.into(),
+            message,
+        })
+    }
+
+    /// Execute all statements in `sql` in order, until the first failure
+    async fn simple_query<S>(&mut self, stream: &mut PgServerStream<S>, sql: &str) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let statements = split_statements(sql);
+        if statements.is_empty() {
+            return stream.send(BackendMessage::EmptyQueryResponse);
+        }
+
+        for statement in statements {
+            let messages = match self.execute_statement(statement, Vec::new()).await {
+                Ok(PgOutcome::Empty) => Ok(vec![BackendMessage::EmptyQueryResponse]),
+                Ok(PgOutcome::Complete { result, tag }) => match result.filter(|result| !result.columns().is_empty()) {
+                    Some(result) => data_rows(&result, &[]).map(|rows| {
+                        std::iter::once(row_description(result.columns(), &[]))
+                            .chain(rows)
+                            .chain(std::iter::once(BackendMessage::CommandComplete(tag)))
+                            .collect()
+                    }),
+                    None => Ok(vec![BackendMessage::CommandComplete(tag)]),
+                },
+                Err(e) => Err(e),
+            };
+
+            match messages {
+                Ok(messages) => {
+                    for message in messages {
+                        stream.send(message)?;
+                    }
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    return stream.send(error_response(&e));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Describe the columns of the portal in its result formats, without executing it
+    async fn describe_portal<S>(&mut self, stream: &mut PgServerStream<S>, name: String) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (query, result_formats) = match self.portals.get(&name) {
+            Some(portal) => (portal.query.clone(), portal.result_formats.clone()),
+            None => {
+                return self.send_extended_error(stream, "34000", format!("portal \"{}\" does not exist", name));
+            }
+        };
+
+        self.describe(stream, &query, &result_formats).await
+    }
+
+    /// Send `BackendMessage::RowDescription` of the columns of `sql` in `formats`,
+    /// or `BackendMessage::NoData` if it returns no rows
+    async fn describe<S>(&mut self, stream: &mut PgServerStream<S>, sql: &str, formats: &[i16]) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match self.describe_columns(sql).await {
+            Ok(columns) if !columns.is_empty() => stream.send(row_description(&columns, formats)),
+            Ok(_) => stream.send(BackendMessage::NoData),
+            Err(e) => {
+                warn!("{}", e);
+                self.skip_until_sync = true;
+                stream.send(error_response(&e))
+            }
+        }
+    }
+
+    /// Get the columns of `sql` by preparing it on a dbproxy, only a `Msql::Query` can return rows
+    async fn describe_columns(&mut self, sql: &str) -> Result<Vec<MsqlColumn>, MsqlError> {
+        let msqlquery = match parse_statement(sql, Vec::new())? {
+            PgStatement::Msql(Msql::Query(msqlquery)) => msqlquery,
+            _ => return Ok(Vec::new()),
+        };
+
+        // Every dbproxy has the schema of all tables, even if it is still catching up
+        let (dbproxy_addr, transceiver_addr) = self
+            .dbproxy_manager
+            .read()
+            .await
+            .to_vec()
+            .into_iter()
+            .min_by_key(|(dbproxy_addr, _)| *dbproxy_addr)
+            .ok_or_else(|| MsqlError::unavailable("No dbproxy to describe the query"))?;
+
+        // Describes have their own request ids, apart from the requests of the transactions
+        let meta = RequestMeta {
+            client_addr: self.conn_state.client_meta().client_addr(),
+            cur_txid: usize::MAX,
+            request_id: self.num_describes,
+        };
+        self.num_describes += 1;
+
+        match transceiver_addr
+            .request(TransceiverRequest::DbproxyMsg {
+                dbproxy_addr,
+                dbproxy_msg: Message::DescribeRequest(meta, msqlquery.query().to_owned()),
+            })
+            .await
+        {
+            Ok(TransceiverReply::DbproxyMsg(Message::Description(_, columns))) => columns,
+            Ok(_) => Err(MsqlError::internal(format!(
+                "Invalid response from dbproxy {}",
+                dbproxy_addr
+            ))),
+            Err(e) => Err(MsqlError::unavailable(format!(
+                "Dbproxy {} failed: {}",
+                dbproxy_addr, e
+            ))),
+        }
+    }
+
+    async fn execute_portal<S>(&mut self, stream: &mut PgServerStream<S>, name: String) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let portal = match self.portals.remove(&name) {
+            Some(portal) => portal,
+            None => {
+                return self.send_extended_error(stream, "34000", format!("portal \"{}\" does not exist", name));
+            }
+        };
+
+        let outcome = self.execute_statement(&portal.query, portal.params.clone()).await;
+
+        let messages = match outcome {
+            Ok(PgOutcome::Empty) => Ok(vec![BackendMessage::EmptyQueryResponse]),
+            Ok(PgOutcome::Complete { result, tag }) => result
+                .map_or_else(|| Ok(Vec::new()), |result| data_rows(&result, &portal.result_formats))
+                .map(|rows| {
+                    rows.into_iter()
+                        .chain(std::iter::once(BackendMessage::CommandComplete(tag)))
+                        .collect()
+                }),
+            Err(e) => Err(e),
+        };
+
+        self.portals.insert(name, portal);
+
+        match messages {
+            Ok(messages) => messages.into_iter().try_for_each(|message| stream.send(message)),
+            Err(e) => {
+                warn!("{}", e);
+                self.skip_until_sync = true;
+                stream.send(error_response(&e))
+            }
+        }
+    }
+
+    /// Execute a single statement as a `Msql` request
+    #[instrument(name="request", skip(self, sql, params), fields(id=field::Empty, txid=field::Empty, cmd=field::Empty))]
+    async fn execute_statement(&mut self, sql: &str, params: Vec<Value>) -> Result<PgOutcome, MsqlError> {
+        let statement = parse_statement(sql, params)?;
+        let is_rollback_to = matches!(&statement, PgStatement::Msql(Msql::Savepoint(savepoint)) if savepoint.mode() == MsqlSavepointMode::RollbackTo);
+
+        let outcome = if self.tx_failed && !statement.is_allowed_in_failed_tx() {
+            Err(MsqlError::database(
+                "current transaction is aborted, commands ignored until end of transaction block",
+                Some("25P02"),
+            ))
+        } else {
+            match statement {
+                PgStatement::Empty => Ok(PgOutcome::Empty),
+                PgStatement::Set(tag) => Ok(PgOutcome::Complete { result: None, tag }),
+                PgStatement::Msql(Msql::EndTx(msqlendtx))
+                    if self.conn_state.current_txvn().is_none() && self.conn_state.tx_timeout().is_none() =>
+                {
+                    warn!("There is no transaction in progress");
+                    Ok(PgOutcome::Complete {
+                        result: None,
+                        tag: String::from(msqlendtx.mode().as_ref()).to_uppercase(),
+                    })
+                }
+                PgStatement::Msql(mut msql) => {
+                    if self.tx_failed {
+                        // A failed transaction can only be rolled back
+                        if let Msql::EndTx(msqlendtx) = &mut msql {
+                            *msqlendtx = msqlendtx.clone().set_mode(MsqlEndTxMode::Rollback);
+                        }
+                    }
+                    let tag = command_tag(&msql);
+                    let msqlresponse = process_msql(
+                        self.conf.clone(),
+                        msql,
+                        &mut self.conn_state,
+                        self.sequencer_socket_pool.clone(),
+                        self.dispatcher_addr.clone(),
+                    )
+                    .await;
+                    into_outcome(tag, msqlresponse)
+                }
+            }
+        };
+
+        // A failure within a transaction fails the transaction,
+        // until the transaction is ended or rolled back to a savepoint
+        if self.conn_state.current_txvn().is_none() {
+            self.tx_failed = false;
+        } else if outcome.is_err() {
+            self.tx_failed = true;
+        } else if is_rollback_to {
+            self.tx_failed = false;
+        }
+
+        outcome
+    }
+}
+
+/// Process the startup phase of the connection, returns whether the connection is ready for queries
+async fn startup<S>(stream: &mut PgServerStream<S>) -> io::Result<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        match stream.read().await? {
+            Some(FrontendMessage::SslRequest) => {
+                stream.send(BackendMessage::SslRefused)?;
+                stream.flush().await?;
+            }
+            Some(FrontendMessage::Startup { version, params }) => {
+                if version != PROTOCOL_VERSION {
+                    stream.send(BackendMessage::ErrorResponse {
+                        code: String::from("0A000"),
+                        message: format!("Unsupported frontend protocol version {}", version),
+                    })?;
+                    stream.flush().await?;
+                    return Ok(false);
+                }
+
+                info!("Startup {:?}", params);
+                stream.send(BackendMessage::AuthenticationOk)?;
+                for (name, value) in &[
+                    ("server_version", "12.0"),
+                    ("server_encoding", "UTF8"),
+                    ("client_encoding", "UTF8"),
+                    ("DateStyle", "ISO, MDY"),
+                    ("integer_datetimes", "on"),
+                    ("standard_conforming_strings", "on"),
+                ] {
+                    stream.send(BackendMessage::ParameterStatus {
+                        name: String::from(*name),
+                        value: String::from(*value),
+                    })?;
+                }
+                // Cancellation is not supported, so the key does not need to be unique
+                stream.send(BackendMessage::BackendKeyData {
+                    process_id: 0,
+                    secret_key: 0,
+                })?;
+                stream.send(BackendMessage::ReadyForQuery(TransactionStatus::Idle))?;
+                stream.flush().await?;
+                return Ok(true);
+            }
+            Some(other) => {
+                info!("Closing connection at startup: {:?}", other);
+                return Ok(false);
+            }
+            None => return Ok(false),
+        }
+    }
+}
+
+/// Call `f` with each character of `sql` that is not within quotes or comments,
+/// each comment is passed as a single whitespace
+fn scan_sql<F: FnMut(usize, char)>(sql: &str, mut f: F) {
+    let mut chars = sql.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        match (c, next) {
+            ('\'', _) | ('"', _) => {
+                // A quote escaped by doubling is treated as two adjacent quoted strings
+                chars.by_ref().find(|(_, quoted)| *quoted == c);
+            }
+            ('-', Some('-')) => {
+                chars.by_ref().find(|(_, commented)| *commented == '\n');
+                f(idx, ' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                chars.by_ref().find(|(_, commented)| {
+                    let is_end = prev == '*' && *commented == '/';
+                    prev = *commented;
+                    is_end
+                });
+                f(idx, ' ');
+            }
+            _ => f(idx, c),
+        }
+    }
+}
+
+/// Split `sql` into statements separated by `;`, empty statements are discarded
+fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    scan_sql(sql, |idx, c| {
+        if c == ';' {
+            statements.push(&sql[start..idx]);
+            start = idx + 1;
+        }
+    });
+    statements.push(&sql[start..]);
+
+    statements
+        .into_iter()
+        .map(|statement| statement.trim())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+/// Get the number of placeholders in `sql`, which is the largest n of placeholders `$n`
+fn count_placeholders(sql: &str) -> usize {
+    let mut max = 0;
+    let mut current = None;
+    scan_sql(sql, |_, c| match (current, c.to_digit(10)) {
+        (Some(n), Some(digit)) => current = Some(n * 10 + digit as usize),
+        _ => {
+            if let Some(n) = current.take() {
+                max = std::cmp::max(max, n);
+            }
+            if c == '$' {
+                current = Some(0);
+            }
+        }
+    });
+    std::cmp::max(max, current.unwrap_or(0))
+}
+
+/// Get the words of `sql` that are not within quotes or comments
+fn words(sql: &str) -> Vec<String> {
+    let mut text = String::new();
+    scan_sql(sql, |_, c| text.push(c));
+    text.split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Classify the Sql statement by its leading keywords, and convert it into `PgStatement`
+fn parse_statement(sql: &str, params: Vec<Value>) -> Result<PgStatement, MsqlError> {
+    let (sql, annotation) = extract_annotation(sql);
+    let words = words(&sql);
+    let keywords: Vec<_> = words.iter().map(|word| word.to_uppercase()).collect();
+    let keyword = |idx: usize| keywords.get(idx).map(|keyword| &keyword[..]);
+    let savepoint_at = |idx: usize| {
+        // The keyword SAVEPOINT is optional
+        let idx = if keyword(idx) == Some("SAVEPOINT") {
+            idx + 1
+        } else {
+            idx
+        };
+        words
+            .get(idx)
+            .cloned()
+            .ok_or_else(|| MsqlError::illegal("Savepoint name is missing"))
+    };
+
+    let msql = match keyword(0) {
+        None => return Ok(PgStatement::Empty),
+        Some("SET") | Some("RESET") => return Ok(PgStatement::Set(keywords[0].clone())),
+        Some("BEGIN") | Some("START") => match annotation {
+            Some((tableops, _)) => Msql::BeginTx(MsqlBeginTx::from(tableops)),
+            None => {
+                return Err(MsqlError::annotation(
+                    "Transaction must be annotated as BEGIN /*o2v read t0 write t1*/",
+                ))
+            }
+        },
+        Some("COMMIT") | Some("END") => Msql::EndTx(MsqlEndTx::commit()),
+        Some("ROLLBACK") | Some("ABORT") => {
+            // The keywords WORK and TRANSACTION are optional
+            let idx = if matches!(keyword(1), Some("WORK") | Some("TRANSACTION")) {
+                2
+            } else {
+                1
+            };
+            if keyword(idx) == Some("TO") {
+                Msql::Savepoint(MsqlSavepoint::rollback_to(savepoint_at(idx + 1)?))
+            } else {
+                Msql::EndTx(MsqlEndTx::rollback())
+            }
+        }
+        Some("SAVEPOINT") => Msql::Savepoint(MsqlSavepoint::savepoint(savepoint_at(1)?)),
+        Some("RELEASE") => Msql::Savepoint(MsqlSavepoint::release(savepoint_at(1)?)),
+        Some(_) => {
+            let msqlquery = match annotation {
+                Some((tableops, ertables)) => MsqlQuery::new(sql, tableops, ertables),
+                None => MsqlQuery::auto_annotated(sql, EarlyReleaseTables::default()),
+            }
+            .map_err(MsqlError::annotation)?;
+            Msql::Query(msqlquery.set_params(params))
+        }
+    };
+
+    Ok(PgStatement::Msql(msql))
+}
+
+/// Get the command tag of the `Msql`, which is then completed with the result for `Msql::Query`
+fn command_tag(msql: &Msql) -> String {
+    match msql {
+        Msql::BeginTx(_) => String::from("BEGIN"),
+        Msql::Query(msqlquery) => words(msqlquery.query())
+            .into_iter()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase(),
+        Msql::EndTx(msqlendtx) => String::from(msqlendtx.mode().as_ref()).to_uppercase(),
+        Msql::Savepoint(msqlsavepoint) => match msqlsavepoint.mode() {
+            MsqlSavepointMode::Savepoint => String::from("SAVEPOINT"),
+            MsqlSavepointMode::RollbackTo => String::from("ROLLBACK"),
+            MsqlSavepointMode::Release => String::from("RELEASE"),
+        },
+    }
+}
+
+fn into_outcome(tag: String, msqlresponse: MsqlResponse) -> Result<PgOutcome, MsqlError> {
+    match msqlresponse {
+        MsqlResponse::Query(res) => res.map(|result| {
+            let rows = result.affected_rows().unwrap_or(result.rows().len() as u64);
+            let tag = match &tag[..] {
+                "INSERT" => format!("INSERT 0 {}", rows),
+                "SELECT" | "UPDATE" | "DELETE" | "MOVE" | "FETCH" | "COPY" => format!("{} {}", tag, rows),
+                "WITH" | "VALUES" | "TABLE" => format!("SELECT {}", rows),
+                _ => tag,
+            };
+            PgOutcome::Complete {
+                result: Some(result),
+                tag,
+            }
+        }),
+        MsqlResponse::BeginTx(res) => res.map(|_| PgOutcome::Complete { result: None, tag }),
+        MsqlResponse::EndTx(res) | MsqlResponse::Savepoint(res) => {
+            res.map(|_| PgOutcome::Complete { result: None, tag })
+        }
+    }
+}
+
+fn error_response(e: &MsqlError) -> BackendMessage {
+    let code = e.sqlstate().unwrap_or_else(|| match e.kind() {
+        MsqlErrorKind::Illegal => "25000",
+        MsqlErrorKind::Annotation => "42000",
+        MsqlErrorKind::Sequencer | MsqlErrorKind::Unavailable => "57P03",
+        MsqlErrorKind::Database | MsqlErrorKind::Internal => "XX000",
+        MsqlErrorKind::Timeout => "25P03",
+        MsqlErrorKind::Cancelled => "57014",
+    });
+    BackendMessage::ErrorResponse {
+        code: String::from(code),
+        message: e.message().to_owned(),
+    }
+}
+
+/// Get the type of the parameters of the prepared statement, unspecified types are described as `Type::TEXT`
+fn describe_params(prepared: &PgPreparedStatement) -> Vec<u32> {
+    let num_params = std::cmp::max(prepared.param_types.len(), count_placeholders(&prepared.query));
+    (0..num_params)
+        .map(|idx| match prepared.param_types.get(idx) {
+            Some(oid) if *oid != 0 => *oid,
+            _ => Type::TEXT.oid(),
+        })
+        .collect()
+}
+
+/// Convert the parameters bound by the client into Json values, which are converted by the dbproxy
+fn bind_params(param_types: &[u32], param_formats: &[i16], params: Vec<Option<Bytes>>) -> Result<Vec<Value>, String> {
+    params
+        .into_iter()
+        .enumerate()
+        .map(|(idx, param)| {
+            let raw = match param {
+                Some(raw) => raw,
+                None => return Ok(Value::Null),
+            };
+            let ty = match param_types.get(idx) {
+                Some(oid) if *oid != 0 => {
+                    Type::from_oid(*oid).ok_or_else(|| format!("Unsupported type {} of parameter ${}", oid, idx + 1))?
+                }
+                _ => Type::TEXT,
+            };
+
+            if format_of(param_formats, idx) == BINARY_FORMAT {
+                SqlValue::from_sql(&ty, &raw)
+                    .map(|value| value.into_inner())
+                    .map_err(|e| format!("Invalid binary parameter ${}: {}", idx + 1, e))
+            } else {
+                let text =
+                    String::from_utf8(raw.to_vec()).map_err(|e| format!("Invalid parameter ${}: {}", idx + 1, e))?;
+                if ty == Type::JSON || ty == Type::JSONB {
+                    serde_json::from_str(&text).map_err(|e| format!("Invalid json parameter ${}: {}", idx + 1, e))
+                } else {
+                    Ok(Value::String(text))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Get the format of the n-th value, where a single format applies to all values
+fn format_of(formats: &[i16], idx: usize) -> i16 {
+    match formats {
+        [] => TEXT_FORMAT,
+        [format] => *format,
+        _ => formats.get(idx).copied().unwrap_or(TEXT_FORMAT),
+    }
+}
+
+/// Get the type of the column by its name, types not supported are described as `Type::TEXT`
+fn column_type(type_name: &str) -> Type {
+    vec![
+        Type::BOOL,
+        Type::INT2,
+        Type::INT4,
+        Type::INT8,
+        Type::OID,
+        Type::FLOAT4,
+        Type::FLOAT8,
+        Type::NUMERIC,
+        Type::DATE,
+        Type::TIME,
+        Type::TIMESTAMP,
+        Type::TIMESTAMPTZ,
+        Type::UUID,
+        Type::BYTEA,
+        Type::CHAR,
+        Type::JSON,
+        Type::JSONB,
+        Type::VARCHAR,
+    ]
+    .into_iter()
+    .find(|ty| ty.name() == type_name)
+    .unwrap_or(Type::TEXT)
+}
+
+fn row_description(columns: &[MsqlColumn], formats: &[i16]) -> BackendMessage {
+    BackendMessage::RowDescription(
+        columns
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| FieldDescription {
+                name: column.name().to_owned(),
+                type_oid: column_type(column.type_name()).oid(),
+                format: format_of(formats, idx),
+            })
+            .collect(),
+    )
+}
+
+fn data_rows(result: &MsqlResult, formats: &[i16]) -> Result<Vec<BackendMessage>, MsqlError> {
+    let types: Vec<_> = result
+        .columns()
+        .iter()
+        .map(|column| column_type(column.type_name()))
+        .collect();
+
+    result
+        .rows()
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(idx, cell)| {
+                    if format_of(formats, idx) == BINARY_FORMAT {
+                        let mut buf = BytesMut::new();
+                        match SqlParam(cell).to_sql(&types[idx], &mut buf) {
+                            Ok(IsNull::Yes) => Ok(None),
+                            Ok(IsNull::No) => Ok(Some(buf.freeze())),
+                            Err(e) => Err(MsqlError::internal(format!("Cannot encode {} in binary: {}", cell, e))),
+                        }
+                    } else {
+                        Ok(cell_to_text(cell))
+                    }
+                })
+                .collect::<Result<_, _>>()
+                .map(BackendMessage::DataRow)
+        })
+        .collect()
+}
+
+fn cell_to_text(cell: &Value) -> Option<Bytes> {
+    match cell {
+        Value::Null => None,
+        Value::Bool(b) => Some(Bytes::from_static(if *b { b"t" } else { b"f" })),
+        Value::String(s) => Some(Bytes::from(s.clone())),
+        other => Some(Bytes::from(other.to_string())),
+    }
+}
+
+/// Unit test for the Sql parsing of `PgStatement`
+#[cfg(test)]
+mod tests_pg_statement {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_split_statements() {
+        assert_eq!(split_statements(""), Vec::<&str>::new());
+        assert_eq!(split_statements(" ; ;"), Vec::<&str>::new());
+        assert_eq!(
+            split_statements("BEGIN; select 'a;b' from t0 -- c;d\n; COMMIT"),
+            vec!["BEGIN", "select 'a;b' from t0 -- c;d", "COMMIT"]
+        );
+        assert_eq!(
+            split_statements("select \"x;y\" /* ; */ from t0;"),
+            vec!["select \"x;y\" /* ; */ from t0"]
+        );
+    }
+
+    #[test]
+    fn test_count_placeholders() {
+        assert_eq!(count_placeholders("select * from t0"), 0);
+        assert_eq!(count_placeholders("select * from t0 where a = $2 and b = $1"), 2);
+        assert_eq!(count_placeholders("insert into t0 values ($1, $12)"), 12);
+        assert_eq!(count_placeholders("select '$3' from t0 where a = $1 -- $4"), 1);
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        assert_eq!(parse_statement("  ", vec![]), Ok(PgStatement::Empty));
+        assert_eq!(parse_statement("-- comment", vec![]), Ok(PgStatement::Empty));
+        assert_eq!(
+            parse_statement("set search_path to public", vec![]),
+            Ok(PgStatement::Set(String::from("SET")))
+        );
+        assert_eq!(
+            parse_statement("BEGIN /*o2v read t0 write t1*/", vec![]),
+            Ok(PgStatement::Msql(Msql::BeginTx(MsqlBeginTx::from(TableOps::from(
+                "read t0 write t1"
+            )))))
+        );
+        assert_eq!(
+            parse_statement("start transaction", vec![]).unwrap_err().kind(),
+            MsqlErrorKind::Annotation
+        );
+        assert_eq!(
+            parse_statement("commit", vec![]),
+            Ok(PgStatement::Msql(Msql::EndTx(MsqlEndTx::commit())))
+        );
+        assert_eq!(
+            parse_statement("ROLLBACK WORK", vec![]),
+            Ok(PgStatement::Msql(Msql::EndTx(MsqlEndTx::rollback())))
+        );
+        assert_eq!(
+            parse_statement("savepoint sp0", vec![]),
+            Ok(PgStatement::Msql(Msql::Savepoint(MsqlSavepoint::savepoint("sp0"))))
+        );
+        assert_eq!(
+            parse_statement("rollback transaction to savepoint sp0", vec![]),
+            Ok(PgStatement::Msql(Msql::Savepoint(MsqlSavepoint::rollback_to("sp0"))))
+        );
+        assert_eq!(
+            parse_statement("release sp0", vec![]),
+            Ok(PgStatement::Msql(Msql::Savepoint(MsqlSavepoint::release("sp0"))))
+        );
+        assert_eq!(
+            parse_statement("release savepoint", vec![]).unwrap_err().kind(),
+            MsqlErrorKind::Illegal
+        );
+        assert_eq!(
+            parse_statement("select * from t0 where a = $1", vec![json!("ray")]),
+            Ok(PgStatement::Msql(Msql::Query(
+                MsqlQuery::new(
+                    "select * from t0 where a = $1",
+                    TableOps::from("read t0"),
+                    EarlyReleaseTables::default()
+                )
+                .unwrap()
+                .set_params(vec![json!("ray")])
+            )))
+        );
+        assert_eq!(
+            parse_statement("update t1 set a = 1 /*o2v write t1 release t1*/", vec![]),
+            Ok(PgStatement::Msql(Msql::Query(
+                MsqlQuery::new(
+                    "update t1 set a = 1",
+                    TableOps::from("write t1"),
+                    EarlyReleaseTables::from("t1")
+                )
+                .unwrap()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_command_tag() {
+        let tag = |sql| match parse_statement(sql, vec![]).unwrap() {
+            PgStatement::Msql(msql) => command_tag(&msql),
+            other => panic!("Unexpected statement {:?}", other),
+        };
+        assert_eq!(tag("BEGIN /*o2v read t0*/"), "BEGIN");
+        assert_eq!(tag("end"), "COMMIT");
+        assert_eq!(tag("abort"), "ROLLBACK");
+        assert_eq!(tag("rollback to sp0"), "ROLLBACK");
+        assert_eq!(tag("release sp0"), "RELEASE");
+        assert_eq!(tag("/*o2v write t0*/ insert into t0 values (1)"), "INSERT");
+
+        let outcome = |tag: &str, result| match into_outcome(String::from(tag), MsqlResponse::query_ok(result)) {
+            Ok(PgOutcome::Complete { tag, .. }) => tag,
+            _ => panic!("Unexpected outcome"),
+        };
+        assert_eq!(outcome("INSERT", MsqlResult::affected(2)), "INSERT 0 2");
+        assert_eq!(outcome("UPDATE", MsqlResult::affected(3)), "UPDATE 3");
+        assert_eq!(outcome("CREATE", MsqlResult::affected(0)), "CREATE");
+        assert_eq!(
+            outcome(
+                "SELECT",
+                MsqlResult::new(vec![MsqlColumn::new("a", "int4")], vec![vec![json!(1)], vec![json!(2)]])
+            ),
+            "SELECT 2"
+        );
+    }
+
+    #[test]
+    fn test_encode_result() {
+        let result = MsqlResult::new(
+            vec![
+                MsqlColumn::new("a", "int4"),
+                MsqlColumn::new("b", "varchar"),
+                MsqlColumn::new("c", "inet"),
+            ],
+            vec![
+                vec![json!(1), json!("x"), json!("127.0.0.1")],
+                vec![json!(null), json!(true), json!(null)],
+            ],
+        );
+
+        assert_eq!(
+            row_description(result.columns(), &[]),
+            BackendMessage::RowDescription(vec![
+                FieldDescription {
+                    name: String::from("a"),
+                    type_oid: Type::INT4.oid(),
+                    format: TEXT_FORMAT
+                },
+                FieldDescription {
+                    name: String::from("b"),
+                    type_oid: Type::VARCHAR.oid(),
+                    format: TEXT_FORMAT
+                },
+                FieldDescription {
+                    name: String::from("c"),
+                    type_oid: Type::TEXT.oid(),
+                    format: TEXT_FORMAT
+                },
+            ])
+        );
+        assert_eq!(
+            data_rows(&result, &[]).unwrap(),
+            vec![
+                BackendMessage::DataRow(vec![
+                    Some(Bytes::from("1")),
+                    Some(Bytes::from("x")),
+                    Some(Bytes::from("127.0.0.1"))
+                ]),
+                BackendMessage::DataRow(vec![None, Some(Bytes::from("t")), None]),
+            ]
+        );
+        assert_eq!(
+            data_rows(&result, &[BINARY_FORMAT, TEXT_FORMAT, TEXT_FORMAT]).unwrap()[0],
+            BackendMessage::DataRow(vec![
+                Some(Bytes::copy_from_slice(&1i32.to_be_bytes())),
+                Some(Bytes::from("x")),
+                Some(Bytes::from("127.0.0.1"))
+            ])
+        );
+    }
+
+    #[test]
+    fn test_bind_params() {
+        assert_eq!(
+            bind_params(
+                &[0, Type::INT4.oid(), Type::JSONB.oid()],
+                &[TEXT_FORMAT, BINARY_FORMAT, TEXT_FORMAT],
+                vec![
+                    Some(Bytes::from("ray")),
+                    Some(Bytes::copy_from_slice(&7i32.to_be_bytes())),
+                    Some(Bytes::from("{\"a\": 1}"))
+                ]
+            ),
+            Ok(vec![json!("ray"), json!(7), json!({"a": 1})])
+        );
+        assert_eq!(bind_params(&[], &[], vec![None]), Ok(vec![Value::Null]));
+        assert!(bind_params(&[Type::INT4.oid()], &[BINARY_FORMAT], vec![Some(Bytes::from("1"))]).is_err());
+
+        assert_eq!(
+            describe_params(&PgPreparedStatement {
+                query: String::from("select * from t0 where a = $1 and b = $2"),
+                param_types: vec![Type::INT4.oid()],
+            }),
+            vec![Type::INT4.oid(), Type::TEXT.oid()]
+        );
+    }
+}
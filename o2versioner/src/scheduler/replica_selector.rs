@@ -0,0 +1,273 @@
+use crate::core::TableOps;
+use crate::util::conf::ReplicaSelection;
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Weight of the latest latency in `EwmaLatency`
+const EWMA_WEIGHT: f64 = 0.3;
+
+/// A dbproxy that has the versions ready for the ReadOnly query
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Candidate {
+    pub dbproxy_addr: SocketAddr,
+    /// Number of outstanding requests on the dbproxy
+    pub load: usize,
+}
+
+impl Candidate {
+    pub fn new(dbproxy_addr: SocketAddr, load: usize) -> Self {
+        Self { dbproxy_addr, load }
+    }
+}
+
+/// Chooses the dbproxy to execute a ReadOnly query
+///
+/// Shared by all requests of the dispatcher, so the state of a policy is kept behind interior mutability.
+pub trait ReplicaSelector: fmt::Debug + Send + Sync {
+    /// Choose one of `candidates` for the ReadOnly query on `tableops`, `candidates` is never empty
+    fn select(&self, tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr;
+
+    /// Called once the dbproxy replies a request, after `latency` since the request was sent
+    fn record_latency(&self, _dbproxy_addr: &SocketAddr, _latency: Duration) {}
+}
+
+/// Create the `ReplicaSelector` of `replica_selection`
+pub fn new_replica_selector(replica_selection: ReplicaSelection) -> Arc<dyn ReplicaSelector> {
+    match replica_selection {
+        ReplicaSelection::LeastOutstanding => Arc::new(LeastOutstanding),
+        ReplicaSelection::RoundRobin => Arc::new(RoundRobin::default()),
+        ReplicaSelection::EwmaLatency => Arc::new(EwmaLatency::default()),
+        ReplicaSelection::PowerOfTwoChoices => Arc::new(PowerOfTwoChoices),
+        ReplicaSelection::TableAffinity => Arc::new(TableAffinity::default()),
+    }
+}
+
+fn least_outstanding(candidates: &[Candidate]) -> SocketAddr {
+    candidates
+        .iter()
+        .min_by_key(|candidate| candidate.load)
+        .expect("No candidate for the ReadOnly query")
+        .dbproxy_addr
+}
+
+/// Choose the dbproxy with the least outstanding requests
+#[derive(Debug)]
+pub struct LeastOutstanding;
+
+impl ReplicaSelector for LeastOutstanding {
+    fn select(&self, _tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr {
+        least_outstanding(candidates)
+    }
+}
+
+/// Choose the dbproxies in turn
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl ReplicaSelector for RoundRobin {
+    fn select(&self, _tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr {
+        candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()].dbproxy_addr
+    }
+}
+
+/// Choose the dbproxy with the lowest exponentially weighted moving average of latencies,
+/// scaled by its outstanding requests. Dbproxies without any latency recorded are tried first
+#[derive(Debug, Default)]
+pub struct EwmaLatency {
+    /// In units of ms
+    latencies: Mutex<HashMap<SocketAddr, f64>>,
+}
+
+impl ReplicaSelector for EwmaLatency {
+    fn select(&self, _tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr {
+        let latencies = self.latencies.lock().unwrap();
+        let cost = |candidate: &Candidate| {
+            latencies.get(&candidate.dbproxy_addr).cloned().unwrap_or_default() * (candidate.load + 1) as f64
+        };
+        candidates
+            .iter()
+            .min_by(|a, b| cost(a).partial_cmp(&cost(b)).unwrap())
+            .expect("No candidate for the ReadOnly query")
+            .dbproxy_addr
+    }
+
+    fn record_latency(&self, dbproxy_addr: &SocketAddr, latency: Duration) {
+        let latency = latency.as_secs_f64() * 1000.0;
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry(*dbproxy_addr)
+            .and_modify(|ewma| *ewma = EWMA_WEIGHT * latency + (1.0 - EWMA_WEIGHT) * *ewma)
+            .or_insert(latency);
+    }
+}
+
+/// Choose two dbproxies at random, and then the one with less outstanding requests
+#[derive(Debug)]
+pub struct PowerOfTwoChoices;
+
+impl ReplicaSelector for PowerOfTwoChoices {
+    fn select(&self, _tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr {
+        let choices: Vec<_> = candidates
+            .choose_multiple(&mut rand::thread_rng(), 2)
+            .cloned()
+            .collect();
+        least_outstanding(&choices)
+    }
+}
+
+/// Keep all reads on a table on the same dbproxy, for cache locality.
+/// Reads on multiple tables follow the first one of `TableOps`
+///
+/// A table is assigned to the dbproxy with the fewest tables, and is reassigned if its dbproxy is not a candidate
+#[derive(Debug, Default)]
+pub struct TableAffinity {
+    affinities: Mutex<HashMap<String, SocketAddr>>,
+}
+
+impl ReplicaSelector for TableAffinity {
+    fn select(&self, tableops: &TableOps, candidates: &[Candidate]) -> SocketAddr {
+        let table = match tableops.get().first() {
+            Some(tableop) => tableop.table(),
+            None => return least_outstanding(candidates),
+        };
+
+        let mut affinities = self.affinities.lock().unwrap();
+        if let Some(dbproxy_addr) = affinities.get(table) {
+            if candidates
+                .iter()
+                .any(|candidate| candidate.dbproxy_addr == *dbproxy_addr)
+            {
+                return *dbproxy_addr;
+            }
+        }
+
+        let num_tables = |candidate: &Candidate| {
+            affinities
+                .values()
+                .filter(|dbproxy_addr| **dbproxy_addr == candidate.dbproxy_addr)
+                .count()
+        };
+        let dbproxy_addr = candidates
+            .iter()
+            .min_by_key(|candidate| (num_tables(candidate), candidate.load))
+            .expect("No candidate for the ReadOnly query")
+            .dbproxy_addr;
+        affinities.insert(table.to_owned(), dbproxy_addr);
+        dbproxy_addr
+    }
+}
+
+#[cfg(test)]
+mod tests_replica_selector {
+    use super::*;
+    use crate::core::{RWOperation, TableOp};
+    use std::iter::FromIterator;
+
+    fn candidates(loads: &[usize]) -> Vec<Candidate> {
+        loads
+            .iter()
+            .enumerate()
+            .map(|(i, load)| Candidate::new(format!("127.0.0.1:{}", 10000 + i).parse().unwrap(), *load))
+            .collect()
+    }
+
+    fn read(table: &str) -> TableOps {
+        TableOps::from_iter(vec![TableOp::new(table, RWOperation::R)])
+    }
+
+    /// Unit test for `LeastOutstanding`
+    #[test]
+    fn test_least_outstanding() {
+        let selector = new_replica_selector(ReplicaSelection::LeastOutstanding);
+        let candidates = candidates(&[3, 1, 1]);
+        assert_eq!(selector.select(&read("t0"), &candidates), candidates[1].dbproxy_addr);
+    }
+
+    /// Unit test for `RoundRobin`
+    #[test]
+    fn test_round_robin() {
+        let selector = new_replica_selector(ReplicaSelection::RoundRobin);
+        let candidates = candidates(&[0, 5, 0]);
+        let selected: Vec<_> = (0..4).map(|_| selector.select(&read("t0"), &candidates)).collect();
+        assert_eq!(
+            selected,
+            vec![
+                candidates[0].dbproxy_addr,
+                candidates[1].dbproxy_addr,
+                candidates[2].dbproxy_addr,
+                candidates[0].dbproxy_addr
+            ]
+        );
+    }
+
+    /// Unit test for `EwmaLatency`
+    #[test]
+    fn test_ewma_latency() {
+        let selector = new_replica_selector(ReplicaSelection::EwmaLatency);
+        let candidates = candidates(&[0, 0]);
+        let (fast, slow) = (candidates[0].dbproxy_addr, candidates[1].dbproxy_addr);
+
+        // Without any latency recorded, the slow one is tried
+        selector.record_latency(&fast, Duration::from_millis(10));
+        assert_eq!(selector.select(&read("t0"), &candidates), slow);
+
+        selector.record_latency(&slow, Duration::from_millis(100));
+        assert_eq!(selector.select(&read("t0"), &candidates), fast);
+
+        // Until the fast one is overloaded
+        let loaded = vec![Candidate::new(fast, 20), Candidate::new(slow, 0)];
+        assert_eq!(selector.select(&read("t0"), &loaded), slow);
+
+        // Or becomes slow
+        selector.record_latency(&fast, Duration::from_millis(1000));
+        assert_eq!(selector.select(&read("t0"), &candidates), slow);
+    }
+
+    /// Unit test for `PowerOfTwoChoices`
+    #[test]
+    fn test_power_of_two_choices() {
+        let selector = new_replica_selector(ReplicaSelection::PowerOfTwoChoices);
+        let candidates = candidates(&[2, 1, 0]);
+
+        // The most loaded one is never chosen, since it always loses to the other choice
+        for _ in 0..20 {
+            assert_ne!(selector.select(&read("t0"), &candidates), candidates[0].dbproxy_addr);
+        }
+        assert_eq!(
+            selector.select(&read("t0"), &candidates[..1]),
+            candidates[0].dbproxy_addr
+        );
+    }
+
+    /// Unit test for `TableAffinity`
+    #[test]
+    fn test_table_affinity() {
+        let selector = new_replica_selector(ReplicaSelection::TableAffinity);
+        let candidates = candidates(&[0, 1]);
+
+        // Tables are spread, and stay on their dbproxies regardless of the load
+        assert_eq!(selector.select(&read("t0"), &candidates), candidates[0].dbproxy_addr);
+        assert_eq!(selector.select(&read("t1"), &candidates), candidates[1].dbproxy_addr);
+        let loaded = vec![Candidate::new(candidates[0].dbproxy_addr, 9), candidates[1]];
+        assert_eq!(selector.select(&read("t0"), &loaded), candidates[0].dbproxy_addr);
+        assert_eq!(
+            selector.select(&read("t1").add_tableop(TableOp::new("t2", RWOperation::R)), &candidates),
+            candidates[1].dbproxy_addr
+        );
+
+        // Reassigned once its dbproxy is not a candidate
+        assert_eq!(
+            selector.select(&read("t0"), &candidates[1..]),
+            candidates[1].dbproxy_addr
+        );
+        assert_eq!(selector.select(&read("t0"), &candidates), candidates[1].dbproxy_addr);
+    }
+}
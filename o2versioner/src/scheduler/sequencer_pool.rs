@@ -0,0 +1,369 @@
+use crate::comm::scheduler_sequencer::Message;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use tracing::{debug, warn};
+
+type SerdedWrite =
+    SymmetricallyFramed<FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>, Message, SymmetricalJson<Message>>;
+
+/// Requests waiting for their replies, keyed by the request id; `None` once the connection is closed
+type PendingRequests = Arc<std::sync::Mutex<Option<HashMap<u64, oneshot::Sender<Message>>>>>;
+
+/// Pipelines requests to the primary Sequencer and its backup over a few shared tcp connections
+///
+/// Every request is sent as a `Message::Tagged` with a unique request id, without waiting for the replies
+/// of the previous requests on the same connection, and is matched with its reply by the request id.
+/// Requests are spread over the connections in a round robin fashion, and a closed connection
+/// is established again once it is needed.
+///
+/// All connections go to the current Sequencer, which is the primary one at the beginning.
+/// Once the current Sequencer is not available, `SequencerPool::request` fails over to the next one,
+/// and all connections to the previous one are dropped.
+#[derive(Clone)]
+pub struct SequencerPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    addrs: Vec<SocketAddr>,
+    current: AtomicUsize,
+    next_request_id: AtomicU64,
+    next_channel: AtomicUsize,
+    channels: Vec<Mutex<Option<Arc<Channel>>>>,
+}
+
+/// A tcp connection to one of the Sequencers
+struct Channel {
+    addr_idx: usize,
+    serded_write: Mutex<SerdedWrite>,
+    pending: PendingRequests,
+}
+
+impl SequencerPool {
+    /// `addrs` of the primary Sequencer and its backup, in the order of failing over,
+    /// with `size` connections shared by all requests
+    pub fn new(addrs: Vec<SocketAddr>, size: u32) -> Self {
+        assert!(!addrs.is_empty(), "Expecting at least one Sequencer");
+        assert!(size > 0, "Expecting at least one connection to Sequencer");
+        Self {
+            inner: Arc::new(Inner {
+                addrs,
+                current: AtomicUsize::new(0),
+                next_request_id: AtomicU64::new(0),
+                next_channel: AtomicUsize::new(0),
+                channels: (0..size).map(|_| Mutex::new(None)).collect(),
+            }),
+        }
+    }
+
+    /// Establish all connections before any request, retrying every `retry_interval` until `timeout`
+    /// if no Sequencer is available yet
+    pub async fn connect_all(&self, timeout: Duration, retry_interval: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        for channel in self.inner.channels.iter() {
+            let connected = loop {
+                match self.connect().await {
+                    Ok(connected) => break connected,
+                    Err(e) if Instant::now() >= deadline => return Err(e),
+                    Err(_) => sleep(retry_interval).await,
+                }
+            };
+            *channel.lock().await = Some(Arc::new(connected));
+        }
+        Ok(())
+    }
+
+    /// Send `msg` to the current Sequencer and wait for its reply
+    ///
+    /// If the current Sequencer is not available or it is not the primary one,
+    /// `msg` is sent again to the next Sequencer.
+    /// A `Message::RequestTxVN` is safe to be sent again, as the Sequencers reply
+    /// the same `TxVN` to the same `ClientMeta`.
+    pub async fn request(&self, msg: Message) -> io::Result<Message> {
+        let mut attempt = 0;
+        loop {
+            let channel = self.get_channel().await?;
+            attempt += 1;
+
+            let request_id = self.inner.next_request_id.fetch_add(1, Ordering::Relaxed);
+            let err = match channel.request(request_id, msg.clone()).await {
+                Ok(Message::ReplyNotPrimary) => {
+                    io::Error::new(io::ErrorKind::NotConnected, "No primary Sequencer is available")
+                }
+                Ok(reply) => return Ok(reply),
+                Err(e) => e,
+            };
+
+            self.failover(&channel);
+            if attempt > self.inner.addrs.len() {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Get the next connection in a round robin fashion,
+    /// which is reconnected if it is closed or not connected to the current Sequencer
+    async fn get_channel(&self) -> io::Result<Arc<Channel>> {
+        let idx = self.inner.next_channel.fetch_add(1, Ordering::Relaxed) % self.inner.channels.len();
+        let mut channel = self.inner.channels[idx].lock().await;
+        match channel.as_ref() {
+            Some(existing) if existing.is_open() && existing.addr_idx == self.inner.current.load(Ordering::SeqCst) => {
+                Ok(existing.clone())
+            }
+            _ => {
+                let connected = Arc::new(self.connect().await?);
+                *channel = Some(connected.clone());
+                Ok(connected)
+            }
+        }
+    }
+
+    /// Connect to the current Sequencer, or fail over to the next available one
+    async fn connect(&self) -> io::Result<Channel> {
+        let addrs = &self.inner.addrs;
+        let current = self.inner.current.load(Ordering::SeqCst);
+        let mut last_err = None;
+        for addr_idx in (current..addrs.len()).chain(0..current) {
+            match TcpStream::connect(addrs[addr_idx]).await {
+                Ok(tcp_stream) => {
+                    if addr_idx != current
+                        && self
+                            .inner
+                            .current
+                            .compare_exchange(current, addr_idx, Ordering::SeqCst, Ordering::SeqCst)
+                            .is_ok()
+                    {
+                        warn!(
+                            "Sequencer {} is not available, failing over to Sequencer {}",
+                            current, addr_idx
+                        );
+                    }
+                    return Ok(Channel::new(tcp_stream, addr_idx));
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    /// Close `channel`, and let the next Sequencer be the current one,
+    /// unless the current one has already been changed
+    fn failover(&self, channel: &Channel) {
+        channel.close();
+        let next = (channel.addr_idx + 1) % self.inner.addrs.len();
+        if self
+            .inner
+            .current
+            .compare_exchange(channel.addr_idx, next, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            warn!(
+                "Sequencer {} is not available, failing over to Sequencer {}",
+                channel.addr_idx, next
+            );
+        }
+    }
+}
+
+impl Channel {
+    /// Spawn a task reading all replies from `tcp_stream`, which are passed to their waiting requests
+    fn new(tcp_stream: TcpStream, addr_idx: usize) -> Self {
+        let (tcp_read, tcp_write) = tcp_stream.into_split();
+
+        // Delimit frames from bytes using a length header
+        let delimited_read = FramedRead::new(tcp_read, LengthDelimitedCodec::new());
+        let delimited_write = FramedWrite::new(tcp_write, LengthDelimitedCodec::new());
+
+        // Deserialize/Serialize frames using JSON codec
+        let mut serded_read = SymmetricallyFramed::new(delimited_read, SymmetricalJson::<Message>::default());
+        let serded_write = SymmetricallyFramed::new(delimited_write, SymmetricalJson::<Message>::default());
+
+        let pending: PendingRequests = Arc::new(std::sync::Mutex::new(Some(HashMap::new())));
+        let pending_cloned = pending.clone();
+        tokio::spawn(async move {
+            loop {
+                match serded_read.try_next().await {
+                    Ok(Some(Message::Tagged(request_id, reply))) => {
+                        let reply_tx = pending_cloned
+                            .lock()
+                            .unwrap()
+                            .as_mut()
+                            .and_then(|pending| pending.remove(&request_id));
+                        let is_received = match reply_tx {
+                            Some(reply_tx) => reply_tx.send(*reply).is_ok(),
+                            None => false,
+                        };
+                        // The request may have been dropped before receiving its reply
+                        if !is_received {
+                            debug!("Dropping the reply of request {}", request_id);
+                        }
+                    }
+                    Ok(Some(other)) => warn!("Unexpected untagged reply from Sequencer: {:?}", other),
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Cannot read the reply from Sequencer: {}", e);
+                        break;
+                    }
+                }
+            }
+            // All waiting requests are failed by dropping their senders
+            pending_cloned.lock().unwrap().take();
+        });
+
+        Self {
+            addr_idx,
+            serded_write: Mutex::new(serded_write),
+            pending,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.pending.lock().unwrap().is_some()
+    }
+
+    fn close(&self) {
+        self.pending.lock().unwrap().take();
+    }
+
+    async fn request(&self, request_id: u64, msg: Message) -> io::Result<Message> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Connection to Sequencer is closed"))?
+            .insert(request_id, reply_tx);
+
+        if let Err(e) = self
+            .serded_write
+            .lock()
+            .await
+            .send(Message::Tagged(request_id, Box::new(msg)))
+            .await
+        {
+            self.close();
+            return Err(e);
+        }
+
+        reply_rx.await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection to Sequencer is closed before replying",
+            )
+        })
+    }
+}
+
+/// Unit test for `SequencerPool`
+#[cfg(test)]
+mod tests_sequencer_pool {
+    use super::*;
+    use crate::util::tests_helper;
+    use tokio::net::TcpListener;
+    use tokio_serde::formats::SymmetricalJson;
+    use tokio_serde::SymmetricallyFramed;
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+    /// Mock Sequencer replying `reply` to every request on a single connection
+    async fn mock_sequencer(listener: TcpListener, reply: Message) {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let mut framed = SymmetricallyFramed::new(
+            Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+            SymmetricalJson::<Message>::default(),
+        );
+        while let Some(msg) = framed.try_next().await.unwrap() {
+            match msg {
+                Message::Tagged(request_id, _) => framed
+                    .send(Message::Tagged(request_id, Box::new(reply.clone())))
+                    .await
+                    .unwrap(),
+                other => panic!("Unexpected untagged request: {:?}", other),
+            }
+        }
+    }
+
+    /// Mock Sequencer echoing all requests on a single connection in the reverse order of every `batch_size` requests
+    async fn mock_reordering_sequencer(listener: TcpListener, batch_size: usize) {
+        let (tcp_stream, _) = listener.accept().await.unwrap();
+        let mut framed = SymmetricallyFramed::new(
+            Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+            SymmetricalJson::<Message>::default(),
+        );
+        let mut batch = Vec::new();
+        while let Some(msg) = framed.try_next().await.unwrap() {
+            batch.push(msg);
+            if batch.len() == batch_size {
+                while let Some(msg) = batch.pop() {
+                    framed.send(msg).await.unwrap();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipelined() {
+        let _guard = tests_helper::init_logger();
+        let addr: SocketAddr = "127.0.0.1:24624".parse().unwrap();
+        let sequencer_handle = tokio::spawn(mock_reordering_sequencer(TcpListener::bind(addr).await.unwrap(), 8));
+
+        // All requests are in flight over a single connection, before any of them is replied
+        let pool = SequencerPool::new(vec![addr], 1);
+        let replies = future::try_join_all((0..8).map(|i| {
+            let pool = pool.clone();
+            async move { pool.request(Message::ReplyBlockUnblock(i.to_string())).await }
+        }))
+        .await
+        .unwrap();
+        replies.into_iter().enumerate().for_each(|(i, reply)| match reply {
+            Message::ReplyBlockUnblock(m) => assert_eq!(m, i.to_string()),
+            other => panic!("Unexpected reply: {:?}", other),
+        });
+
+        drop(pool);
+        sequencer_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let _guard = tests_helper::init_logger();
+        let fenced_addr: SocketAddr = "127.0.0.1:24621".parse().unwrap();
+        let backup_addr: SocketAddr = "127.0.0.1:24622".parse().unwrap();
+        let closed_addr: SocketAddr = "127.0.0.1:24623".parse().unwrap();
+
+        let fenced_handle = tokio::spawn(mock_sequencer(
+            TcpListener::bind(fenced_addr).await.unwrap(),
+            Message::ReplyNotPrimary,
+        ));
+        let backup_handle = tokio::spawn(mock_sequencer(
+            TcpListener::bind(backup_addr).await.unwrap(),
+            Message::ReplyStop,
+        ));
+
+        // Failing over from the fenced primary to the backup
+        let pool = SequencerPool::new(vec![fenced_addr, backup_addr], 1);
+        for _ in 0..2 {
+            assert!(matches!(
+                pool.request(Message::RequestStop).await.unwrap(),
+                Message::ReplyStop
+            ));
+        }
+        drop(pool);
+        fenced_handle.await.unwrap();
+        backup_handle.await.unwrap();
+
+        // Neither Sequencer is available
+        let pool = SequencerPool::new(vec![closed_addr], 1);
+        assert!(pool.request(Message::RequestStop).await.is_err());
+    }
+}
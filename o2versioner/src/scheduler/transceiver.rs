@@ -1,19 +1,28 @@
+use super::membership::Evictor;
 use crate::comm::scheduler_dbproxy::*;
+use crate::comm::MsqlResponse;
+use crate::core::{MsqlError, RequestMeta, VN};
 use crate::util::executor::Executor;
 use crate::util::executor_addr::*;
+use crate::util::tcp::backoff;
 use async_trait::async_trait;
+use futures::future::Either;
+use futures::pin_mut;
 use futures::prelude::*;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::net::Shutdown;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::oneshot;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{interval, sleep, timeout, Duration, Instant};
 use tokio_serde::formats::SymmetricalJson;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
-use tracing::{field, info, info_span, instrument, trace, warn, Instrument, Span};
+use tracing::{error, field, info, info_span, instrument, trace, warn, Instrument, Span};
 
 /// Request sent from dispatcher direction
 #[derive(Debug)]
@@ -23,6 +32,8 @@ pub enum TransceiverRequest {
         dbproxy_msg: Message,
     },
     DbproxyLoad,
+    /// Send `Message::Cancel` to the dbproxy, which is never replied
+    Cancel(RequestMeta),
 }
 
 impl TransceiverRequest {
@@ -54,9 +65,69 @@ pub type TransceiverAddr = ExecutorAddr<TransceiverRequest>;
 /// # Notes
 /// 1. Uses a single TCP connection for both sending and receiving
 /// 2. Sending and receiving are split so can send and receive concurrently
+/// 3. Once all `TransceiverAddr` are dropped, the tcp connection is closed after
+///    all outstanding requests are replied
+/// 4. The tcp connection is broken if it cannot be connected, fails to send or receive,
+///    or nothing is received for 3 heartbeat intervals. The broken connection is reset, and then
+///    reconnected with exponential backoff for the reconnect attempts
+/// 5. Once reconnected, `Resync` tells the dbproxy the outstanding requests, the ones never received
+///    are sent again, and the dbproxy must not have lost any released versions
+/// 6. The dbproxy fails if it cannot be reconnected, a request is not replied within the request timeout,
+///    or it cannot resync or restore. A request is only timed while the dbproxy is working on it, since the
+///    `HeartbeatAck` restarts its timer if it is still waiting for its versions held by other transactions
+/// 7. A failed dbproxy is evicted, and then all its outstanding requests are dropped. The last live dbproxy is
+///    never evicted, its outstanding requests are failed as `Unavailable` instead, and so are all requests
+///    until it is reconnected
+#[derive(Debug)]
 pub struct Transceiver {
     dbproxy_addr: SocketAddr,
+    tcp_stream: Option<TcpStream>,
     request_rx: RequestReceiver<TransceiverRequest>,
+    heartbeat_interval: Duration,
+    request_timeout: Option<Duration>,
+    reconnect_attempts: u32,
+    evictor: Option<Evictor>,
+}
+
+/// A request sent to the dbproxy and not replied yet
+#[derive(Debug)]
+struct OutstandingRequest {
+    sent: Instant,
+    /// Since when the request is timed, restarted while it is waiting for its versions
+    timed_since: Instant,
+    request: RequestWrapper<TransceiverRequest>,
+}
+
+impl OutstandingRequest {
+    fn new(request: RequestWrapper<TransceiverRequest>) -> Self {
+        let now = Instant::now();
+        Self {
+            sent: now,
+            timed_since: now,
+            request,
+        }
+    }
+
+    fn request_meta(&self) -> &RequestMeta {
+        self.request
+            .request()
+            .try_get_dbproxy_msg_inner()
+            .unwrap()
+            .1
+            .try_get_request_meta()
+            .unwrap()
+    }
+}
+
+/// (ClientAddr) -> LinkedList<OutstandingRequest>
+type OutstandingRequests = Arc<Mutex<HashMap<SocketAddr, VecDeque<OutstandingRequest>>>>;
+
+/// Why a tcp connection with the dbproxy ends
+enum Failure {
+    /// The dbproxy may still be alive, and is reconnected
+    Disconnected(String),
+    /// The dbproxy is evicted
+    Failed(String),
 }
 
 impl Transceiver {
@@ -67,10 +138,42 @@ impl Transceiver {
             addr,
             Self {
                 dbproxy_addr,
+                tcp_stream: None,
                 request_rx,
+                heartbeat_interval: Duration::from_millis(1000),
+                request_timeout: None,
+                reconnect_attempts: 3,
+                evictor: None,
             },
         )
     }
+
+    /// Use an already connected `tcp_stream` to the dbproxy, instead of connecting to it once running
+    pub fn set_tcp_stream(mut self, tcp_stream: TcpStream) -> Self {
+        self.tcp_stream = Some(tcp_stream);
+        self
+    }
+
+    pub fn set_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    pub fn set_request_timeout(mut self, request_timeout: Option<Duration>) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn set_reconnect_attempts(mut self, reconnect_attempts: u32) -> Self {
+        self.reconnect_attempts = reconnect_attempts;
+        self
+    }
+
+    /// The failed dbproxy is only logged if there is no `Evictor`
+    pub fn set_evictor(mut self, evictor: Evictor) -> Self {
+        self.evictor = Some(evictor);
+        self
+    }
 }
 
 #[async_trait]
@@ -79,138 +182,461 @@ impl Executor for Transceiver {
     async fn run(mut self: Box<Self>) {
         Span::current().record("message", &&self.dbproxy_addr.to_string()[..]);
 
-        let Transceiver {
+        let outstanding_req = OutstandingRequests::default();
+        let mut tcp_stream = self.tcp_stream.take();
+        let mut reconnecting = false;
+        loop {
+            let failure = loop {
+                let socket = match tcp_stream.take() {
+                    Some(tcp_stream) => tcp_stream,
+                    None => match Self::connect(self.dbproxy_addr, self.reconnect_attempts, reconnecting).await {
+                        Ok(tcp_stream) => tcp_stream,
+                        Err(e) => break Some(e),
+                    },
+                };
+
+                match self.transceive(socket, &outstanding_req, reconnecting).await {
+                    Ok(()) => break None,
+                    Err(Failure::Disconnected(e)) if self.reconnect_attempts == 0 => break Some(e),
+                    Err(Failure::Disconnected(e)) => {
+                        warn!("Disconnected from dbproxy, reconnecting: {}", e);
+                        reconnecting = true;
+                    }
+                    Err(Failure::Failed(e)) => break Some(e),
+                }
+            };
+
+            // Evict the failed dbproxy before dropping request_rx and outstanding requests,
+            // so that the dispatcher won't send to the dbproxy again when the dropped requests are retried
+            if let Some(e) = failure {
+                error!("Dbproxy {} failed: {}", self.dbproxy_addr, e);
+                if let Some(evictor) = &self.evictor {
+                    if !evictor.evict(&self.dbproxy_addr).await {
+                        warn!("Keeping the last live dbproxy, failing all requests until reconnected");
+                        let err = MsqlError::unavailable(format!("Dbproxy {} failed: {}", self.dbproxy_addr, e));
+                        outstanding_req
+                            .lock()
+                            .await
+                            .drain()
+                            .flat_map(|(_, queue)| queue)
+                            .for_each(|outstanding| reject(outstanding.request, &err));
+                        match self.reject_until_reconnected(&err).await {
+                            Some(socket) => {
+                                tcp_stream = Some(socket);
+                                reconnecting = true;
+                                continue;
+                            }
+                            None => break,
+                        }
+                    }
+                }
+                drop(self);
+                outstanding_req.lock().await.clear();
+            }
+            break;
+        }
+
+        info!("DIES");
+    }
+}
+
+impl Transceiver {
+    /// Connect to the dbproxy, retrying with exponential backoff for `reconnect_attempts` times.
+    /// Every attempt is a retry when `reconnecting`
+    async fn connect(
+        dbproxy_addr: SocketAddr,
+        reconnect_attempts: u32,
+        reconnecting: bool,
+    ) -> Result<TcpStream, String> {
+        let mut last_error = String::new();
+        for attempt in (reconnecting as u32)..=reconnect_attempts {
+            if attempt > 0 {
+                sleep(backoff(attempt - 1)).await;
+            }
+            match TcpStream::connect(dbproxy_addr).await {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(e) => {
+                    warn!("Cannot connect to dbproxy, attempt {}: {}", attempt, e);
+                    last_error = format!("Cannot connect to dbproxy: {}", e);
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Reconnect to the dbproxy every heartbeat interval, rejecting all requests with `err` meanwhile.
+    /// Returns `None` once all `TransceiverAddr` are dropped
+    async fn reject_until_reconnected(&mut self, err: &MsqlError) -> Option<TcpStream> {
+        let Self {
             dbproxy_addr,
-            mut request_rx,
-        } = *self;
+            request_rx,
+            heartbeat_interval,
+            reconnect_attempts,
+            ..
+        } = self;
+        loop {
+            let connecting = async {
+                sleep(*heartbeat_interval).await;
+                Self::connect(*dbproxy_addr, *reconnect_attempts, false).await
+            };
+            pin_mut!(connecting);
+            let connected = loop {
+                tokio::select! {
+                    connected = &mut connecting => break connected,
+                    request = request_rx.next() => match request {
+                        Some(request) => reject(request, err),
+                        None => return None,
+                    },
+                }
+            };
+            match connected {
+                Ok(tcp_stream) => return Some(tcp_stream),
+                Err(e) => warn!("Cannot reconnect the last live dbproxy: {}", e),
+            }
+        }
+    }
 
-        let socket = TcpStream::connect(dbproxy_addr)
-            .await
-            .expect("Cannot connect to dbproxy");
+    /// Transceive over `socket` until all `TransceiverAddr` are dropped, or the connection fails
+    ///
+    /// When `resync`, the dbproxy is resynchronized first, and no request is sent until then.
+    async fn transceive(
+        &mut self,
+        socket: TcpStream,
+        outstanding_req: &OutstandingRequests,
+        resync: bool,
+    ) -> Result<(), Failure> {
+        let Self {
+            dbproxy_addr,
+            request_rx,
+            heartbeat_interval,
+            request_timeout,
+            evictor,
+            ..
+        } = self;
+        let heartbeat_interval = *heartbeat_interval;
         let (reader, mut writer) = socket.into_split();
 
-        // (ClientAddr) -> LinkedList<RequestWrapper<TransceiverRequest>>
-        let outstanding_req: HashMap<SocketAddr, VecDeque<RequestWrapper<TransceiverRequest>>> = HashMap::new();
-        let outstanding_req = Arc::new(Mutex::new(outstanding_req));
+        // Notified whenever an outstanding request is replied
+        let replied_notify = Arc::new(Notify::new());
+        let (resynced_tx, resynced_rx) = oneshot::channel();
 
-        // Spawn dbproxy reader, processing reply from dbproxy in serial one after one
+        // Dbproxy reader, processing reply from dbproxy in serial one after one, until the dbproxy fails
         let delimited_read = FramedRead::new(reader, LengthDelimitedCodec::new());
         let mut serded_read = SymmetricallyFramed::new(delimited_read, SymmetricalJson::<Message>::default());
         let outstanding_req_clone = outstanding_req.clone();
+        let replied_notify_clone = replied_notify.clone();
         let reader_task = async move {
-            while let Some(msg) = serded_read.try_next().await.unwrap() {
-                async {
-                    match &msg {
-                        Message::MsqlResponse(arrived_request_meta, _msqlresponse) => {
+            let mut resynced_tx = Some(resynced_tx);
+            loop {
+                let msg = match timeout(heartbeat_interval * 3, serded_read.try_next()).await {
+                    Ok(Ok(Some(msg))) => msg,
+                    Ok(Ok(None)) => return Failure::Disconnected(String::from("Connection closed by dbproxy")),
+                    Ok(Err(e)) => return Failure::Disconnected(format!("Cannot receive from dbproxy: {}", e)),
+                    Err(_) => {
+                        return Failure::Disconnected(format!("Nothing received for {:?}", heartbeat_interval * 3))
+                    }
+                };
+
+                let failure = async {
+                    match msg {
+                        Message::MsqlResponse(..)
+                        | Message::Snapshot(..)
+                        | Message::Description(..)
+                        | Message::Restored(..) => {
+                            let arrived_request_meta = msg.try_get_request_meta().unwrap().clone();
                             Span::current().record("message", &&arrived_request_meta.to_string()[..]);
                             let mut guard = outstanding_req_clone.lock().await;
-                            let queue = guard
-                                .get_mut(&arrived_request_meta.client_addr)
-                                .expect("No client addr in outstanding_req");
+                            let queue = guard.entry(arrived_request_meta.client_addr).or_default();
 
-                            let queue_idx = queue
+                            // Replied again after reconnecting, if replied right before the connection broke
+                            let queue_idx = match queue
                                 .iter()
-                                .position(|req_wrapper| {
-                                    req_wrapper
-                                        .request()
-                                        .try_get_dbproxy_msg_inner()
-                                        .unwrap()
-                                        .1
-                                        .try_get_request_meta()
-                                        .unwrap()
-                                        == arrived_request_meta
-                                })
-                                .expect("No record in outstanding_req (position)");
-                            let (popped_request, reply_ch) = queue
+                                .position(|outstanding| outstanding.request_meta() == &arrived_request_meta)
+                            {
+                                Some(queue_idx) => queue_idx,
+                                None => {
+                                    warn!("Ignoring the reply to a request not outstanding");
+                                    return None;
+                                }
+                            };
+                            let (_, reply_ch) = queue
                                 .remove(queue_idx)
                                 .expect("No record in outstanding_req (remove)")
+                                .request
                                 .unwrap();
 
                             if queue_idx != 0 {
                                 warn!("conn fifo is popping at an out-of-order position {}", queue_idx);
                             }
-                            if queue.len() > 0 {
+                            if !queue.is_empty() {
                                 info!("conn fifo has {} after Pop", queue.len());
                             } else {
                                 trace!("conn fifo has {} after Pop", queue.len());
                             }
 
-                            assert_eq!(
-                                popped_request
-                                    .try_get_dbproxy_msg_inner()
-                                    .unwrap()
-                                    .1
-                                    .try_get_request_meta()
-                                    .unwrap(),
-                                arrived_request_meta
-                            );
+                            // A dbproxy failed to restore holds all requests
+                            let failure = match &msg {
+                                Message::Restored(_, Err(e)) => Some(Failure::Failed(format!("Cannot restore: {}", e))),
+                                _ => None,
+                            };
 
                             trace!("-> {:?}", msg);
-                            reply_ch.unwrap().send(TransceiverReply::DbproxyMsg(msg)).unwrap();
+                            // The requester may be gone, such as a cancelled request
+                            let _ = reply_ch.unwrap().send(TransceiverReply::DbproxyMsg(msg));
+                            replied_notify_clone.notify_one();
+                            failure
+                        }
+                        Message::Resynced(missing, version_vector) => {
+                            match resynced_tx.take() {
+                                Some(resynced_tx) => {
+                                    let _ = resynced_tx.send((missing, version_vector));
+                                }
+                                None => warn!("Unexpected Resynced"),
+                            }
+                            None
                         }
-                        other => warn!("Unsupported {:?}", other),
-                    };
+                        Message::HeartbeatAck(waiting) => {
+                            trace!("<- HeartbeatAck, {} waiting", waiting.len());
+                            // Not timed while waiting for the versions
+                            let now = Instant::now();
+                            outstanding_req_clone
+                                .lock()
+                                .await
+                                .values_mut()
+                                .flatten()
+                                .filter(|outstanding| waiting.contains(outstanding.request_meta()))
+                                .for_each(|outstanding| outstanding.timed_since = now);
+                            None
+                        }
+                        other => {
+                            warn!("Unsupported {:?}", other);
+                            None
+                        }
+                    }
                 }
                 .instrument(info_span!("<-dbproxy", message = field::Empty))
                 .await;
-            }
 
-            info!("Tcp receiver service terminated");
+                if let Some(failure) = failure {
+                    return failure;
+                }
+            }
         };
-        let reader_handle = tokio::spawn(reader_task.in_current_span());
-
-        // Spawn request_rx, processing request from dispatcher in serial one after one
-        let request_rx_task = async move {
-            while let Some(request) = request_rx.next().await {
-                let task = async {
-                    match request.request() {
-                        TransceiverRequest::DbproxyMsg {
-                            dbproxy_addr: _dbproxy_addr,
-                            dbproxy_msg,
-                        } => {
-                            let dbproxy_msg = dbproxy_msg.clone();
-                            let meta = dbproxy_msg.try_get_request_meta().unwrap();
-                            let client_addr = meta.client_addr.clone();
-                            Span::current().record("message", &&meta.to_string()[..]);
-
-                            trace!("-> {:?}", dbproxy_msg);
-                            let mut guard = outstanding_req.lock().await;
-                            let queue = guard.entry(client_addr.clone()).or_default();
-                            if queue.len() > 0 {
-                                info!("conn fifo has {} before Push", queue.len());
-                            } else {
-                                trace!("conn fifo has {} before Push", queue.len());
+
+        // Request_rx, processing request from dispatcher in serial one after one,
+        // and sending heartbeats to dbproxy in between
+        let request_rx_task = async {
+            if resync {
+                Self::resync(&mut writer, outstanding_req, resynced_rx, *dbproxy_addr, evictor).await?;
+            }
+
+            let mut heartbeat = interval(heartbeat_interval);
+            let mut request_rx_ended = false;
+            loop {
+                // When the request_rx channel is disconnected, drain all outstanding requests
+                // and then shutdown the tcp socket
+                if request_rx_ended {
+                    if outstanding_req.lock().await.values().all(|queue| queue.is_empty()) {
+                        break;
+                    }
+                    info!("Draining outstanding requests");
+                }
+
+                tokio::select! {
+                    request = request_rx.next(), if !request_rx_ended => {
+                        let request = match request {
+                            Some(request) => request,
+                            None => {
+                                request_rx_ended = true;
+                                continue;
+                            }
+                        };
+                        let task = async {
+                            match request.request() {
+                                TransceiverRequest::DbproxyMsg {
+                                    dbproxy_addr: _dbproxy_addr,
+                                    dbproxy_msg,
+                                } => {
+                                    let dbproxy_msg = dbproxy_msg.clone();
+                                    let meta = dbproxy_msg.try_get_request_meta().unwrap();
+                                    let client_addr = meta.client_addr.clone();
+                                    Span::current().record("message", &&meta.to_string()[..]);
+
+                                    trace!("-> {:?}", dbproxy_msg);
+                                    let mut guard = outstanding_req.lock().await;
+                                    let queue = guard.entry(client_addr.clone()).or_default();
+                                    if !queue.is_empty() {
+                                        info!("conn fifo has {} before Push", queue.len());
+                                    } else {
+                                        trace!("conn fifo has {} before Push", queue.len());
+                                    }
+                                    queue.push_back(OutstandingRequest::new(request));
+                                    drop(guard);
+                                    send(&mut writer, dbproxy_msg).await.map_err(Failure::Disconnected)
+                                }
+                                TransceiverRequest::DbproxyLoad => {
+                                    Span::current().record("message", &"Load");
+                                    let load: usize =
+                                        outstanding_req.lock().await.values().map(|queue| queue.len()).sum();
+                                    trace!("{}", load);
+                                    // The dispatcher stops waiting for the load once the request is cancelled
+                                    let _ = request.unwrap().1.unwrap().send(TransceiverReply::DbproxyLoad(load));
+                                    Ok(())
+                                }
+                                TransceiverRequest::Cancel(meta) => {
+                                    Span::current().record("message", &&meta.to_string()[..]);
+                                    trace!("-> Cancel");
+                                    send(&mut writer, Message::Cancel(meta.clone()))
+                                        .await
+                                        .map_err(Failure::Disconnected)
+                                }
+                            }
+                        };
+                        task.instrument(info_span!("->dbproxy", message = field::Empty)).await?;
+                    }
+                    _ = replied_notify.notified(), if request_rx_ended => {}
+                    _ = heartbeat.tick() => {
+                        if let Some(request_timeout) = request_timeout {
+                            let oldest = outstanding_req
+                                .lock()
+                                .await
+                                .values()
+                                .flatten()
+                                .map(|outstanding| outstanding.timed_since)
+                                .min();
+                            if let Some(oldest) = oldest.filter(|timed_since| timed_since.elapsed() > *request_timeout) {
+                                return Err(Failure::Failed(format!("Request not replied for {:?}", oldest.elapsed())));
                             }
-                            queue.push_back(request);
-                            let delimited_write = FramedWrite::new(&mut writer, LengthDelimitedCodec::new());
-                            let mut serded_write =
-                                SymmetricallyFramed::new(delimited_write, SymmetricalJson::<Message>::default());
-                            serded_write.send(dbproxy_msg).await.expect("cannot send to dbproxy");
-                        }
-                        TransceiverRequest::DbproxyLoad => {
-                            Span::current().record("message", &"Load");
-                            let load: usize = outstanding_req.lock().await.values().map(|queue| queue.len()).sum();
-                            trace!("{}", load);
-                            request
-                                .unwrap()
-                                .1
-                                .unwrap()
-                                .send(TransceiverReply::DbproxyLoad(load))
-                                .unwrap();
                         }
+                        send(&mut writer, Message::Heartbeat).await.map_err(Failure::Disconnected)?;
                     }
-                };
-                task.instrument(info_span!("->dbproxy", message = field::Empty)).await;
+                }
             }
 
-            // When the request_rx channel is disconnected, shutdown the tcp socket
             writer.as_ref().shutdown(Shutdown::Both).unwrap();
             info!("Request rx service terminated");
+            Ok(())
         };
-        let request_rx_handle = tokio::spawn(request_rx_task.in_current_span());
 
-        tokio::try_join!(reader_handle, request_rx_handle).unwrap();
+        let result = {
+            pin_mut!(reader_task, request_rx_task);
+            match future::select(reader_task, request_rx_task).await {
+                Either::Left((failure, _)) => Err(failure),
+                Either::Right((Err(failure), _)) => Err(failure),
+                Either::Right((Ok(()), _)) => {
+                    info!("Tcp receiver service terminated");
+                    Ok(())
+                }
+            }
+        };
 
-        info!("DIES");
+        // Reset instead of closing, so that the dbproxy waits for reconnecting instead of stopping
+        if result.is_err() {
+            let _ = writer.as_ref().set_linger(Some(Duration::from_secs(0)));
+            writer.forget();
+        }
+        result
+    }
+
+    /// Send `Resync` with all outstanding requests, and then send again the ones never received by the dbproxy
+    async fn resync(
+        writer: &mut OwnedWriteHalf,
+        outstanding_req: &OutstandingRequests,
+        resynced_rx: oneshot::Receiver<(Vec<RequestMeta>, HashMap<String, VN>)>,
+        dbproxy_addr: SocketAddr,
+        evictor: &Option<Evictor>,
+    ) -> Result<(), Failure> {
+        // In the order they were sent, requests of the same client are kept in order
+        let mut outstanding_msgs: Vec<_> = outstanding_req
+            .lock()
+            .await
+            .values()
+            .flatten()
+            .map(|outstanding| {
+                let msg = outstanding
+                    .request
+                    .request()
+                    .try_get_dbproxy_msg_inner()
+                    .unwrap()
+                    .1
+                    .clone();
+                (outstanding.sent, msg)
+            })
+            .collect();
+        outstanding_msgs.sort_by_key(|(sent, _)| *sent);
+        let metas = outstanding_msgs
+            .iter()
+            .map(|(_, msg)| msg.try_get_request_meta().unwrap().clone())
+            .collect();
+
+        send(writer, Message::Resync(metas))
+            .await
+            .map_err(Failure::Disconnected)?;
+        let (missing, version_vector) = resynced_rx
+            .await
+            .map_err(|_| Failure::Disconnected(String::from("Cannot resync")))?;
+        if let Some(evictor) = evictor {
+            if evictor.has_lost_versions(&dbproxy_addr, &version_vector).await {
+                return Err(Failure::Failed(format!(
+                    "Dbproxy lost its versions, now at {:?}",
+                    version_vector
+                )));
+            }
+        }
+
+        for (_, msg) in outstanding_msgs {
+            if missing.contains(msg.try_get_request_meta().unwrap()) {
+                send(writer, msg).await.map_err(Failure::Disconnected)?;
+            }
+        }
+
+        // Not timed out because of the disconnection
+        let now = Instant::now();
+        outstanding_req
+            .lock()
+            .await
+            .values_mut()
+            .flatten()
+            .for_each(|outstanding| outstanding.timed_since = now);
+
+        info!("Resynced, {} requests sent again", missing.len());
+        Ok(())
     }
 }
+
+/// Reply the request with `err` without sending it to the dbproxy
+fn reject(request: RequestWrapper<TransceiverRequest>, err: &MsqlError) {
+    let (request, reply_ch) = request.unwrap();
+    let reply = match request {
+        TransceiverRequest::DbproxyMsg { dbproxy_msg, .. } => {
+            let msg = match dbproxy_msg {
+                Message::MsqlRequest(meta, msql, _) => {
+                    Message::MsqlResponse(meta, MsqlResponse::err(err.clone(), &msql))
+                }
+                Message::SnapshotRequest(meta, _) => Message::Snapshot(meta, Err(err.clone())),
+                Message::DescribeRequest(meta, _) => Message::Description(meta, Err(err.clone())),
+                Message::Restore(meta, _, _) => Message::Restored(meta, Err(err.clone())),
+                other => {
+                    warn!("Cannot reject {:?}", other);
+                    return;
+                }
+            };
+            TransceiverReply::DbproxyMsg(msg)
+        }
+        TransceiverRequest::DbproxyLoad => TransceiverReply::DbproxyLoad(0),
+        TransceiverRequest::Cancel(_) => return,
+    };
+    if let Some(reply_ch) = reply_ch {
+        let _ = reply_ch.send(reply);
+    }
+}
+
+/// Send a `Message` to the dbproxy
+async fn send(writer: &mut OwnedWriteHalf, msg: Message) -> Result<(), String> {
+    let delimited_write = FramedWrite::new(writer, LengthDelimitedCodec::new());
+    let mut serded_write = SymmetricallyFramed::new(delimited_write, SymmetricalJson::<Message>::default());
+    serded_write
+        .send(msg)
+        .await
+        .map_err(|e| format!("Cannot send to dbproxy: {}", e))
+}
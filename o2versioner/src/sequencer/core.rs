@@ -1,13 +1,18 @@
-use crate::core::MsqlBeginTx;
+use super::persistence::{merge_records, Persistence};
+use super::replication::Replication;
+use crate::comm::scheduler_sequencer::{Message, TableVNRecord, VNRecords};
+use crate::core::{ClientMeta, MsqlBeginTx, TableOps};
 use crate::core::{RWOperation, TxTableVN, TxVN, VN};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tracing::{error, info, warn};
 
-/// Version number info for a single table
-#[derive(Default, Debug, Eq, PartialEq)]
-struct TableVNRecord {
-    next_for_read: VN,
-    next_for_write: VN,
-}
+/// Maximum number of recently assigned `TxVN`s kept for retried requests
+const ASSIGNED_TXVNS_CAPACITY: usize = 1024;
 
 impl TableVNRecord {
     fn assign_read(&mut self) -> VN {
@@ -35,10 +40,78 @@ impl TableVNRecord {
     }
 }
 
+/// Recently assigned `TxVN`s of each `ClientMeta`,
+/// so that a retried `Message::RequestTxVN` gets the same `TxVN` again
+#[derive(Default)]
+struct AssignedTxVNs {
+    order: VecDeque<ClientMeta>,
+    txvns: HashMap<ClientMeta, TxVN>,
+}
+
+impl AssignedTxVNs {
+    fn get(&self, client_meta: &ClientMeta, tableops: &TableOps) -> Option<TxVN> {
+        self.txvns
+            .get(client_meta)
+            .filter(|txvn| txvn.to_tableops() == *tableops)
+            .cloned()
+    }
+
+    fn insert(&mut self, client_meta: ClientMeta, txvn: TxVN) {
+        if self.txvns.insert(client_meta.clone(), txvn).is_none() {
+            self.order.push_back(client_meta);
+        }
+        if self.order.len() > ASSIGNED_TXVNS_CAPACITY {
+            let oldest = self.order.pop_front().unwrap();
+            self.txvns.remove(&oldest);
+        }
+    }
+
+    fn to_vec(&self) -> Vec<(ClientMeta, TxVN)> {
+        self.order
+            .iter()
+            .map(|client_meta| (client_meta.clone(), self.txvns.get(client_meta).unwrap().clone()))
+            .collect()
+    }
+}
+
+impl Extend<(ClientMeta, TxVN)> for AssignedTxVNs {
+    fn extend<I: IntoIterator<Item = (ClientMeta, TxVN)>>(&mut self, iter: I) {
+        iter.into_iter()
+            .for_each(|(client_meta, txvn)| self.insert(client_meta, txvn));
+    }
+}
+
+/// A `Message::RequestTxVN` received while blocked, waiting to be resumed once unblocked
+struct QueuedTxVN {
+    queued_id: u64,
+    client_meta: ClientMeta,
+    msqlbegintx: MsqlBeginTx,
+    reply_tx: oneshot::Sender<Message>,
+}
+
+/// Role of the Sequencer in a primary/backup pair
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Role {
+    /// Assigns version numbers, which are replicated to the backup if there is one
+    Primary,
+    /// Hot standby of the primary, takes over once receiving a `Message::RequestTxVN`,
+    /// but only if it has been synced with the primary
+    Backup { is_synced: bool },
+    /// Primary that has been taken over by its backup, never assigns version numbers again
+    Fenced,
+}
+
 /// Sequencer state
 pub struct State {
-    vn_record: HashMap<String, TableVNRecord>,
+    vn_record: VNRecords,
     is_vn_record_blocked: bool,
+    persistence: Option<Persistence>,
+    role: Role,
+    replication: Option<Replication>,
+    assigned_txvns: AssignedTxVNs,
+    block_wait_timeout: Option<Duration>,
+    queued_txvns: VecDeque<QueuedTxVN>,
+    next_queued_id: u64,
 }
 
 impl State {
@@ -46,35 +119,306 @@ impl State {
         Self {
             vn_record: HashMap::new(),
             is_vn_record_blocked: false,
+            persistence: None,
+            role: Role::Primary,
+            replication: None,
+            assigned_txvns: Default::default(),
+            block_wait_timeout: None,
+            queued_txvns: VecDeque::new(),
+            next_queued_id: 0,
         }
     }
 
+    /// Restore the state persisted in `dir`, and persist all further assignments into it
+    pub fn restore<P: AsRef<Path>>(dir: P, snapshot_interval: u32) -> io::Result<Self> {
+        let (persistence, vn_record) = Persistence::open(dir, snapshot_interval)?;
+        Ok(Self {
+            vn_record,
+            persistence: Some(persistence),
+            ..Self::new()
+        })
+    }
+
+    /// Set the address of the backup Sequencer, to which every assignment is replicated before being replied,
+    /// will overwrite previous value
+    pub fn set_backup_addr(mut self, backup_addr: Option<SocketAddr>) -> Self {
+        self.replication = backup_addr.map(Replication::new);
+        self
+    }
+
+    /// Set whether the Sequencer starts as a backup, will overwrite previous value
+    pub fn set_is_backup(mut self, is_backup: bool) -> Self {
+        self.role = if is_backup {
+            Role::Backup { is_synced: false }
+        } else {
+            Role::Primary
+        };
+        self
+    }
+
+    /// Set how long a `Message::RequestTxVN` can wait while blocked, will overwrite previous value.
+    /// If `None`, a `Message::RequestTxVN` fails immediately while blocked
+    pub fn set_block_wait_timeout(mut self, block_wait_timeout: Option<Duration>) -> Self {
+        self.block_wait_timeout = block_wait_timeout;
+        self
+    }
+
+    pub fn block_wait_timeout(&self) -> Option<Duration> {
+        self.block_wait_timeout
+    }
+
+    /// Whether a `Message::RequestTxVN` should wait via `queue_txvn` instead of being replied immediately
+    pub fn should_queue_txvn(&self) -> bool {
+        self.is_vn_record_blocked && self.block_wait_timeout.is_some()
+    }
+
+    /// Queue a `Message::RequestTxVN` received while blocked, which is replied to the returned receiver
+    /// once unblocked. Returns the id to cancel it via `cancel_queued_txvn` as well
+    pub fn queue_txvn(
+        &mut self,
+        client_meta: ClientMeta,
+        msqlbegintx: MsqlBeginTx,
+    ) -> (u64, oneshot::Receiver<Message>) {
+        let queued_id = self.next_queued_id;
+        self.next_queued_id += 1;
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.queued_txvns.push_back(QueuedTxVN {
+            queued_id,
+            client_meta,
+            msqlbegintx,
+            reply_tx,
+        });
+        (queued_id, reply_rx)
+    }
+
+    /// Remove a queued `Message::RequestTxVN`, returns `false` if it has already been replied
+    pub fn cancel_queued_txvn(&mut self, queued_id: u64) -> bool {
+        match self
+            .queued_txvns
+            .iter()
+            .position(|queued| queued.queued_id == queued_id)
+        {
+            Some(idx) => {
+                self.queued_txvns.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unblock and reply to all queued `Message::RequestTxVN` in their arrival order,
+    /// returns the previous value of `is_vn_record_blocked`
+    pub async fn unblock(&mut self) -> bool {
+        let prev_is_blocked = self.set_vn_record_blocked(false);
+
+        if !self.queued_txvns.is_empty() {
+            info!("Resuming {} queued requests", self.queued_txvns.len());
+        }
+        while let Some(queued) = self.queued_txvns.pop_front() {
+            // No version number is assigned to a request that is no longer waiting
+            if queued.reply_tx.is_closed() {
+                continue;
+            }
+            let reply = self.request_txvn(queued.client_meta, queued.msqlbegintx).await;
+            if queued.reply_tx.send(reply).is_err() {
+                warn!("Request {} is dropped before its reply", queued.queued_id);
+            }
+        }
+
+        prev_is_blocked
+    }
+
+    /// Reply to a `Message::RequestTxVN`
+    ///
+    /// A retried request of the same `ClientMeta` gets the same `TxVN` again.
+    /// If the Sequencer has a backup, the `TxVN` is only replied once the backup acknowledges it;
+    /// the backup takes over and the Sequencer is fenced once the backup receives any `Message::RequestTxVN`.
+    /// While the backup cannot be reached, `Message::ReplyNotPrimary` is replied instead,
+    /// see `SequencerConf::backup_addr`.
+    pub async fn request_txvn(&mut self, client_meta: ClientMeta, msqlbegintx: MsqlBeginTx) -> Message {
+        if let Some(txvn) = self.assigned_txvns.get(&client_meta, msqlbegintx.tableops()) {
+            warn!("Replying the TxVN already assigned to {}", client_meta);
+            return Message::ReplyTxVN(Some(txvn));
+        }
+
+        match self.role {
+            Role::Primary => (),
+            Role::Backup { is_synced: true } => {
+                warn!("Taking over from the primary Sequencer");
+                self.role = Role::Primary;
+            }
+            Role::Backup { is_synced: false } | Role::Fenced => return Message::ReplyNotPrimary,
+        }
+
+        let txvn = if self.replication.is_some() {
+            match self.assign_vn_replicated(&client_meta, msqlbegintx).await {
+                Ok(txvn) => txvn,
+                Err(msg) => return msg,
+            }
+        } else {
+            self.assign_vn(msqlbegintx)
+        };
+
+        if let Some(txvn) = txvn.as_ref() {
+            self.assigned_txvns.insert(client_meta, txvn.clone());
+        }
+        Message::ReplyTxVN(txvn)
+    }
+
     /// If `is_vn_record_blocked() == true`, will return `None`;
+    /// If the assignment cannot be persisted, will return `None` and leave the state unchanged;
     /// Else, will return `Some<TxVN>`
     pub fn assign_vn(&mut self, msqlbegintx: MsqlBeginTx) -> Option<TxVN> {
-        let (tx, tableops) = msqlbegintx.unwrap();
-
         if self.is_vn_record_blocked() {
+            return None;
+        }
+
+        let (txvn, assigned) = self.stage_vn(msqlbegintx);
+        if self.merge_vn_record(assigned, false) {
+            Some(txvn)
+        } else {
             None
+        }
+    }
+
+    /// Same as `assign_vn`, but the assignment is replicated to the backup before taking effect;
+    /// returns `Err` with the reply if it fails to replicate
+    async fn assign_vn_replicated(
+        &mut self,
+        client_meta: &ClientMeta,
+        msqlbegintx: MsqlBeginTx,
+    ) -> Result<Option<TxVN>, Message> {
+        if self.is_vn_record_blocked() {
+            return Ok(None);
+        }
+
+        let replication = self.replication.as_mut().unwrap();
+        if !replication.is_synced() {
+            let reply = replication
+                .sync(self.vn_record.clone(), self.assigned_txvns.to_vec())
+                .await;
+            match reply {
+                Ok(Some((vn_record, assigned_txvns))) => {
+                    self.merge_vn_record(vn_record, true);
+                    self.assigned_txvns.extend(assigned_txvns);
+                }
+                Ok(None) => return Err(self.fence()),
+                Err(e) => {
+                    error!("Cannot sync with the backup Sequencer: {}", e);
+                    return Err(Message::ReplyNotPrimary);
+                }
+            }
+        }
+
+        let (txvn, assigned) = self.stage_vn(msqlbegintx);
+        let reply = self
+            .replication
+            .as_mut()
+            .unwrap()
+            .replicate(client_meta.clone(), txvn.clone(), assigned.clone())
+            .await;
+        match reply {
+            Ok(true) => {
+                // The backup holds the assignment even if it cannot be persisted here
+                self.merge_vn_record(assigned, true);
+                Ok(Some(txvn))
+            }
+            Ok(false) => Err(self.fence()),
+            Err(e) => {
+                error!("Cannot replicate to the backup Sequencer: {}", e);
+                Err(Message::ReplyNotPrimary)
+            }
+        }
+    }
+
+    /// Reply to a `Message::RequestSync` from the primary
+    pub fn sync_from_primary(&mut self, vn_record: VNRecords, assigned_txvns: Vec<(ClientMeta, TxVN)>) -> Message {
+        if let Role::Backup { .. } = self.role {
+            self.merge_vn_record(vn_record, true);
+            self.assigned_txvns.extend(assigned_txvns);
+            self.role = Role::Backup { is_synced: true };
+            Message::ReplySync(Some((self.vn_record.clone(), self.assigned_txvns.to_vec())))
+        } else {
+            Message::ReplySync(None)
+        }
+    }
+
+    /// Reply to a `Message::RequestReplicate` from the primary
+    pub fn replicate_from_primary(&mut self, client_meta: ClientMeta, txvn: TxVN, assigned: VNRecords) -> Message {
+        if let Role::Backup { is_synced: true } = self.role {
+            self.merge_vn_record(assigned, true);
+            self.assigned_txvns.insert(client_meta, txvn);
+            Message::ReplyReplicate(true)
         } else {
-            Some(
-                TxVN::new()
-                    .set_tx(tx)
-                    .set_txtablevns(tableops.into_iter().map(|tableop| {
-                        TxTableVN {
-                            table: tableop.table().to_string(),
-                            vn: self
-                                .vn_record
-                                .entry(tableop.table().to_string())
-                                .or_default()
-                                .assign(&tableop.op()),
-                            op: tableop.op(),
-                        }
-                    })),
-            )
+            Message::ReplyReplicate(false)
         }
     }
 
+    /// Assign on copies of the records, which only replace the current ones once merged
+    fn stage_vn(&self, msqlbegintx: MsqlBeginTx) -> (TxVN, VNRecords) {
+        let (tx, tableops) = msqlbegintx.unwrap();
+
+        let mut assigned = VNRecords::new();
+        let txtablevns: Vec<_> = tableops
+            .into_iter()
+            .map(|tableop| {
+                let vn_record = &self.vn_record;
+                TxTableVN {
+                    table: tableop.table().to_string(),
+                    vn: assigned
+                        .entry(tableop.table().to_string())
+                        .or_insert_with(|| vn_record.get(tableop.table()).cloned().unwrap_or_default())
+                        .assign(&tableop.op()),
+                    op: tableop.op(),
+                }
+            })
+            .collect();
+
+        (TxVN::new().set_tx(tx).set_txtablevns(txtablevns), assigned)
+    }
+
+    /// Persist `vn_record` and merge it into the current records, returns whether it is persisted.
+    /// If it cannot be persisted, it is still merged if `merge_anyway`
+    fn merge_vn_record(&mut self, vn_record: VNRecords, merge_anyway: bool) -> bool {
+        let is_persisted = match self
+            .persistence
+            .as_mut()
+            .map(|persistence| persistence.append(&vn_record))
+        {
+            Some(Err(e)) => {
+                error!("Cannot persist the assigned version numbers: {}", e);
+                false
+            }
+            _ => true,
+        };
+
+        if is_persisted || merge_anyway {
+            merge_records(&mut self.vn_record, vn_record);
+        }
+
+        if let Some(persistence) = self.persistence.as_mut() {
+            if is_persisted && persistence.should_snapshot() {
+                // The log is kept if the snapshot fails
+                if let Err(e) = persistence.snapshot(&self.vn_record) {
+                    warn!("Cannot snapshot the version numbers: {}", e);
+                }
+            }
+        }
+
+        is_persisted
+    }
+
+    fn fence(&mut self) -> Message {
+        error!("The backup Sequencer has taken over, no longer assigning version numbers");
+        self.role = Role::Fenced;
+        Message::ReplyNotPrimary
+    }
+
+    pub fn vn_records(&self) -> &VNRecords {
+        &self.vn_record
+    }
+
     pub fn is_vn_record_blocked(&self) -> bool {
         self.is_vn_record_blocked
     }
@@ -224,6 +568,7 @@ mod tests_table_vn_record {
 #[cfg(test)]
 mod tests_state {
     use super::State;
+    use crate::comm::scheduler_sequencer::Message;
     use crate::core::*;
     use std::iter::FromIterator;
 
@@ -417,4 +762,142 @@ mod tests_state {
             .map(|txvn| txvn.erase_uuid())
         );
     }
+
+    #[test]
+    fn test_restore() {
+        let dir = std::env::temp_dir().join(format!("o2versioner_sequencer_{}", uuid::Uuid::new_v4()));
+        let assign_vns = |state: &mut State, tableops: &str| {
+            state
+                .assign_vn(MsqlBeginTx::from(TableOps::from(tableops)))
+                .unwrap()
+                .txtablevns()
+                .iter()
+                .map(|txtablevn| txtablevn.vn)
+                .collect::<Vec<_>>()
+        };
+
+        //                   a     b
+        // next_for_read     1     0
+        // next_for_write    1     2
+        let mut state = State::restore(&dir, 2).unwrap();
+        assert_eq!(assign_vns(&mut state, "write a read b"), vec![0, 0]);
+        assert_eq!(assign_vns(&mut state, "read b"), vec![0]);
+        drop(state);
+
+        //                   a     b
+        // next_for_read     3     0
+        // next_for_write    4     4
+        let mut state = State::restore(&dir, 2).unwrap();
+        assert_eq!(assign_vns(&mut state, "write a read b"), vec![1, 0]);
+        assert_eq!(assign_vns(&mut state, "write a read b"), vec![2, 0]);
+        assert_eq!(assign_vns(&mut state, "read a"), vec![3]);
+        drop(state);
+
+        let mut state = State::restore(&dir, 2).unwrap();
+        assert_eq!(assign_vns(&mut state, "write a write b"), vec![4, 4]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    async fn request_vns(state: &mut State, client_meta: &ClientMeta, tableops: &str) -> Option<Vec<VN>> {
+        match state
+            .request_txvn(client_meta.clone(), MsqlBeginTx::from(TableOps::from(tableops)))
+            .await
+        {
+            Message::ReplyTxVN(Some(txvn)) => Some(txvn.txtablevns().iter().map(|txtablevn| txtablevn.vn).collect()),
+            Message::ReplyNotPrimary => None,
+            other => panic!("Unexpected reply {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_txvn() {
+        let client_metas: Vec<_> = (0..3)
+            .map(|txid| {
+                let mut client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+                (0..txid).for_each(|_| client_meta.transaction_finished());
+                client_meta
+            })
+            .collect();
+
+        // A retried request gets the same TxVN
+        let mut primary = State::new();
+        assert_eq!(
+            request_vns(&mut primary, &client_metas[0], "write a").await,
+            Some(vec![0])
+        );
+        assert_eq!(
+            request_vns(&mut primary, &client_metas[0], "write a").await,
+            Some(vec![0])
+        );
+        assert_eq!(
+            request_vns(&mut primary, &client_metas[1], "write a").await,
+            Some(vec![1])
+        );
+
+        // The backup only takes over after being synced
+        let mut backup = State::new().set_is_backup(true);
+        assert_eq!(request_vns(&mut backup, &client_metas[1], "write a").await, None);
+        assert!(matches!(
+            backup.sync_from_primary(primary.vn_record.clone(), primary.assigned_txvns.to_vec()),
+            Message::ReplySync(Some(_))
+        ));
+        assert_eq!(
+            request_vns(&mut backup, &client_metas[1], "write a").await,
+            Some(vec![1])
+        );
+        assert_eq!(
+            request_vns(&mut backup, &client_metas[2], "write a").await,
+            Some(vec![2])
+        );
+
+        // The backup has taken over, and no longer accepts any replication
+        assert!(matches!(
+            backup.sync_from_primary(primary.vn_record.clone(), primary.assigned_txvns.to_vec()),
+            Message::ReplySync(None)
+        ));
+        assert!(matches!(
+            backup.replicate_from_primary(client_metas[0].clone(), TxVN::new(), Default::default()),
+            Message::ReplyReplicate(false)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unblock() {
+        let mut state = State::new().set_block_wait_timeout(Some(std::time::Duration::from_millis(100)));
+        assert!(!state.should_queue_txvn());
+        state.set_vn_record_blocked(true);
+        assert!(state.should_queue_txvn());
+
+        let client_metas: Vec<_> = (0..4)
+            .map(|port| ClientMeta::new(format!("127.0.0.1:{}", 8080 + port).parse().unwrap()))
+            .collect();
+        let mut queued: Vec<_> = client_metas
+            .iter()
+            .map(|client_meta| state.queue_txvn(client_meta.clone(), MsqlBeginTx::from(TableOps::from("write a"))))
+            .collect();
+
+        // Requests cancelled or no longer waiting are not assigned
+        let (cancelled_id, _) = queued.remove(1);
+        assert!(state.cancel_queued_txvn(cancelled_id));
+        drop(queued.remove(1));
+
+        assert!(state.unblock().await);
+        assert!(!state.is_vn_record_blocked());
+        assert!(!state.cancel_queued_txvn(queued[0].0));
+
+        // Replied in the arrival order
+        let mut vns = Vec::new();
+        for (_, reply_rx) in queued {
+            match reply_rx.await.unwrap() {
+                Message::ReplyTxVN(Some(txvn)) => vns.push(txvn.txtablevns()[0].vn),
+                other => panic!("Unexpected reply {:?}", other),
+            }
+        }
+        assert_eq!(vns, vec![0, 1]);
+        assert_eq!(
+            request_vns(&mut state, &client_metas[1], "write a").await,
+            Some(vec![2])
+        );
+    }
 }
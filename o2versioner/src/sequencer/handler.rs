@@ -2,15 +2,21 @@ use super::core::State;
 use crate::comm::scheduler_sequencer::*;
 use crate::util::conf::SequencerConf;
 use crate::util::tcp;
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::sync::{oneshot, Mutex};
+use tokio::time;
 use tokio_serde::formats::SymmetricalJson;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 use tracing::{field, info, info_span, instrument, trace, warn, Instrument, Span};
 
+/// Maximum number of requests already arrived on a single connection to be processed together
+const MAX_BATCH_SIZE: usize = 64;
+
 /// Main entrance for Sequencer
 ///
 /// Three flavors:
@@ -20,9 +26,26 @@ use tracing::{field, info, info_span, instrument, trace, warn, Instrument, Span}
 /// 3. Admin port, can send `kill`, `exit` or `quit` in raw bytes
 /// to the admin port, which will then force to not accept any new
 /// connections.
+///
+/// If `SequencerConf::data_dir` is set, the assigned version numbers are persisted into it,
+/// and restored from it when the Sequencer starts again.
+///
+/// If `SequencerConf::backup_addr` is set, every assignment is replicated to the backup
+/// Sequencer before being replied. If `SequencerConf::is_backup` is set, the Sequencer
+/// stands by until it receives a `Message::RequestTxVN`, and then takes over from the primary.
+///
+/// If `SequencerConf::block_wait_timeout` is set, a `Message::RequestTxVN` received while blocked
+/// waits until unblocked or timed out, instead of failing immediately.
 #[instrument(name = "sequencer", skip(conf))]
 pub async fn main(conf: SequencerConf) {
-    let state = Arc::new(Mutex::new(State::new()));
+    let state = match &conf.data_dir {
+        Some(data_dir) => State::restore(data_dir, conf.snapshot_interval).expect("Cannot restore Sequencer state"),
+        None => State::new(),
+    }
+    .set_backup_addr(conf.to_backup_addr())
+    .set_is_backup(conf.is_backup)
+    .set_block_wait_timeout(conf.block_wait_timeout.map(Duration::from_millis));
+    let state = Arc::new(Mutex::new(state));
 
     let (stop_tx, stop_rx) = oneshot::channel();
     let stop_tx = Arc::new(Mutex::new(Some(stop_tx)));
@@ -69,66 +92,191 @@ async fn process_connection(
     let serded_read = SymmetricallyFramed::new(delimited_read, SymmetricalJson::<Message>::default());
     let serded_write = SymmetricallyFramed::new(delimited_write, SymmetricalJson::<Message>::default());
 
-    // Process a stream of incoming messages from a single tcp connection
-    serded_read
-        .and_then(move |msg| {
-            let state_cloned = state.clone();
-            let stop_tx_cloned = stop_tx.clone();
-
-            let process_req = async move {
-                Span::current().record("message", &msg.as_ref());
-                match msg {
-                    Message::RequestTxVN(client_meta, sqlbegintx) => {
-                        Span::current().record("client", &&client_meta.to_string()[..]);
-                        info!("<- {:?}", sqlbegintx);
-                        let txvn = state_cloned.lock().await.assign_vn(sqlbegintx);
-                        trace!("-> {:?}", txvn);
-                        Ok(Message::ReplyTxVN(txvn))
-                    }
-                    Message::RequestBlock => {
-                        let prev_is_blocked = state_cloned.lock().await.set_vn_record_blocked(true);
-                        let status = if prev_is_blocked {
-                            "is already blocked"
-                        } else {
-                            "set blocked successfully"
-                        };
-
-                        warn!("{}", status);
-                        Ok(Message::ReplyBlockUnblock(String::from(status)))
-                    }
-                    Message::RequestUnblock => {
-                        let prev_is_blocked = state_cloned.lock().await.set_vn_record_blocked(false);
-
-                        let status = if prev_is_blocked {
-                            "set unblocked successfully"
-                        } else {
-                            "is already unblocked"
-                        };
+    // Process a stream of incoming messages from a single tcp connection,
+    // messages already arrived together are processed as a batch under a single lock of the state
+    let mut serded_read = serded_read.ready_chunks(MAX_BATCH_SIZE);
+    let mut serded_write = serded_write;
+    let mut waiting_replies = stream::FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            msgs = serded_read.next() => {
+                let msgs = match msgs {
+                    Some(msgs) => msgs,
+                    None => break,
+                };
 
-                        warn!("{}", status);
-                        Ok(Message::ReplyBlockUnblock(String::from(status)))
+                let mut state_guard = state.lock().await;
+                let mut replies = Vec::with_capacity(msgs.len());
+                let mut is_read_failed = false;
+                for msg in msgs {
+                    match msg {
+                        Ok(msg) => {
+                            let is_tagged = matches!(msg, Message::Tagged(..));
+                            let reply = process_request(&state, &mut state_guard, &stop_tx, msg)
+                                .instrument(info_span!("request", message = field::Empty, client = field::Empty))
+                                .await;
+                            replies.push((is_tagged, reply));
+                        }
+                        Err(e) => {
+                            warn!("Cannot read the request: {}", e);
+                            is_read_failed = true;
+                            break;
+                        }
                     }
-                    Message::RequestStop => {
-                        warn!("Receiving stop request, will shutdown soon");
-                        stop_tx_cloned
-                            .lock()
-                            .await
-                            .take()
-                            .expect("The StopTx is already used!")
-                            .send(())
-                            .unwrap();
-                        Ok(Message::ReplyStop)
-                    }
-                    other => {
-                        warn!("<- Unsupported: {:?}", other);
-                        Ok(Message::Invalid)
+                }
+                drop(state_guard);
+
+                for (is_tagged, reply) in replies {
+                    let reply = match reply {
+                        Reply::Ready(reply) => reply,
+                        // Tagged requests can be replied out of order, so the waiting ones do not hold up the others
+                        Reply::Waiting(reply) if is_tagged => {
+                            waiting_replies.push(reply);
+                            continue;
+                        }
+                        Reply::Waiting(reply) => reply.await,
+                    };
+                    if let Err(e) = serded_write.send(reply).await {
+                        warn!("Cannot send the reply: {}", e);
+                        return;
                     }
                 }
+                if is_read_failed {
+                    break;
+                }
+            }
+            Some(reply) = waiting_replies.next(), if !waiting_replies.is_empty() => {
+                if let Err(e) = serded_write.send(reply).await {
+                    warn!("Cannot send the reply: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    while let Some(reply) = waiting_replies.next().await {
+        if let Err(e) = serded_write.send(reply).await {
+            warn!("Cannot send the reply: {}", e);
+            return;
+        }
+    }
+}
+
+/// Reply to a single request
+enum Reply {
+    Ready(Message),
+    /// A `Message::RequestTxVN` waiting for the Sequencer to be unblocked
+    Waiting(BoxFuture<'static, Message>),
+}
+
+/// Process a single request, a `Message::Tagged` request is replied with the same request id
+async fn process_request(
+    shared_state: &Arc<Mutex<State>>,
+    state: &mut State,
+    stop_tx: &Mutex<Option<tcp::StopTx>>,
+    msg: Message,
+) -> Reply {
+    let (request_id, msg) = match msg {
+        Message::Tagged(request_id, msg) => (Some(request_id), *msg),
+        msg => (None, msg),
+    };
+
+    Span::current().record("message", &msg.as_ref());
+    let reply = match msg {
+        Message::RequestTxVN(client_meta, sqlbegintx) if state.should_queue_txvn() => {
+            Span::current().record("client", &&client_meta.to_string()[..]);
+            info!("<- {:?}, waiting to be unblocked", sqlbegintx);
+            let timeout = state.block_wait_timeout().unwrap();
+            let (queued_id, reply_rx) = state.queue_txvn(client_meta, sqlbegintx);
+            let reply = wait_for_unblock(shared_state.clone(), queued_id, reply_rx, timeout).in_current_span();
+            return match request_id {
+                Some(request_id) => Reply::Waiting(
+                    reply
+                        .map(move |reply| Message::Tagged(request_id, Box::new(reply)))
+                        .boxed(),
+                ),
+                None => Reply::Waiting(reply.boxed()),
             };
+        }
+        Message::RequestTxVN(client_meta, sqlbegintx) => {
+            Span::current().record("client", &&client_meta.to_string()[..]);
+            info!("<- {:?}", sqlbegintx);
+            let reply = state.request_txvn(client_meta, sqlbegintx).await;
+            trace!("-> {:?}", reply);
+            reply
+        }
+        Message::RequestSync(vn_record, assigned_txvns) => {
+            warn!("Syncing with the primary Sequencer");
+            state.sync_from_primary(vn_record, assigned_txvns)
+        }
+        Message::RequestReplicate(client_meta, txvn, assigned) => {
+            Span::current().record("client", &&client_meta.to_string()[..]);
+            trace!("<- {:?}", txvn);
+            state.replicate_from_primary(client_meta, txvn, assigned)
+        }
+        Message::RequestBlock => {
+            let prev_is_blocked = state.set_vn_record_blocked(true);
+            let status = if prev_is_blocked {
+                "is already blocked"
+            } else {
+                "set blocked successfully"
+            };
+
+            warn!("{}", status);
+            Message::ReplyBlockUnblock(String::from(status))
+        }
+        Message::RequestUnblock => {
+            let prev_is_blocked = state.unblock().await;
+
+            let status = if prev_is_blocked {
+                "set unblocked successfully"
+            } else {
+                "is already unblocked"
+            };
+
+            warn!("{}", status);
+            Message::ReplyBlockUnblock(String::from(status))
+        }
+        Message::RequestVNRecords => Message::ReplyVNRecords(state.vn_records().clone()),
+        Message::RequestStop => {
+            warn!("Receiving stop request, will shutdown soon");
+            stop_tx
+                .lock()
+                .await
+                .take()
+                .expect("The StopTx is already used!")
+                .send(())
+                .unwrap();
+            Message::ReplyStop
+        }
+        other => {
+            warn!("<- Unsupported: {:?}", other);
+            Message::Invalid
+        }
+    };
+
+    Reply::Ready(match request_id {
+        Some(request_id) => Message::Tagged(request_id, Box::new(reply)),
+        None => reply,
+    })
+}
+
+/// Wait for the queued `Message::RequestTxVN` to be replied once unblocked, or cancel it after `timeout`
+async fn wait_for_unblock(
+    state: Arc<Mutex<State>>,
+    queued_id: u64,
+    mut reply_rx: oneshot::Receiver<Message>,
+    timeout: Duration,
+) -> Message {
+    if let Ok(reply) = time::timeout(timeout, &mut reply_rx).await {
+        return reply.unwrap_or(Message::ReplyTxVN(None));
+    }
 
-            process_req.instrument(info_span!("request", message = field::Empty, client = field::Empty))
-        })
-        .forward(serded_write)
-        .map(|_| ())
-        .await;
+    if state.lock().await.cancel_queued_txvn(queued_id) {
+        warn!("Timed out waiting for the Sequencer to be unblocked");
+        Message::ReplyTxVN(None)
+    } else {
+        // Unblocked right before being cancelled, so the reply has already been sent
+        reply_rx.await.unwrap_or(Message::ReplyTxVN(None))
+    }
 }
@@ -1,4 +1,6 @@
 mod core;
 mod handler;
+mod persistence;
+mod replication;
 
 pub use handler::main;
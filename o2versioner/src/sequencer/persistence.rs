@@ -0,0 +1,241 @@
+use crate::comm::scheduler_sequencer::VNRecords;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const LOG_FILE: &str = "vn.log";
+const SNAPSHOT_FILE: &str = "vn.snapshot";
+const SNAPSHOT_TMP_FILE: &str = "vn.snapshot.tmp";
+
+/// Persists the version number records of the Sequencer in a directory
+///
+/// The directory contains:
+/// 1. `vn.snapshot`, the records of all tables at the time of the last snapshot
+/// 2. `vn.log`, an append-only log with one line of json per assignment,
+///    holding the new records of the tables assigned since the last snapshot
+///
+/// Every log entry is synced to the disk before the assigned `TxVN` is replied,
+/// so that a restarted Sequencer never assigns a version number again.
+/// As `next_for_read` and `next_for_write` never decrease, the records are restored by
+/// taking the maximum of the snapshot and all log entries, which is safe even if
+/// the Sequencer is killed in the middle of a snapshot.
+pub(super) struct Persistence {
+    dir: PathBuf,
+    log: File,
+    snapshot_interval: u32,
+    entries_since_snapshot: u32,
+}
+
+impl Persistence {
+    /// Open the persistence in `dir`, which is created if not existing,
+    /// and return the restored records of all tables
+    pub(super) fn open<P: AsRef<Path>>(dir: P, snapshot_interval: u32) -> io::Result<(Self, VNRecords)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut records = VNRecords::new();
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let snapshot: VNRecords = serde_json::from_slice(&fs::read(&snapshot_path)?)?;
+            merge_records(&mut records, snapshot);
+        }
+
+        let log_path = dir.join(LOG_FILE);
+        let mut entries_since_snapshot = 0;
+        let mut is_newline_missing = false;
+        if log_path.exists() {
+            let content = fs::read(&log_path)?;
+            let mut valid_len = 0;
+            for line in content.split_inclusive(|b| *b == b'\n') {
+                match serde_json::from_slice::<VNRecords>(line) {
+                    Ok(entry) => {
+                        merge_records(&mut records, entry);
+                        entries_since_snapshot += 1;
+                        valid_len += line.len();
+                    }
+                    // The last entry can be partially written if the Sequencer was killed while appending it,
+                    // whose TxVN was never replied
+                    Err(e) if valid_len + line.len() == content.len() => {
+                        warn!("Discarding the partially written last entry of {:?}: {}", log_path, e);
+                        OpenOptions::new()
+                            .write(true)
+                            .open(&log_path)?
+                            .set_len(valid_len as u64)?;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            is_newline_missing = valid_len > 0 && content[valid_len - 1] != b'\n';
+        }
+
+        let mut log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+        if is_newline_missing {
+            log.write_all(b"\n")?;
+            log.sync_data()?;
+        }
+        info!(
+            "Restored version numbers of {} tables from {:?}, with {} log entries",
+            records.len(),
+            dir,
+            entries_since_snapshot
+        );
+
+        Ok((
+            Self {
+                dir,
+                log,
+                snapshot_interval,
+                entries_since_snapshot,
+            },
+            records,
+        ))
+    }
+
+    /// Append the new records of the assigned tables to the log, and sync it to the disk
+    pub(super) fn append(&mut self, entry: &VNRecords) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        self.log.sync_data()?;
+        self.entries_since_snapshot += 1;
+        Ok(())
+    }
+
+    /// Whether enough entries are logged since the last snapshot
+    pub(super) fn should_snapshot(&self) -> bool {
+        self.entries_since_snapshot >= self.snapshot_interval
+    }
+
+    /// Replace the snapshot with `records`, and then truncate the log
+    pub(super) fn snapshot(&mut self, records: &VNRecords) -> io::Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec(records)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.entries_since_snapshot = 0;
+        Ok(())
+    }
+}
+
+/// Merge `entry` into `records` by taking the maximum of each version number
+pub(super) fn merge_records(records: &mut VNRecords, entry: VNRecords) {
+    entry.into_iter().for_each(|(table, new)| {
+        let record = records.entry(table).or_default();
+        record.next_for_read = record.next_for_read.max(new.next_for_read);
+        record.next_for_write = record.next_for_write.max(new.next_for_write);
+    });
+}
+
+/// Unit test for `Persistence`
+#[cfg(test)]
+mod tests_persistence {
+    use super::*;
+    use crate::comm::scheduler_sequencer::TableVNRecord;
+    use std::env;
+    use uuid::Uuid;
+
+    fn record(next_for_read: u64, next_for_write: u64) -> TableVNRecord {
+        TableVNRecord {
+            next_for_read,
+            next_for_write,
+        }
+    }
+
+    fn records(tables: Vec<(&str, TableVNRecord)>) -> VNRecords {
+        tables
+            .into_iter()
+            .map(|(table, record)| (table.to_owned(), record))
+            .collect()
+    }
+
+    fn temp_dir() -> PathBuf {
+        env::temp_dir().join(format!("o2versioner_sequencer_{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = temp_dir();
+
+        let (mut persistence, restored) = Persistence::open(&dir, 3).unwrap();
+        assert!(restored.is_empty());
+        persistence.append(&records(vec![("a", record(1, 1))])).unwrap();
+        persistence
+            .append(&records(vec![("a", record(1, 2)), ("b", record(0, 1))]))
+            .unwrap();
+        assert!(!persistence.should_snapshot());
+        drop(persistence);
+
+        let (mut persistence, restored) = Persistence::open(&dir, 3).unwrap();
+        assert_eq!(restored, records(vec![("a", record(1, 2)), ("b", record(0, 1))]));
+        persistence.append(&records(vec![("b", record(2, 2))])).unwrap();
+        assert!(persistence.should_snapshot());
+        persistence
+            .snapshot(&records(vec![("a", record(1, 2)), ("b", record(2, 2))]))
+            .unwrap();
+        assert!(!persistence.should_snapshot());
+        persistence.append(&records(vec![("c", record(1, 1))])).unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir, 3).unwrap();
+        assert_eq!(
+            restored,
+            records(vec![("a", record(1, 2)), ("b", record(2, 2)), ("c", record(1, 1))])
+        );
+        assert_eq!(fs::read_to_string(dir.join(LOG_FILE)).unwrap().lines().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_interrupted() {
+        let dir = temp_dir();
+
+        let (mut persistence, _) = Persistence::open(&dir, 10).unwrap();
+        persistence.append(&records(vec![("a", record(1, 1))])).unwrap();
+        persistence.append(&records(vec![("a", record(2, 2))])).unwrap();
+        drop(persistence);
+
+        // Killed in the middle of appending an entry
+        let mut log = OpenOptions::new().append(true).open(dir.join(LOG_FILE)).unwrap();
+        log.write_all(br#"{"a":{"next_for_re"#).unwrap();
+        drop(log);
+
+        // Killed after replacing the snapshot but before truncating the log
+        fs::write(
+            dir.join(SNAPSHOT_FILE),
+            r#"{"a":{"next_for_read":3,"next_for_write":4}}"#,
+        )
+        .unwrap();
+
+        let (mut persistence, restored) = Persistence::open(&dir, 10).unwrap();
+        assert_eq!(restored, records(vec![("a", record(3, 4))]));
+        persistence.append(&records(vec![("a", record(5, 5))])).unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir, 10).unwrap();
+        assert_eq!(restored, records(vec![("a", record(5, 5))]));
+
+        // Killed right before the newline of the last entry is written
+        fs::write(dir.join(LOG_FILE), r#"{"a":{"next_for_read":6,"next_for_write":6}}"#).unwrap();
+        let (mut persistence, restored) = Persistence::open(&dir, 10).unwrap();
+        assert_eq!(restored, records(vec![("a", record(6, 6))]));
+        persistence.append(&records(vec![("a", record(7, 7))])).unwrap();
+        drop(persistence);
+
+        let (_, restored) = Persistence::open(&dir, 10).unwrap();
+        assert_eq!(restored, records(vec![("a", record(7, 7))]));
+
+        // Entries before the last one are never partially written
+        fs::write(dir.join(LOG_FILE), "{\"a\":{\n{}\n").unwrap();
+        assert!(Persistence::open(&dir, 10).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
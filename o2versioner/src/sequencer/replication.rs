@@ -0,0 +1,86 @@
+use crate::comm::scheduler_sequencer::{Message, VNRecords};
+use crate::core::{ClientMeta, TxVN};
+use crate::util::tcp;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tracing::info;
+
+/// Replicates the assignments of the primary Sequencer to its backup
+///
+/// Before replicating on a new tcp connection, both Sequencers exchange all of their records
+/// via `Message::RequestSync`, so that both of them hold the maximum of the two.
+/// Once the tcp connection is broken, it is reconnected and synced again for the next replication.
+pub(super) struct Replication {
+    backup_addr: SocketAddr,
+    /// `None` if not connected or not synced
+    tcp_stream: Option<TcpStream>,
+}
+
+impl Replication {
+    pub(super) fn new(backup_addr: SocketAddr) -> Self {
+        Self {
+            backup_addr,
+            tcp_stream: None,
+        }
+    }
+
+    /// Whether there is a synced tcp connection to the backup
+    pub(super) fn is_synced(&self) -> bool {
+        self.tcp_stream.is_some()
+    }
+
+    /// Connect to the backup and exchange all records,
+    /// returns `None` if the backup has already taken over
+    pub(super) async fn sync(
+        &mut self,
+        vn_record: VNRecords,
+        assigned_txvns: Vec<(ClientMeta, TxVN)>,
+    ) -> io::Result<Option<(VNRecords, Vec<(ClientMeta, TxVN)>)>> {
+        let mut tcp_stream = TcpStream::connect(self.backup_addr).await?;
+        let reply =
+            tcp::send_and_receive_single_as_json(&mut tcp_stream, Message::RequestSync(vn_record, assigned_txvns))
+                .await?;
+        match reply {
+            Message::ReplySync(Some(synced)) => {
+                info!("Synced with the backup Sequencer at {}", self.backup_addr);
+                self.tcp_stream = Some(tcp_stream);
+                Ok(Some(synced))
+            }
+            Message::ReplySync(None) => Ok(None),
+            other => Err(invalid_reply(other)),
+        }
+    }
+
+    /// Replicate a single assignment to the backup, returns `false` if the backup has already taken over
+    pub(super) async fn replicate(
+        &mut self,
+        client_meta: ClientMeta,
+        txvn: TxVN,
+        assigned: VNRecords,
+    ) -> io::Result<bool> {
+        let tcp_stream = self
+            .tcp_stream
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "Not synced with the backup Sequencer"))?;
+
+        let reply =
+            tcp::send_and_receive_single_as_json(tcp_stream, Message::RequestReplicate(client_meta, txvn, assigned))
+                .await
+                .and_then(|reply| match reply {
+                    Message::ReplyReplicate(is_replicated) => Ok(is_replicated),
+                    other => Err(invalid_reply(other)),
+                });
+        if reply.is_err() {
+            self.tcp_stream = None;
+        }
+        reply
+    }
+}
+
+fn invalid_reply(reply: Message) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid reply from the backup Sequencer: {:?}", reply),
+    )
+}
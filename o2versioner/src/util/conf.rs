@@ -5,6 +5,7 @@
 //! 2. Construct the structs directly
 //! 3. Use builder style setter functions to modify them
 
+use crate::core::CommitAck;
 use config;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -55,7 +56,10 @@ impl Conf {
 pub struct SchedulerConf {
     pub addr: String,
     pub admin_addr: Option<String>,
+    /// Address to accept clients speaking the PostgreSQL frontend/backend protocol, disabled if `None`
+    pub pg_addr: Option<String>,
     pub max_connection: Option<u32>,
+    /// Number of tcp connections to Sequencer, shared by all pipelined requests
     pub sequencer_pool_size: u32,
     pub dispatcher_queue_size: usize,
     pub transceiver_queue_size: usize,
@@ -63,6 +67,24 @@ pub struct SchedulerConf {
     pub detailed_logging: Option<String>,
     pub disable_early_release: bool,
     pub disable_single_read_optimization: bool,
+    /// Verify the `TableOps` of each query against its Sql, and reject mismatched queries
+    pub strict_annotation: bool,
+    /// In units of ms. Heartbeats are sent to each dbproxy at this interval, and a dbproxy that
+    /// has not replied anything for 3 intervals is evicted
+    pub dbproxy_heartbeat_interval: u64,
+    /// In units of ms. If set, a dbproxy that has not replied a request within this timeout is evicted
+    pub dbproxy_request_timeout: Option<u64>,
+    /// Times to reconnect to a disconnected dbproxy with exponential backoff before evicting it
+    pub dbproxy_reconnect_attempts: u32,
+    /// Policy to choose the dbproxy for a ReadOnly query, among the ones with the versions ready
+    pub replica_selection: ReplicaSelection,
+    /// Number of live dbproxies that must apply a commit before it is replied,
+    /// can be overridden by `MsqlEndTx` of each transaction
+    pub commit_ack: CommitAck,
+    /// In units of ms. If set, a transaction is rolled back once its client has not sent anything for this timeout
+    pub tx_idle_timeout: Option<u64>,
+    /// In units of ms. If set, a transaction is rolled back once it has begun for this duration
+    pub tx_max_lifetime: Option<u64>,
 }
 
 impl Default for SchedulerConf {
@@ -70,6 +92,7 @@ impl Default for SchedulerConf {
         Self {
             addr: String::new(),
             admin_addr: None,
+            pg_addr: None,
             max_connection: None,
             sequencer_pool_size: 10,
             dispatcher_queue_size: 500,
@@ -78,6 +101,14 @@ impl Default for SchedulerConf {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::default(),
+            commit_ack: CommitAck::default(),
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         }
     }
 }
@@ -97,6 +128,11 @@ impl SchedulerConf {
         self
     }
 
+    pub fn set_pg_addr<S: Into<String>>(mut self, pg_addr: Option<S>) -> Self {
+        self.pg_addr = pg_addr.map(|s| s.into());
+        self
+    }
+
     pub fn set_max_connection(mut self, max_connection: Option<u32>) -> Self {
         self.max_connection = max_connection;
         self
@@ -137,17 +173,96 @@ impl SchedulerConf {
         self
     }
 
+    pub fn set_strict_annotation(mut self, strict_annotation: bool) -> Self {
+        self.strict_annotation = strict_annotation;
+        self
+    }
+
+    pub fn set_dbproxy_heartbeat_interval(mut self, dbproxy_heartbeat_interval: u64) -> Self {
+        self.dbproxy_heartbeat_interval = dbproxy_heartbeat_interval;
+        self
+    }
+
+    pub fn set_dbproxy_request_timeout(mut self, dbproxy_request_timeout: Option<u64>) -> Self {
+        self.dbproxy_request_timeout = dbproxy_request_timeout;
+        self
+    }
+
+    pub fn set_dbproxy_reconnect_attempts(mut self, dbproxy_reconnect_attempts: u32) -> Self {
+        self.dbproxy_reconnect_attempts = dbproxy_reconnect_attempts;
+        self
+    }
+
+    pub fn set_replica_selection(mut self, replica_selection: ReplicaSelection) -> Self {
+        self.replica_selection = replica_selection;
+        self
+    }
+
+    pub fn set_commit_ack(mut self, commit_ack: CommitAck) -> Self {
+        self.commit_ack = commit_ack;
+        self
+    }
+
+    pub fn set_tx_idle_timeout(mut self, tx_idle_timeout: Option<u64>) -> Self {
+        self.tx_idle_timeout = tx_idle_timeout;
+        self
+    }
+
+    pub fn set_tx_max_lifetime(mut self, tx_max_lifetime: Option<u64>) -> Self {
+        self.tx_max_lifetime = tx_max_lifetime;
+        self
+    }
+
     pub fn to_addr(&self) -> SocketAddr {
         self.addr.parse().expect("Invalid scheduler addr")
     }
 }
 
+/// Policy for the scheduler to choose the dbproxy for a ReadOnly query
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaSelection {
+    /// The one with the least outstanding requests
+    LeastOutstanding,
+    /// Each one in turn
+    RoundRobin,
+    /// The one with the lowest moving average of latencies, scaled by its outstanding requests
+    EwmaLatency,
+    /// The less loaded one of two chosen at random
+    PowerOfTwoChoices,
+    /// The same one for all reads on the same table, for cache locality
+    TableAffinity,
+}
+
+impl Default for ReplicaSelection {
+    fn default() -> Self {
+        Self::LeastOutstanding
+    }
+}
+
 /// Conf for sequencer
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct SequencerConf {
     pub addr: String,
     pub max_connection: Option<u32>,
+    /// Directory to persist the assigned version numbers, which are only kept in memory if `None`
+    pub data_dir: Option<String>,
+    /// Number of logged version number assignments between two snapshots of `data_dir`
+    pub snapshot_interval: u32,
+    /// Address of the hot-standby backup Sequencer, to which every assignment is replicated before being replied.
+    /// The Scheduler fails over to it once the primary Sequencer is not available.
+    ///
+    /// This trades availability for never assigning the same version numbers twice: the primary cannot tell
+    /// a backup that is down from one that has taken over behind a partition, so it replies
+    /// `Message::ReplyNotPrimary` to every new transaction until the backup is reachable again.
+    /// While the backup is down, no transaction can begin, even though the primary is alive.
+    pub backup_addr: Option<String>,
+    /// Whether this Sequencer is the hot-standby backup, which only assigns version numbers after taking over
+    pub is_backup: bool,
+    /// In units of ms. If set, new transactions are queued while blocked, and resumed in their arrival order
+    /// once unblocked or failed after this timeout; if `None`, they fail immediately while blocked
+    pub block_wait_timeout: Option<u64>,
 }
 
 impl Default for SequencerConf {
@@ -155,6 +270,11 @@ impl Default for SequencerConf {
         Self {
             addr: String::new(),
             max_connection: None,
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         }
     }
 }
@@ -174,9 +294,45 @@ impl SequencerConf {
         self
     }
 
+    pub fn set_data_dir<S: Into<String>>(mut self, data_dir: Option<S>) -> Self {
+        self.data_dir = data_dir.map(|s| s.into());
+        self
+    }
+
+    pub fn set_snapshot_interval(mut self, snapshot_interval: u32) -> Self {
+        self.snapshot_interval = snapshot_interval;
+        self
+    }
+
+    pub fn set_backup_addr<S: Into<String>>(mut self, backup_addr: Option<S>) -> Self {
+        self.backup_addr = backup_addr.map(|s| s.into());
+        self
+    }
+
+    pub fn set_is_backup(mut self, is_backup: bool) -> Self {
+        self.is_backup = is_backup;
+        self
+    }
+
+    pub fn set_block_wait_timeout(mut self, block_wait_timeout: Option<u64>) -> Self {
+        self.block_wait_timeout = block_wait_timeout;
+        self
+    }
+
     pub fn to_addr(&self) -> SocketAddr {
         self.addr.parse().expect("Invalid sequencer addr")
     }
+
+    pub fn to_backup_addr(&self) -> Option<SocketAddr> {
+        self.backup_addr
+            .as_ref()
+            .map(|backup_addr| backup_addr.parse().expect("Invalid sequencer backup_addr"))
+    }
+
+    /// Return the addresses of the primary Sequencer and its backup if any, in `Vec<SocketAddr>`
+    pub fn to_addrs(&self) -> Vec<SocketAddr> {
+        std::iter::once(self.to_addr()).chain(self.to_backup_addr()).collect()
+    }
 }
 
 /// Conf for a single dbproxy instance
@@ -315,6 +471,7 @@ mod tests_conf {
             Conf {
                 scheduler: SchedulerConf::new("127.0.0.1:1077")
                     .set_admin_addr(Option::<String>::None)
+                    .set_pg_addr(Some("127.0.0.1:1078"))
                     .set_max_connection(Some(50))
                     .set_sequencer_pool_size(20)
                     .set_dispatcher_queue_size(500)
@@ -322,8 +479,22 @@ mod tests_conf {
                     .set_performance_logging(Some("./perf"))
                     .set_detailed_logging(Option::<String>::None)
                     .set_disable_early_release(false)
-                    .set_disable_single_read_optimization(false),
-                sequencer: SequencerConf::new("127.0.0.1:9876").set_max_connection(Some(50)),
+                    .set_disable_single_read_optimization(false)
+                    .set_strict_annotation(false)
+                    .set_dbproxy_heartbeat_interval(500)
+                    .set_dbproxy_request_timeout(Some(30000))
+                    .set_dbproxy_reconnect_attempts(5)
+                    .set_replica_selection(ReplicaSelection::TableAffinity)
+                    .set_commit_ack(CommitAck::Majority)
+                    .set_tx_idle_timeout(Some(10000))
+                    .set_tx_max_lifetime(Some(60000)),
+                sequencer: SequencerConf::new("127.0.0.1:9876")
+                    .set_max_connection(Some(50))
+                    .set_data_dir(Some("./sequencer_data"))
+                    .set_snapshot_interval(100)
+                    .set_backup_addr(Some("127.0.0.1:9877"))
+                    .set_is_backup(false)
+                    .set_block_wait_timeout(Some(5000)),
                 dbproxy: vec![
                     DbProxyConf::new("127.0.0.1:8876")
                         .set_sql_conf(Some(
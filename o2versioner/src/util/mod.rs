@@ -4,5 +4,6 @@ pub mod common;
 pub mod conf;
 pub mod executor;
 pub mod executor_addr;
+pub mod sql_types;
 pub mod tcp;
 pub mod tests_helper;
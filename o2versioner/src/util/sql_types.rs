@@ -0,0 +1,539 @@
+//! Conversion between Json values and the Sql values of a postgres DBMS
+//!
+//! Supported Sql types are `bool`, `int2`, `int4`, `int8`, `oid`, `float4`, `float8`, `numeric`,
+//! `date`, `time`, `timestamp`, `timestamptz`, `uuid`, `bytea`, `"char"`, all text types, `json` and `jsonb`.
+//! Other types need to be casted in the Sql, for example `$1::text::interval` for a parameter,
+//! or `col::text` for a column of a query with parameters.
+//! Queries without parameters return columns of other types in the text format of the DBMS.
+//!
+//! `numeric`, date and time types, `uuid` and `bytea` are represented as Json strings
+//! in the same text format as the DBMS, so that no precision is lost.
+
+use bytes::buf::BufMutExt;
+use bytes::{Buf, BufMut, BytesMut};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::error::Error;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+
+const TIME_FORMAT: &str = "%H:%M:%S%.f";
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+const TIMESTAMPTZ_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f%:z";
+
+/// A Json value to be bound to a placeholder of a Sql query
+///
+/// The value is converted according to the type of the placeholder,
+/// and a Json string is parsed if the placeholder is not of a text type.
+#[derive(Debug)]
+pub struct SqlParam<'a>(pub &'a Value);
+
+impl<'a> ToSql for SqlParam<'a> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let value = self.0;
+        if value.is_null() {
+            Ok(IsNull::Yes)
+        } else if *ty == Type::BOOL {
+            param_as_bool(value, ty)?.to_sql(ty, out)
+        } else if *ty == Type::INT2 {
+            i16::try_from(param_as_i64(value, ty)?)?.to_sql(ty, out)
+        } else if *ty == Type::INT4 {
+            i32::try_from(param_as_i64(value, ty)?)?.to_sql(ty, out)
+        } else if *ty == Type::INT8 {
+            param_as_i64(value, ty)?.to_sql(ty, out)
+        } else if *ty == Type::OID {
+            u32::try_from(param_as_i64(value, ty)?)?.to_sql(ty, out)
+        } else if *ty == Type::FLOAT4 {
+            (param_as_f64(value, ty)? as f32).to_sql(ty, out)
+        } else if *ty == Type::FLOAT8 {
+            param_as_f64(value, ty)?.to_sql(ty, out)
+        } else if *ty == Type::NUMERIC {
+            numeric_to_sql(&param_as_text(value, ty)?, out).map_err(|_| param_conversion_error(value, ty))
+        } else if *ty == Type::DATE {
+            NaiveDate::parse_from_str(&param_as_text(value, ty)?, "%Y-%m-%d")
+                .map_err(|_| param_conversion_error(value, ty))?
+                .to_sql(ty, out)
+        } else if *ty == Type::TIME {
+            NaiveTime::parse_from_str(&param_as_text(value, ty)?, TIME_FORMAT)
+                .map_err(|_| param_conversion_error(value, ty))?
+                .to_sql(ty, out)
+        } else if *ty == Type::TIMESTAMP {
+            param_as_timestamp(value, ty)?.to_sql(ty, out)
+        } else if *ty == Type::TIMESTAMPTZ {
+            let text = param_as_text(value, ty)?;
+            DateTime::parse_from_rfc3339(&text)
+                .or_else(|_| DateTime::parse_from_str(&text, TIMESTAMPTZ_FORMAT))
+                .map(|datetime| datetime.with_timezone(&Utc))
+                // A timestamp without time zone is taken as UTC
+                .or_else(|_| param_as_timestamp(value, ty).map(|datetime| DateTime::<Utc>::from_utc(datetime, Utc)))?
+                .to_sql(ty, out)
+        } else if *ty == Type::UUID {
+            uuid::Uuid::parse_str(&param_as_text(value, ty)?)
+                .map_err(|_| param_conversion_error(value, ty))?
+                .to_sql(ty, out)
+        } else if *ty == Type::BYTEA {
+            let text = param_as_text(value, ty)?;
+            match text.strip_prefix("\\x") {
+                Some(hex) => hex_decode(hex).ok_or_else(|| param_conversion_error(value, ty))?,
+                None => text.into_bytes(),
+            }
+            .to_sql(ty, out)
+        } else if *ty == Type::CHAR {
+            match param_as_text(value, ty)?.as_bytes() {
+                [byte] => (*byte as i8).to_sql(ty, out),
+                _ => Err(param_conversion_error(value, ty)),
+            }
+        } else if *ty == Type::JSON || *ty == Type::JSONB {
+            if *ty == Type::JSONB {
+                // jsonb binary format version
+                out.put_u8(1);
+            }
+            serde_json::to_writer(out.writer(), value)?;
+            Ok(IsNull::No)
+        } else if <&str as ToSql>::accepts(ty) {
+            match value {
+                Value::String(s) => s.as_str().to_sql(ty, out),
+                other => other.to_string().as_str().to_sql(ty, out),
+            }
+        } else {
+            Err(format!(
+                "Unsupported parameter type {}, please cast the placeholder to a supported type",
+                ty
+            )
+            .into())
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn param_as_bool(value: &Value, ty: &Type) -> Result<bool, BoxError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::String(s) => match &s.trim().to_lowercase()[..] {
+            "true" | "t" | "yes" | "y" | "on" | "1" => Ok(true),
+            "false" | "f" | "no" | "n" | "off" | "0" => Ok(false),
+            _ => Err(param_conversion_error(value, ty)),
+        },
+        _ => Err(param_conversion_error(value, ty)),
+    }
+}
+
+fn param_as_i64(value: &Value, ty: &Type) -> Result<i64, BoxError> {
+    match value {
+        Value::Number(n) => n.as_i64().ok_or_else(|| param_conversion_error(value, ty)),
+        Value::String(s) => s.trim().parse().map_err(|_| param_conversion_error(value, ty)),
+        _ => Err(param_conversion_error(value, ty)),
+    }
+}
+
+fn param_as_f64(value: &Value, ty: &Type) -> Result<f64, BoxError> {
+    match value {
+        Value::Number(n) => n.as_f64().ok_or_else(|| param_conversion_error(value, ty)),
+        Value::String(s) => s.trim().parse().map_err(|_| param_conversion_error(value, ty)),
+        _ => Err(param_conversion_error(value, ty)),
+    }
+}
+
+/// Json strings are taken as is, and other scalar values are converted into their text
+fn param_as_text(value: &Value, ty: &Type) -> Result<String, BoxError> {
+    match value {
+        Value::String(s) => Ok(s.trim().to_owned()),
+        Value::Bool(_) | Value::Number(_) => Ok(value.to_string()),
+        _ => Err(param_conversion_error(value, ty)),
+    }
+}
+
+fn param_as_timestamp(value: &Value, ty: &Type) -> Result<NaiveDateTime, BoxError> {
+    let text = param_as_text(value, ty)?;
+    NaiveDateTime::parse_from_str(&text, TIMESTAMP_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f"))
+        .or_else(|_| NaiveDate::parse_from_str(&text, "%Y-%m-%d").map(|date| date.and_hms(0, 0, 0)))
+        .map_err(|_| param_conversion_error(value, ty))
+}
+
+fn param_conversion_error(value: &Value, ty: &Type) -> BoxError {
+    format!("Cannot convert parameter {} into type {}", value, ty).into()
+}
+
+/// A Sql value of a column converted into a Json value, Sql `NULL` is converted into Json `null`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlValue(pub Value);
+
+impl SqlValue {
+    /// Unwrap into the Json value
+    pub fn into_inner(self) -> Value {
+        self.0
+    }
+
+    /// Convert a cell in the text format of the DBMS, such as a cell returned by the simple query protocol
+    ///
+    /// Cells of `bool`, integer, floating point, `json` and `jsonb` types are converted into
+    /// the same Json values as with `FromSql`, and cells of all other types are kept as Json strings.
+    pub fn from_text(ty: &Type, text: Option<&str>) -> Self {
+        let text = match text {
+            Some(text) => text,
+            None => return Self(Value::Null),
+        };
+        let value = if *ty == Type::BOOL {
+            match text {
+                "t" => Some(Value::from(true)),
+                "f" => Some(Value::from(false)),
+                _ => None,
+            }
+        } else if *ty == Type::INT2 || *ty == Type::INT4 || *ty == Type::INT8 || *ty == Type::OID {
+            text.parse::<i64>().ok().map(Value::from)
+        } else if *ty == Type::FLOAT4 || *ty == Type::FLOAT8 {
+            text.parse::<f64>().ok().map(Value::from)
+        } else if *ty == Type::JSON || *ty == Type::JSONB {
+            serde_json::from_str(text).ok()
+        } else {
+            None
+        };
+
+        Self(value.unwrap_or_else(|| Value::from(text)))
+    }
+}
+
+impl<'a> FromSql<'a> for SqlValue {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let value = if *ty == Type::BOOL {
+            Value::from(bool::from_sql(ty, raw)?)
+        } else if *ty == Type::INT2 {
+            Value::from(i16::from_sql(ty, raw)?)
+        } else if *ty == Type::INT4 {
+            Value::from(i32::from_sql(ty, raw)?)
+        } else if *ty == Type::INT8 {
+            Value::from(i64::from_sql(ty, raw)?)
+        } else if *ty == Type::OID {
+            Value::from(u32::from_sql(ty, raw)?)
+        } else if *ty == Type::FLOAT4 {
+            Value::from(f32::from_sql(ty, raw)?)
+        } else if *ty == Type::FLOAT8 {
+            Value::from(f64::from_sql(ty, raw)?)
+        } else if *ty == Type::NUMERIC {
+            Value::from(numeric_from_sql(raw)?)
+        } else if *ty == Type::DATE {
+            Value::from(NaiveDate::from_sql(ty, raw)?.to_string())
+        } else if *ty == Type::TIME {
+            Value::from(NaiveTime::from_sql(ty, raw)?.format(TIME_FORMAT).to_string())
+        } else if *ty == Type::TIMESTAMP {
+            Value::from(NaiveDateTime::from_sql(ty, raw)?.format(TIMESTAMP_FORMAT).to_string())
+        } else if *ty == Type::TIMESTAMPTZ {
+            Value::from(
+                DateTime::<Utc>::from_sql(ty, raw)?
+                    .format(TIMESTAMPTZ_FORMAT)
+                    .to_string(),
+            )
+        } else if *ty == Type::UUID {
+            Value::from(uuid::Uuid::from_sql(ty, raw)?.to_string())
+        } else if *ty == Type::BYTEA {
+            Value::from(format!("\\x{}", hex_encode(raw)))
+        } else if *ty == Type::CHAR {
+            Value::from((i8::from_sql(ty, raw)? as u8 as char).to_string())
+        } else if *ty == Type::JSON {
+            serde_json::from_slice(raw)?
+        } else if *ty == Type::JSONB {
+            match raw.split_first() {
+                Some((1, json)) => serde_json::from_slice(json)?,
+                _ => return Err("Unsupported jsonb binary format".into()),
+            }
+        } else if <&str as FromSql>::accepts(ty) {
+            Value::from(<&str as FromSql>::from_sql(ty, raw)?)
+        } else {
+            return Err(format!(
+                "Unsupported column type {}, please cast the column to a supported type",
+                ty
+            )
+            .into());
+        };
+
+        Ok(Self(value))
+    }
+
+    fn from_sql_null(_ty: &Type) -> Result<Self, BoxError> {
+        Ok(Self(Value::Null))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Encode a decimal string into the binary format of `numeric`,
+/// which is a sequence of base 10000 digits with a weight, a sign and a display scale
+fn numeric_to_sql(text: &str, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+    if text.eq_ignore_ascii_case("nan") {
+        out.put_i16(0);
+        out.put_i16(0);
+        out.put_u16(NUMERIC_NAN);
+        out.put_u16(0);
+        return Ok(IsNull::No);
+    }
+
+    let (is_negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (int_part, frac_part) = match text.find('.') {
+        Some(idx) => (&text[..idx], &text[idx + 1..]),
+        None => (text, ""),
+    };
+    if (int_part.is_empty() && frac_part.is_empty())
+        || !int_part.bytes().chain(frac_part.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return Err(format!("Invalid numeric {}", text).into());
+    }
+
+    // Align both parts to the base 10000 digits
+    let int_part = int_part.trim_start_matches('0');
+    let int_padding = (4 - int_part.len() % 4) % 4;
+    let frac_padding = (4 - frac_part.len() % 4) % 4;
+    let decimal_digits = "0".repeat(int_padding) + int_part + frac_part + &"0".repeat(frac_padding);
+    let mut digits: Vec<i16> = decimal_digits
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| chunk.iter().fold(0, |acc, b| acc * 10 + (b - b'0') as i16))
+        .collect();
+    let mut weight = ((int_padding + int_part.len()) / 4) as i16 - 1;
+
+    let leading_zeros = digits.iter().take_while(|digit| **digit == 0).count();
+    digits.drain(..leading_zeros);
+    weight -= leading_zeros as i16;
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    if digits.is_empty() {
+        weight = 0;
+    }
+
+    out.put_i16(i16::try_from(digits.len())?);
+    out.put_i16(weight);
+    out.put_u16(if is_negative && !digits.is_empty() {
+        NUMERIC_NEG
+    } else {
+        NUMERIC_POS
+    });
+    out.put_u16(u16::try_from(frac_part.len())?);
+    digits.into_iter().for_each(|digit| out.put_i16(digit));
+
+    Ok(IsNull::No)
+}
+
+/// Decode the binary format of `numeric` into a decimal string
+fn numeric_from_sql(mut raw: &[u8]) -> Result<String, BoxError> {
+    if raw.len() < 8 {
+        return Err("Invalid numeric binary format".into());
+    }
+    let ndigits = raw.get_i16();
+    let weight = raw.get_i16() as i32;
+    let sign = raw.get_u16();
+    let dscale = raw.get_u16() as usize;
+    if sign == NUMERIC_NAN {
+        return Ok(String::from("NaN"));
+    }
+    if ndigits < 0 || raw.len() != ndigits as usize * 2 {
+        return Err("Invalid numeric binary format".into());
+    }
+    let digits: Vec<i16> = (0..ndigits).map(|_| raw.get_i16()).collect();
+    // The digit at idx has a weight of (weight - idx)
+    let digit = |idx: i32| {
+        if idx >= 0 && (idx as usize) < digits.len() {
+            digits[idx as usize]
+        } else {
+            0
+        }
+    };
+
+    let mut text = String::new();
+    if sign == NUMERIC_NEG {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(0).to_string());
+        (1..=weight).for_each(|idx| text.push_str(&format!("{:04}", digit(idx))));
+    }
+    if dscale > 0 {
+        let mut frac = String::new();
+        let mut idx = weight + 1;
+        while frac.len() < dscale {
+            frac.push_str(&format!("{:04}", digit(idx)));
+            idx += 1;
+        }
+        frac.truncate(dscale);
+        text.push('.');
+        text.push_str(&frac);
+    }
+
+    Ok(text)
+}
+
+fn hex_encode(raw: &[u8]) -> String {
+    raw.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// Unit test for `SqlParam` and `SqlValue`
+#[cfg(test)]
+mod tests_sql_types {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(value: Value, ty: Type) -> Result<Value, BoxError> {
+        let mut buf = BytesMut::new();
+        match SqlParam(&value).to_sql(&ty, &mut buf)? {
+            IsNull::Yes => SqlValue::from_sql_null(&ty),
+            IsNull::No => SqlValue::from_sql(&ty, &buf),
+        }
+        .map(SqlValue::into_inner)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(round_trip(json!(true), Type::BOOL).unwrap(), json!(true));
+        assert_eq!(round_trip(json!(-12), Type::INT2).unwrap(), json!(-12));
+        assert_eq!(round_trip(json!(123456), Type::INT4).unwrap(), json!(123456));
+        assert_eq!(
+            round_trip(json!(1234567890123i64), Type::INT8).unwrap(),
+            json!(1234567890123i64)
+        );
+        assert_eq!(round_trip(json!(7), Type::OID).unwrap(), json!(7));
+        assert_eq!(round_trip(json!(1.5), Type::FLOAT4).unwrap(), json!(1.5));
+        assert_eq!(round_trip(json!(2.25), Type::FLOAT8).unwrap(), json!(2.25));
+        assert_eq!(round_trip(json!("ray"), Type::TEXT).unwrap(), json!("ray"));
+        assert_eq!(round_trip(json!("ray"), Type::VARCHAR).unwrap(), json!("ray"));
+        assert_eq!(
+            round_trip(json!({"a": [1, "b"]}), Type::JSON).unwrap(),
+            json!({"a": [1, "b"]})
+        );
+        assert_eq!(
+            round_trip(json!({"a": [1, "b"]}), Type::JSONB).unwrap(),
+            json!({"a": [1, "b"]})
+        );
+        assert_eq!(round_trip(json!(null), Type::INT4).unwrap(), json!(null));
+    }
+
+    #[test]
+    fn test_lenient_param() {
+        assert_eq!(round_trip(json!(" 42 "), Type::INT4).unwrap(), json!(42));
+        assert_eq!(round_trip(json!("0.5"), Type::FLOAT8).unwrap(), json!(0.5));
+        assert_eq!(round_trip(json!("off"), Type::BOOL).unwrap(), json!(false));
+        assert_eq!(round_trip(json!(42), Type::TEXT).unwrap(), json!("42"));
+        assert_eq!(round_trip(json!(false), Type::TEXT).unwrap(), json!("false"));
+    }
+
+    #[test]
+    fn test_invalid_param() {
+        assert!(round_trip(json!("ray"), Type::INT4).is_err());
+        assert!(round_trip(json!(1.5), Type::INT8).is_err());
+        assert!(round_trip(json!(70000), Type::INT2).is_err());
+        assert!(round_trip(json!(-1), Type::OID).is_err());
+        assert!(round_trip(json!("maybe"), Type::BOOL).is_err());
+        assert!(round_trip(json!([1, 2]), Type::FLOAT8).is_err());
+        assert!(round_trip(json!("2020-13-01"), Type::DATE).is_err());
+        assert!(round_trip(json!("1.2.3"), Type::NUMERIC).is_err());
+        assert!(round_trip(json!(1e100), Type::NUMERIC).is_err());
+        assert!(round_trip(json!("not-a-uuid"), Type::UUID).is_err());
+        assert!(round_trip(json!("\\xabc"), Type::BYTEA).is_err());
+        assert!(round_trip(json!("1 day"), Type::INTERVAL).is_err());
+    }
+
+    #[test]
+    fn test_numeric() {
+        let cases = vec![
+            ("0", "0"),
+            ("0.00", "0.00"),
+            ("1", "1"),
+            ("-1", "-1"),
+            ("10000", "10000"),
+            ("123456789.123456789", "123456789.123456789"),
+            ("0.0001", "0.0001"),
+            ("-0.000012300", "-0.000012300"),
+            ("00042.50", "42.50"),
+            ("+.5", "0.5"),
+            ("NaN", "NaN"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(round_trip(json!(input), Type::NUMERIC).unwrap(), json!(expected));
+        }
+        assert_eq!(round_trip(json!(12.75), Type::NUMERIC).unwrap(), json!("12.75"));
+        assert_eq!(round_trip(json!(-3), Type::NUMERIC).unwrap(), json!("-3"));
+    }
+
+    #[test]
+    fn test_date_time() {
+        assert_eq!(
+            round_trip(json!("2020-11-03"), Type::DATE).unwrap(),
+            json!("2020-11-03")
+        );
+        assert_eq!(round_trip(json!("13:45:01"), Type::TIME).unwrap(), json!("13:45:01"));
+        assert_eq!(
+            round_trip(json!("2020-11-03 13:45:01.250"), Type::TIMESTAMP).unwrap(),
+            json!("2020-11-03 13:45:01.250")
+        );
+        assert_eq!(
+            round_trip(json!("2020-11-03T13:45:01"), Type::TIMESTAMP).unwrap(),
+            json!("2020-11-03 13:45:01")
+        );
+        assert_eq!(
+            round_trip(json!("2020-11-03"), Type::TIMESTAMP).unwrap(),
+            json!("2020-11-03 00:00:00")
+        );
+        assert_eq!(
+            round_trip(json!("2020-11-03T13:45:01-05:00"), Type::TIMESTAMPTZ).unwrap(),
+            json!("2020-11-03 18:45:01+00:00")
+        );
+        assert_eq!(
+            round_trip(json!("2020-11-03 13:45:01"), Type::TIMESTAMPTZ).unwrap(),
+            json!("2020-11-03 13:45:01+00:00")
+        );
+    }
+
+    #[test]
+    fn test_misc_types() {
+        assert_eq!(
+            round_trip(json!("67e55044-10b1-426f-9247-bb680e5fe0c8"), Type::UUID).unwrap(),
+            json!("67e55044-10b1-426f-9247-bb680e5fe0c8")
+        );
+        assert_eq!(round_trip(json!("\\x00ff10"), Type::BYTEA).unwrap(), json!("\\x00ff10"));
+        assert_eq!(round_trip(json!("ab"), Type::BYTEA).unwrap(), json!("\\x6162"));
+        assert_eq!(round_trip(json!("y"), Type::CHAR).unwrap(), json!("y"));
+        assert!(round_trip(json!("yes"), Type::CHAR).is_err());
+    }
+
+    #[test]
+    fn test_from_text() {
+        let from_text = |ty: Type, text: Option<&str>| SqlValue::from_text(&ty, text).into_inner();
+        assert_eq!(from_text(Type::BOOL, Some("t")), json!(true));
+        assert_eq!(from_text(Type::INT4, Some("-42")), json!(-42));
+        assert_eq!(from_text(Type::OID, Some("7")), json!(7));
+        assert_eq!(from_text(Type::FLOAT8, Some("2.25")), json!(2.25));
+        assert_eq!(from_text(Type::NUMERIC, Some("12.50")), json!("12.50"));
+        assert_eq!(from_text(Type::JSONB, Some(r#"{"a": [1]}"#)), json!({"a": [1]}));
+        assert_eq!(from_text(Type::TEXT, Some("ray")), json!("ray"));
+        assert_eq!(from_text(Type::INTERVAL, Some("1 day")), json!("1 day"));
+        assert_eq!(from_text(Type::INT4_ARRAY, Some("{1,2}")), json!("{1,2}"));
+        assert_eq!(from_text(Type::INT4, None), json!(null));
+    }
+}
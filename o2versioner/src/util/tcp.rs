@@ -8,6 +8,7 @@ use std::fmt::Debug;
 use std::iter;
 use std::net::SocketAddr;
 use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::time::Duration;
 use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs};
 use tokio::sync::oneshot;
 use tokio_serde::formats::SymmetricalJson;
@@ -24,6 +25,7 @@ pub type StopRx = oneshot::Receiver<()>;
 /// 1. `addr` is the tcp port to bind to
 /// 2. `connection_handler` is a `FnMut` closure takes in `TcpStream` and returns `Future<Output=()>`
 /// 3. `max_connection` can be specified to limit the max number of connections allowed. Server will shutdown immediately once `max_connection` connections are all dropped.
+/// 4. If any `connection_handler` panics, the panic is resumed once all connections are dropped.
 #[instrument(name="listen", skip(addr, connection_handler, max_connection, stop_rx), fields(message=field::Empty))]
 pub async fn start_tcplistener<A, C, Fut>(
     addr: A,
@@ -88,8 +90,15 @@ pub async fn start_tcplistener<A, C, Fut>(
         };
     }
 
-    // Wait on all spawned tasks to finish
-    futures::future::join_all(spawned_tasks).await;
+    // Wait on all spawned tasks to finish, and resume the panic of any of them
+    if let Some(e) = futures::future::join_all(spawned_tasks)
+        .await
+        .into_iter()
+        .filter_map(|res| res.err())
+        .find(|e| e.is_panic())
+    {
+        std::panic::resume_unwind(e.into_panic());
+    }
     info!("Service terminated, have a good night");
 }
 
@@ -142,10 +151,12 @@ where
                     serded_write
                         .send(send_msg)
                         .and_then(|_| serded_read.try_next())
-                        .map_ok(|received_msg| {
-                            let received_msg = received_msg.unwrap();
+                        .and_then(|received_msg| async {
+                            let received_msg = received_msg.ok_or_else(|| {
+                                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed by peer")
+                            })?;
                             trace!("<- {:?}", received_msg);
-                            received_msg
+                            Ok(received_msg)
                         })
                         .await,
                 );
@@ -160,6 +171,24 @@ where
     responses
 }
 
+/// Exponential backoff before the `attempt`th retry of reconnecting or accepting, capped at 5s
+///
+/// # Example
+/// ```
+/// use o2versioner::util::tcp::backoff;
+/// use std::time::Duration;
+///
+/// assert_eq!(backoff(0), Duration::from_millis(100));
+/// assert_eq!(backoff(3), Duration::from_millis(800));
+/// assert_eq!(backoff(100), Duration::from_secs(5));
+/// ```
+pub fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(100)
+        .checked_mul(2u32.saturating_pow(attempt))
+        .unwrap_or(Duration::MAX)
+        .min(Duration::from_secs(5))
+}
+
 /// For asynchronous pooled tcp connection
 ///
 /// The pool will be destructed once all references
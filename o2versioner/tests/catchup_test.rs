@@ -0,0 +1,162 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::conf::*;
+use o2versioner::util::tests_helper;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+#[tokio::test]
+async fn test_catchup() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45340, 1);
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let confc = conf.clone();
+    let dbproxy_handle = tokio::spawn(async move {
+        dbproxy_main(confc.dbproxy[0].clone()).await;
+
+        println!("\ndbproxy DONE\n");
+    });
+
+    // Not in the conf, caught up later by admin
+    let joining_dbproxy_handle = tokio::spawn(async move {
+        dbproxy_main(DbProxyConf::new("127.0.0.1:45344")).await;
+
+        println!("\njoining dbproxy DONE\n");
+    });
+    let failed_dbproxy_handle = tokio::spawn(failed_to_restore_dbproxy("127.0.0.1:45345"));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        conn.query(query("update t0 set a = 1;")).await.unwrap();
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 2;")).await.unwrap();
+        tx.query(query("update t1 set a = 2;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // Copied from the only live dbproxy at the current version
+        assert_eq!(
+            admin_command(&mut admin, "catchup 127.0.0.1:45344").await,
+            "Catching up dbproxy 127.0.0.1:45344 from donor 127.0.0.1:45343 \
+            at version vector {\"t0\": 2, \"t1\": 1}. Sequencer is unblocked. Caught up dbproxy 127.0.0.1:45344"
+        );
+        assert_eq!(
+            admin_command(&mut admin, "catchup 127.0.0.1:45344").await,
+            "Dbproxy 127.0.0.1:45344 already exists"
+        );
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45343, 127.0.0.1:45344], evicted: [], quarantined: []"
+        );
+
+        // Evicted if it cannot restore
+        assert_eq!(
+            admin_command(&mut admin, "catchup 127.0.0.1:45345").await,
+            "Catching up dbproxy 127.0.0.1:45345 from donor 127.0.0.1:45343 \
+            at version vector {\"t0\": 2, \"t1\": 1}. Sequencer is unblocked. \
+            Cannot catch up dbproxy 127.0.0.1:45345: Database [53100]: disk full"
+        );
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45343, 127.0.0.1:45344], evicted: [127.0.0.1:45345], quarantined: []"
+        );
+
+        // The caught up dbproxy keeps up with the donor, and serves on its own once the donor is removed
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 3;")).await.unwrap();
+        tx.query(query("select * from t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(
+            admin_command(&mut admin, "remove 127.0.0.1:45343").await,
+            "Removed dbproxy 127.0.0.1:45343 at version vector {\"t0\": 3, \"t1\": 2}. Sequencer is unblocked"
+        );
+        conn.query(query("select * from t0;")).await.unwrap();
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("read t0 t1")))
+            .await
+            .unwrap();
+        tx.query(query("select * from t0, t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(
+            admin_command(&mut admin, "catchup").await,
+            "Usage: catchup <dbproxy_addr>. The dbproxy must already have the schema of all tables, \
+            and foreign keys between the tables are not supported"
+        );
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        dbproxy_handle,
+        joining_dbproxy_handle,
+        failed_dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+}
+
+/// Replies heartbeats, but fails to restore
+async fn failed_to_restore_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut framed = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    );
+    while let Ok(Some(msg)) = framed.try_next().await {
+        let reply = match msg {
+            Message::Heartbeat => Message::HeartbeatAck(Vec::new()),
+            Message::Restore(meta, _, _) => {
+                Message::Restored(meta, Err(MsqlError::database("disk full", Some("53100"))))
+            }
+            _ => continue,
+        };
+        if framed.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
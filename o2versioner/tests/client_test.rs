@@ -0,0 +1,125 @@
+use bb8::Pool;
+use common::{local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::{Connection, ConnectionManager};
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::time::{sleep, Duration};
+mod common;
+
+#[tokio::test]
+async fn test_client() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45300, 2);
+    conf.scheduler = conf
+        .scheduler
+        .set_admin_addr(Option::<String>::None)
+        .set_max_connection(Some(2));
+    conf.sequencer = conf.sequencer.set_max_connection(Some(1));
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let confc = conf.clone();
+    let dbproxies_handle = tokio::spawn(async move {
+        stream::iter(confc.dbproxy.into_iter().enumerate())
+            .for_each_concurrent(None, move |(idx, conf)| async move {
+                dbproxy_main(conf).await;
+
+                println!("\ndbproxy {} DONE\n", idx);
+            })
+            .await;
+    });
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        test_transaction(&mut conn).await;
+        test_dropped_transaction(&mut conn).await;
+        test_single_query(&mut conn).await;
+        drop(conn);
+
+        let pool = Pool::builder()
+            .max_size(1)
+            .build(ConnectionManager::new(&confc.scheduler.addr).await)
+            .await
+            .unwrap();
+        test_pool(&pool).await;
+        drop(pool);
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxies_handle, client_handle).unwrap();
+}
+
+async fn test_transaction(conn: &mut Connection) {
+    let mut tx = conn
+        .begin(MsqlBeginTx::from(TableOps::from("read t0 write t1")))
+        .await
+        .unwrap();
+    tx.query(query("select * from t0;")).await.unwrap();
+    tx.savepoint("sp0").await.unwrap();
+    tx.query(query("update t1 set a = 1;")).await.unwrap();
+    tx.rollback_to("sp0").await.unwrap();
+    tx.release("sp0").await.unwrap();
+
+    // Query does not match with the annotation of the transaction
+    let err = tx.query(query("update t0 set a = 2;")).await.unwrap_err();
+    assert_eq!(err.kind(), MsqlErrorKind::Annotation);
+    tx.commit().await.unwrap();
+
+    let tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t1"))).await.unwrap();
+    tx.rollback().await.unwrap();
+}
+
+async fn test_dropped_transaction(conn: &mut Connection) {
+    let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t1"))).await.unwrap();
+    tx.query(query("update t1 set a = 3;")).await.unwrap();
+    drop(tx);
+
+    // The dropped transaction is rolled back, so that a new one can begin
+    let tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t1"))).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(conn.is_connected());
+}
+
+async fn test_single_query(conn: &mut Connection) {
+    let result = conn.query(query("select * from t0;")).await.unwrap();
+    assert!(result.rows().is_empty());
+    conn.query(query("update t1 set a = 4;")).await.unwrap();
+
+    let err = conn
+        .execute_text(MsqlText::auto_query("drop table t0;", Option::<String>::None))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), MsqlErrorKind::Annotation);
+}
+
+async fn test_pool(pool: &Pool<ConnectionManager>) {
+    let mut conn = pool.get().await.unwrap();
+    let tx = conn.begin(MsqlBeginTx::from(TableOps::from("read t0"))).await.unwrap();
+    drop(tx);
+    drop(conn);
+
+    // The same connection is reused after the rollback of the dropped transaction
+    let mut conn = pool.get().await.unwrap();
+    conn.query(query("select * from t0;")).await.unwrap();
+}
@@ -0,0 +1,159 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::comm::{MsqlResponse, MsqlResult};
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+const COMMIT_DELAY: Duration = Duration::from_millis(1000);
+const FAILED_COMMIT: usize = 4;
+
+#[tokio::test]
+async fn test_commit_ack() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45370, 3);
+    conf.scheduler = conf
+        .scheduler
+        .set_commit_ack(CommitAck::Majority)
+        .set_dbproxy_reconnect_attempts(0);
+
+    // The last dbproxy is slow to commit, and fails at the `FAILED_COMMIT`th commit
+    let dbproxy_handles = vec![
+        tokio::spawn(dbproxy_main(conf.dbproxy[0].clone())),
+        tokio::spawn(dbproxy_main(conf.dbproxy[1].clone())),
+    ];
+    let slow_dbproxy_handle = tokio::spawn(slow_dbproxy("127.0.0.1:45375"));
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // The majority from SchedulerConf does not wait for the slow dbproxy
+        let (response, elapsed) = write_tx(&conn, None).await;
+        assert_eq!(response, MsqlResponse::endtx_ok("Applied on 2 of 3 replicas"));
+        assert!(elapsed < COMMIT_DELAY, "{:?}", elapsed);
+
+        // Overridden by the transaction
+        let (response, elapsed) = write_tx(&conn, Some(CommitAck::All)).await;
+        assert_eq!(response, MsqlResponse::endtx_ok("Applied on 3 of 3 replicas"));
+        assert!(elapsed >= COMMIT_DELAY, "{:?}", elapsed);
+
+        let (response, elapsed) = write_tx(&conn, Some(CommitAck::First)).await;
+        assert_eq!(response, MsqlResponse::endtx_ok("Applied on 1 of 3 replicas"));
+        assert!(elapsed < COMMIT_DELAY, "{:?}", elapsed);
+        sleep(COMMIT_DELAY).await;
+
+        // All the live dbproxies once the slow dbproxy fails
+        let (response, elapsed) = write_tx(&conn, Some(CommitAck::All)).await;
+        assert_eq!(response, MsqlResponse::endtx_ok("Applied on 2 of 3 replicas"));
+        assert!(elapsed < COMMIT_DELAY, "{:?}", elapsed);
+        drop(conn);
+
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, slow_dbproxy_handle, client_handle).unwrap();
+    for dbproxy_handle in dbproxy_handles {
+        dbproxy_handle.await.unwrap();
+    }
+}
+
+/// Run a write transaction, returns the response of its commit and the time taken by the commit
+async fn write_tx(conn: &Connection, ack: Option<CommitAck>) -> (MsqlResponse, Duration) {
+    let begintx = MsqlBeginTx::from(TableOps::from("write t0"));
+    assert!(conn.execute(Msql::BeginTx(begintx)).await.unwrap().is_ok());
+    assert!(conn
+        .execute(Msql::Query(query("update t0 set a = 1;")))
+        .await
+        .unwrap()
+        .is_ok());
+
+    let start = Instant::now();
+    let response = conn
+        .execute(Msql::EndTx(MsqlEndTx::commit().set_ack(ack)))
+        .await
+        .unwrap();
+    (response, start.elapsed())
+}
+
+/// Replies the same as the mocked db, but only replies commits after `COMMIT_DELAY`,
+/// and closes the connection at the `FAILED_COMMIT`th commit
+async fn slow_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let (mut sink, mut stream) = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    )
+    .split();
+
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+    let writer_handle = tokio::spawn(async move {
+        while let Some(reply) = reply_rx.recv().await {
+            if sink.send(reply).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut num_commit = 0;
+    while let Ok(Some(msg)) = stream.try_next().await {
+        let (reply, delay) = match msg {
+            Message::Heartbeat => (Message::HeartbeatAck(Vec::new()), Duration::default()),
+            Message::MsqlRequest(meta, Msql::Query(_), _) => (
+                Message::MsqlResponse(meta, MsqlResponse::query_ok(MsqlResult::default())),
+                Duration::default(),
+            ),
+            Message::MsqlRequest(meta, Msql::EndTx(_), _) => {
+                num_commit += 1;
+                if num_commit == FAILED_COMMIT {
+                    break;
+                }
+                (Message::MsqlResponse(meta, MsqlResponse::endtx_ok("")), COMMIT_DELAY)
+            }
+            _ => continue,
+        };
+        let reply_tx = reply_tx.clone();
+        tokio::spawn(async move {
+            sleep(delay).await;
+            let _ = reply_tx.send(reply);
+        });
+    }
+    drop(reply_tx);
+    writer_handle.await.unwrap();
+}
@@ -1,5 +1,40 @@
+#![allow(dead_code)]
+
 use o2versioner::comm::scheduler_api::*;
 use o2versioner::core::*;
+use o2versioner::util::conf::*;
+use o2versioner::util::tests_helper;
+use serde_json::json;
+use tokio::net::TcpStream;
+
+/// `Conf` of a scheduler with its admin port, a sequencer and `num_dbproxy` mock dbproxies,
+/// listening on the consecutive ports of localhost from `base_port` in this order.
+/// Each test adjusts it with the setters of `SchedulerConf`, `SequencerConf` and `DbProxyConf`
+pub fn local_conf(base_port: u16, num_dbproxy: u16) -> Conf {
+    let addr = |offset: u16| format!("127.0.0.1:{}", base_port + offset);
+    Conf {
+        scheduler: SchedulerConf::new(addr(0))
+            .set_admin_addr(Some(addr(1)))
+            .set_sequencer_pool_size(1)
+            .set_dispatcher_queue_size(1)
+            .set_transceiver_queue_size(1)
+            .set_performance_logging(Option::<String>::None),
+        sequencer: SequencerConf::new(addr(2)),
+        dbproxy: (0..num_dbproxy).map(|i| DbProxyConf::new(addr(3 + i))).collect(),
+    }
+}
+
+pub fn query(query: &str) -> MsqlQuery {
+    MsqlQuery::auto_annotated(query, EarlyReleaseTables::default()).unwrap()
+}
+
+/// Send a single command to the admin port of the scheduler, and return its reply
+pub async fn admin_command(admin: &mut TcpStream, command: &str) -> String {
+    tests_helper::mock_ascii_client(admin, vec![command])
+        .await
+        .remove(0)
+        .unwrap()
+}
 
 pub fn sql_transaction_samples() -> Vec<Vec<Message>> {
     vec![
@@ -54,12 +89,23 @@ pub fn sql_transaction_samples() -> Vec<Vec<Message>> {
                 Some(""),
             )),
             Message::RequestMsqlText(MsqlText::query("select * from r0;", "read r0", Some(""))),
-            Message::RequestMsqlText(MsqlText::query("select * from r0;", "read r0", Some(""))),
+            Message::RequestMsqlText(
+                MsqlText::query("select * from r0 where id = $1;", "read r0", Some("")).set_params(vec![json!(1)]),
+            ),
+            Message::RequestMsqlText(
+                MsqlText::query("update w2 set name = $1 where id = $2;", "write w2", Some(""))
+                    .set_params(vec![json!("ray"), json!(20)]),
+            ),
             Message::RequestMsqlText(MsqlText::query(
                 "update w1 set name=\"ray\" where id = 20;",
                 "write w1",
                 Some(""),
             )),
+            Message::RequestMsqlText(MsqlText::query(
+                "insert into w3 select * from r1;",
+                "write w3 read r1",
+                Some(""),
+            )),
             Message::RequestMsqlText(MsqlText::query("select * from r0;", "read r0", Some(""))),
             Message::RequestMsqlText(MsqlText::query(
                 "update w1 set name=\"ray\" where id = 20;",
@@ -155,5 +201,32 @@ pub fn sql_transaction_samples() -> Vec<Vec<Message>> {
             "write w3",
             Some("w3"),
         ))],
+        // #7 Single mixed
+        vec![Message::RequestMsqlText(MsqlText::query(
+            "insert into w1 select * from r0;",
+            "write w1 read r0",
+            Some(""),
+        ))],
+        // #8 Savepoints with partial rollback
+        vec![
+            Message::RequestMsqlText(MsqlText::begintx(Option::<String>::None, "READ r0 WRITE w1 w2")),
+            Message::RequestMsqlText(MsqlText::query(
+                "update w1 set name=\"ray\" where id = 20;",
+                "write w1",
+                Some(""),
+            )),
+            Message::RequestMsqlText(MsqlText::savepoint("sp0", MsqlSavepointMode::Savepoint)),
+            Message::RequestMsqlText(MsqlText::query(
+                "update w2 set name=\"ray\" where id = 22;",
+                "write w2",
+                Some("w2"),
+            )),
+            Message::RequestMsqlText(MsqlText::savepoint("sp9", MsqlSavepointMode::RollbackTo)),
+            Message::RequestMsqlText(MsqlText::savepoint("sp0", MsqlSavepointMode::RollbackTo)),
+            Message::RequestMsqlText(MsqlText::savepoint("sp1", MsqlSavepointMode::Savepoint)),
+            Message::RequestMsqlText(MsqlText::query("select * from r0;", "read r0", Some(""))),
+            Message::RequestMsqlText(MsqlText::savepoint("sp1", MsqlSavepointMode::Release)),
+            Message::RequestMsqlText(MsqlText::endtx(Option::<String>::None, MsqlEndTxMode::Commit)),
+        ],
     ]
 }
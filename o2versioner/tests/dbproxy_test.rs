@@ -121,6 +121,7 @@ async fn test_dbproxy_end_to_end() {
                 MsqlResponse::EndTx(_e) => {
                     end_count += 1;
                 }
+                MsqlResponse::Savepoint(_s) => {}
             },
             _other => {
                 println!("nope");
@@ -245,6 +246,7 @@ async fn test_dbproxy_end_to_end_2() {
                 MsqlResponse::EndTx(_e) => {
                     end_count += 1;
                 }
+                MsqlResponse::Savepoint(_s) => {}
             },
             _other => {
                 println!("nope");
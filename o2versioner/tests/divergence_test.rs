@@ -0,0 +1,136 @@
+use common::{admin_command, local_conf, query};
+use flate2::read::GzDecoder;
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::comm::{MsqlResponse, MsqlResult};
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use std::io::Read;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+#[tokio::test]
+async fn test_divergence() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let perf_dir = std::env::temp_dir().join("o2versioner_divergence_test");
+    let mut conf = local_conf(45360, 3);
+    conf.scheduler = conf.scheduler.set_performance_logging(Some(perf_dir.to_string_lossy()));
+
+    // The last dbproxy reports a different number of affected rows for writes
+    let dbproxy_handles = vec![
+        tokio::spawn(dbproxy_main(conf.dbproxy[0].clone())),
+        tokio::spawn(dbproxy_main(conf.dbproxy[1].clone())),
+    ];
+    let diverged_dbproxy_handle = tokio::spawn(diverged_dbproxy("127.0.0.1:45365"));
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // Quarantined once all dbproxies reply
+        conn.query(query("update t0 set a = 1;")).await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45363, 127.0.0.1:45364], evicted: [], quarantined: [127.0.0.1:45365]"
+        );
+
+        // The others keep serving
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 2;")).await.unwrap();
+        tx.query(query("select * from t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // Recorded in the perf logs
+        let reply = admin_command(&mut admin, "perf").await;
+        let log_dir = reply.split('"').nth(1).unwrap();
+        let mut divergence_log = String::new();
+        GzDecoder::new(std::fs::File::open(format!("{}/divergence.csv.gz", log_dir)).unwrap())
+            .read_to_string(&mut divergence_log)
+            .unwrap();
+        let lines: Vec<_> = divergence_log.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", divergence_log);
+        assert_eq!(
+            lines[0],
+            "client_addr,request_type,timestamp,majority,quarantined,responses"
+        );
+        assert!(lines[1].contains(",WriteOnly,"), "{}", lines[1]);
+        assert!(
+            lines[1].contains(",127.0.0.1:45363 127.0.0.1:45364,127.0.0.1:45365,"),
+            "{}",
+            lines[1]
+        );
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        diverged_dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+    for dbproxy_handle in dbproxy_handles {
+        dbproxy_handle.await.unwrap();
+    }
+}
+
+/// Replies every write with 1 affected row, while the mocked db replies none
+async fn diverged_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut framed = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    );
+    while let Ok(Some(msg)) = framed.try_next().await {
+        let reply = match msg {
+            Message::Heartbeat => Message::HeartbeatAck(Vec::new()),
+            Message::MsqlRequest(meta, Msql::Query(_), _) => {
+                Message::MsqlResponse(meta, MsqlResponse::Query(Ok(MsqlResult::affected(1))))
+            }
+            Message::MsqlRequest(meta, Msql::EndTx(_), _) => Message::MsqlResponse(meta, MsqlResponse::endtx_ok("")),
+            _ => continue,
+        };
+        if framed.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use futures::prelude::*;
 use o2versioner::comm::scheduler_dbproxy;
-use o2versioner::comm::MsqlResponse;
+use o2versioner::comm::{MsqlResponse, MsqlResult};
 use o2versioner::core::*;
 use o2versioner::scheduler_main;
 use o2versioner::sequencer_main;
@@ -26,6 +26,7 @@ async fn test_double_s() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -34,10 +35,23 @@ async fn test_double_s() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:6379"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![],
     };
@@ -80,6 +94,7 @@ async fn run_double_s_limited() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: Some(String::from("127.0.0.1:24251")),
+            pg_addr: None,
             max_connection: None,
             sequencer_pool_size: 10,
             dispatcher_queue_size: 1,
@@ -88,10 +103,23 @@ async fn run_double_s_limited() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:24212"),
             max_connection: None,
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new(dbproxy0_addr).set_sql_conf(Some(
@@ -118,6 +146,8 @@ async fn run_double_s_limited() {
         transaction_samples[4].clone(),
         transaction_samples[2].clone(),
         transaction_samples[4].clone(),
+        transaction_samples[7].clone(),
+        transaction_samples[8].clone(),
     ]
     .concat();
     let tester_handle_0 = tokio::spawn(async move {
@@ -169,6 +199,7 @@ async fn run_double_s_unlimited() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: Some(String::from("127.0.0.1:19999")),
+            pg_addr: None,
             max_connection: None,
             sequencer_pool_size: 10,
             dispatcher_queue_size: 5000,
@@ -177,10 +208,23 @@ async fn run_double_s_unlimited() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:20001"),
             max_connection: None,
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new(dbproxy0_addr).set_sql_conf(Some(
@@ -299,11 +343,12 @@ where
                             match msg {
                                 scheduler_dbproxy::Message::MsqlRequest(client_addr, msql, _txvn) => {
                                     let response = match msql {
-                                        Msql::BeginTx(_) => {
-                                            MsqlResponse::begintx_err("Dbproxy does not handle BeginTx")
-                                        }
-                                        Msql::Query(_) => MsqlResponse::query_ok("QUERY GOOD"),
+                                        Msql::BeginTx(_) => MsqlResponse::begintx_err(MsqlError::internal(
+                                            "Dbproxy does not handle BeginTx",
+                                        )),
+                                        Msql::Query(_) => MsqlResponse::query_ok(MsqlResult::default()),
                                         Msql::EndTx(_) => MsqlResponse::endtx_ok("ENDTX GOOD"),
+                                        Msql::Savepoint(_) => MsqlResponse::savepoint_ok("SAVEPOINT GOOD"),
                                     };
 
                                     Ok(scheduler_dbproxy::Message::MsqlResponse(client_addr, response))
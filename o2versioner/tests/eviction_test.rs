@@ -0,0 +1,235 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+#[tokio::test]
+async fn test_eviction() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45330, 6);
+    conf.scheduler = conf
+        .scheduler
+        .set_dbproxy_heartbeat_interval(100)
+        .set_dbproxy_request_timeout(Some(500))
+        .set_dbproxy_reconnect_attempts(0);
+
+    // Only the first and the last dbproxies are working, the others fail in different ways,
+    // and 127.0.0.1:45337 is not listening at all
+    let dbproxy_handle = tokio::spawn(dbproxy_main(conf.dbproxy[0].clone()));
+    let other_dbproxy_handle = tokio::spawn(dbproxy_main(conf.dbproxy[5].clone()));
+    let silent_dbproxy_handle = tokio::spawn(silent_dbproxy("127.0.0.1:45334"));
+    let closed_dbproxy_handle = tokio::spawn(closed_dbproxy("127.0.0.1:45335"));
+    let heartbeat_only_dbproxy_handle = tokio::spawn(heartbeat_only_dbproxy("127.0.0.1:45336"));
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut other_conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // Completed by the working dbproxies, while the heartbeat-only dbproxy never replies
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 1;")).await.unwrap();
+        tx.query(query("select * from t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // Evicted once the request timeout is reached
+        sleep(Duration::from_millis(1000)).await;
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45333, 127.0.0.1:45338], \
+            evicted: [127.0.0.1:45334, 127.0.0.1:45335, 127.0.0.1:45336, 127.0.0.1:45337], quarantined: []"
+        );
+
+        // Not timed out while waiting for the versions held by another transaction
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t0"))).await.unwrap();
+        tx.query(query("update t0 set a = 2;")).await.unwrap();
+        let mut other_tx = other_conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0")))
+            .await
+            .unwrap();
+        let other_update = other_tx.query(query("update t0 set a = 3;"));
+        let commit = async {
+            sleep(Duration::from_millis(1000)).await;
+            tx.commit().await.unwrap();
+        };
+        let (other_update, _) = tokio::join!(other_update, commit);
+        other_update.unwrap();
+        other_tx.commit().await.unwrap();
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45333, 127.0.0.1:45338], \
+            evicted: [127.0.0.1:45334, 127.0.0.1:45335, 127.0.0.1:45336, 127.0.0.1:45337], quarantined: []"
+        );
+
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("read t0"))).await.unwrap();
+        tx.query(query("select * from t0;")).await.unwrap();
+        tx.commit().await.unwrap();
+        conn.query(query("select * from t1;")).await.unwrap();
+        drop(conn);
+        drop(other_conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        dbproxy_handle,
+        other_dbproxy_handle,
+        silent_dbproxy_handle,
+        closed_dbproxy_handle,
+        heartbeat_only_dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_last_dbproxy_kept() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45410, 1);
+    conf.scheduler = conf
+        .scheduler
+        .set_dbproxy_heartbeat_interval(100)
+        .set_dbproxy_request_timeout(Some(500))
+        .set_dbproxy_reconnect_attempts(0);
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    // The only dbproxy never replies requests on its first connection, and then is down for a while
+    let heartbeat_only_dbproxy_handle = tokio::spawn(heartbeat_only_dbproxy("127.0.0.1:45413"));
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // Kept even though it failed, since there would be no dbproxy left,
+        // but the pending request fails once the request timeout is reached
+        let err = timeout(Duration::from_secs(3), conn.query(query("select * from t0;")))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        heartbeat_only_dbproxy_handle.await.unwrap();
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45413], evicted: [], quarantined: []"
+        );
+
+        // Requests fail right away while it is down
+        let err = timeout(Duration::from_secs(1), conn.query(query("select * from t0;")))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+
+        // Reconnected once it is back
+        let dbproxy_handle = tokio::spawn(dbproxy_main(confc.dbproxy[0].clone()));
+        sleep(Duration::from_millis(300)).await;
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t0"))).await.unwrap();
+        tx.query(query("update t0 set a = 1;")).await.unwrap();
+        tx.commit().await.unwrap();
+        conn.query(query("select * from t0;")).await.unwrap();
+        drop(conn);
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45413], evicted: [], quarantined: []"
+        );
+
+        admin_command(&mut admin, "kill").await;
+        dbproxy_handle.await.unwrap();
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, client_handle).unwrap();
+}
+
+/// Never replies anything, until the connection is reset by the scheduler
+async fn silent_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (mut tcp_stream, _) = listener.accept().await.unwrap();
+    let mut buf = Vec::new();
+    let _ = tcp_stream.read_to_end(&mut buf).await;
+}
+
+/// Closes the connection right away
+async fn closed_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    listener.accept().await.unwrap();
+}
+
+/// Replies heartbeats, but never replies requests
+async fn heartbeat_only_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut framed = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    );
+    while let Ok(Some(msg)) = framed.try_next().await {
+        if let Message::Heartbeat = msg {
+            if framed.send(Message::HeartbeatAck(Vec::new())).await.is_err() {
+                break;
+            }
+        }
+    }
+}
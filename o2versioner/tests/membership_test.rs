@@ -0,0 +1,140 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::conf::*;
+use o2versioner::util::tests_helper;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+mod common;
+
+#[tokio::test]
+async fn test_membership() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45320, 2);
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let confc = conf.clone();
+    let dbproxies_handle = tokio::spawn(async move {
+        stream::iter(confc.dbproxy.into_iter().enumerate())
+            .for_each_concurrent(None, move |(idx, conf)| async move {
+                dbproxy_main(conf).await;
+
+                println!("\ndbproxy {} DONE\n", idx);
+            })
+            .await;
+    });
+
+    // Not in the conf, added later by admin
+    let added_dbproxy_handle = tokio::spawn(async move {
+        dbproxy_main(DbProxyConf::new("127.0.0.1:45325")).await;
+
+        println!("\nadded dbproxy DONE\n");
+    });
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        conn.query(query("update t0 set a = 1;")).await.unwrap();
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45323, 127.0.0.1:45324], evicted: [], quarantined: []"
+        );
+
+        // Added at the current version
+        assert_eq!(
+            admin_command(&mut admin, "add 127.0.0.1:45325").await,
+            r#"Added dbproxy 127.0.0.1:45325 at version vector {"t0": 1}. Sequencer is unblocked"#
+        );
+        assert_eq!(
+            admin_command(&mut admin, "add 127.0.0.1:45325").await,
+            "Dbproxy 127.0.0.1:45325 already exists"
+        );
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45323, 127.0.0.1:45324, 127.0.0.1:45325], evicted: [], quarantined: []"
+        );
+
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 2;")).await.unwrap();
+        tx.query(query("select * from t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // The added dbproxy keeps up with the others
+        assert_eq!(
+            admin_command(&mut admin, "quiesce").await,
+            r#"Quiesced at version vector {"t0": 2, "t1": 1}"#
+        );
+        admin_command(&mut admin, "unblock").await;
+
+        // Removed once the transaction in flight finishes
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 3;")).await.unwrap();
+        let mut remove_handle = tokio::spawn(async move {
+            let reply = admin_command(&mut admin, "remove 127.0.0.1:45323").await;
+            (admin, reply)
+        });
+        assert!(timeout(Duration::from_millis(300), &mut remove_handle).await.is_err());
+        tx.query(query("update t1 set a = 3;")).await.unwrap();
+        tx.commit().await.unwrap();
+        let (mut admin, reply) = remove_handle.await.unwrap();
+        assert_eq!(
+            reply,
+            r#"Removed dbproxy 127.0.0.1:45323 at version vector {"t0": 3, "t1": 2}. Sequencer is unblocked"#
+        );
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45324, 127.0.0.1:45325], evicted: [], quarantined: []"
+        );
+        conn.query(query("select * from t0;")).await.unwrap();
+
+        assert_eq!(
+            admin_command(&mut admin, "remove 127.0.0.1:45323").await,
+            "Dbproxy 127.0.0.1:45323 does not exist"
+        );
+        assert_eq!(admin_command(&mut admin, "add").await, "Usage: add <dbproxy_addr>");
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        dbproxies_handle,
+        added_dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+}
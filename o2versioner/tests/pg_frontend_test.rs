@@ -0,0 +1,285 @@
+use common::local_conf;
+use futures::prelude::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, NoTls, SimpleQueryMessage};
+mod common;
+
+#[tokio::test]
+async fn test_pg_frontend() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45200, 2);
+    conf.scheduler = conf
+        .scheduler
+        .set_admin_addr(Option::<String>::None)
+        .set_pg_addr(Some("127.0.0.1:45205"))
+        .set_max_connection(Some(1));
+    conf.sequencer = conf.sequencer.set_max_connection(Some(1));
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let confc = conf.clone();
+    let dbproxies_handle = tokio::spawn(async move {
+        stream::iter(confc.dbproxy.into_iter().enumerate())
+            .for_each_concurrent(None, move |(idx, conf)| async move {
+                dbproxy_main(conf).await;
+
+                println!("\ndbproxy {} DONE\n", idx);
+            })
+            .await;
+    });
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        // The scheduler only stops after max_connection on both ports
+        TcpStream::connect(&confc.scheduler.addr).await.unwrap();
+
+        let (client, connection) = tokio_postgres::connect("host=127.0.0.1 port=45205 user=o2v", NoTls)
+            .await
+            .unwrap();
+        let connection_handle = tokio::spawn(connection);
+
+        test_transaction(&client).await;
+        test_failed_transaction(&client).await;
+        test_savepoint(&client).await;
+        test_extended_query(&client).await;
+
+        drop(client);
+        connection_handle.await.unwrap().unwrap();
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxies_handle, client_handle).unwrap();
+}
+
+#[tokio::test]
+#[ignore]
+/// Run `cargo test test_pg_frontend_describe -- --ignored` with a postgres holding the database `Test`
+async fn test_pg_frontend_describe() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45420, 1);
+    conf.scheduler = conf
+        .scheduler
+        .set_admin_addr(Option::<String>::None)
+        .set_pg_addr(Some("127.0.0.1:45424"))
+        .set_max_connection(Some(2));
+    conf.sequencer = conf.sequencer.set_max_connection(Some(1));
+    conf.dbproxy[0] = conf.dbproxy[0].clone().set_sql_conf(Some(
+        "host=localhost port=5432 dbname=Test user=postgres password=Abc@123",
+    ));
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let dbproxy_handle = tokio::spawn(dbproxy_main(conf.dbproxy[0].clone()));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        // The scheduler only stops after max_connection on both ports
+        for _ in 0..2 {
+            TcpStream::connect(&confc.scheduler.addr).await.unwrap();
+        }
+
+        let (client, connection) = tokio_postgres::connect("host=127.0.0.1 port=45424 user=o2v", NoTls)
+            .await
+            .unwrap();
+        let connection_handle = tokio::spawn(connection);
+        client
+            .simple_query(
+                "/*o2v write tbldescribe*/ CREATE TABLE IF NOT EXISTS tbldescribe (a int4, b varchar); \
+                /*o2v write tbldescribe*/ DELETE FROM tbldescribe; \
+                /*o2v write tbldescribe*/ INSERT INTO tbldescribe VALUES (1, 'x')",
+            )
+            .await
+            .unwrap();
+
+        // The columns are described before the query is executed
+        let statement = client.prepare("select a, b from tbldescribe").await.unwrap();
+        let columns: Vec<_> = statement
+            .columns()
+            .iter()
+            .map(|column| (column.name(), column.type_().clone()))
+            .collect();
+        assert_eq!(columns, vec![("a", Type::INT4), ("b", Type::VARCHAR)]);
+        let rows = client.query(&statement, &[]).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get::<_, i32>("a"), 1);
+        assert_eq!(rows[0].get::<_, &str>("b"), "x");
+
+        // Describing a portal never executes it
+        let mut raw = TcpStream::connect("127.0.0.1:45424").await.unwrap();
+        assert_eq!(
+            raw_exchange(&mut raw, vec![startup_message()]).await.last(),
+            Some(&b'Z')
+        );
+        let messages = vec![
+            frontend_message(
+                b'P',
+                b"\0/*o2v write tbldescribe*/ insert into tbldescribe values (2, 'y')\0\0\0",
+            ),
+            frontend_message(b'B', b"\0\0\0\0\0\0\0\0"),
+            frontend_message(b'D', b"P\0"),
+            frontend_message(b'S', b""),
+        ];
+        assert_eq!(raw_exchange(&mut raw, messages).await, b"12nZ");
+        drop(raw);
+
+        let messages = client.simple_query("select count(*) from tbldescribe").await.unwrap();
+        match &messages[0] {
+            SimpleQueryMessage::Row(row) => assert_eq!(row.get(0), Some("1")),
+            _ => panic!("Expecting a row"),
+        }
+
+        drop(client);
+        connection_handle.await.unwrap().unwrap();
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxy_handle, client_handle).unwrap();
+}
+
+fn startup_message() -> Vec<u8> {
+    let body = [&196608i32.to_be_bytes()[..], b"user\0o2v\0\0"].concat();
+    [&(body.len() as i32 + 4).to_be_bytes()[..], &body].concat()
+}
+
+fn frontend_message(tag: u8, body: &[u8]) -> Vec<u8> {
+    [&[tag][..], &(body.len() as i32 + 4).to_be_bytes(), body].concat()
+}
+
+/// Send `messages`, and then receive until `ReadyForQuery`, returns the tags of the messages received
+async fn raw_exchange(tcp_stream: &mut TcpStream, messages: Vec<Vec<u8>>) -> Vec<u8> {
+    tcp_stream.write_all(&messages.concat()).await.unwrap();
+
+    let mut tags = Vec::new();
+    while tags.last() != Some(&b'Z') {
+        let tag = tcp_stream.read_u8().await.unwrap();
+        let len = tcp_stream.read_i32().await.unwrap();
+        let mut body = vec![0; len as usize - 4];
+        tcp_stream.read_exact(&mut body).await.unwrap();
+        tags.push(tag);
+    }
+    tags
+}
+
+fn command_completes(messages: &[SimpleQueryMessage]) -> usize {
+    messages
+        .iter()
+        .filter(|message| matches!(message, SimpleQueryMessage::CommandComplete(_)))
+        .count()
+}
+
+fn sqlstate<T>(res: Result<T, tokio_postgres::Error>) -> String {
+    match res {
+        Ok(_) => panic!("Expecting an error"),
+        Err(e) => e.code().unwrap().code().to_owned(),
+    }
+}
+
+async fn test_transaction(client: &Client) {
+    let messages = client
+        .simple_query("BEGIN /*o2v read t0 write t1*/; select * from t0; update t1 set a = 1; COMMIT")
+        .await
+        .unwrap();
+    assert_eq!(command_completes(&messages), 4);
+
+    // Single read and single write are executed outside of a transaction
+    let messages = client
+        .simple_query("select * from t0; /*o2v write t1*/ update t1 set a = 2")
+        .await
+        .unwrap();
+    assert_eq!(command_completes(&messages), 2);
+
+    // Empty statements and session settings are acknowledged
+    assert_eq!(command_completes(&client.simple_query(";").await.unwrap()), 1);
+    assert_eq!(
+        command_completes(&client.simple_query("SET search_path TO public").await.unwrap()),
+        1
+    );
+
+    // A transaction must be annotated
+    assert_eq!(sqlstate(client.simple_query("BEGIN").await), "42000");
+}
+
+async fn test_failed_transaction(client: &Client) {
+    client.simple_query("BEGIN /*o2v write t1*/").await.unwrap();
+
+    // Query does not match with the annotation of the transaction
+    assert_eq!(sqlstate(client.simple_query("select * from t0").await), "42000");
+    assert_eq!(sqlstate(client.simple_query("update t1 set a = 3").await), "25P02");
+
+    // Transaction is rolled back
+    client.simple_query("COMMIT").await.unwrap();
+    client.simple_query("select * from t0").await.unwrap();
+}
+
+async fn test_savepoint(client: &Client) {
+    client
+        .simple_query("BEGIN /*o2v write t1*/; SAVEPOINT sp0; update t1 set a = 4")
+        .await
+        .unwrap();
+
+    assert_eq!(sqlstate(client.simple_query("ROLLBACK TO sp1").await), "3B001");
+    assert_eq!(sqlstate(client.simple_query("update t1 set a = 5").await), "25P02");
+
+    // Rolling back to the savepoint recovers the transaction
+    let messages = client
+        .simple_query("ROLLBACK TO SAVEPOINT sp0; update t1 set a = 6; RELEASE sp0; COMMIT")
+        .await
+        .unwrap();
+    assert_eq!(command_completes(&messages), 4);
+}
+
+async fn test_extended_query(client: &Client) {
+    let statement = client
+        .prepare("/*o2v write t1*/ update t1 set a = $1 where b = $2")
+        .await
+        .unwrap();
+    assert_eq!(statement.params().len(), 2);
+    assert_eq!(client.execute(&statement, &[&"7", &"ray"]).await.unwrap(), 0);
+
+    client.simple_query("BEGIN /*o2v read t0*/").await.unwrap();
+    assert!(client
+        .query("select * from t0 where a = $1", &[&"8"])
+        .await
+        .unwrap()
+        .is_empty());
+    client.simple_query("COMMIT").await.unwrap();
+}
@@ -0,0 +1,144 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+mod common;
+
+#[tokio::test]
+async fn test_quiesce() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45310, 2);
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let confc = conf.clone();
+    let dbproxies_handle = tokio::spawn(async move {
+        stream::iter(confc.dbproxy.into_iter().enumerate())
+            .for_each_concurrent(None, move |(idx, conf)| async move {
+                dbproxy_main(conf).await;
+
+                println!("\ndbproxy {} DONE\n", idx);
+            })
+            .await;
+    });
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // Quiesced immediately without any transaction
+        assert_eq!(
+            admin_command(&mut admin, "quiesce").await,
+            "Quiesced at version vector {}"
+        );
+        admin_command(&mut admin, "unblock").await;
+
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 1;")).await.unwrap();
+
+        // Waiting for the transaction in flight
+        let mut quiesce_handle = tokio::spawn(async move {
+            let reply = admin_command(&mut admin, "quiesce").await;
+            (admin, reply)
+        });
+        assert!(timeout(Duration::from_millis(300), &mut quiesce_handle).await.is_err());
+        tx.commit().await.unwrap();
+        let (mut admin, reply) = quiesce_handle.await.unwrap();
+        assert_eq!(reply, r#"Quiesced at version vector {"t0": 1, "t1": 1}"#);
+
+        // Still blocked after being quiesced
+        let err = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0")))
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Sequencer);
+
+        admin_command(&mut admin, "unblock").await;
+        let tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t0"))).await.unwrap();
+        tx.rollback().await.unwrap();
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxies_handle, client_handle).unwrap();
+}
+
+#[tokio::test]
+async fn test_single_write_blocked() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45440, 1);
+    conf.sequencer = conf.sequencer.set_block_wait_timeout(Some(300));
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    let dbproxy_handle = tokio::spawn(dbproxy_main(conf.dbproxy[0].clone()));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // The single write fails once the block wait timeout is reached, without any TxVN
+        admin_command(&mut admin, "block").await;
+        let err = conn.query(query("update t0 set a = 1;")).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Sequencer);
+
+        admin_command(&mut admin, "unblock").await;
+        conn.query(query("update t0 set a = 1;")).await.unwrap();
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxy_handle, client_handle).unwrap();
+}
@@ -0,0 +1,136 @@
+use common::{admin_command, local_conf, query};
+use o2versioner::client::Connection;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::conf::*;
+use o2versioner::util::tests_helper;
+use std::sync::Arc;
+use tokio::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+mod common;
+
+#[tokio::test]
+async fn test_reconnect() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45350, 2);
+    conf.scheduler = conf
+        .scheduler
+        .set_dbproxy_heartbeat_interval(100)
+        .set_dbproxy_request_timeout(Some(2000));
+
+    // The scheduler connects to both dbproxies through flaky proxies.
+    // 127.0.0.1:45354 comes back as a restarted dbproxy with an empty database
+    let reset = Arc::new(Notify::new());
+    let restarted_reset = Arc::new(Notify::new());
+    let proxy_handle = tokio::spawn(flaky_proxy("127.0.0.1:45353", &["127.0.0.1:45355"; 3], reset.clone()));
+    let _restarted_proxy_handle = tokio::spawn(flaky_proxy(
+        "127.0.0.1:45354",
+        &["127.0.0.1:45356", "127.0.0.1:45357"],
+        restarted_reset.clone(),
+    ));
+    let dbproxy_handle = tokio::spawn(dbproxy_main(DbProxyConf::new("127.0.0.1:45355")));
+    let _restarted_dbproxy_handles = [
+        tokio::spawn(dbproxy_main(DbProxyConf::new("127.0.0.1:45356"))),
+        tokio::spawn(dbproxy_main(DbProxyConf::new("127.0.0.1:45357"))),
+    ];
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // The transaction keeps going while the connection is reset in the middle
+        let mut tx = conn
+            .begin(MsqlBeginTx::from(TableOps::from("write t0 read t1")))
+            .await
+            .unwrap();
+        tx.query(query("update t0 set a = 1;")).await.unwrap();
+        reset.notify_one();
+        tx.query(query("select * from t1;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // Reset while the request is outstanding
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("read t0"))).await.unwrap();
+        reset.notify_one();
+        tx.query(query("select * from t0;")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // The restarted dbproxy lost the released versions
+        restarted_reset.notify_one();
+        sleep(Duration::from_millis(1000)).await;
+        assert_eq!(
+            admin_command(&mut admin, "dbproxies").await,
+            "Dbproxies: [127.0.0.1:45353], evicted: [127.0.0.1:45354], quarantined: []"
+        );
+
+        conn.query(query("update t0 set a = 2;")).await.unwrap();
+        conn.query(query("select * from t0;")).await.unwrap();
+        drop(conn);
+
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        proxy_handle,
+        dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+}
+
+/// Forwards the connections to `dbproxy_addrs` in turn, and resets the current connection once `reset` is notified.
+/// Stops once the connection is closed
+async fn flaky_proxy(addr: &str, dbproxy_addrs: &[&str], reset: Arc<Notify>) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    for dbproxy_addr in dbproxy_addrs {
+        let (scheduler, _) = listener.accept().await.unwrap();
+        let dbproxy = TcpStream::connect(dbproxy_addr).await.unwrap();
+        let (mut scheduler_read, mut scheduler_write) = scheduler.into_split();
+        let (mut dbproxy_read, mut dbproxy_write) = dbproxy.into_split();
+
+        let reset = tokio::select! {
+            _ = io::copy(&mut scheduler_read, &mut dbproxy_write) => false,
+            _ = io::copy(&mut dbproxy_read, &mut scheduler_write) => false,
+            _ = reset.notified() => true,
+        };
+        if !reset {
+            return;
+        }
+        scheduler_write
+            .as_ref()
+            .set_linger(Some(Duration::from_secs(0)))
+            .unwrap();
+        dbproxy_write.as_ref().set_linger(Some(Duration::from_secs(0))).unwrap();
+        scheduler_write.forget();
+        dbproxy_write.forget();
+    }
+}
@@ -0,0 +1,125 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::comm::{MsqlResponse, MsqlResult};
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+#[tokio::test]
+async fn test_replica_failure() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45380, 3);
+
+    // The last dbproxy fails the first write
+    let dbproxy_handles = vec![
+        tokio::spawn(dbproxy_main(conf.dbproxy[0].clone())),
+        tokio::spawn(dbproxy_main(conf.dbproxy[1].clone())),
+    ];
+    let (endtx_tx, mut endtx_rx) = mpsc::unbounded_channel();
+    let failing_dbproxy_handle = tokio::spawn(failing_dbproxy("127.0.0.1:45385", endtx_tx));
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let mut conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // Aborted on all dbproxies
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t0"))).await.unwrap();
+        let err = tx.query(query("update t0 set a = 1;")).await.unwrap_err();
+        assert_eq!(err.kind(), MsqlErrorKind::Unavailable);
+        assert_eq!(err.message(), "transaction aborted due to replica failure");
+        assert_eq!(endtx_rx.recv().await, Some(MsqlEndTxMode::Rollback));
+        drop(tx);
+
+        // The versions are released, so that the next transaction on the same table can proceed
+        let mut tx = conn.begin(MsqlBeginTx::from(TableOps::from("write t0"))).await.unwrap();
+        timeout(Duration::from_secs(3), tx.query(query("update t0 set a = 2;")))
+            .await
+            .expect("Versions of the aborted transaction are not released")
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(endtx_rx.recv().await, Some(MsqlEndTxMode::Commit));
+        drop(conn);
+
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(
+        scheduler_handle,
+        sequencer_handle,
+        failing_dbproxy_handle,
+        client_handle
+    )
+    .unwrap();
+    for dbproxy_handle in dbproxy_handles {
+        dbproxy_handle.await.unwrap();
+    }
+}
+
+/// Fails the first write, and reports the mode of every `Msql::EndTx` to `endtx_tx`
+async fn failing_dbproxy(addr: &str, endtx_tx: mpsc::UnboundedSender<MsqlEndTxMode>) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut framed = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    );
+    let mut failed = false;
+    while let Ok(Some(msg)) = framed.try_next().await {
+        let reply = match msg {
+            Message::Heartbeat => Message::HeartbeatAck(Vec::new()),
+            Message::MsqlRequest(meta, Msql::Query(_), _) if !failed => {
+                failed = true;
+                Message::MsqlResponse(
+                    meta,
+                    MsqlResponse::query_err(MsqlError::database("disk full", Some("53100"))),
+                )
+            }
+            Message::MsqlRequest(meta, Msql::Query(_), _) => {
+                Message::MsqlResponse(meta, MsqlResponse::query_ok(MsqlResult::default()))
+            }
+            Message::MsqlRequest(meta, Msql::EndTx(endtx), _) => {
+                endtx_tx.send(endtx.mode()).unwrap();
+                Message::MsqlResponse(meta, MsqlResponse::endtx_ok(""))
+            }
+            _ => continue,
+        };
+        if framed.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use common::{admin_command, local_conf, query};
+use futures::prelude::*;
+use o2versioner::client::Connection;
+use o2versioner::comm::scheduler_dbproxy::Message;
+use o2versioner::comm::{MsqlResponse, MsqlResult};
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use std::collections::HashMap;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+mod common;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(300);
+const RESYNC_DELAY: Duration = Duration::from_millis(1500);
+
+#[tokio::test]
+async fn test_request_cancel() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45400, 2);
+
+    let dbproxy_handles: Vec<_> = conf
+        .dbproxy
+        .iter()
+        .map(|dbproxy_conf| tokio::spawn(dbproxy_main(dbproxy_conf.clone())))
+        .collect();
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let writer = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let reader = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let other_writer = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // Holds the versions of t0
+        begin_tx(&writer, "write t0").await;
+        assert!(writer
+            .execute(Msql::Query(query("update t0 set a = 1;")))
+            .await
+            .unwrap()
+            .is_ok());
+
+        // The read waiting on the versions in the scheduler is cancelled once timed out
+        begin_tx(&reader, "read t0").await;
+        let start = Instant::now();
+        let response = reader
+            .execute_with_timeout(Msql::Query(query("select * from t0;")), REQUEST_TIMEOUT)
+            .await
+            .unwrap();
+        let err = into_cancelled_err(response);
+        assert_eq!(err.message(), "request was not done within its timeout of 300 ms");
+        assert!(start.elapsed() >= REQUEST_TIMEOUT, "{:?}", start.elapsed());
+
+        // Or cancelled by the client
+        let response = reader
+            .execute_cancellable(Msql::Query(query("select * from t0;")), sleep(REQUEST_TIMEOUT))
+            .await
+            .unwrap();
+        let err = into_cancelled_err(response);
+        assert_eq!(err.message(), "request was cancelled by the client");
+
+        // The write waiting on the versions in the PendingQueue of the dbproxies is cancelled there
+        begin_tx(&other_writer, "write t0").await;
+        let response = other_writer
+            .execute_with_timeout(Msql::Query(query("update t0 set a = 2;")), REQUEST_TIMEOUT)
+            .await
+            .unwrap();
+        into_cancelled_err(response);
+
+        // The commit waiting on the versions in the PendingQueue of the dbproxies is never cancelled,
+        // and is applied once the versions are released by the other transactions
+        let start = Instant::now();
+        let other_commit = other_writer.execute_with_timeout(commit(), REQUEST_TIMEOUT);
+        let other_txs = async {
+            sleep(REQUEST_TIMEOUT * 2).await;
+
+            // The transactions go on after their requests are cancelled
+            assert!(writer.execute(commit()).await.unwrap().is_ok());
+            assert!(reader
+                .execute(Msql::Query(query("select * from t0;")))
+                .await
+                .unwrap()
+                .is_ok());
+            assert!(reader.execute(commit()).await.unwrap().is_ok());
+        };
+        let (other_commit, _) = tokio::join!(other_commit, other_txs);
+        assert!(other_commit.unwrap().is_ok());
+        assert!(start.elapsed() >= REQUEST_TIMEOUT * 2, "{:?}", start.elapsed());
+
+        // Not cancelled if done in time
+        let response = reader
+            .execute_with_timeout(Msql::Query(query("select * from t0;")), Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert!(response.is_ok());
+        drop(writer);
+        drop(reader);
+        drop(other_writer);
+
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, client_handle).unwrap();
+    for dbproxy_handle in dbproxy_handles {
+        dbproxy_handle.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_request_cancel_waiting_for_load() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let conf = local_conf(45430, 1);
+
+    let dbproxy_addr = conf.dbproxy[0].addr.clone();
+    let dbproxy_handle = tokio::spawn(async move { resyncing_dbproxy(&dbproxy_addr).await });
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let reader = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // The dbproxy is resyncing, so its load is not replied until the read is cancelled
+        let response = reader
+            .execute_with_timeout(Msql::Query(query("select * from t0;")), REQUEST_TIMEOUT)
+            .await
+            .unwrap();
+        into_cancelled_err(response);
+
+        // The dbproxy is still serving once resynced
+        let response = reader
+            .execute_with_timeout(Msql::Query(query("select * from t0;")), RESYNC_DELAY * 2)
+            .await
+            .unwrap();
+        assert!(response.is_ok(), "{:?}", response);
+        drop(reader);
+
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, dbproxy_handle, client_handle).unwrap();
+}
+
+async fn begin_tx(conn: &Connection, tableops: &str) {
+    let msqlbegintx = MsqlBeginTx::from(TableOps::from(tableops));
+    assert!(conn.execute(Msql::BeginTx(msqlbegintx)).await.unwrap().is_ok());
+}
+
+fn commit() -> Msql {
+    Msql::EndTx(MsqlEndTx::commit())
+}
+
+/// Closes the first connection, and replies the `Resync` of the next connection after `RESYNC_DELAY`.
+/// Replies every query with an empty result.
+async fn resyncing_dbproxy(addr: &str) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    drop(listener.accept().await.unwrap());
+
+    let (tcp_stream, _) = listener.accept().await.unwrap();
+    let mut framed = SymmetricallyFramed::new(
+        Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+        SymmetricalJson::<Message>::default(),
+    );
+    while let Ok(Some(msg)) = framed.try_next().await {
+        let reply = match msg {
+            Message::Resync(_) => {
+                sleep(RESYNC_DELAY).await;
+                Message::Resynced(Vec::new(), HashMap::new())
+            }
+            Message::Heartbeat => Message::HeartbeatAck(Vec::new()),
+            Message::MsqlRequest(meta, Msql::Query(_), _) => {
+                Message::MsqlResponse(meta, MsqlResponse::query_ok(MsqlResult::default()))
+            }
+            _ => continue,
+        };
+        if framed.send(reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn into_cancelled_err(response: MsqlResponse) -> MsqlError {
+    let err = response.try_get_err().cloned().unwrap();
+    assert_eq!(err.kind(), MsqlErrorKind::Cancelled);
+    assert!(!err.is_retryable());
+    err
+}
@@ -17,6 +17,7 @@ async fn test_scheduler() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -25,10 +26,23 @@ async fn test_scheduler() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:6379"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![],
     };
@@ -90,6 +104,7 @@ async fn test_scheduler_with_admin() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: Some(String::from(scheduler_admin_addr)),
+            pg_addr: None,
             max_connection: None,
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -98,10 +113,23 @@ async fn test_scheduler_with_admin() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from(sequencer_addr),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![],
     };
@@ -183,6 +211,7 @@ async fn test_scheduler_with_request_crash() {
         scheduler: SchedulerConf {
             addr: String::from(scheduler_addr),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -191,10 +220,23 @@ async fn test_scheduler_with_request_crash() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from(sequencer_addr),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![],
     };
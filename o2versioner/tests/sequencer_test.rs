@@ -1,3 +1,4 @@
+use futures::prelude::*;
 use o2versioner::comm::scheduler_sequencer::Message;
 use o2versioner::core::*;
 use o2versioner::sequencer_main;
@@ -5,6 +6,9 @@ use o2versioner::util::conf::SequencerConf;
 use o2versioner::util::tests_helper;
 use tokio::net::TcpStream;
 use tokio::time::{sleep, Duration};
+use tokio_serde::formats::SymmetricalJson;
+use tokio_serde::SymmetricallyFramed;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tracing::{info_span, Instrument};
 
 #[tokio::test]
@@ -15,6 +19,11 @@ async fn test_sequencer() {
     let conf = SequencerConf {
         addr: String::from(sequencer_addr),
         max_connection: Some(2),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: None,
     };
 
     let sequencer_handle = tokio::spawn(sequencer_main(conf));
@@ -107,6 +116,11 @@ async fn test_sequencer_block_unblock() {
     let conf = SequencerConf {
         addr: String::from(sequencer_addr),
         max_connection: Some(2),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: None,
     };
 
     let sequencer_handle = tokio::spawn(sequencer_main(conf));
@@ -181,6 +195,11 @@ async fn test_sequencer_stop() {
     let conf = SequencerConf {
         addr: String::from(sequencer_addr),
         max_connection: None,
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: None,
     };
 
     let sequencer_handle = tokio::spawn(sequencer_main(conf));
@@ -245,3 +264,312 @@ async fn test_sequencer_stop() {
     // Must run, otherwise it won't do the work
     tokio::try_join!(tester_handle_0, tester_handle_1, sequencer_handle).unwrap();
 }
+
+#[tokio::test]
+async fn test_sequencer_restart() {
+    let _guard = tests_helper::init_logger();
+
+    let sequencer_addr = "127.0.0.1:52845";
+    let data_dir = std::env::temp_dir().join(format!("o2versioner_sequencer_{}", uuid::Uuid::new_v4()));
+    let conf = SequencerConf {
+        addr: String::from(sequencer_addr),
+        max_connection: Some(1),
+        data_dir: Some(data_dir.to_str().unwrap().to_owned()),
+        snapshot_interval: 2,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: None,
+    };
+
+    let request_vns = |count: usize| async move {
+        // Each request is from a different transaction
+        let msgs = (0..count).map(|txid| {
+            let mut client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+            (0..txid).for_each(|_| client_meta.transaction_finished());
+            Message::RequestTxVN(
+                client_meta,
+                MsqlBeginTx::from(TableOps::from("write table0 read table1")),
+            )
+        });
+
+        let mut tcp_stream = TcpStream::connect(sequencer_addr).await.unwrap();
+        tests_helper::mock_json_client(&mut tcp_stream, msgs)
+            .await
+            .into_iter()
+            .map(|reply| match reply.unwrap() {
+                Message::ReplyTxVN(Some(txvn)) => txvn.txtablevns().iter().map(|txtablevn| txtablevn.vn).collect(),
+                other => panic!("Unexpected reply {:?}", other),
+            })
+            .collect::<Vec<Vec<VN>>>()
+    };
+
+    // The version numbers are continued after the sequencer restarts
+    for (count, expected) in [
+        (3, vec![vec![0, 0], vec![1, 0], vec![2, 0]]),
+        (2, vec![vec![3, 0], vec![4, 0]]),
+    ] {
+        let sequencer_handle = tokio::spawn(sequencer_main(conf.clone()));
+        sleep(Duration::from_millis(200)).await;
+
+        let tester_handle = tokio::spawn(request_vns(count).instrument(info_span!("tester")));
+        let (vns, _) = tokio::try_join!(tester_handle, sequencer_handle).unwrap();
+        assert_eq!(vns, expected);
+    }
+
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_sequencer_failover() {
+    let _guard = tests_helper::init_logger();
+
+    let primary_addr = "127.0.0.1:52846";
+    let backup_addr = "127.0.0.1:52847";
+    let primary_conf = SequencerConf {
+        addr: String::from(primary_addr),
+        max_connection: Some(2),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: Some(String::from(backup_addr)),
+        is_backup: false,
+        block_wait_timeout: None,
+    };
+    let backup_conf = SequencerConf {
+        addr: String::from(backup_addr),
+        max_connection: Some(2),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: true,
+        block_wait_timeout: None,
+    };
+
+    let primary_handle = tokio::spawn(sequencer_main(primary_conf));
+    let backup_handle = tokio::spawn(sequencer_main(backup_conf));
+
+    sleep(Duration::from_millis(200)).await;
+
+    let request_txvns = |addr: &'static str, txids: Vec<usize>| async move {
+        let msgs = txids.into_iter().map(|txid| {
+            let mut client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+            (0..txid).for_each(|_| client_meta.transaction_finished());
+            Message::RequestTxVN(client_meta, MsqlBeginTx::from(TableOps::from("write table0")))
+        });
+
+        let mut tcp_stream = TcpStream::connect(addr).await.unwrap();
+        tests_helper::mock_json_client(&mut tcp_stream, msgs)
+            .await
+            .into_iter()
+            .map(|reply| match reply.unwrap() {
+                Message::ReplyTxVN(Some(txvn)) => Some(txvn.txtablevns()[0].vn),
+                Message::ReplyNotPrimary => None,
+                other => panic!("Unexpected reply {:?}", other),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let tester_handle = tokio::spawn(
+        async move {
+            assert_eq!(request_txvns(primary_addr, vec![0, 1]).await, vec![Some(0), Some(1)]);
+
+            // The backup takes over, and replies the same TxVN to the retried request
+            assert_eq!(request_txvns(backup_addr, vec![1, 2]).await, vec![Some(1), Some(2)]);
+
+            // The primary is fenced
+            assert_eq!(request_txvns(primary_addr, vec![3]).await, vec![None]);
+        }
+        .instrument(info_span!("tester")),
+    );
+
+    tokio::try_join!(tester_handle, primary_handle, backup_handle).unwrap();
+}
+
+#[tokio::test]
+async fn test_sequencer_backup_down() {
+    let _guard = tests_helper::init_logger();
+
+    let primary_addr = "127.0.0.1:52850";
+    let backup_addr = "127.0.0.1:52851";
+    let primary_conf = SequencerConf::new(primary_addr)
+        .set_max_connection(Some(2))
+        .set_backup_addr(Some(backup_addr));
+    let backup_conf = SequencerConf::new(backup_addr)
+        .set_max_connection(Some(1))
+        .set_is_backup(true);
+
+    let primary_handle = tokio::spawn(sequencer_main(primary_conf));
+
+    sleep(Duration::from_millis(200)).await;
+
+    let request_txvn = move |client_meta: ClientMeta| async move {
+        let msg = Message::RequestTxVN(client_meta, MsqlBeginTx::from(TableOps::from("write table0")));
+        let mut tcp_stream = TcpStream::connect(primary_addr).await.unwrap();
+        tests_helper::mock_json_client(&mut tcp_stream, vec![msg])
+            .await
+            .pop()
+            .unwrap()
+            .unwrap()
+    };
+
+    let client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+
+    // No transaction can begin while the backup is down, although the primary is alive
+    assert!(matches!(
+        request_txvn(client_meta.clone()).await,
+        Message::ReplyNotPrimary
+    ));
+
+    // Once the backup is back, the retried request gets the first version number,
+    // since nothing was assigned while the backup was down
+    let backup_handle = tokio::spawn(sequencer_main(backup_conf));
+    sleep(Duration::from_millis(200)).await;
+    match request_txvn(client_meta).await {
+        Message::ReplyTxVN(Some(txvn)) => assert_eq!(txvn.txtablevns()[0].vn, 0),
+        other => panic!("Unexpected reply {:?}", other),
+    }
+
+    tokio::try_join!(primary_handle, backup_handle).unwrap();
+}
+
+#[tokio::test]
+async fn test_sequencer_pipelined() {
+    let _guard = tests_helper::init_logger();
+
+    let sequencer_addr = "127.0.0.1:52848";
+    let conf = SequencerConf {
+        addr: String::from(sequencer_addr),
+        max_connection: Some(1),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: None,
+    };
+
+    let sequencer_handle = tokio::spawn(sequencer_main(conf));
+    sleep(Duration::from_millis(200)).await;
+
+    let tester_handle = tokio::spawn(
+        async move {
+            let tcp_stream = TcpStream::connect(sequencer_addr).await.unwrap();
+            let mut framed = SymmetricallyFramed::new(
+                Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+                SymmetricalJson::<Message>::default(),
+            );
+
+            // All requests are sent before receiving any reply, each from a different transaction
+            let request_ids = [7, 3, 11, 5, 2];
+            for (txid, request_id) in request_ids.iter().enumerate() {
+                let mut client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+                (0..txid).for_each(|_| client_meta.transaction_finished());
+                let msg = Message::RequestTxVN(client_meta, MsqlBeginTx::from(TableOps::from("write table0")));
+                framed.send(Message::Tagged(*request_id, Box::new(msg))).await.unwrap();
+            }
+            // An untagged request is replied without a request id
+            framed.send(Message::RequestBlock).await.unwrap();
+
+            for (vn, request_id) in request_ids.iter().enumerate() {
+                match framed.try_next().await.unwrap().unwrap() {
+                    Message::Tagged(reply_id, reply) => {
+                        assert_eq!(reply_id, *request_id);
+                        match *reply {
+                            Message::ReplyTxVN(Some(txvn)) => assert_eq!(txvn.txtablevns()[0].vn, vn as VN),
+                            other => panic!("Unexpected reply {:?}", other),
+                        }
+                    }
+                    other => panic!("Unexpected reply {:?}", other),
+                }
+            }
+            assert!(matches!(
+                framed.try_next().await.unwrap().unwrap(),
+                Message::ReplyBlockUnblock(_)
+            ));
+        }
+        .instrument(info_span!("tester")),
+    );
+
+    tokio::try_join!(tester_handle, sequencer_handle).unwrap();
+}
+
+#[tokio::test]
+async fn test_sequencer_block_wait() {
+    let _guard = tests_helper::init_logger();
+
+    let sequencer_addr = "127.0.0.1:52849";
+    let conf = SequencerConf {
+        addr: String::from(sequencer_addr),
+        max_connection: Some(1),
+        data_dir: None,
+        snapshot_interval: 1000,
+        backup_addr: None,
+        is_backup: false,
+        block_wait_timeout: Some(300),
+    };
+
+    let sequencer_handle = tokio::spawn(sequencer_main(conf));
+    sleep(Duration::from_millis(200)).await;
+
+    let tester_handle = tokio::spawn(
+        async move {
+            let tcp_stream = TcpStream::connect(sequencer_addr).await.unwrap();
+            let mut framed = SymmetricallyFramed::new(
+                Framed::new(tcp_stream, LengthDelimitedCodec::new()),
+                SymmetricalJson::<Message>::default(),
+            );
+            let request_txvn = |request_id: u64| {
+                let mut client_meta = ClientMeta::new("127.0.0.1:8080".parse().unwrap());
+                (0..request_id).for_each(|_| client_meta.transaction_finished());
+                let msg = Message::RequestTxVN(client_meta, MsqlBeginTx::from(TableOps::from("write table0")));
+                Message::Tagged(request_id, Box::new(msg))
+            };
+
+            framed.send(Message::RequestBlock).await.unwrap();
+            assert!(matches!(
+                framed.try_next().await.unwrap().unwrap(),
+                Message::ReplyBlockUnblock(_)
+            ));
+
+            // Queued while blocked, and resumed in the arrival order once unblocked
+            framed.send(request_txvn(0)).await.unwrap();
+            framed.send(request_txvn(1)).await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            framed.send(Message::RequestUnblock).await.unwrap();
+            let mut vns = Vec::new();
+            let mut is_unblocked = false;
+            while vns.len() < 2 || !is_unblocked {
+                match framed.try_next().await.unwrap().unwrap() {
+                    Message::Tagged(request_id, reply) => match *reply {
+                        Message::ReplyTxVN(Some(txvn)) => vns.push((request_id, txvn.txtablevns()[0].vn)),
+                        other => panic!("Unexpected reply {:?}", other),
+                    },
+                    Message::ReplyBlockUnblock(_) => is_unblocked = true,
+                    other => panic!("Unexpected reply {:?}", other),
+                }
+            }
+            vns.sort_unstable();
+            assert_eq!(vns, vec![(0, 0), (1, 1)]);
+
+            // Failed once timed out, without being assigned
+            framed.send(Message::RequestBlock).await.unwrap();
+            framed.try_next().await.unwrap().unwrap();
+            framed.send(request_txvn(2)).await.unwrap();
+            match framed.try_next().await.unwrap().unwrap() {
+                Message::Tagged(2, reply) => assert!(matches!(*reply, Message::ReplyTxVN(None))),
+                other => panic!("Unexpected reply {:?}", other),
+            }
+            framed.send(Message::RequestUnblock).await.unwrap();
+            framed.try_next().await.unwrap().unwrap();
+            framed.send(request_txvn(3)).await.unwrap();
+            match framed.try_next().await.unwrap().unwrap() {
+                Message::Tagged(3, reply) => match *reply {
+                    Message::ReplyTxVN(Some(txvn)) => assert_eq!(txvn.txtablevns()[0].vn, 2),
+                    other => panic!("Unexpected reply {:?}", other),
+                },
+                other => panic!("Unexpected reply {:?}", other),
+            }
+        }
+        .instrument(info_span!("tester")),
+    );
+
+    tokio::try_join!(tester_handle, sequencer_handle).unwrap();
+}
@@ -1,5 +1,6 @@
 use futures::prelude::*;
 use o2versioner::comm::scheduler_api::Message;
+use o2versioner::core::CommitAck;
 use o2versioner::dbproxy_main;
 use o2versioner::scheduler_main;
 use o2versioner::sequencer_main;
@@ -19,6 +20,7 @@ async fn test_ssd_constrained_default() {
         scheduler: SchedulerConf {
             addr: String::from("127.0.0.1:45000"),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -27,10 +29,23 @@ async fn test_ssd_constrained_default() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:45001"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new("127.0.0.1:45002"),
@@ -50,6 +65,7 @@ async fn test_ssd_constrained_disable_single_read_opt() {
         scheduler: SchedulerConf {
             addr: String::from("127.0.0.1:45120"),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -58,10 +74,23 @@ async fn test_ssd_constrained_disable_single_read_opt() {
             detailed_logging: None,
             disable_early_release: false,
             disable_single_read_optimization: true,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:45121"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new("127.0.0.1:45122"),
@@ -81,6 +110,7 @@ async fn test_ssd_constrained_disable_early_release() {
         scheduler: SchedulerConf {
             addr: String::from("127.0.0.1:45040"),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -89,10 +119,23 @@ async fn test_ssd_constrained_disable_early_release() {
             detailed_logging: None,
             disable_early_release: true,
             disable_single_read_optimization: false,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:45041"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new("127.0.0.1:45042"),
@@ -112,6 +155,7 @@ async fn test_ssd_constrained_disable_single_read_opt_and_early_release() {
         scheduler: SchedulerConf {
             addr: String::from("127.0.0.1:45060"),
             admin_addr: None,
+            pg_addr: None,
             max_connection: Some(2),
             sequencer_pool_size: sequencer_max_connection,
             dispatcher_queue_size: 1,
@@ -120,10 +164,23 @@ async fn test_ssd_constrained_disable_single_read_opt_and_early_release() {
             detailed_logging: None,
             disable_early_release: true,
             disable_single_read_optimization: true,
+            strict_annotation: false,
+            dbproxy_heartbeat_interval: 1000,
+            dbproxy_request_timeout: None,
+            dbproxy_reconnect_attempts: 3,
+            replica_selection: ReplicaSelection::LeastOutstanding,
+            commit_ack: CommitAck::First,
+            tx_idle_timeout: None,
+            tx_max_lifetime: None,
         },
         sequencer: SequencerConf {
             addr: String::from("127.0.0.1:45061"),
             max_connection: Some(sequencer_max_connection),
+            data_dir: None,
+            snapshot_interval: 1000,
+            backup_addr: None,
+            is_backup: false,
+            block_wait_timeout: None,
         },
         dbproxy: vec![
             DbProxyConf::new("127.0.0.1:45062"),
@@ -144,6 +201,7 @@ async fn test_suites(conf: Conf, transaction_samples: &Vec<Vec<Message>>) {
     test_suites_single_write(conf.clone(), transaction_samples).await;
     test_suites_single_read_with_early_release(conf.clone(), transaction_samples).await;
     test_suites_single_write_with_early_release(conf.clone(), transaction_samples).await;
+    test_suites_savepoint(conf.clone(), transaction_samples).await;
 }
 
 async fn test_suites_mixed(conf: Conf, transaction_samples: &Vec<Vec<Message>>) {
@@ -188,6 +246,11 @@ async fn test_suites_single_write_with_early_release(conf: Conf, transaction_sam
     spawn_test(conf, tx_sets).await;
 }
 
+async fn test_suites_savepoint(conf: Conf, transaction_samples: &[Vec<Message>]) {
+    let tx_sets = [transaction_samples[8].clone(), transaction_samples[4].clone()].concat();
+    spawn_test(conf, tx_sets).await;
+}
+
 /// Launch the entire setup based on `Conf`, and with
 /// `inputs: Vec<Message>`
 async fn spawn_test(conf: Conf, inputs: Vec<Message>) {
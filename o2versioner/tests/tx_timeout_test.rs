@@ -0,0 +1,112 @@
+use common::{admin_command, local_conf, query};
+use o2versioner::client::Connection;
+use o2versioner::comm::MsqlResponse;
+use o2versioner::core::*;
+use o2versioner::dbproxy_main;
+use o2versioner::scheduler_main;
+use o2versioner::sequencer_main;
+use o2versioner::util::tests_helper;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+mod common;
+
+#[tokio::test]
+async fn test_tx_timeout() {
+    let _guard = tests_helper::init_fast_logger();
+
+    let mut conf = local_conf(45390, 2);
+    conf.scheduler = conf
+        .scheduler
+        .set_tx_idle_timeout(Some(300))
+        .set_tx_max_lifetime(Some(1000));
+
+    let dbproxy_handles: Vec<_> = conf
+        .dbproxy
+        .iter()
+        .map(|dbproxy_conf| tokio::spawn(dbproxy_main(dbproxy_conf.clone())))
+        .collect();
+
+    let confc = conf.clone();
+    let sequencer_handle = tokio::spawn(async move {
+        sequencer_main(confc.sequencer).await;
+
+        println!("\nsequencer DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler_main(confc).await;
+
+        println!("\nscheduler DONE\n");
+    });
+
+    sleep(Duration::from_millis(300)).await;
+
+    let confc = conf.clone();
+    let client_handle = tokio::spawn(async move {
+        let conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+        let other_conn = Connection::connect(&confc.scheduler.addr).await.unwrap();
+
+        // The other transaction on the same table proceeds once the idle transaction is rolled back
+        begin_write_tx(&conn).await;
+        begin_write_tx(&other_conn).await;
+        timeout(Duration::from_secs(3), update(&other_conn))
+            .await
+            .expect("Versions of the idle transaction are not released");
+        assert!(other_conn.execute(commit()).await.unwrap().is_ok());
+
+        // The next request of the idle client fails
+        let err = update(&conn).await.try_get_err().cloned().unwrap();
+        assert_eq!(err.kind(), MsqlErrorKind::Timeout);
+        assert_eq!(err.message(), "transaction was idle for more than 300 ms");
+
+        // A busy transaction is rolled back once it reaches its maximum lifetime
+        begin_write_tx(&conn).await;
+        let mut num_updates = 0;
+        let err = loop {
+            let response = update(&conn).await;
+            if let Some(err) = response.try_get_err() {
+                break err.clone();
+            }
+            num_updates += 1;
+            sleep(Duration::from_millis(100)).await;
+        };
+        assert_eq!(err.kind(), MsqlErrorKind::Timeout);
+        assert_eq!(err.message(), "transaction exceeded the maximum lifetime of 1000 ms");
+        assert!(num_updates >= 5, "{}", num_updates);
+
+        // The connection still works
+        begin_write_tx(&conn).await;
+        assert!(update(&conn).await.is_ok());
+        assert!(conn.execute(commit()).await.unwrap().is_ok());
+        drop(conn);
+        drop(other_conn);
+
+        let mut admin = TcpStream::connect(confc.scheduler.admin_addr.as_ref().unwrap())
+            .await
+            .unwrap();
+        admin_command(&mut admin, "kill").await;
+
+        println!("\nclient DONE\n");
+    });
+
+    tokio::try_join!(scheduler_handle, sequencer_handle, client_handle).unwrap();
+    for dbproxy_handle in dbproxy_handles {
+        dbproxy_handle.await.unwrap();
+    }
+}
+
+async fn begin_write_tx(conn: &Connection) {
+    let msqlbegintx = MsqlBeginTx::from(TableOps::from("write t0"));
+    assert!(conn.execute(Msql::BeginTx(msqlbegintx)).await.unwrap().is_ok());
+}
+
+async fn update(conn: &Connection) -> MsqlResponse {
+    conn.execute(Msql::Query(query("update t0 set a = 1;"))).await.unwrap()
+}
+
+fn commit() -> Msql {
+    Msql::EndTx(MsqlEndTx::commit())
+}